@@ -2,7 +2,6 @@ const anchor = require("@coral-xyz/anchor");
 const {
   PublicKey,
   SystemProgram,
-  SYSVAR_RENT_PUBKEY,
   Keypair,
 } = require("@solana/web3.js");
 const fs = require("fs");
@@ -56,7 +55,6 @@ const fs = require("fs");
         multisig: multisigPda,
         payer: payerKeypair.publicKey,
         systemProgram: SystemProgram.programId,
-        rent: SYSVAR_RENT_PUBKEY,
       })
       .signers([payerKeypair])
       .rpc();
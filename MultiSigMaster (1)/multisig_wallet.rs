@@ -1,8 +1,28 @@
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_lang::solana_program::{
-    instruction::{AccountMeta, Instruction},
-    program::invoke_signed,
+    bpf_loader_upgradeable,
+    hash::hash,
+    instruction::{get_stack_height, AccountMeta, Instruction, TRANSACTION_LEVEL_STACK_HEIGHT},
+    program::{invoke, invoke_signed, set_return_data},
+    program_option::COption,
+    program_pack::Pack,
+    stake::{
+        self,
+        state::{Authorized, Lockup},
+    },
+    system_instruction,
+    sysvar::{
+        self,
+        instructions::{load_current_index_checked, load_instruction_at_checked},
+    },
+    vote,
 };
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token::spl_token::{self, native_mint};
+use anchor_spl::token_2022::spl_token_2022;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
@@ -16,17 +36,43 @@ pub mod multisig_wallet {
     /// - `initial_signers`: List of public keys that can approve transactions.
     /// - `threshold`: Number of approvals required to execute a transaction.
     /// - `expiration_timestamp`: Optional timestamp after which transactions expire.
+    ///   Must be in the future, and at most `MAX_EXPIRATION_HORIZON` out.
+    /// - `activation`: Optional acceptance requirement. When set, the multisig
+    ///   starts out pending and only activates once enough of the listed
+    ///   signers have called `accept_initial_membership`.
     pub fn initialize_multisig(
         ctx: Context<InitializeMultisig>,
         initial_signers: Vec<Pubkey>,
         threshold: u8,
-        expiration_timestamp: Option<u64>,
+        expiration_timestamp: Option<i64>,
+        activation: Option<Activation>,
     ) -> Result<()> {
-        // Validate threshold
+        // Validate the signers, then the threshold
+        let (vault, vault_bump) = find_vault_address(&ctx.accounts.multisig.key());
+        validate_signer_keys(&initial_signers, &ctx.accounts.multisig.key(), &vault)?;
+        let limits = load_program_config(&ctx.accounts.program_config)?.limits;
+        if initial_signers.len() > limits.max_signers as usize {
+            return err!(MultisigWalletError::TooManySigners);
+        }
         if threshold == 0 || threshold as usize > initial_signers.len() {
             return err!(MultisigWalletError::InvalidThreshold);
         }
 
+        // Validate the expiration
+        if let Some(expiration) = expiration_timestamp {
+            validate_expiration(expiration, Clock::get()?.unix_timestamp)?;
+        }
+
+        // Validate the acceptance requirement
+        if let Some(activation) = &activation {
+            if activation.required_acceptances == 0
+                || activation.required_acceptances as usize > initial_signers.len()
+                || activation.deadline <= Clock::get()?.unix_timestamp
+            {
+                return err!(MultisigWalletError::InvalidActivation);
+            }
+        }
+
         // Initialize multisig account
         let multisig = &mut ctx.accounts.multisig;
         multisig.signers = initial_signers;
@@ -34,6 +80,69 @@ pub mod multisig_wallet {
         multisig.expiration_timestamp = expiration_timestamp;
         multisig.nonce = 0;
         multisig.bump = *ctx.bumps.get("multisig").unwrap();
+        multisig.create_key = ctx.accounts.create_key.key();
+        multisig.authority = ctx.accounts.payer.key();
+        multisig.mint_caps = Vec::new();
+        multisig.memo_on_execute = false;
+        multisig.ordered_execution = false;
+        multisig.last_executed_index = None;
+        multisig.owner_set_seqno = 0;
+        multisig.invalidate_on_member_change = false;
+        multisig.proposal_cooldown_seconds = 0;
+        multisig.member_activity = Vec::new();
+        multisig.member_swap_window_seconds = DEFAULT_MEMBER_SWAP_WINDOW;
+        multisig.emergency_timelock_seconds = DEFAULT_EMERGENCY_TIMELOCK;
+        multisig.pending_emergency = None;
+        multisig.recovery_members = Vec::new();
+        multisig.recovery_threshold = 0;
+        multisig.recovery_delay_seconds = 0;
+        multisig.approver_program = None;
+        multisig.max_external_approvals = 0;
+        multisig.allowed_programs = Vec::new();
+        multisig.default_proposal_ttl_seconds = limits.default_proposal_ttl_seconds;
+        multisig.reimburse_proposal_rent = false;
+        multisig.cleanup_bounty_lamports = 0;
+        multisig.cleanup_bounty_vault_cap = 0;
+        multisig.snapshot_count = 0;
+        multisig.max_account_metas = limits.default_max_account_metas;
+        multisig.sub_budgets = Vec::new();
+        multisig.key_rotation_cooldown_seconds = 0;
+        multisig.reassign_approvals_on_rotation = false;
+        multisig.rate_limits = Vec::new();
+        multisig.status = if activation.is_some() { MultisigStatus::Pending } else { MultisigStatus::Active };
+        multisig.required_acceptances = activation.as_ref().map_or(0, |activation| activation.required_acceptances);
+        multisig.activation_deadline = activation.as_ref().map_or(0, |activation| activation.deadline);
+        multisig.accepted_members = Vec::new();
+        multisig.priority_levels = Vec::new();
+        multisig.token_vote = None;
+        multisig.vault_bump = vault_bump;
+        multisig.prune_removed_approvals = true;
+        multisig.execution_policy = ExecutionPolicy::Anyone;
+        multisig.automation_authority = None;
+        multisig.live_threshold = false;
+        multisig.execution_grace_seconds = 0;
+        multisig.allow_dangerous_system_instructions = false;
+        multisig.payout_caps = Vec::new();
+        multisig.inactivity_period_seconds = 0;
+        multisig.budget_epochs = None;
+        multisig.epoch_caps = Vec::new();
+        multisig.fee_payer = FeePayer::Vault;
+        multisig.history_hash = [0; 32];
+        multisig.typed_only = false;
+        multisig.typed_only_disable_at = 0;
+        multisig.vote_cooldown_slots = 0;
+        multisig.confirmation_threshold_bytes = 0;
+        multisig.proposer_excluded = false;
+        multisig.open_window = OpenProposalWindow::default();
+        multisig.member_allowances = Vec::new();
+
+        // Count the multisigs made at this address, so proposals left behind
+        // by a closed one never pass for this one's
+        let generation = &mut ctx.accounts.generation;
+        generation.generation += 1;
+        generation.bump = *ctx.bumps.get("generation").unwrap();
+        multisig.generation = generation.generation;
+        multisig.reserved = [0; MULTISIG_RESERVED_SPACE];
 
         Ok(())
     }
@@ -44,326 +153,7239 @@ pub mod multisig_wallet {
     /// - `program_id`: The program ID of the instruction to execute.
     /// - `accounts`: Serialized account metas for the instruction.
     /// - `instruction_data`: The instruction data.
+    /// - `options`: Everything else about the proposal, see
+    ///   `ProposalOptions`. Its default proposes with none of them.
     pub fn propose_transaction(
         ctx: Context<ProposeTransaction>,
         program_id: Pubkey,
         accounts: Vec<u8>,
         instruction_data: Vec<u8>,
+        options: ProposalOptions,
     ) -> Result<()> {
         let multisig = &mut ctx.accounts.multisig;
-        let transaction = &mut ctx.accounts.transaction;
         let proposer = ctx.accounts.proposer.key();
 
+        multisig.require_active()?;
+
         // Validate proposer is a signer in the multisig
         if !is_signer_in_multisig(&multisig.signers, &proposer) {
             return err!(MultisigWalletError::SignerNotFound);
         }
 
-        // Validate accounts vector length
-        if accounts.len() % 33 != 0 {
-            return err!(MultisigWalletError::InvalidAccountMetas);
+        // Enforce the per-proposer cooldown
+        let clock = Clock::get()?;
+        multisig.record_proposal(proposer, clock.unix_timestamp)?;
+        note_member_action(multisig, proposer, clock.unix_timestamp);
+
+        let config = load_program_config(&ctx.accounts.program_config)?;
+        propose_opaque(
+            multisig,
+            &ctx.accounts.transaction,
+            &ctx.accounts.rent_payer,
+            &ctx.accounts.system_program.to_account_info(),
+            &config,
+            OpaqueProposal {
+                proposer,
+                program_id,
+                accounts,
+                instruction_data,
+                memo: options.memo,
+                assertions: options.assertions,
+                read_only: options.read_only,
+                priority: options.priority,
+                authority_kind: options.authority_kind,
+                automatable: options.automatable,
+                category: options.category,
+                now: clock.unix_timestamp,
+                slot: clock.slot,
+                ttl_seconds: options.ttl_seconds,
+                joint_with: None,
+                depends_on: options.depends_on,
+                compute: options.compute,
+                sweep: options.sweep,
+                ephemeral_signers: options.ephemeral_signers,
+                reserved_index: None,
+            },
+        )
+    }
+
+    /// Proposes between two and five transactions at once, at consecutive
+    /// indexes. The batch fails as a whole if any proposal is invalid.
+    ///
+    /// Each proposal gets the defaults of `propose_transaction`: no balance
+    /// assertions, the multisig's default TTL, normal priority, signed by the
+    /// vault and not automatable. The transaction accounts, one per proposal
+    /// in order, are passed as writable remaining accounts.
+    ///
+    /// # Arguments
+    /// - `proposals`: The proposals, in index order.
+    pub fn propose_transactions_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProposeTransactionsBatch<'info>>,
+        proposals: Vec<BatchProposal>,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let proposer = ctx.accounts.proposer.key();
+
+        multisig.require_active()?;
+
+        // Validate proposer is a signer in the multisig
+        if !is_signer_in_multisig(&multisig.signers, &proposer) {
+            return err!(MultisigWalletError::SignerNotFound);
         }
 
-        // Initialize transaction account
-        transaction.multisig = multisig.key();
-        transaction.proposer = proposer;
-        transaction.tx_index = multisig.nonce;
-        transaction.program_id = program_id;
-        transaction.accounts = accounts;
-        transaction.data = instruction_data;
-        transaction.executed = false;
-        transaction.bump = *ctx.bumps.get("transaction").unwrap();
-        transaction.signers = vec![proposer]; // Proposer auto-approves
+        if proposals.len() < 2 || proposals.len() > MAX_BATCH_PROPOSALS {
+            return err!(MultisigWalletError::InvalidBatchSize);
+        }
+        if ctx.remaining_accounts.len() < proposals.len() {
+            return err!(MultisigWalletError::InsufficientAccounts);
+        }
 
-        // Increment transaction counter
-        multisig.nonce += 1;
+        // The batch counts as one proposal for the per-proposer cooldown
+        let clock = Clock::get()?;
+        multisig.record_proposal(proposer, clock.unix_timestamp)?;
+        note_member_action(multisig, proposer, clock.unix_timestamp);
+
+        let config = load_program_config(&ctx.accounts.program_config)?;
+        for (proposal, transaction) in proposals.into_iter().zip(ctx.remaining_accounts) {
+            propose_opaque(
+                multisig,
+                transaction,
+                &ctx.accounts.rent_payer,
+                &ctx.accounts.system_program.to_account_info(),
+                &config,
+                OpaqueProposal {
+                    proposer,
+                    program_id: proposal.program_id,
+                    accounts: proposal.accounts,
+                    instruction_data: proposal.instruction_data,
+                    memo: proposal.memo,
+                    assertions: Vec::new(),
+                    read_only: false,
+                    priority: 0,
+                    authority_kind: AuthorityKind::Vault(0),
+                    automatable: false,
+                    category: proposal.category,
+                    now: clock.unix_timestamp,
+                    slot: clock.slot,
+                    ttl_seconds: None,
+                    joint_with: None,
+                    depends_on: None,
+                    compute: None,
+                    sweep: false,
+                    ephemeral_signers: 0,
+                    reserved_index: None,
+                },
+            )?;
+        }
 
         Ok(())
     }
 
-    /// Approves a proposed transaction.
-    pub fn approve_transaction(ctx: Context<ApproveTransaction>) -> Result<()> {
-        let multisig = &ctx.accounts.multisig;
-        let transaction = &mut ctx.accounts.transaction;
-        let signer = ctx.accounts.signer.key();
+    /// Proposes staking vault SOL with a validator.
+    ///
+    /// On execution a new stake account is created at a PDA of the proposal,
+    /// funded from the vault and delegated, with the vault as both stake and
+    /// withdraw authority.
+    ///
+    /// # Arguments
+    /// - `validator`: Vote account to delegate to (passed as the first remaining account).
+    /// - `lamports`: Lamports moved into the stake account, including its rent reserve.
+    pub fn propose_stake_delegate(
+        ctx: Context<ProposeAction>,
+        validator: Pubkey,
+        lamports: u64,
+    ) -> Result<()> {
+        if lamports == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
 
-        // Check if transaction has expired
-        if let Some(expiration) = multisig.expiration_timestamp {
-            let clock = Clock::get()?;
-            if clock.unix_timestamp >= 0 && (clock.unix_timestamp as u64) > expiration {
-                return err!(MultisigWalletError::TransactionExpired);
-            }
+        let vote_account = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if vote_account.key() != validator || *vote_account.owner != vote::program::ID {
+            return err!(MultisigWalletError::InvalidVoteAccount);
         }
 
-        // Check if signer is in multisig
-        if !is_signer_in_multisig(&multisig.signers, &signer) {
-            return err!(MultisigWalletError::SignerNotFound);
+        propose_action(ctx, stake::program::ID, TypedAction::DelegateStake { validator, lamports }, 0, false)
+    }
+
+    /// Proposes deactivating a stake account whose stake authority is the vault.
+    ///
+    /// # Arguments
+    /// - `stake_account`: The stake account (passed as the first remaining account).
+    pub fn propose_stake_deactivate(ctx: Context<ProposeAction>, stake_account: Pubkey) -> Result<()> {
+        let vault = vault_address(&ctx.accounts.multisig.key(), ctx.accounts.multisig.vault_bump)?;
+        let (staker, _) = read_stake_authorities(ctx.remaining_accounts, &stake_account)?;
+        if staker != vault {
+            return err!(MultisigWalletError::InvalidStakeAccount);
         }
 
-        // Check if signer has already approved
-        if transaction.signers.contains(&signer) {
-            return err!(MultisigWalletError::AlreadyApproved);
+        propose_action(ctx, stake::program::ID, TypedAction::DeactivateStake { stake_account }, 0, false)
+    }
+
+    /// Proposes withdrawing lamports from a stake account whose withdraw
+    /// authority is the vault.
+    ///
+    /// # Arguments
+    /// - `stake_account`: The stake account (passed as the first remaining account).
+    /// - `lamports`: Lamports to withdraw.
+    /// - `recipient`: Account receiving the withdrawn lamports.
+    pub fn propose_stake_withdraw(
+        ctx: Context<ProposeAction>,
+        stake_account: Pubkey,
+        lamports: u64,
+        recipient: Pubkey,
+    ) -> Result<()> {
+        if lamports == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
         }
 
-        // Add signer to approvals
-        transaction.signers.push(signer);
+        let vault = vault_address(&ctx.accounts.multisig.key(), ctx.accounts.multisig.vault_bump)?;
+        let (_, withdrawer) = read_stake_authorities(ctx.remaining_accounts, &stake_account)?;
+        if withdrawer != vault {
+            return err!(MultisigWalletError::InvalidStakeAccount);
+        }
 
-        Ok(())
+        propose_action(
+            ctx,
+            stake::program::ID,
+            TypedAction::WithdrawStake { stake_account, lamports, recipient },
+            0,
+            false,
+        )
     }
 
-    /// Executes a transaction that has enough approvals.
-    pub fn execute_transaction(ctx: Context<ExecuteTransaction>) -> Result<()> {
-        let multisig = &ctx.accounts.multisig;
-        let transaction = &mut ctx.accounts.transaction;
+    /// Proposes wrapping vault SOL into the vault's wSOL associated token account.
+    ///
+    /// Execution creates the wSOL account if needed, moves the lamports and
+    /// calls `sync_native`.
+    ///
+    /// # Arguments
+    /// - `amount`: Lamports to wrap.
+    pub fn propose_wrap_sol(ctx: Context<ProposeAction>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
 
-        // Check if transaction has already been executed
-        if transaction.executed {
-            return err!(MultisigWalletError::TransactionAlreadyExecuted);
+        propose_action(ctx, spl_token::ID, TypedAction::WrapSol { amount }, 0, false)
+    }
+
+    /// Proposes closing the vault's wSOL associated token account, returning
+    /// its balance and rent to the vault as native SOL.
+    pub fn propose_unwrap_sol(ctx: Context<ProposeAction>) -> Result<()> {
+        propose_action(ctx, spl_token::ID, TypedAction::UnwrapSol, 0, false)
+    }
+
+    /// Proposes sending an NFT held by the vault to a recipient.
+    ///
+    /// Execution creates the recipient's associated token account if needed and
+    /// performs a `transfer_checked` of one token with zero decimals, for both
+    /// the legacy Token program and Token-2022.
+    ///
+    /// # Arguments
+    /// - `mint`: The NFT mint (first remaining account).
+    /// - `recipient`: Wallet receiving the NFT.
+    ///
+    /// The vault's associated token account for the mint is the second
+    /// remaining account and must hold the token.
+    pub fn propose_nft_transfer(ctx: Context<ProposeAction>, mint: Pubkey, recipient: Pubkey) -> Result<()> {
+        let vault = vault_address(&ctx.accounts.multisig.key(), ctx.accounts.multisig.vault_bump)?;
+
+        let mint_account = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if mint_account.key() != mint {
+            return err!(MultisigWalletError::InvalidMint);
+        }
+        let token_program = *mint_account.owner;
+        if load_mint(mint_account)?.decimals != 0 {
+            return err!(MultisigWalletError::InvalidMint);
         }
 
-        // Check if there are enough approvals
-        if transaction.signers.len() < multisig.threshold as usize {
-            return err!(MultisigWalletError::InsufficientApprovals);
+        let source = get_associated_token_address_with_program_id(&vault, &mint, &token_program);
+        let source_account = ctx.remaining_accounts.get(1).ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if source_account.key() != source {
+            return err!(MultisigWalletError::InvalidTokenAccount);
+        }
+        let holding = load_token_account(source_account)?;
+        if holding.mint != mint || holding.owner != vault || holding.amount == 0 {
+            return err!(MultisigWalletError::InsufficientVaultBalance);
         }
 
-        // Deserialize account metas
-        let account_metas = deserialize_account_metas(&transaction.accounts)?;
+        propose_action(
+            ctx,
+            token_program,
+            TypedAction::NftTransfer { mint, recipient, token_program },
+            0,
+            false,
+        )
+    }
 
-        // Validate remaining accounts
-        if ctx.remaining_accounts.len() < account_metas.len() {
-            return err!(MultisigWalletError::InsufficientAccounts);
+    /// Proposes creating a new token mint with the vault as mint authority.
+    ///
+    /// The mint is created at the PDA `["mint", transaction]`, funded by the vault.
+    ///
+    /// # Arguments
+    /// - `decimals`: Decimals of the new mint.
+    /// - `freeze_authority`: Optional freeze authority.
+    /// - `token_program`: Token or Token-2022 program owning the mint.
+    pub fn propose_create_mint(
+        ctx: Context<ProposeAction>,
+        decimals: u8,
+        freeze_authority: Option<Pubkey>,
+        token_program: Pubkey,
+    ) -> Result<()> {
+        if token_program != spl_token::ID && token_program != spl_token_2022::ID {
+            return err!(MultisigWalletError::InvalidMint);
+        }
+
+        propose_action(
+            ctx,
+            token_program,
+            TypedAction::CreateMint { decimals, freeze_authority, token_program },
+            0,
+            false,
+        )
+    }
+
+    /// Proposes minting tokens from a mint whose mint authority is the vault.
+    ///
+    /// Mints with a configured mint cap are limited per period at execution.
+    ///
+    /// # Arguments
+    /// - `mint`: The mint (first remaining account).
+    /// - `destination`: Token account receiving the tokens (second remaining account).
+    /// - `amount`: Amount to mint, in base units.
+    pub fn propose_mint_to(
+        ctx: Context<ProposeAction>,
+        mint: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
         }
 
-        // Create remaining accounts array
-        let mut invoke_accounts = Vec::with_capacity(account_metas.len());
-        for (i, meta) in account_metas.iter().enumerate() {
-            let account = ctx.remaining_accounts.get(i).ok_or(MultisigWalletError::InsufficientAccounts)?;
-            if account.key() != meta.pubkey {
-                return err!(MultisigWalletError::InvalidAccountMetas);
+        let vault = vault_address(&ctx.accounts.multisig.key(), ctx.accounts.multisig.vault_bump)?;
+        let mint_account = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if mint_account.key() != mint {
+            return err!(MultisigWalletError::InvalidMint);
+        }
+        let token_program = *mint_account.owner;
+        if load_mint(mint_account)?.mint_authority != COption::Some(vault) {
+            return err!(MultisigWalletError::InvalidMintAuthority);
+        }
+
+        let destination_account = ctx.remaining_accounts.get(1).ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if destination_account.key() != destination || load_token_account(destination_account)?.mint != mint {
+            return err!(MultisigWalletError::InvalidTokenAccount);
+        }
+
+        propose_action(
+            ctx,
+            token_program,
+            TypedAction::MintTo { mint, destination, amount, token_program },
+            0,
+            false,
+        )
+    }
+
+    /// Proposes changing an authority the vault holds over a mint or token account.
+    ///
+    /// # Arguments
+    /// - `target_account`: The mint or token account (first remaining account).
+    /// - `authority_type`: Which authority to change.
+    /// - `new_authority`: The new authority, or `None` to remove it permanently.
+    /// - `confirm_irrevocable`: Must be set when `new_authority` is `None`.
+    pub fn propose_set_authority(
+        ctx: Context<ProposeAction>,
+        target_account: Pubkey,
+        authority_type: TokenAuthorityType,
+        new_authority: Option<Pubkey>,
+        confirm_irrevocable: bool,
+    ) -> Result<()> {
+        if new_authority.is_none() {
+            if authority_type == TokenAuthorityType::AccountOwner {
+                return err!(MultisigWalletError::InvalidAuthorityChange);
+            }
+            if !confirm_irrevocable {
+                return err!(MultisigWalletError::IrrevocableChangeNotConfirmed);
             }
-            invoke_accounts.push(AccountMeta {
-                pubkey: account.key(),
-                is_signer: meta.is_signer,
-                is_writable: meta.is_writable,
-            });
         }
 
-        // Prevent recursive CPI to this program
-        if transaction.program_id == ctx.program_id {
-            return err!(MultisigWalletError::RecursiveCallNotAllowed);
+        let vault = vault_address(&ctx.accounts.multisig.key(), ctx.accounts.multisig.vault_bump)?;
+        let target = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if target.key() != target_account {
+            return err!(MultisigWalletError::InvalidAuthorityChange);
         }
 
-        // Create instruction
-        let instruction = Instruction {
-            program_id: transaction.program_id,
-            accounts: invoke_accounts,
-            data: transaction.data.clone(),
+        // The vault must currently hold the authority being changed
+        let current_authority = match authority_type {
+            TokenAuthorityType::MintTokens => load_mint(target)?.mint_authority,
+            TokenAuthorityType::FreezeAccount => load_mint(target)?.freeze_authority,
+            TokenAuthorityType::AccountOwner => COption::Some(load_token_account(target)?.owner),
+            TokenAuthorityType::CloseAccount => {
+                let token_account = load_token_account(target)?;
+                match token_account.close_authority {
+                    COption::Some(close_authority) => COption::Some(close_authority),
+                    COption::None => COption::Some(token_account.owner),
+                }
+            }
         };
+        if current_authority != COption::Some(vault) {
+            return err!(MultisigWalletError::InvalidAuthorityChange);
+        }
 
-        // Get PDA signer
-        let multisig_key = multisig.key();
-        let seeds = &[
-            b"multisig".as_ref(),
-            ctx.accounts.creator.key.as_ref(),
-            &[multisig.bump],
-        ];
-        let signer_seeds = &[&seeds[..]];
+        let token_program = *target.owner;
+        propose_action(
+            ctx,
+            token_program,
+            TypedAction::SetAuthority { target: target_account, authority_type, new_authority, token_program },
+            0,
+            false,
+        )
+    }
 
-        // Execute transaction via CPI
-        invoke_signed(&instruction, ctx.remaining_accounts, signer_seeds)?;
+    /// Proposes burning tokens from the vault's associated token account.
+    ///
+    /// # Arguments
+    /// - `mint`: The mint (first remaining account).
+    /// - `amount`: Amount to burn, in base units.
+    /// - `close_if_empty`: Close the token account, returning its rent to the
+    ///   vault, when the burn empties it.
+    ///
+    /// The vault's associated token account for the mint is the second
+    /// remaining account and must hold at least `amount`.
+    pub fn propose_token_burn(
+        ctx: Context<ProposeAction>,
+        mint: Pubkey,
+        amount: u64,
+        close_if_empty: bool,
+    ) -> Result<()> {
+        if amount == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
 
-        // Mark transaction as executed
-        transaction.executed = true;
+        let vault = vault_address(&ctx.accounts.multisig.key(), ctx.accounts.multisig.vault_bump)?;
+        let mint_account = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if mint_account.key() != mint {
+            return err!(MultisigWalletError::InvalidMint);
+        }
+        let token_program = *mint_account.owner;
+        let decimals = load_mint(mint_account)?.decimals;
 
-        Ok(())
+        let source = get_associated_token_address_with_program_id(&vault, &mint, &token_program);
+        let source_account = ctx.remaining_accounts.get(1).ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if source_account.key() != source {
+            return err!(MultisigWalletError::InvalidTokenAccount);
+        }
+        if load_token_account(source_account)?.amount < amount {
+            return err!(MultisigWalletError::InsufficientVaultBalance);
+        }
+
+        propose_action(
+            ctx,
+            token_program,
+            TypedAction::TokenBurn { mint, amount, decimals, close_if_empty, token_program },
+            0,
+            false,
+        )
     }
 
-    /// Updates the multisig configuration (signers, threshold, or expiration).
-    pub fn update_multisig(
-        ctx: Context<UpdateMultisig>,
-        new_signers: Option<Vec<Pubkey>>,
-        new_threshold: Option<u8>,
-        new_expiration: Option<u64>,
+    /// Proposes sending SOL from the vault to a recipient.
+    ///
+    /// # Arguments
+    /// - `recipient`: Account receiving the lamports.
+    /// - `lamports`: Amount to send.
+    /// - `escrow`: Lock the lamports in an escrow once the proposal reaches
+    ///   its threshold, see `lock_escrow`.
+    /// - `category`: Budget category the transfer counts towards, `0` for none.
+    /// - `sweep`: Whether the transfer may empty the vault entirely rather
+    ///   than leave it rent-exempt.
+    pub fn propose_transfer(
+        ctx: Context<ProposeAction>,
+        recipient: Pubkey,
+        lamports: u64,
+        escrow: bool,
+        category: u16,
+        sweep: bool,
     ) -> Result<()> {
-        let multisig = &mut ctx.accounts.multisig;
+        if lamports == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
 
-        // Verify all current signers have approved
-        for signer in &multisig.signers {
-            let found = ctx.remaining_accounts.iter().any(|account| {
-                account.key() == *signer && account.is_signer
-            });
-            if !found {
-                return err!(MultisigWalletError::NotAllSignersApproved);
-            }
+        propose_action(
+            ctx,
+            anchor_lang::system_program::ID,
+            TypedAction::Transfer { recipient, lamports, escrow },
+            category,
+            sweep,
+        )
+    }
+
+    /// Proposes sending tokens from the vault's associated token account to
+    /// a recipient, whose associated token account is created if needed.
+    ///
+    /// # Arguments
+    /// - `mint`: The mint (first remaining account).
+    /// - `recipient`: Wallet receiving the tokens.
+    /// - `amount`: Amount to send, in base units.
+    /// - `escrow`: Lock the tokens in an escrow once the proposal reaches its
+    ///   threshold, see `lock_escrow`.
+    /// - `category`: Budget category the transfer counts towards, `0` for none.
+    pub fn propose_token_transfer(
+        ctx: Context<ProposeAction>,
+        mint: Pubkey,
+        recipient: Pubkey,
+        amount: u64,
+        escrow: bool,
+        category: u16,
+    ) -> Result<()> {
+        if amount == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
         }
 
-        // Update signers if provided
-        if let Some(signers) = new_signers {
-            multisig.signers = signers;
+        let mint_account = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+        if mint_account.key() != mint {
+            return err!(MultisigWalletError::InvalidMint);
         }
+        let token_program = *mint_account.owner;
+        let decimals = load_mint(mint_account)?.decimals;
 
-        // Update threshold if provided
-        if let Some(threshold) = new_threshold {
-            if threshold == 0 || threshold as usize > multisig.signers.len() {
-                return err!(MultisigWalletError::InvalidThreshold);
+        propose_action(
+            ctx,
+            token_program,
+            TypedAction::TokenTransfer { mint, recipient, amount, decimals, token_program, escrow },
+            category,
+            false,
+        )
+    }
+
+    /// Proposes paying several recipients from the vault in one proposal,
+    /// such as a payroll run. Execution makes every transfer or none.
+    ///
+    /// # Arguments
+    /// - `mint`: The token to pay out (first remaining account), or `None`
+    ///   for SOL.
+    /// - `entries`: Up to 20 recipients, each at most once, with the
+    ///   lamports or token base units they get.
+    /// - `category`: Budget category the payout counts towards, `0` for none.
+    pub fn propose_payout(
+        ctx: Context<ProposeAction>,
+        mint: Option<Pubkey>,
+        entries: Vec<PayoutEntry>,
+        category: u16,
+    ) -> Result<()> {
+        if entries.is_empty() || entries.len() > MAX_PAYOUT_ENTRIES {
+            return err!(MultisigWalletError::InvalidPayoutSize);
+        }
+        if entries.iter().any(|entry| entry.amount == 0) {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            if entries[..i].iter().any(|other| other.recipient == entry.recipient) {
+                msg!("Payout recipient {} is listed twice", entry.recipient);
+                return err!(MultisigWalletError::DuplicatePayoutRecipient);
             }
-            multisig.threshold = threshold;
         }
 
-        // Update expiration if provided
-        multisig.expiration_timestamp = new_expiration;
+        let total = entries
+            .iter()
+            .try_fold(0u64, |total, entry| total.checked_add(entry.amount))
+            .ok_or(MultisigWalletError::InvalidAmount)?;
+        if ctx.accounts.multisig.payout_cap(mint).is_some_and(|cap| total > cap) {
+            return err!(MultisigWalletError::PayoutCapExceeded);
+        }
+
+        let (program_id, decimals) = match mint {
+            None => (anchor_lang::system_program::ID, 0),
+            Some(mint) => {
+                let mint_account = ctx.remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+                if mint_account.key() != mint {
+                    return err!(MultisigWalletError::InvalidMint);
+                }
+                (*mint_account.owner, load_mint(mint_account)?.decimals)
+            }
+        };
 
-        Ok(())
+        propose_action(
+            ctx,
+            program_id,
+            TypedAction::Payout { mint, decimals, token_program: program_id, entries },
+            category,
+            false,
+        )
+    }
+
+    /// Proposes an external instruction the board registered with
+    /// `register_external_action`. The instruction is built at execution
+    /// from the registered template and `args`, and only while the template
+    /// is still registered unchanged.
+    ///
+    /// # Arguments
+    /// - `tag`: The registered action's tag.
+    /// - `args`: Values for the template's arguments, in order and of the
+    ///   declared types.
+    /// - `category`: Budget category the action counts towards, `0` for none.
+    ///
+    /// The multisig's action registry must be among the remaining accounts.
+    pub fn propose_registered_action(
+        ctx: Context<ProposeAction>,
+        tag: String,
+        args: Vec<ActionArg>,
+        category: u16,
+    ) -> Result<()> {
+        let template = load_registered_action(ctx.remaining_accounts, &ctx.accounts.multisig.key(), &tag)?;
+        template.check_args(&args)?;
+
+        let program_id = template.program_id;
+        propose_action(
+            ctx,
+            program_id,
+            TypedAction::Registered { tag, program_id, args, template_hash: template.hash() },
+            category,
+            false,
+        )
+    }
+
+    /// Approves a proposed transaction. With `expected_hash`, the approval
+    /// only goes through if the proposal still hashes to what the client
+    /// displayed.
+    pub fn approve_transaction(ctx: Context<ApproveTransaction>, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let signer = ctx.accounts.signer.key();
+        record_approval(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, signer, String::new(), false)
     }
 
-    /// Closes the multisig account and transfers lamports to the receiver.
-    pub fn close_multisig(ctx: Context<CloseMultisig>) -> Result<()> {
+    /// Approves the proposal at `tx_index`. The transaction account is
+    /// checked against the PDA derived from the multisig and the index, so a
+    /// client passing the wrong address fails with a seeds error naming it.
+    /// `expected_hash` pins the proposal as for `approve_transaction`.
+    pub fn approve_transaction_by_index(ctx: Context<ApproveTransactionByIndex>, _tx_index: u64, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let signer = ctx.accounts.signer.key();
+        record_approval(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, signer, String::new(), false)
+    }
+
+    /// Like `approve_transaction`, but succeeds without changing anything or
+    /// emitting events when the signer has already approved, so a client can
+    /// safely retry an approval whose outcome it didn't see. Every other
+    /// check still runs.
+    pub fn approve_transaction_idempotent(
+        ctx: Context<ApproveTransaction>,
+        expected_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let signer = ctx.accounts.signer.key();
+        record_approval(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, signer, String::new(), true)
+    }
+
+    /// Approves a transaction and leaves a short note alongside the approval.
+    pub fn approve_transaction_with_memo(ctx: Context<ApproveTransaction>, memo: String) -> Result<()> {
+        if memo.len() > MAX_APPROVAL_MEMO_LEN {
+            return err!(MultisigWalletError::MemoTooLong);
+        }
+
+        let signer = ctx.accounts.signer.key();
+        record_approval(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, signer, memo, false)
+    }
+
+    /// Rejects a proposed transaction, recording an optional reason. Once
+    /// enough signers reject that the threshold can no longer be reached,
+    /// the transaction is cancelled.
+    pub fn reject_transaction(ctx: Context<ApproveTransaction>, reason: String) -> Result<()> {
         let multisig = &mut ctx.accounts.multisig;
-        let receiver = &mut ctx.accounts.receiver;
+        let transaction = &mut ctx.accounts.transaction;
+        let signer = ctx.accounts.signer.key();
 
-        // Verify all current signers have approved
-        for signer in &multisig.signers {
-            let found = ctx.remaining_accounts.iter().any(|account| {
-                account.key() == *signer && account.is_signer
-            });
-            if !found {
-                return err!(MultisigWalletError::NotAllSignersApproved);
-            }
+        assert_approvable(multisig, transaction, &Clock::get()?)?;
+
+        if reason.len() > MAX_REJECTION_REASON_LEN {
+            return err!(MultisigWalletError::MemoTooLong);
         }
 
-        // Transfer lamports to receiver
-        let multisig_lamports = multisig.to_account_info().lamports();
-        **multisig.to_account_info().lamports.borrow_mut() = 0;
-        **receiver.to_account_info().lamports.borrow_mut() += multisig_lamports;
+        // Check if signer was a member when the proposal was made
+        if !transaction.is_eligible_approver(&signer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        // A signer either approves or rejects, once
+        if transaction.signers.contains(&signer) {
+            return err!(MultisigWalletError::AlreadyApproved);
+        }
+        if transaction.rejections.contains(&signer) {
+            return err!(MultisigWalletError::AlreadyRejected);
+        }
+        transaction.note_vote(&signer, Clock::get()?.slot, multisig.vote_cooldown_slots)?;
+
+        transaction.rejections.push(signer);
+        transaction.rejection_reasons.push(reason.clone());
+
+        // Cancel once the remaining signers, plus any approvals the approver
+        // program could still grant, can't reach the threshold. An excluded
+        // proposer is no remaining signer. An open token vote could still
+        // reach its quorum, so it holds off the cancellation
+        let proposer_uncounted = transaction.proposer_excluded
+            && transaction.is_eligible_approver(&transaction.proposer)
+            && !transaction.rejections.contains(&transaction.proposer);
+        let remaining = transaction
+            .eligible_approvers
+            .len()
+            .saturating_sub(transaction.rejections.len() + proposer_uncounted as usize)
+            + multisig.external_approval_capacity();
+        let now = Clock::get()?.unix_timestamp;
+        let vote_open =
+            multisig.token_vote.is_some() && transaction.vote_ends_at.is_some_and(|vote_ends_at| now <= vote_ends_at);
+        if remaining < multisig.threshold_for(transaction) && !vote_open {
+            transaction.cancelled = true;
+            record_terminal_history(multisig, transaction, HistoryRecord::Cancelled)?;
+        }
+
+        emit!(TransactionRejected {
+            multisig: multisig.key(),
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            priority: transaction.priority,
+            signer,
+            reason,
+            cancelled: transaction.cancelled,
+        });
 
         Ok(())
     }
-}
 
-// Helper function to check if a signer is in the multisig
-fn is_signer_in_multisig(signers: &[Pubkey], signer: &Pubkey) -> bool {
-    signers.contains(signer)
-}
+    /// Records approvals granted by the registered approver program, such as
+    /// a governance program once a vote passes there. Must be invoked by a
+    /// direct CPI from that program, and only once per transaction.
+    pub fn approve_external(ctx: Context<ApproveExternal>, approvals: u8) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
 
-// Helper function to deserialize account metas
-fn deserialize_account_metas(data: &[u8]) -> Result<Vec<AccountMeta>> {
-    if data.len() % 33 != 0 {
-        return err!(MultisigWalletError::InvalidAccountMetas);
-    }
+        assert_approvable(multisig, transaction, &Clock::get()?)?;
+
+        // Verify the calling program: with a direct CPI, the current top-level
+        // instruction belongs to the caller
+        let approver_program = multisig
+            .approver_program
+            .ok_or(MultisigWalletError::InvalidApproverProgram)?;
+        let instructions = ctx.accounts.instructions.to_account_info();
+        let current_index = load_current_index_checked(&instructions)?;
+        let caller = load_instruction_at_checked(current_index as usize, &instructions)?;
+        if get_stack_height() != TRANSACTION_LEVEL_STACK_HEIGHT + 1 || caller.program_id != approver_program {
+            return err!(MultisigWalletError::InvalidApproverProgram);
+        }
 
-    let mut account_metas = Vec::with_capacity(data.len() / 33);
-    let mut i = 0;
+        if transaction.external_approvals > 0 {
+            return err!(MultisigWalletError::ExternalApprovalAlreadyRecorded);
+        }
+        if approvals == 0 || approvals > multisig.max_external_approvals {
+            return err!(MultisigWalletError::ExternalApprovalsExceedCap);
+        }
 
-    while i < data.len() {
-        let pubkey_bytes = data.get(i..i + 32).ok_or(MultisigWalletError::InvalidAccountMetas)?;
-        let pubkey = Pubkey::new_from_array(pubkey_bytes.try_into().unwrap());
-        let flags = *data.get(i + 32).ok_or(MultisigWalletError::InvalidAccountMetas)?;
-        let is_signer = (flags & 1) != 0;
-        let is_writable = (flags & 2) != 0;
+        let was_approved = multisig.is_approved(transaction);
+        transaction.external_approvals = approvals;
+        let required = multisig.threshold_for(transaction);
+        transaction.note_approvals(required, Clock::get()?.unix_timestamp);
 
-        account_metas.push(AccountMeta {
-            pubkey,
-            is_signer,
-            is_writable,
+        emit!(ExternalApprovalRecorded {
+            multisig: multisig.key(),
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            priority: transaction.priority,
+            approver_program,
+            approvals,
         });
+        emit_threshold_reached(multisig, transaction, was_approved);
 
-        i += 33;
+        Ok(())
     }
 
-    Ok(account_metas)
-}
+    /// Executes a transaction that has enough approvals. With
+    /// `expected_hash`, the execution only goes through if the proposal still
+    /// hashes to what the client displayed.
+    pub fn execute_transaction<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteTransaction<'info>>, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let executor = ctx.accounts.executor.as_ref().map(|executor| executor.to_account_info());
+        execute(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, executor, ctx.remaining_accounts, false)
+    }
 
-#[derive(Accounts)]
-#[instruction(initial_signers: Vec<Pubkey>, threshold: u8, expiration_timestamp: Option<u64>)]
-pub struct InitializeMultisig<'info> {
-    #[account(
-        init,
-        payer = payer,
-        space = 8 + // discriminator
-                4 + (initial_signers.len() * 32) + // signers vector
-                1 + // threshold
-                9 + // optional expiration timestamp
-                8 + // nonce
-                1,  // bump
-        seeds = [b"multisig", payer.key().as_ref()],
-        bump
-    )]
-    pub multisig: Account<'info, MultisigAccount>,
-    
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+    /// Executes the proposal at `tx_index`, with the transaction account
+    /// checked against the PDA derived from the multisig and the index.
+    /// Otherwise as for `execute_transaction`.
+    pub fn execute_transaction_by_index<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteTransactionByIndex<'info>>, _tx_index: u64, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let executor = ctx.accounts.executor.as_ref().map(|executor| executor.to_account_info());
+        execute(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, executor, ctx.remaining_accounts, false)
+    }
 
-#[derive(Accounts)]
-#[instruction(program_id: Pubkey, accounts: Vec<u8>, instruction_data: Vec<u8>)]
-pub struct ProposeTransaction<'info> {
-    #[account(
-        mut,
-        seeds = [b"multisig", creator.key().as_ref()],
-        bump = multisig.bump
-    )]
-    pub multisig: Account<'info, MultisigAccount>,
-    
-    #[account(
-        init,
-        payer = proposer,
-        space = 8 + // discriminator
-                32 + // multisig pubkey
-                32 + // proposer pubkey
-                8 +  // tx_index
-                32 + // program_id
-                4 + accounts.len() + // accounts vector
-                4 + instruction_data.len() + // data vector
-                4 + (multisig.signers.len() * 32) + // signers vector (dynamic)
-                1 + // executed
-                1,  // bump
-        seeds = [b"tx", multisig.key().as_ref(), &multisig.nonce.to_le_bytes()],
-        bump
-    )]
-    pub transaction: Account<'info, TransactionAccount>,
-    
-    /// CHECK: This is just used as a seed for the multisig PDA
-    pub creator: UncheckedAccount<'info>,
-    
-    #[account(mut)]
-    pub proposer: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
-}
+    /// Executes a transaction like `execute_transaction`, but records a
+    /// failed check instead of failing: `failed_attempts` goes up,
+    /// `last_error_code` holds the error, and the call succeeds so the
+    /// caller's fees aren't wasted. The proposal stays executable.
+    ///
+    /// Only failures before anything is invoked can be recorded: missing or
+    /// mismatched accounts, preconditions, limits, timelocks and the like. A
+    /// failing CPI or fee payment, or a balance assertion that doesn't hold
+    /// afterwards, still fails the whole transaction, since the runtime
+    /// can't undo an invoke short of that.
+    pub fn try_execute_transaction<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteTransaction<'info>>, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let executor_account = ctx.accounts.executor.as_ref().map(|executor| executor.to_account_info());
+        let executor = executor_account.as_ref().map(|executor| *executor.key);
 
-#[derive(Accounts)]
-pub struct ApproveTransaction<'info> {
-    #[account(
-        seeds = [b"multisig", creator.key().as_ref()],
-        bump = multisig.bump
-    )]
-    pub multisig: Account<'info, MultisigAccount>,
-    
-    #[account(
-        mut,
-        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
-        bump = transaction.bump,
-        constraint = transaction.multisig == multisig.key()
-    )]
-    pub transaction: Account<'info, TransactionAccount>,
-    
-    /// CHECK: This is just used as a seed for the multisig PDA
-    pub creator: UncheckedAccount<'info>,
-    
-    pub signer: Signer<'info>,
-}
+        // Checks may meter outflows or advance the ordered index before
+        // failing; a recorded failure must leave none of that behind
+        let multisig_before = ctx.accounts.multisig.clone().into_inner();
+        let multisig = &mut ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+        match prepare_execution(multisig, transaction, executor, ctx.remaining_accounts, false, false) {
+            Ok(prepared) => {
+                perform_execution(multisig, transaction, executor_account, ctx.remaining_accounts, prepared)
+            }
+            Err(error) => {
+                multisig.set_inner(multisig_before);
+                let error_code = u64::from(ProgramError::from(error));
+                transaction.failed_attempts = transaction.failed_attempts.saturating_add(1);
+                transaction.last_error_code = error_code;
+                msg!(
+                    "Execution attempt {} of transaction {} failed: {}",
+                    transaction.failed_attempts,
+                    transaction.tx_index,
+                    error_code
+                );
 
-#[derive(Accounts)]
+                emit!(ExecutionAttemptFailed {
+                    multisig: multisig.key(),
+                    tx_index: transaction.tx_index,
+                    transaction_hash: transaction.hash,
+                    executor,
+                    error_code,
+                    failed_attempts: transaction.failed_attempts,
+                });
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Executes a read-only proposal. Every stored account meta is
+    /// downgraded to read-only before the CPI, so the target program can't
+    /// mutate any account passed to it; if it needs to, the execution fails.
+    pub fn execute_readonly<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteTransaction<'info>>, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let executor = ctx.accounts.executor.as_ref().map(|executor| executor.to_account_info());
+        execute(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, executor, ctx.remaining_accounts, true)
+    }
+
+    /// Proposes this multisig's half of a joint operation with `partner`:
+    /// one instruction signed by both multisigs. The partner proposes the
+    /// same program, account metas and data naming this multisig, and
+    /// `execute_joint` runs it once both halves are approved.
+    ///
+    /// # Arguments
+    /// - `partner`: The other multisig.
+    /// - `program_id`, `accounts`, `instruction_data`, `memo`,
+    ///   `authority_kind`: As for `propose_transaction`. Signer metas may
+    ///   also name the partner multisig or its main vault.
+    pub fn propose_joint_transaction(
+        ctx: Context<ProposeTransaction>,
+        partner: Pubkey,
+        program_id: Pubkey,
+        accounts: Vec<u8>,
+        instruction_data: Vec<u8>,
+        memo: Option<String>,
+        authority_kind: AuthorityKind,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let proposer = ctx.accounts.proposer.key();
+
+        multisig.require_active()?;
+
+        // Validate proposer is a signer in the multisig
+        if !is_signer_in_multisig(&multisig.signers, &proposer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        // Enforce the per-proposer cooldown
+        let clock = Clock::get()?;
+        multisig.record_proposal(proposer, clock.unix_timestamp)?;
+        note_member_action(multisig, proposer, clock.unix_timestamp);
+
+        let config = load_program_config(&ctx.accounts.program_config)?;
+        propose_opaque(
+            multisig,
+            &ctx.accounts.transaction,
+            &ctx.accounts.rent_payer,
+            &ctx.accounts.system_program.to_account_info(),
+            &config,
+            OpaqueProposal {
+                proposer,
+                program_id,
+                accounts,
+                instruction_data,
+                memo,
+                assertions: Vec::new(),
+                read_only: false,
+                priority: 0,
+                authority_kind,
+                automatable: false,
+                category: 0,
+                now: clock.unix_timestamp,
+                slot: clock.slot,
+                ttl_seconds: None,
+                joint_with: Some(partner),
+                depends_on: None,
+                compute: None,
+                sweep: false,
+                ephemeral_signers: 0,
+                reserved_index: None,
+            },
+        )
+    }
+
+    /// Executes two halves of a joint proposal in a single CPI signed by
+    /// both multisigs. The halves must name each other and describe the
+    /// same operation, and each must pass the checks `execute_transaction`
+    /// would apply to it; if either side fails, nothing runs.
+    ///
+    /// Remaining accounts are the instruction's accounts, as for
+    /// `execute_transaction`.
+    pub fn execute_joint<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteJoint<'info>>) -> Result<()> {
+        let clock = Clock::get()?;
+        let executor = ctx.accounts.executor.as_ref().map(|executor| executor.key());
+        let multisig_key = ctx.accounts.multisig.key();
+        let partner_key = ctx.accounts.partner_multisig.key();
+
+        let transaction = &ctx.accounts.transaction;
+        let partner_transaction = &ctx.accounts.partner_transaction;
+        let operation_hash = transaction.joint_operation_hash();
+        if transaction.joint_with != Some(partner_key)
+            || partner_transaction.joint_with != Some(multisig_key)
+            || operation_hash != partner_transaction.joint_operation_hash()
+        {
+            return err!(MultisigWalletError::JointProposalMismatch);
+        }
+        if transaction.program_id == *ctx.program_id {
+            return err!(MultisigWalletError::RecursiveCallNotAllowed);
+        }
+
+        let (authority, authority_bump) =
+            check_joint_side(&mut ctx.accounts.multisig, transaction, executor, &clock)?;
+        let (partner_authority, partner_bump) =
+            check_joint_side(&mut ctx.accounts.partner_multisig, partner_transaction, executor, &clock)?;
+        check_cluster(transaction, ctx.remaining_accounts)?;
+        check_cluster(partner_transaction, ctx.remaining_accounts)?;
+
+        // Each side pays its own protocol fee before the CPI
+        let executor_account = ctx.accounts.executor.as_ref().map(|executor| executor.to_account_info());
+        pay_protocol_fee(&ctx.accounts.multisig, transaction, executor_account.as_ref(), ctx.remaining_accounts)?;
+        pay_protocol_fee(
+            &ctx.accounts.partner_multisig,
+            partner_transaction,
+            executor_account.as_ref(),
+            ctx.remaining_accounts,
+        )?;
+
+        let account_metas = transaction.account_metas();
+        let instruction = build_opaque_instruction(
+            transaction,
+            &account_metas,
+            ctx.remaining_accounts,
+            &[authority, partner_authority],
+            false,
+        )?;
+
+        // Each side records the execution with the Memo program first if it
+        // asks for it, as it would on its own
+        let sides = [
+            (&ctx.accounts.multisig, transaction, multisig_key),
+            (&ctx.accounts.partner_multisig, partner_transaction, partner_key),
+        ];
+        let mut instructions = Vec::with_capacity(3);
+        for (multisig, transaction, key) in sides {
+            if transaction.memo.is_some() || multisig.memo_on_execute {
+                let memo = execution_memo(&key, transaction.tx_index, transaction.memo.as_deref());
+                instructions.push(Instruction { program_id: spl_memo::ID, accounts: vec![], data: memo.into_bytes() });
+            }
+        }
+        instructions.push(instruction);
+
+        let seeds = authority_seeds(&ctx.accounts.multisig, transaction.authority_kind, authority_bump);
+        let partner_seeds =
+            authority_seeds(&ctx.accounts.partner_multisig, partner_transaction.authority_kind, partner_bump);
+        let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+        let partner_seeds: Vec<&[u8]> = partner_seeds.iter().map(Vec::as_slice).collect();
+
+        // Snapshot the vaults each side could draw from, to keep them
+        // rent-exempt
+        let vault = vault_address(&multisig_key, ctx.accounts.multisig.vault_bump)?;
+        let partner_vault = vault_address(&partner_key, ctx.accounts.partner_multisig.vault_bump)?;
+        let vaults_before = vault_balances(ctx.remaining_accounts, &[vault, authority]);
+        let partner_vaults_before = vault_balances(ctx.remaining_accounts, &[partner_vault, partner_authority]);
+
+        let units_before = remaining_compute_units();
+        for instruction in &instructions {
+            invoke_signed(instruction, ctx.remaining_accounts, &[&seeds[..], &partner_seeds[..]])?;
+        }
+
+        // Fail (and roll back the CPI) if either side's vault was drawn
+        // below rent exemption
+        check_vault_rent(ctx.remaining_accounts, &vaults_before, transaction.sweep)?;
+        check_vault_rent(ctx.remaining_accounts, &partner_vaults_before, partner_transaction.sweep)?;
+
+        // Each side keeps its receipt if asked for, as it would on its own
+        for (multisig, transaction) in [
+            (&ctx.accounts.multisig, &ctx.accounts.transaction),
+            (&ctx.accounts.partner_multisig, &ctx.accounts.partner_transaction),
+        ] {
+            let executor = executor_account.as_ref();
+            create_execution_receipt(multisig, transaction, executor, ctx.remaining_accounts, clock.unix_timestamp)?;
+        }
+
+        for (multisig, transaction) in [
+            (multisig_key, &mut ctx.accounts.transaction),
+            (partner_key, &mut ctx.accounts.partner_transaction),
+        ] {
+            transaction.executed = true;
+            emit!(TransactionExecuted {
+                multisig,
+                tx_index: transaction.tx_index,
+                transaction_hash: transaction.hash,
+                priority: transaction.priority,
+                executor,
+                automated: false,
+                category: transaction.category,
+                kind: transaction.kind.clone(),
+                remaining_compute_units: units_before,
+            });
+        }
+        let tx_index = ctx.accounts.transaction.tx_index;
+        ctx.accounts.multisig.note_proposal_closed(tx_index);
+        let partner_tx_index = ctx.accounts.partner_transaction.tx_index;
+        ctx.accounts.partner_multisig.note_proposal_closed(partner_tx_index);
+        emit!(JointTransactionExecuted {
+            multisig: multisig_key,
+            tx_index: ctx.accounts.transaction.tx_index,
+            partner_multisig: partner_key,
+            partner_tx_index: ctx.accounts.partner_transaction.tx_index,
+            operation_hash: operation_hash.unwrap(),
+        });
+
+        Ok(())
+    }
+
+    /// Cancels the next transaction in ordered mode so later ones can execute.
+    ///
+    /// At least `threshold` current signers must sign, passed as remaining accounts.
+    pub fn skip_transaction(ctx: Context<SkipTransaction>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+
+        // Verify a threshold of current signers approved the skip
+        require_threshold_signers(&multisig.signers, multisig.threshold, ctx.remaining_accounts)?;
+
+        if transaction.executed {
+            return err!(MultisigWalletError::TransactionAlreadyExecuted);
+        }
+        if transaction.cancelled {
+            return err!(MultisigWalletError::TransactionCancelled);
+        }
+        if !multisig.ordered_execution || transaction.tx_index != multisig.next_ordered_index() {
+            return err!(MultisigWalletError::OutOfOrderExecution);
+        }
+
+        transaction.cancelled = true;
+        multisig.last_executed_index = Some(transaction.tx_index);
+        record_terminal_history(multisig, transaction, HistoryRecord::Cancelled)?;
+
+        Ok(())
+    }
+
+    /// Closes a transaction that can no longer execute: expired, cancelled,
+    /// or made stale by a signer change. Anyone may crank this and collect
+    /// the configured cleanup bounty, paid from the reclaimed rent and topped
+    /// up from the vault within its cap; the rest of the rent goes back to
+    /// the rent payer.
+    pub fn close_expired_transaction(ctx: Context<CloseExpiredTransaction>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let transaction = &ctx.accounts.transaction;
+
+        if transaction.executed {
+            return err!(MultisigWalletError::TransactionAlreadyExecuted);
+        }
+        let stale =
+            transaction.owner_set_seqno != multisig.owner_set_seqno || transaction.generation != multisig.generation;
+        let expired = transaction.execution_expired(Clock::get()?.unix_timestamp, multisig.execution_grace_seconds);
+        if !transaction.cancelled && !stale && !expired {
+            return err!(MultisigWalletError::TransactionStillActive);
+        }
+        if transaction.escrowed {
+            return err!(MultisigWalletError::EscrowStillFunded);
+        }
+        // Its stage account would be left behind; release it first
+        if transaction.staged {
+            return err!(MultisigWalletError::ExecutionStaged);
+        }
+
+        let transaction_info = transaction.to_account_info();
+        let cranker = ctx.accounts.cranker.to_account_info();
+        let bounty = multisig.cleanup_bounty_lamports;
+        let from_rent = bounty.min(transaction_info.lamports());
+        let from_vault = (bounty - from_rent).min(multisig.cleanup_bounty_vault_cap);
+
+        // Top up from the vault first, skipping it if the vault can't afford it
+        let vault = &ctx.accounts.vault;
+        if from_vault > 0 && vault.lamports() >= from_vault.saturating_add(Rent::get()?.minimum_balance(0)) {
+            let multisig_key = multisig.key();
+            let vault_bump = multisig.vault_bump;
+            let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[vault_bump]];
+            invoke_signed(
+                &system_instruction::transfer(vault.key, cranker.key, from_vault),
+                &[
+                    vault.to_account_info(),
+                    cranker.clone(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+                &[&vault_seeds[..]],
+            )?;
+        }
+
+        // The rest of the rent goes to the rent payer when the account closes
+        **transaction_info.try_borrow_mut_lamports()? -= from_rent;
+        **cranker.try_borrow_mut_lamports()? += from_rent;
+
+        record_terminal_history(multisig, transaction, HistoryRecord::Closed)?;
+
+        Ok(())
+    }
+
+    /// Re-encodes the account metas of a pending proposal made before the
+    /// compact encoding, and shrinks the account to fit. The freed rent goes
+    /// back to the rent payer. Any member can call it. The hash covers the
+    /// metas as proposed, so it and the approvals bound to it carry over.
+    pub fn migrate_transaction_encoding(ctx: Context<MigrateTransactionEncoding>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+
+        if !is_signer_in_multisig(&multisig.signers, &ctx.accounts.member.key()) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+        if transaction.executed {
+            return err!(MultisigWalletError::TransactionAlreadyExecuted);
+        }
+        if transaction.cancelled {
+            return err!(MultisigWalletError::TransactionCancelled);
+        }
+        if transaction.version != TRANSACTION_VERSION_LEGACY_METAS {
+            return err!(MultisigWalletError::TransactionAlreadyMigrated);
+        }
+
+        let accounts = encode_compact_account_metas(&deserialize_account_metas(&transaction.accounts));
+        let freed = transaction.accounts.len() - accounts.len();
+        transaction.accounts = accounts;
+        transaction.version = TRANSACTION_VERSION_COMPACT_METAS;
+
+        // The serialized proposal shrinks by exactly the bytes freed
+        let transaction_info = transaction.to_account_info();
+        let rent_payer = ctx.accounts.rent_payer.to_account_info();
+        let space = transaction_info.data_len() - freed;
+        let rent = Rent::get()?;
+        let refund = (rent.minimum_balance(transaction_info.data_len()) - rent.minimum_balance(space))
+            .min(transaction_info.lamports().saturating_sub(rent.minimum_balance(space)));
+        transaction_info.realloc(space, false)?;
+        **transaction_info.try_borrow_mut_lamports()? -= refund;
+        **rent_payer.try_borrow_mut_lamports()? += refund;
+
+        emit!(TransactionMigrated {
+            multisig: multisig.key(),
+            tx_index: transaction.tx_index,
+            version: transaction.version,
+            freed_bytes: freed as u32,
+            refund,
+        });
+
+        Ok(())
+    }
+
+    /// Grows a multisig created before `proposer_excluded` and `open_window`
+    /// to the current layout. Such a multisig with every bounded list full
+    /// has no unused room to read them from, and fails to load until grown.
+    /// The new bytes are zeroed, so the flag reads `false` and the window
+    /// empty until `sync_open_window`. Anyone may call it; the payer covers
+    /// the added rent.
+    pub fn migrate_multisig_account(ctx: Context<MigrateMultisigAccount>) -> Result<()> {
+        let multisig = ctx.accounts.multisig.to_account_info();
+        let old_len = multisig.data_len();
+
+        // Checked by hand: an account that needs this can't deserialize
+        let signers_len = {
+            let data = multisig.try_borrow_data()?;
+            if *multisig.owner != crate::ID || old_len < 12 || data[..8] != MultisigAccount::DISCRIMINATOR {
+                return err!(MultisigWalletError::InvalidMultisigAccount);
+            }
+            u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize
+        };
+        let new_len = MultisigAccount::space(signers_len).max(old_len);
+        if new_len == old_len {
+            return err!(MultisigWalletError::MultisigAlreadyMigrated);
+        }
+
+        let rent = Rent::get()?.minimum_balance(new_len).saturating_sub(multisig.lamports());
+        if rent > 0 {
+            let payer = ctx.accounts.payer.to_account_info();
+            invoke(
+                &system_instruction::transfer(payer.key, multisig.key, rent),
+                &[payer, multisig.clone(), ctx.accounts.system_program.to_account_info()],
+            )?;
+        }
+        multisig.realloc(new_len, true)?;
+
+        emit!(MultisigMigrated {
+            multisig: multisig.key(),
+            old_len: old_len as u32,
+            new_len: new_len as u32,
+            rent,
+        });
+
+        Ok(())
+    }
+
+    /// Records deposits that reached the vault since the last call. Plain
+    /// transfers can't run program code, so depositors (or anyone) call this
+    /// afterwards: it compares the vault's lamports and the given vault token
+    /// accounts (passed as remaining accounts) against the deposit ledger's
+    /// last snapshot and emits the change.
+    pub fn record_deposit(ctx: Context<RecordDeposit>, memo: String) -> Result<()> {
+        if memo.len() > MAX_MEMO_LEN {
+            return err!(MultisigWalletError::MemoTooLong);
+        }
+
+        let vault = ctx.accounts.vault.key();
+        let ledger = &mut ctx.accounts.ledger;
+        ledger.multisig = ctx.accounts.multisig.key();
+        ledger.bump = *ctx.bumps.get("ledger").unwrap();
+
+        let lamports = ctx.accounts.vault.lamports();
+        let lamports_delta = lamports as i128 - ledger.lamports as i128;
+        ledger.lamports = lamports;
+
+        let mut token_deltas = Vec::with_capacity(ctx.remaining_accounts.len());
+        for account in ctx.remaining_accounts {
+            let token_account = load_token_account(account)?;
+            if token_account.owner != vault {
+                return err!(MultisigWalletError::InvalidTokenAccount);
+            }
+
+            let amount = token_account.amount;
+            let previous = match ledger.tokens.iter_mut().find(|balance| balance.account == account.key()) {
+                Some(balance) => std::mem::replace(&mut balance.amount, amount),
+                None => {
+                    if ledger.tokens.len() >= MAX_TRACKED_TOKEN_ACCOUNTS {
+                        return err!(MultisigWalletError::TooManyTrackedTokenAccounts);
+                    }
+                    ledger.tokens.push(TrackedBalance { account: account.key(), amount });
+                    0
+                }
+            };
+            token_deltas.push(TokenDelta {
+                account: account.key(),
+                mint: token_account.mint,
+                delta: amount as i128 - previous as i128,
+            });
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        ledger.last_recorded_at = now;
+
+        emit!(DepositRecorded {
+            multisig: ledger.multisig,
+            recorded_by: ctx.accounts.payer.key(),
+            lamports_delta,
+            token_deltas,
+            memo,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Writes a point-in-time record of the treasury into a new snapshot
+    /// account: the vault's lamports plus the mint and amount of each vault
+    /// token account passed as remaining accounts.
+    pub fn snapshot_balances(ctx: Context<SnapshotBalances>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        if !is_signer_in_multisig(&multisig.signers, &ctx.accounts.member.key()) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+        if ctx.remaining_accounts.len() > MAX_SNAPSHOT_TOKEN_ACCOUNTS {
+            return err!(MultisigWalletError::TooManySnapshotAccounts);
+        }
+
+        // Every registered vault token account must be in the snapshot
+        let listed: Vec<Pubkey> = ctx.remaining_accounts.iter().map(|account| account.key()).collect();
+        require_registered_listed(&ctx.accounts.registry, &listed)?;
+
+        let vault = ctx.accounts.vault.key();
+        let mut holdings = Vec::with_capacity(ctx.remaining_accounts.len());
+        for account in ctx.remaining_accounts {
+            let token_account = load_token_account(account)?;
+            if token_account.owner != vault {
+                return err!(MultisigWalletError::InvalidTokenAccount);
+            }
+            holdings.push(TokenHolding {
+                mint: token_account.mint,
+                amount: token_account.amount,
+            });
+        }
+
+        let snapshot = &mut ctx.accounts.snapshot;
+        snapshot.multisig = multisig.key();
+        snapshot.index = multisig.snapshot_count;
+        snapshot.lamports = ctx.accounts.vault.lamports();
+        snapshot.holdings = holdings;
+        snapshot.taken_at = Clock::get()?.unix_timestamp;
+        snapshot.bump = *ctx.bumps.get("snapshot").unwrap();
+        multisig.snapshot_count += 1;
+
+        emit!(BalancesSnapshotted {
+            multisig: snapshot.multisig,
+            snapshot: snapshot.key(),
+            index: snapshot.index,
+            lamports: snapshot.lamports,
+            holdings: snapshot.holdings.clone(),
+            taken_at: snapshot.taken_at,
+        });
+
+        Ok(())
+    }
+
+    /// Creates the vault's associated token account for a mint, paid by the
+    /// calling member, and records it in the vault's token registry. Does
+    /// nothing but register it if the account already exists.
+    pub fn create_vault_ata(ctx: Context<CreateVaultAta>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        if !is_signer_in_multisig(&multisig.signers, &ctx.accounts.member.key()) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        let member = ctx.accounts.member.to_account_info();
+        let vault = ctx.accounts.vault.to_account_info();
+        let mint = ctx.accounts.mint.to_account_info();
+        let token_account = ctx.accounts.token_account.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        invoke(
+            &create_associated_token_account_idempotent(member.key, vault.key, mint.key, token_program.key),
+            &[
+                member.clone(),
+                token_account.clone(),
+                vault,
+                mint.clone(),
+                ctx.accounts.system_program.to_account_info(),
+                token_program,
+                ctx.accounts.associated_token_program.to_account_info(),
+            ],
+        )?;
+
+        let registry = &mut ctx.accounts.registry;
+        registry.multisig = multisig.key();
+        registry.bump = *ctx.bumps.get("registry").unwrap();
+        registry.register(token_account.key(), mint.key())
+    }
+
+    /// Removes closed accounts from the vault's token registry. Anyone may
+    /// call this; each account passed as a remaining account must be
+    /// registered and no longer be a token account of the vault.
+    pub fn prune_token_registry(ctx: Context<PruneTokenRegistry>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let vault = vault_address(&multisig.key(), multisig.vault_bump)?;
+        let registry = &mut ctx.accounts.registry;
+
+        for account in ctx.remaining_accounts {
+            if !registry.contains(account.key) {
+                return err!(MultisigWalletError::TokenAccountNotRegistered);
+            }
+            if load_token_account(account).is_ok_and(|state| state.owner == vault) {
+                return err!(MultisigWalletError::TokenAccountStillOpen);
+            }
+            registry.unregister(account.key);
+        }
+
+        Ok(())
+    }
+
+    /// Starts a new reporting period for the per-category outflow totals,
+    /// creating the stats account on first use. Like `configure_multisig`,
+    /// every current signer must approve, passed as signing remaining
+    /// accounts. The closing period's totals are emitted before clearing.
+    pub fn reset_category_stats(ctx: Context<ResetCategoryStats>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        require_all_signers(&multisig.signers, ctx.remaining_accounts)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let stats = &mut ctx.accounts.stats;
+        if stats.multisig != Pubkey::default() {
+            emit!(CategoryStatsReset {
+                multisig: multisig.key(),
+                outflows: stats.outflows.clone(),
+                period_started_at: stats.period_started_at,
+                period_ended_at: now,
+            });
+        }
+
+        stats.multisig = multisig.key();
+        stats.outflows = Vec::new();
+        stats.period_started_at = now;
+        stats.bump = *ctx.bumps.get("stats").unwrap();
+
+        Ok(())
+    }
+
+    /// Decodes a proposal into a compact `Explanation` and returns it as
+    /// return data. Nothing is written, so approval UIs can simulate this
+    /// instead of shipping a decoder for every typed action.
+    pub fn explain_transaction(ctx: Context<ExplainTransaction>) -> Result<()> {
+        let explanation = ctx.accounts.transaction.explain();
+        set_return_data(&explanation.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Returns the multisig's PDAs with their bumps as a Borsh-encoded `Pdas`
+    /// in return data: the multisig and its vault from their stored bumps, and
+    /// the transaction account the next proposal will create. Nothing is
+    /// written, so clients can simulate this instead of re-deriving bumps.
+    pub fn get_pdas(ctx: Context<GetPdas>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let multisig_key = multisig.key();
+        let (next_transaction, next_transaction_bump) = Pubkey::find_program_address(
+            &[b"tx", multisig_key.as_ref(), &multisig.nonce.to_le_bytes()],
+            ctx.program_id,
+        );
+        let pdas = Pdas {
+            multisig: Pda { address: multisig_key, bump: multisig.bump },
+            vault: Pda { address: vault_address(&multisig_key, multisig.vault_bump)?, bump: multisig.vault_bump },
+            next_transaction: Pda { address: next_transaction, bump: next_transaction_bump },
+            next_tx_index: multisig.nonce,
+        };
+        set_return_data(&pdas.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Flags members who haven't proposed, approved or executed for the
+    /// configured inactivity period, emitting `MemberFlaggedInactive` for
+    /// each. Anyone may call it. A member never seen acting starts its clock
+    /// at the first call, and acting again clears the flag.
+    pub fn flag_inactive_members(ctx: Context<UpdateMultisig>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let period = multisig.inactivity_period_seconds;
+        if period == 0 {
+            return err!(MultisigWalletError::InactivityPeriodNotSet);
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        let multisig_key = multisig.key();
+        let mut flagged = 0;
+        for member in multisig.signers.clone() {
+            if multisig.member_activity.iter().all(|activity| activity.member != member) {
+                multisig.record_action(member, now);
+            }
+            let activity = multisig.member_activity.iter_mut().find(|activity| activity.member == member).unwrap();
+            if activity.last_action_at == 0 {
+                activity.last_action_at = now;
+            }
+            if !activity.inactive && now >= activity.last_action_at.saturating_add(period) {
+                activity.inactive = true;
+                flagged += 1;
+                emit!(MemberFlaggedInactive {
+                    multisig: multisig_key,
+                    member,
+                    last_action_at: activity.last_action_at,
+                });
+            }
+        }
+
+        let inactive = multisig.member_activity.iter().filter(|activity| activity.inactive).count();
+        msg!("{} newly flagged, {} of {} members inactive", flagged, inactive, multisig.signers.len());
+
+        Ok(())
+    }
+
+    /// Spends from a budget the multisig granted to a child multisig, see
+    /// `ConfigAction::CreateSubBudget`. The child signs through its own
+    /// execution, so its quorum rather than the parent's approves the spend.
+    ///
+    /// Without a mint, `amount` lamports move from the vault to `recipient`.
+    /// With one, `recipient` is the destination token account and the mint,
+    /// the vault's associated token account and the token program are passed
+    /// as remaining accounts.
+    pub fn spend_from_budget<'info>(ctx: Context<'_, '_, '_, 'info, SpendFromBudget<'info>>, mint: Option<Pubkey>, amount: u64) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let child = ctx.accounts.child.key();
+        let now = Clock::get()?.unix_timestamp;
+
+        let budget = multisig
+            .sub_budgets
+            .iter_mut()
+            .find(|budget| budget.child == child)
+            .ok_or(MultisigWalletError::SubBudgetNotFound)?;
+        if budget.is_expired(now) {
+            return err!(MultisigWalletError::SubBudgetExpired);
+        }
+        let remaining = match mint {
+            None => &mut budget.lamports,
+            Some(mint) => {
+                &mut budget
+                    .mint_budgets
+                    .iter_mut()
+                    .find(|entry| entry.mint == mint)
+                    .ok_or(MultisigWalletError::SubBudgetExhausted)?
+                    .amount
+            }
+        };
+        *remaining = remaining.checked_sub(amount).ok_or(MultisigWalletError::SubBudgetExhausted)?;
+        multisig.consume_outflow(mint, amount, now)?;
+
+        let multisig_key = multisig.key();
+        let vault = ctx.accounts.vault.to_account_info();
+        let vault_bump = multisig.vault_bump;
+        let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[vault_bump]];
+        let recipient = ctx.accounts.recipient.to_account_info();
+
+        match mint {
+            None => invoke_signed(
+                &system_instruction::transfer(vault.key, recipient.key, amount),
+                &[vault, recipient, ctx.accounts.system_program.to_account_info()],
+                &[&vault_seeds[..]],
+            )?,
+            Some(mint) => {
+                let mint_info = find_account(ctx.remaining_accounts, &mint)?;
+                let token_program = *mint_info.owner;
+                let decimals = load_mint(mint_info)?.decimals;
+                let source = get_associated_token_address_with_program_id(vault.key, &mint, &token_program);
+                let mut accounts = ctx.remaining_accounts.to_vec();
+                accounts.extend([vault.clone(), recipient.clone()]);
+                invoke_signed(
+                    &spl_token_2022::instruction::transfer_checked(
+                        &token_program,
+                        &source,
+                        &mint,
+                        recipient.key,
+                        vault.key,
+                        &[],
+                        amount,
+                        decimals,
+                    )?,
+                    &accounts,
+                    &[&vault_seeds[..]],
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the caller's key in the signer set without a full config
+    /// round, e.g. after moving to a new hardware wallet. Pending transactions
+    /// passed as writable remaining accounts have the old key's approvals
+    /// moved to the new key, or dropped unless `reassign_approvals_on_rotation`
+    /// is set. Every other pending transaction becomes stale, so the old key
+    /// can't keep approving it.
+    pub fn rotate_my_key(ctx: Context<RotateKey>, new_key: Pubkey) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let old_key = ctx.accounts.member.key();
+
+        let position = multisig
+            .signers
+            .iter()
+            .position(|signer| *signer == old_key)
+            .ok_or(MultisigWalletError::SignerNotFound)?;
+        let mut signers = multisig.signers.clone();
+        signers[position] = new_key;
+        let vault = vault_address(&multisig.key(), multisig.vault_bump)?;
+        validate_signer_keys(&signers, &multisig.key(), &vault)?;
+        if multisig.recovery_members.contains(&new_key) {
+            return err!(MultisigWalletError::RecoverySetOverlap);
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        multisig.record_rotation(old_key, new_key, now)?;
+        multisig.signers = signers;
+        if let Some(allowance) = multisig.member_allowances.iter_mut().find(|allowance| allowance.member == old_key) {
+            allowance.member = new_key;
+        }
+
+        // Bump the signer set's sequence number, carrying over only the
+        // supplied transactions that were current
+        let multisig_key = multisig.key();
+        let reassign = multisig.reassign_approvals_on_rotation;
+        let previous_seqno = multisig.owner_set_seqno;
+        multisig.owner_set_seqno = previous_seqno.wrapping_add(1);
+        for info in ctx.remaining_accounts {
+            if *info.owner != crate::ID || !info.is_writable {
+                continue;
+            }
+            let Ok(mut transaction) = Account::<TransactionAccount>::try_from(info) else {
+                continue;
+            };
+            if transaction.multisig != multisig_key || transaction.executed {
+                continue;
+            }
+            transaction.rotate_member(&old_key, new_key, reassign);
+            if transaction.owner_set_seqno == previous_seqno {
+                transaction.owner_set_seqno = multisig.owner_set_seqno;
+            }
+            transaction.exit(&crate::ID)?;
+        }
+
+        emit!(KeyRotated {
+            multisig: multisig_key,
+            old_key,
+            new_key,
+            approvals_reassigned: reassign,
+        });
+
+        Ok(())
+    }
+
+    /// Locks the funds of an escrowed transfer proposal once it has enough
+    /// approvals, moving them from the vault into the proposal's escrow PDA so
+    /// other proposals can't spend them first. Anyone may call this.
+    ///
+    /// For token transfers, the mint, the vault's associated token account
+    /// and the token program are passed as remaining accounts.
+    pub fn lock_escrow<'info>(ctx: Context<'_, '_, '_, 'info, EscrowAction<'info>>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &ctx.accounts.transaction;
+
+        let action = match &transaction.action {
+            Some(action) if action.is_escrowed() => action.clone(),
+            _ => return err!(MultisigWalletError::NotEscrowed),
+        };
+        if transaction.escrowed {
+            return err!(MultisigWalletError::EscrowAlreadyFunded);
+        }
+        assert_approvable(multisig, transaction, &Clock::get()?)?;
+        if !multisig.is_approved(transaction) {
+            return err!(MultisigWalletError::InsufficientApprovals);
+        }
+
+        // The escrow can only be funded from what the vault actually holds
+        let vault = ctx.accounts.vault.to_account_info();
+        let (available, required) = match &action {
+            TypedAction::Transfer { lamports, .. } => {
+                (vault.lamports(), lamports.saturating_add(Rent::get()?.minimum_balance(0)))
+            }
+            TypedAction::TokenTransfer { mint, amount, token_program, .. } => {
+                let source = get_associated_token_address_with_program_id(vault.key, mint, token_program);
+                (load_token_account(find_account(ctx.remaining_accounts, &source)?)?.amount, *amount)
+            }
+            _ => return err!(MultisigWalletError::NotEscrowed),
+        };
+        if available < required {
+            return err!(MultisigWalletError::InsufficientVaultBalance);
+        }
+
+        let escrow = ctx.accounts.escrow.to_account_info();
+        let instructions = build_escrow_lock(&action, escrow.key, vault.key)?;
+        invoke_escrow_instructions(&ctx, &instructions)?;
+
+        ctx.accounts.transaction.escrowed = true;
+
+        Ok(())
+    }
+
+    /// Returns an escrowed proposal's funds to the vault once it can no
+    /// longer execute: cancelled, expired, or stale after a signer change.
+    /// Anyone may call this.
+    ///
+    /// For token transfers, the mint, the vault's associated token account
+    /// and the token program are passed as remaining accounts.
+    pub fn release_escrow<'info>(ctx: Context<'_, '_, '_, 'info, EscrowAction<'info>>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &ctx.accounts.transaction;
+
+        if !transaction.escrowed || transaction.executed {
+            return err!(MultisigWalletError::EscrowNotFunded);
+        }
+        let stale =
+            transaction.owner_set_seqno != multisig.owner_set_seqno || transaction.generation != multisig.generation;
+        let expired = transaction.execution_expired(Clock::get()?.unix_timestamp, multisig.execution_grace_seconds);
+        if !transaction.cancelled && !stale && !expired {
+            return err!(MultisigWalletError::TransactionStillActive);
+        }
+
+        let action = transaction.action.clone().unwrap();
+        let escrow = ctx.accounts.escrow.to_account_info();
+        let instructions = build_escrow_payout(&action, escrow.key, ctx.accounts.vault.key, escrow.lamports(), false)?;
+        invoke_escrow_instructions(&ctx, &instructions)?;
+
+        ctx.accounts.transaction.escrowed = false;
+
+        Ok(())
+    }
+
+    /// Votes for a proposal with governance tokens, the alternative to member
+    /// signatures enabled by `SetTokenVote`. The tokens are locked in the
+    /// voter's escrow for the proposal until `unlock_vote_tokens`; once the
+    /// locked total reaches the configured quorum the proposal can execute.
+    pub fn vote_with_tokens(ctx: Context<VoteWithTokens>, amount: u64) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &ctx.accounts.transaction;
+        let clock = Clock::get()?;
+        assert_approvable(multisig, transaction, &clock)?;
+
+        let token_vote = multisig.token_vote.clone().ok_or(MultisigWalletError::TokenVoteNotConfigured)?;
+        if ctx.accounts.mint.key() != token_vote.mint {
+            return err!(MultisigWalletError::InvalidMint);
+        }
+        if amount == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
+        // Proposals made before token votes were enabled have no window
+        let now = clock.unix_timestamp;
+        let vote_ends_at = transaction.vote_ends_at.ok_or(MultisigWalletError::TokenVoteNotConfigured)?;
+        if now > vote_ends_at {
+            return err!(MultisigWalletError::VoteWindowClosed);
+        }
+
+        let decimals = load_mint(&ctx.accounts.mint)?.decimals;
+        let token_program = ctx.accounts.token_program.key();
+        let voter = ctx.accounts.voter.key();
+        let escrow = ctx.accounts.escrow.key();
+
+        // The first vote creates the escrow, a token account owned by itself
+        let mut instructions = Vec::new();
+        if ctx.accounts.escrow.data_is_empty() {
+            let space = spl_token::state::Account::LEN;
+            instructions.push(system_instruction::create_account(
+                &voter,
+                &escrow,
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                &token_program,
+            ));
+            instructions.push(spl_token_2022::instruction::initialize_account3(
+                &token_program,
+                &escrow,
+                &token_vote.mint,
+                &escrow,
+            )?);
+        }
+        instructions.push(spl_token_2022::instruction::transfer_checked(
+            &token_program,
+            ctx.accounts.source.key,
+            &token_vote.mint,
+            &escrow,
+            &voter,
+            &[],
+            amount,
+            decimals,
+        )?);
+
+        let transaction_key = transaction.key();
+        let escrow_seeds = &[
+            b"vote_escrow".as_ref(),
+            transaction_key.as_ref(),
+            voter.as_ref(),
+            &[*ctx.bumps.get("escrow").unwrap()],
+        ];
+        let accounts = ctx.accounts.to_account_infos();
+        for instruction in &instructions {
+            invoke_signed(instruction, &accounts, &[&escrow_seeds[..]])?;
+        }
+
+        let transaction = &mut ctx.accounts.transaction;
+        let was_approved = multisig.is_approved(transaction);
+        transaction.token_votes = transaction.token_votes.saturating_add(amount);
+        if transaction.approved_at.is_none() && transaction.token_votes >= token_vote.required_amount {
+            transaction.approved_at = Some(now);
+        }
+
+        emit!(TokenVoteRecorded {
+            multisig: transaction.multisig,
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            priority: transaction.priority,
+            voter,
+            amount,
+            total: transaction.token_votes,
+            required: token_vote.required_amount,
+        });
+        emit_threshold_reached(multisig, transaction, was_approved);
+
+        Ok(())
+    }
+
+    /// Returns a voter's locked governance tokens and closes their escrow.
+    /// Allowed once the proposal's vote window has ended, or earlier once it
+    /// was executed, cancelled or closed. The proposal's tally is left as is:
+    /// a quorum reached stays reached.
+    pub fn unlock_vote_tokens(ctx: Context<UnlockVoteTokens>) -> Result<()> {
+        let transaction = &ctx.accounts.transaction;
+        let settled = if transaction.data_is_empty() {
+            true
+        } else {
+            if *transaction.owner != crate::ID {
+                return err!(MultisigWalletError::InvalidTransactionAccount);
+            }
+            let data = transaction.try_borrow_data()?;
+            let transaction = TransactionAccount::try_deserialize(&mut &data[..])?;
+            let now = Clock::get()?.unix_timestamp;
+            transaction.executed
+                || transaction.cancelled
+                || transaction.vote_ends_at.is_none_or(|vote_ends_at| now > vote_ends_at)
+        };
+        if !settled {
+            return err!(MultisigWalletError::VoteTokensLocked);
+        }
+
+        let voter = ctx.accounts.voter.key();
+        if load_token_account(&ctx.accounts.destination)?.owner != voter {
+            return err!(MultisigWalletError::InvalidTokenAccount);
+        }
+        let locked = load_token_account(&ctx.accounts.escrow)?;
+        let decimals = load_mint(&ctx.accounts.mint)?.decimals;
+
+        let token_program = ctx.accounts.token_program.key();
+        let escrow = ctx.accounts.escrow.key();
+        let instructions = vec![
+            spl_token_2022::instruction::transfer_checked(
+                &token_program,
+                &escrow,
+                ctx.accounts.mint.key,
+                ctx.accounts.destination.key,
+                &escrow,
+                &[],
+                locked.amount,
+                decimals,
+            )?,
+            spl_token_2022::instruction::close_account(&token_program, &escrow, &voter, &escrow, &[])?,
+        ];
+
+        let transaction_key = transaction.key();
+        let escrow_seeds = &[
+            b"vote_escrow".as_ref(),
+            transaction_key.as_ref(),
+            voter.as_ref(),
+            &[*ctx.bumps.get("escrow").unwrap()],
+        ];
+        let accounts = ctx.accounts.to_account_infos();
+        for instruction in &instructions {
+            invoke_signed(instruction, &accounts, &[&escrow_seeds[..]])?;
+        }
+
+        emit!(VoteTokensUnlocked {
+            transaction: transaction_key,
+            voter,
+            amount: locked.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Updates the multisig configuration (signers, threshold, or expiration).
+    /// Fails with `NoOpUpdate` unless at least one of them actually changes.
+    pub fn update_multisig<'info>(
+        ctx: Context<'_, '_, '_, 'info, UpdateMultisigWithConfig<'info>>,
+        new_signers: Option<Vec<Pubkey>>,
+        new_threshold: Option<u8>,
+        new_expiration: ExpirationUpdate,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+
+        // Verify all current signers have approved
+        require_all_signers(&multisig.signers, ctx.remaining_accounts)?;
+
+        // Drop fields that already hold the requested value, and reject the
+        // ceremony if nothing is left to change
+        let new_signers = new_signers.filter(|signers| *signers != multisig.signers);
+        let new_threshold = new_threshold.filter(|threshold| *threshold != multisig.threshold);
+        let new_expiration = new_expiration.resolve(multisig.expiration_timestamp);
+        if new_signers.is_none() && new_threshold.is_none() && new_expiration.is_none() {
+            return err!(MultisigWalletError::NoOpUpdate);
+        }
+
+        // Update signers if provided
+        if let Some(signers) = new_signers {
+            let max_signers = load_program_config(&ctx.accounts.program_config)?.limits.max_signers;
+            replace_signers(multisig, signers, max_signers, ctx.remaining_accounts)?;
+        }
+
+        // Update threshold if provided, then check it against the signer
+        // set, so a kept threshold can't outgrow a shrunken one
+        if let Some(threshold) = new_threshold {
+            multisig.threshold = threshold;
+        }
+        if multisig.threshold == 0 || multisig.threshold as usize > multisig.max_threshold(multisig.signers.len()) {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+
+        // Update expiration only when explicitly set or cleared
+        if let Some(expiration) = new_expiration {
+            if let Some(expiration) = expiration {
+                validate_expiration(expiration, Clock::get()?.unix_timestamp)?;
+            }
+            multisig.expiration_timestamp = expiration;
+        }
+
+        Ok(())
+    }
+
+    /// Applies a configuration change to the multisig.
+    ///
+    /// Like `update_multisig`, every current signer must sign.
+    pub fn configure_multisig<'info>(ctx: Context<'_, '_, '_, 'info, UpdateMultisig<'info>>, action: ConfigAction) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+
+        // Verify all current signers have approved
+        require_all_signers(&multisig.signers, ctx.remaining_accounts)?;
+
+        apply_config_action(multisig, action, ctx.remaining_accounts)
+    }
+
+    /// Proposes replacing the signer set and threshold. Nothing changes until
+    /// `finalize_member_swap`, which needs the current quorum's approval and
+    /// an `accept_membership` from every incoming signer, so a mistyped key
+    /// can never join the signer set.
+    pub fn propose_member_swap(
+        ctx: Context<ProposeMemberSwap>,
+        new_signers: Vec<Pubkey>,
+        new_threshold: u8,
+    ) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let member_swap = &mut ctx.accounts.member_swap;
+        let proposer = ctx.accounts.proposer.key();
+
+        // Validate proposer is a signer in the multisig
+        if !is_signer_in_multisig(&multisig.signers, &proposer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        // Validate the new signer set, then the new threshold against it
+        let max_signers = load_program_config(&ctx.accounts.program_config)?.limits.max_signers;
+        validate_signer_set(multisig, &new_signers, max_signers)?;
+        if new_threshold == 0 || new_threshold as usize > multisig.max_threshold(new_signers.len()) {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+
+        member_swap.multisig = multisig.key();
+        member_swap.proposer = proposer;
+        member_swap.new_signers = new_signers;
+        member_swap.new_threshold = new_threshold;
+        member_swap.approvals = vec![proposer]; // Proposer auto-approves
+        member_swap.accepted = Vec::new();
+        member_swap.expires_at = Clock::get()?
+            .unix_timestamp
+            .saturating_add(multisig.member_swap_window_seconds);
+        member_swap.bump = *ctx.bumps.get("member_swap").unwrap();
+
+        Ok(())
+    }
+
+    /// Approves the pending member swap as a current signer.
+    pub fn approve_member_swap(ctx: Context<MemberSwapVote>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let member_swap = &mut ctx.accounts.member_swap;
+        let signer = ctx.accounts.signer.key();
+
+        if member_swap.is_expired(Clock::get()?.unix_timestamp) {
+            return err!(MultisigWalletError::MemberSwapExpired);
+        }
+
+        if !is_signer_in_multisig(&multisig.signers, &signer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        if member_swap.approvals.contains(&signer) {
+            return err!(MultisigWalletError::AlreadyApproved);
+        }
+
+        member_swap.approvals.push(signer);
+
+        Ok(())
+    }
+
+    /// Accepts membership in the pending member swap, proving the incoming
+    /// signer controls its key.
+    pub fn accept_membership(ctx: Context<MemberSwapVote>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let member_swap = &mut ctx.accounts.member_swap;
+        let signer = ctx.accounts.signer.key();
+
+        if member_swap.is_expired(Clock::get()?.unix_timestamp) {
+            return err!(MultisigWalletError::MemberSwapExpired);
+        }
+
+        if !member_swap.incoming(&multisig.signers).any(|incoming| *incoming == signer) {
+            return err!(MultisigWalletError::NotIncomingSigner);
+        }
+
+        if member_swap.accepted.contains(&signer) {
+            return err!(MultisigWalletError::AlreadyAccepted);
+        }
+
+        member_swap.accepted.push(signer);
+
+        Ok(())
+    }
+
+    /// Applies an approved and fully accepted member swap, closing the swap
+    /// account back to its proposer.
+    pub fn finalize_member_swap<'info>(ctx: Context<'_, '_, '_, 'info, ResolveMemberSwap<'info>>) -> Result<()> {
+        let max_signers = load_program_config(&ctx.accounts.program_config)?.limits.max_signers;
+        let multisig = &mut ctx.accounts.multisig;
+        let member_swap = &ctx.accounts.member_swap;
+
+        if member_swap.is_expired(Clock::get()?.unix_timestamp) {
+            return err!(MultisigWalletError::MemberSwapExpired);
+        }
+
+        // Only approvals from current signers count towards the quorum
+        let approvals = member_swap
+            .approvals
+            .iter()
+            .filter(|approver| is_signer_in_multisig(&multisig.signers, approver))
+            .count();
+        if approvals < multisig.threshold as usize {
+            return err!(MultisigWalletError::InsufficientApprovals);
+        }
+
+        if member_swap
+            .incoming(&multisig.signers)
+            .any(|incoming| !member_swap.accepted.contains(incoming))
+        {
+            return err!(MultisigWalletError::MembershipNotAccepted);
+        }
+
+        replace_signers(multisig, member_swap.new_signers.clone(), max_signers, ctx.remaining_accounts)?;
+        multisig.threshold = member_swap.new_threshold;
+
+        Ok(())
+    }
+
+    /// Cancels the pending member swap, closing it back to its proposer. The
+    /// current quorum must sign unless the swap has already expired.
+    pub fn cancel_member_swap(ctx: Context<ResolveMemberSwap>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+
+        if !ctx.accounts.member_swap.is_expired(Clock::get()?.unix_timestamp) {
+            require_threshold_signers(&multisig.signers, multisig.threshold, ctx.remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts an emergency reduction of the threshold, for when too few
+    /// signers remain reachable to meet it. Any current signer can initiate;
+    /// the reduction only applies after the emergency timelock, and any
+    /// signer can veto it until then.
+    pub fn initiate_emergency_threshold(ctx: Context<EmergencyThresholdAction>, new_threshold: u8) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let signer = ctx.accounts.signer.key();
+
+        if !is_signer_in_multisig(&multisig.signers, &signer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        if multisig.pending_emergency.is_some() {
+            return err!(MultisigWalletError::EmergencyAlreadyPending);
+        }
+
+        // Only a reduction makes sense in an emergency
+        if new_threshold == 0 || new_threshold >= multisig.threshold {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+
+        let unlocks_at = Clock::get()?
+            .unix_timestamp
+            .saturating_add(multisig.emergency_timelock_seconds);
+        multisig.pending_emergency = Some(EmergencyThreshold {
+            new_threshold,
+            initiator: signer,
+            unlocks_at,
+        });
+
+        emit!(EmergencyThresholdInitiated {
+            multisig: multisig.key(),
+            initiator: signer,
+            new_threshold,
+            unlocks_at,
+        });
+
+        Ok(())
+    }
+
+    /// Vetoes the pending emergency threshold reduction.
+    pub fn veto_emergency_threshold(ctx: Context<EmergencyThresholdAction>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let signer = ctx.accounts.signer.key();
+
+        if !is_signer_in_multisig(&multisig.signers, &signer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        let emergency = multisig
+            .pending_emergency
+            .take()
+            .ok_or(MultisigWalletError::NoEmergencyPending)?;
+
+        emit!(EmergencyThresholdVetoed {
+            multisig: multisig.key(),
+            vetoed_by: signer,
+            new_threshold: emergency.new_threshold,
+        });
+
+        Ok(())
+    }
+
+    /// Applies the pending emergency threshold reduction once its timelock
+    /// has elapsed. Anyone may call this.
+    pub fn execute_emergency_threshold(ctx: Context<EmergencyThresholdAction>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+
+        let emergency = multisig
+            .pending_emergency
+            .clone()
+            .ok_or(MultisigWalletError::NoEmergencyPending)?;
+        if Clock::get()?.unix_timestamp < emergency.unlocks_at {
+            return err!(MultisigWalletError::EmergencyTimelockActive);
+        }
+
+        // The signer set may have shrunk since initiation
+        if emergency.new_threshold as usize > multisig.max_threshold(multisig.signers.len()) {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+
+        multisig.threshold = emergency.new_threshold;
+        multisig.pending_emergency = None;
+
+        emit!(EmergencyThresholdApplied {
+            multisig: multisig.key(),
+            new_threshold: emergency.new_threshold,
+        });
+
+        Ok(())
+    }
+
+    /// Proposes replacing the operational signer set on behalf of the
+    /// recovery members. Recovery can never execute transactions; once the
+    /// recovery threshold approves, the change applies after the recovery
+    /// delay unless an operational signer cancels it first.
+    pub fn propose_recovery(ctx: Context<ProposeRecovery>, new_signers: Vec<Pubkey>, new_threshold: u8) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let recovery = &mut ctx.accounts.recovery;
+        let proposer = ctx.accounts.proposer.key();
+
+        if !multisig.recovery_members.contains(&proposer) {
+            return err!(MultisigWalletError::NotRecoveryMember);
+        }
+
+        // Validate the new signer set, then the new threshold against it
+        let max_signers = load_program_config(&ctx.accounts.program_config)?.limits.max_signers;
+        validate_signer_set(multisig, &new_signers, max_signers)?;
+        if new_threshold == 0 || new_threshold as usize > multisig.max_threshold(new_signers.len()) {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+
+        if new_signers.iter().any(|signer| multisig.recovery_members.contains(signer)) {
+            return err!(MultisigWalletError::RecoverySetOverlap);
+        }
+
+        recovery.multisig = multisig.key();
+        recovery.proposer = proposer;
+        recovery.new_signers = new_signers;
+        recovery.new_threshold = new_threshold;
+        recovery.approvals = vec![proposer]; // Proposer auto-approves
+        recovery.approved_at = None;
+        recovery.bump = *ctx.bumps.get("recovery").unwrap();
+        recovery.check_approved(multisig.recovery_threshold, Clock::get()?.unix_timestamp);
+
+        Ok(())
+    }
+
+    /// Approves the pending recovery as a recovery member.
+    pub fn approve_recovery(ctx: Context<RecoveryVote>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let recovery = &mut ctx.accounts.recovery;
+        let signer = ctx.accounts.signer.key();
+
+        if !multisig.recovery_members.contains(&signer) {
+            return err!(MultisigWalletError::NotRecoveryMember);
+        }
+
+        if recovery.approvals.contains(&signer) {
+            return err!(MultisigWalletError::AlreadyApproved);
+        }
+
+        recovery.approvals.push(signer);
+        recovery.check_approved(multisig.recovery_threshold, Clock::get()?.unix_timestamp);
+
+        Ok(())
+    }
+
+    /// Applies an approved recovery once its delay has elapsed, closing the
+    /// recovery account back to its proposer. Anyone may call this.
+    pub fn execute_recovery<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteRecovery<'info>>) -> Result<()> {
+        let max_signers = load_program_config(&ctx.accounts.program_config)?.limits.max_signers;
+        let multisig = &mut ctx.accounts.multisig;
+        let recovery = &ctx.accounts.recovery;
+
+        let approved_at = recovery.approved_at.ok_or(MultisigWalletError::InsufficientApprovals)?;
+        if Clock::get()?.unix_timestamp < approved_at.saturating_add(multisig.recovery_delay_seconds) {
+            return err!(MultisigWalletError::RecoveryDelayActive);
+        }
+
+        // The signer limit and proposer exclusion may have changed during the
+        // delay, so both are checked again
+        replace_signers(multisig, recovery.new_signers.clone(), max_signers, ctx.remaining_accounts)?;
+        if recovery.new_threshold as usize > multisig.max_threshold(multisig.signers.len()) {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+        multisig.threshold = recovery.new_threshold;
+
+        Ok(())
+    }
+
+    /// Cancels the pending recovery. Any single operational signer may do so.
+    pub fn cancel_recovery(ctx: Context<CancelRecovery>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+
+        if !is_signer_in_multisig(&multisig.signers, &ctx.accounts.signer.key()) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Accepts membership in a pending multisig as one of its listed signers.
+    /// The multisig activates once the required number of signers have
+    /// accepted before the activation deadline.
+    pub fn accept_initial_membership(ctx: Context<AcceptInitialMembership>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let member = ctx.accounts.member.key();
+
+        if multisig.status != MultisigStatus::Pending {
+            return err!(MultisigWalletError::MultisigNotPending);
+        }
+
+        if Clock::get()?.unix_timestamp > multisig.activation_deadline {
+            return err!(MultisigWalletError::ActivationDeadlinePassed);
+        }
+
+        if !is_signer_in_multisig(&multisig.signers, &member) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        if multisig.accepted_members.contains(&member) {
+            return err!(MultisigWalletError::AlreadyAccepted);
+        }
+
+        multisig.accepted_members.push(member);
+
+        if multisig.accepted_members.len() >= multisig.required_acceptances as usize {
+            multisig.status = MultisigStatus::Active;
+            emit!(MultisigActivated {
+                multisig: multisig.key(),
+                accepted_members: multisig.accepted_members.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Closes a multisig that was not accepted by its activation deadline,
+    /// refunding the rent to its creator.
+    pub fn close_pending_multisig(ctx: Context<ClosePendingMultisig>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+
+        if multisig.status != MultisigStatus::Pending {
+            return err!(MultisigWalletError::MultisigNotPending);
+        }
+
+        if Clock::get()?.unix_timestamp <= multisig.activation_deadline {
+            return err!(MultisigWalletError::ActivationDeadlineNotReached);
+        }
+
+        Ok(())
+    }
+
+    /// Closes the multisig account and sweeps its vault, transferring all
+    /// lamports to the receiver.
+    ///
+    /// Remaining accounts: every current signer, then a
+    /// `[token account, mint, destination]` triple for each of the vault's
+    /// `token_accounts`, then the token programs involved. Each token
+    /// account's balance goes to its destination and its rent to the
+    /// receiver. A token account that can't be swept completely, because it
+    /// is frozen or has a delegate, fails the close instead of being left
+    /// behind.
+    pub fn close_multisig<'info>(ctx: Context<'_, '_, '_, 'info, CloseMultisig<'info>>, token_accounts: u8) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let receiver = &mut ctx.accounts.receiver;
+
+        // Verify all current signers have approved
+        require_all_signers(&multisig.signers, ctx.remaining_accounts)?;
+
+        let multisig_key = multisig.key();
+        let vault = ctx.accounts.vault.to_account_info();
+        let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[multisig.vault_bump]];
+        let mut accounts = ctx.remaining_accounts.to_vec();
+        accounts.extend([vault.clone(), receiver.to_account_info()]);
+
+        // Empty and close the listed token accounts of the vault
+        let sweeps = ctx
+            .remaining_accounts
+            .get(multisig.signers.len()..)
+            .and_then(|sweeps| sweeps.get(..token_accounts as usize * 3))
+            .ok_or(MultisigWalletError::InsufficientAccounts)?;
+
+        // Refuse to strand a registered token account by leaving it out
+        let listed: Vec<Pubkey> = sweeps.chunks(3).map(|sweep| sweep[0].key()).collect();
+        require_registered_listed(&ctx.accounts.registry, &listed)?;
+        for sweep in sweeps.chunks(3) {
+            let (token_account, mint, destination) = (&sweep[0], &sweep[1], &sweep[2]);
+            let state = load_token_account(token_account)?;
+            if state.owner != *vault.key || state.mint != *mint.key {
+                return err!(MultisigWalletError::InvalidTokenAccount);
+            }
+            if state.is_frozen() || state.delegate.is_some() {
+                return err!(MultisigWalletError::UnsweepableTokenAccount);
+            }
+
+            let token_program = *token_account.owner;
+            let mut instructions = Vec::new();
+            if state.amount > 0 {
+                instructions.push(spl_token_2022::instruction::transfer_checked(
+                    &token_program,
+                    token_account.key,
+                    mint.key,
+                    destination.key,
+                    vault.key,
+                    &[],
+                    state.amount,
+                    load_mint(mint)?.decimals,
+                )?);
+            }
+            instructions.push(spl_token_2022::instruction::close_account(
+                &token_program,
+                token_account.key,
+                receiver.key,
+                vault.key,
+                &[],
+            )?);
+            for instruction in &instructions {
+                invoke_signed(instruction, &accounts, &[&vault_seeds[..]])?;
+            }
+        }
+
+        // Then the vault's SOL
+        let vault_lamports = vault.lamports();
+        if vault_lamports > 0 {
+            invoke_signed(
+                &system_instruction::transfer(vault.key, receiver.key, vault_lamports),
+                &[vault.clone(), receiver.to_account_info(), ctx.accounts.system_program.to_account_info()],
+                &[&vault_seeds[..]],
+            )?;
+        }
+
+        // Close the token registry along with the multisig
+        let registry = ctx.accounts.registry.to_account_info();
+        if !registry.data_is_empty() {
+            let registry_lamports = registry.lamports();
+            **registry.lamports.borrow_mut() = 0;
+            **receiver.to_account_info().lamports.borrow_mut() += registry_lamports;
+            registry.try_borrow_mut_data()?.fill(0);
+        }
+
+        // Transfer lamports to receiver
+        let multisig_lamports = multisig.to_account_info().lamports();
+        **multisig.to_account_info().lamports.borrow_mut() = 0;
+        **receiver.to_account_info().lamports.borrow_mut() += multisig_lamports;
+
+        Ok(())
+    }
+
+    /// Initializes a zero-copy multisig, for very active multisigs where
+    /// deserializing the full `MultisigAccount` on every approval is too
+    /// expensive. It keeps up to `MAX_MEMBERS_V2` members in a fixed array
+    /// and supports plain proposals only.
+    pub fn initialize_multisig_v2(
+        ctx: Context<InitializeMultisigV2>,
+        members: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let (vault, vault_bump) = find_vault_address(&ctx.accounts.multisig.key());
+        validate_signer_keys(&members, &ctx.accounts.multisig.key(), &vault)?;
+        if members.len() > MAX_MEMBERS_V2 {
+            return err!(MultisigWalletError::TooManyMembers);
+        }
+        if threshold == 0 || threshold as usize > members.len() {
+            return err!(MultisigWalletError::InvalidThreshold);
+        }
+
+        let mut multisig = ctx.accounts.multisig.load_init()?;
+        for (slot, member) in multisig.members.iter_mut().zip(&members) {
+            slot.key = *member;
+        }
+        multisig.member_count = members.len() as u8;
+        multisig.threshold = threshold;
+        multisig.nonce = 0;
+        multisig.create_key = ctx.accounts.create_key.key();
+        multisig.bump = *ctx.bumps.get("multisig").unwrap();
+        multisig.vault_bump = vault_bump;
+
+        Ok(())
+    }
+
+    /// Proposes a transaction on a zero-copy multisig. `tx_index` must be
+    /// the multisig's current nonce.
+    pub fn propose_transaction_v2(
+        ctx: Context<ProposeTransactionV2>,
+        tx_index: u64,
+        program_id: Pubkey,
+        accounts: Vec<u8>,
+        instruction_data: Vec<u8>,
+    ) -> Result<()> {
+        let multisig_key = ctx.accounts.multisig.key();
+        let mut multisig = ctx.accounts.multisig.load_mut()?;
+        let transaction = &mut ctx.accounts.transaction;
+        let proposer = ctx.accounts.proposer.key();
+
+        if !multisig.is_member(&proposer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+        if tx_index != multisig.nonce {
+            return err!(MultisigWalletError::InvalidTransactionIndex);
+        }
+
+        let vault = vault_address(&multisig_key, multisig.vault_bump)?;
+        let account_metas = parse_account_metas(&accounts, DEFAULT_MAX_ACCOUNT_METAS, &[multisig_key, vault])?;
+        check_self_references(&multisig_key, tx_index, &account_metas)?;
+        check_system_instruction(&program_id, &instruction_data, &account_metas, &[multisig_key, vault])?;
+
+        transaction.multisig = multisig_key;
+        transaction.proposer = proposer;
+        transaction.rent_payer = proposer;
+        transaction.tx_index = tx_index;
+        transaction.program_id = program_id;
+        transaction.accounts = encode_compact_account_metas(&account_metas);
+        transaction.version = TRANSACTION_VERSION_COMPACT_METAS;
+        transaction.data = instruction_data;
+        transaction.signers = vec![proposer]; // Proposer auto-approves
+        transaction.approval_memos = vec![String::new()];
+        transaction.eligible_approvers =
+            multisig.members[..multisig.member_count as usize].iter().map(|slot| slot.key).collect();
+        transaction.generation = multisig.generation();
+        transaction.owner_set_seqno = multisig.owner_set_seqno();
+        // A zero-copy multisig has no proposal TTL
+        transaction.expires_at = None;
+        let clock = Clock::get()?;
+        transaction.created_at = clock.unix_timestamp;
+        transaction.created_slot = clock.slot;
+        transaction.required_approvals = multisig.threshold;
+        transaction.note_approvals(multisig.threshold as usize, clock.unix_timestamp);
+        transaction.kind = TransactionKind::of(&program_id, &transaction.data, None)?;
+        transaction.hash = transaction.compute_hash();
+        transaction.bump = *ctx.bumps.get("transaction").unwrap();
+
+        multisig.nonce += 1;
+
+        Ok(())
+    }
+
+    /// Approves a proposal on a zero-copy multisig. The multisig is read in
+    /// place rather than deserialized.
+    pub fn approve_transaction_v2(ctx: Context<ApproveTransactionV2>) -> Result<()> {
+        let multisig = ctx.accounts.multisig.load()?;
+        let transaction = &mut ctx.accounts.transaction;
+        let signer = ctx.accounts.signer.key();
+
+        assert_approvable(&*multisig, transaction, &Clock::get()?)?;
+        if !multisig.is_member(&signer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+        if transaction.signers.contains(&signer) {
+            return err!(MultisigWalletError::AlreadyApproved);
+        }
+
+        let required = multisig.threshold as usize;
+        let was_approved = transaction.approved_at.is_some();
+        transaction.signers.push(signer);
+        transaction.approval_memos.push(String::new());
+        let now = Clock::get()?.unix_timestamp;
+        transaction.note_approvals(required, now);
+
+        // The same events as a v1 approval, so indexers follow both flavors
+        let approvals = transaction.approval_count();
+        emit!(TransactionApproved {
+            multisig: ctx.accounts.multisig.key(),
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            priority: transaction.priority,
+            signer,
+            memo: String::new(),
+            approvals: approvals as u8,
+            threshold: required as u8,
+            expires_in: None,
+        });
+        if !was_approved && transaction.approved_at.is_some() {
+            emit!(ThresholdReached {
+                multisig: ctx.accounts.multisig.key(),
+                tx_index: transaction.tx_index,
+                transaction_hash: transaction.hash,
+                priority: transaction.priority,
+                approvals: approvals as u8,
+                threshold: required as u8,
+                timelock_ends_at: None,
+                expires_at: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Executes a proposal on a zero-copy multisig once it has enough
+    /// approvals from current members.
+    pub fn execute_transaction_v2(ctx: Context<ExecuteTransactionV2>) -> Result<()> {
+        let multisig_key = ctx.accounts.multisig.key();
+        let transaction = &mut ctx.accounts.transaction;
+
+        // Copy out what's needed, so the multisig isn't borrowed during the CPI
+        let (create_key, bump, vault_bump) = {
+            let multisig = ctx.accounts.multisig.load()?;
+            assert_executable(&*multisig, transaction, &Clock::get()?)?;
+            let approvals = transaction.signers.iter().filter(|signer| multisig.is_member(signer)).count();
+            if approvals < multisig.threshold as usize {
+                return err!(MultisigWalletError::InsufficientApprovals);
+            }
+            multisig.check_allowlist(&transaction.program_id, &transaction.data)?;
+            (multisig.create_key, multisig.bump, multisig.vault_bump)
+        };
+
+        if transaction.program_id == *ctx.program_id {
+            return err!(MultisigWalletError::RecursiveCallNotAllowed);
+        }
+
+        // The same checks as an opaque v1 execution: no System Program call
+        // handing over a multisig PDA, and no proposal of this multisig
+        // passed as writable
+        let vault = vault_address(&multisig_key, vault_bump)?;
+        let account_metas = transaction.account_metas();
+        check_system_instruction(&transaction.program_id, &transaction.data, &account_metas, &[multisig_key, vault])?;
+        let instruction =
+            build_opaque_instruction(transaction, &account_metas, ctx.remaining_accounts, &[multisig_key, vault], false)?;
+        let seeds = &[b"multisig_v2".as_ref(), create_key.as_ref(), &[bump]];
+        let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[vault_bump]];
+        let vaults_before = vault_balances(ctx.remaining_accounts, &[vault]);
+        let units_before = remaining_compute_units();
+        invoke_signed(&instruction, ctx.remaining_accounts, &[&seeds[..], &vault_seeds[..]])?;
+
+        // Fail (and roll back the CPI) if the vault was drawn below rent
+        // exemption
+        check_vault_rent(ctx.remaining_accounts, &vaults_before, transaction.sweep)?;
+
+        transaction.executed = true;
+
+        emit!(TransactionExecuted {
+            multisig: multisig_key,
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            priority: transaction.priority,
+            executor: None,
+            automated: false,
+            category: transaction.category,
+            kind: transaction.kind.clone(),
+            remaining_compute_units: units_before,
+        });
+
+        Ok(())
+    }
+
+    /// Creates the program config holding the deployment-wide limits. Only
+    /// the program's upgrade authority can create it, once, and becomes its
+    /// admin. Until then, the compiled defaults apply. The protocol fee
+    /// starts at zero, collected by the authority once set.
+    pub fn initialize_program_config(ctx: Context<InitializeProgramConfig>, limits: ProgramLimits) -> Result<()> {
+        limits.validate()?;
+
+        let config = &mut ctx.accounts.program_config;
+        config.admin = ctx.accounts.authority.key();
+        config.limits = limits;
+        config.fee_lamports = 0;
+        config.fee_collector = ctx.accounts.authority.key();
+        config.cluster = Cluster::Unspecified;
+        config.wallet_programs = Vec::new();
+        config.execution_receipts = false;
+        config.bump = *ctx.bumps.get("program_config").unwrap();
+
+        emit!(ProgramConfigUpdated {
+            admin: config.admin,
+            limits,
+            fee_lamports: config.fee_lamports,
+            fee_collector: config.fee_collector,
+            cluster: config.cluster,
+            wallet_programs: config.wallet_programs.clone(),
+            execution_receipts: config.execution_receipts,
+        });
+
+        Ok(())
+    }
+
+    /// Changes the program config's limits, protocol fee, fee collector,
+    /// cluster, wallet programs or execution receipts, or hands it to a new
+    /// admin. Signed by the current admin. New limits apply to instructions
+    /// from then on; multisigs already created keep the defaults they
+    /// started with, and proposals already made keep the fee, cluster and
+    /// receipt setting they were stamped with.
+    pub fn update_program_config(ctx: Context<UpdateProgramConfig>, update: ProgramConfigUpdate) -> Result<()> {
+        if update == ProgramConfigUpdate::default() {
+            return err!(MultisigWalletError::NoOpUpdate);
+        }
+
+        let ProgramConfigUpdate {
+            limits,
+            new_admin,
+            fee_lamports,
+            fee_collector,
+            cluster,
+            wallet_programs,
+            execution_receipts,
+        } = update;
+        let config = &mut ctx.accounts.program_config;
+        if let Some(limits) = limits {
+            limits.validate()?;
+            config.limits = limits;
+        }
+        if let Some(admin) = new_admin {
+            config.admin = admin;
+        }
+        if let Some(lamports) = fee_lamports {
+            config.fee_lamports = lamports;
+        }
+        if let Some(collector) = fee_collector {
+            config.fee_collector = collector;
+        }
+        if let Some(cluster) = cluster {
+            config.cluster = cluster;
+        }
+        if let Some(wallet_programs) = wallet_programs {
+            if wallet_programs.len() > MAX_WALLET_PROGRAMS {
+                return err!(MultisigWalletError::TooManyWalletPrograms);
+            }
+            config.wallet_programs = wallet_programs;
+        }
+        if let Some(enabled) = execution_receipts {
+            config.execution_receipts = enabled;
+        }
+
+        emit!(ProgramConfigUpdated {
+            admin: config.admin,
+            limits: config.limits,
+            fee_lamports: config.fee_lamports,
+            fee_collector: config.fee_collector,
+            cluster: config.cluster,
+            wallet_programs: config.wallet_programs.clone(),
+            execution_receipts: config.execution_receipts,
+        });
+
+        Ok(())
+    }
+
+    /// Joins the multisig as the invitee of an open invitation, made with
+    /// `ConfigAction::InviteSigner`. The invitee is added to the signer set
+    /// with the checks a signer set change gets, and pays to grow the
+    /// multisig account; the invitation's rent goes back to the vault.
+    pub fn accept_invitation<'info>(ctx: Context<'_, '_, '_, 'info, AcceptInvitation<'info>>) -> Result<()> {
+        let max_signers = load_program_config(&ctx.accounts.program_config)?.limits.max_signers;
+        let multisig = &mut ctx.accounts.multisig;
+        let invitation = &ctx.accounts.invitation;
+
+        multisig.require_active()?;
+        if invitation.is_expired(Clock::get()?.unix_timestamp) {
+            return err!(MultisigWalletError::InvitationExpired);
+        }
+        if is_signer_in_multisig(&multisig.signers, &invitation.invitee) {
+            return err!(MultisigWalletError::AlreadyMember);
+        }
+
+        let mut signers = multisig.signers.clone();
+        signers.push(invitation.invitee);
+        replace_signers(multisig, signers, max_signers, ctx.remaining_accounts)?;
+
+        emit!(InvitationAccepted {
+            multisig: multisig.key(),
+            invitee: invitation.invitee,
+            roles: invitation.roles,
+        });
+
+        Ok(())
+    }
+
+    /// Closes an invitation that lapsed without being accepted, returning
+    /// its rent to the vault. Anyone can call it.
+    pub fn close_expired_invitation(ctx: Context<CloseExpiredInvitation>) -> Result<()> {
+        if !ctx.accounts.invitation.is_expired(Clock::get()?.unix_timestamp) {
+            return err!(MultisigWalletError::InvitationNotExpired);
+        }
+
+        Ok(())
+    }
+
+    /// Claims lamports from the member's personal allowance, see
+    /// `ConfigAction::SetMemberAllowance`. The member signs alone; the claim
+    /// still counts against the vault's rate limits and epoch caps.
+    pub fn claim_allowance(ctx: Context<ClaimAllowance>, amount: u64) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let member = ctx.accounts.member.key();
+        let now = Clock::get()?.unix_timestamp;
+
+        multisig.require_active()?;
+        if amount == 0 {
+            return err!(MultisigWalletError::InvalidAmount);
+        }
+
+        let allowance = multisig
+            .member_allowances
+            .iter_mut()
+            .find(|allowance| allowance.member == member)
+            .ok_or(MultisigWalletError::AllowanceNotFound)?;
+        if allowance.frozen {
+            return err!(MultisigWalletError::AllowanceFrozen);
+        }
+        allowance.claim(amount, now)?;
+        let remaining = allowance.remaining;
+        multisig.consume_outflow(None, amount, now)?;
+
+        let multisig_key = multisig.key();
+        let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[multisig.vault_bump]];
+        let vault = ctx.accounts.vault.to_account_info();
+        let recipient = ctx.accounts.recipient.to_account_info();
+        invoke_signed(
+            &system_instruction::transfer(vault.key, recipient.key, amount),
+            &[vault, recipient.clone(), ctx.accounts.system_program.to_account_info()],
+            &[&vault_seeds[..]],
+        )?;
+
+        emit!(AllowanceClaimed { multisig: multisig_key, member, recipient: recipient.key(), amount, remaining });
+
+        Ok(())
+    }
+
+    /// Approves a proposed transaction for a member that is a smart wallet's
+    /// PDA. Must be invoked by a direct CPI from one of the wallet programs
+    /// registered in the program config, with the PDA signing. Otherwise as
+    /// for `approve_transaction`.
+    pub fn approve_for(ctx: Context<ApproveFor>, expected_hash: Option<[u8; 32]>) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+
+        // Verify the calling program: with a direct CPI, the current top-level
+        // instruction belongs to the caller
+        let wallet_programs = load_program_config(&ctx.accounts.program_config)?.wallet_programs;
+        let instructions = ctx.accounts.instructions.to_account_info();
+        let current_index = load_current_index_checked(&instructions)?;
+        let caller = load_instruction_at_checked(current_index as usize, &instructions)?;
+        if get_stack_height() != TRANSACTION_LEVEL_STACK_HEIGHT + 1 || !wallet_programs.contains(&caller.program_id) {
+            return err!(MultisigWalletError::UnregisteredWalletProgram);
+        }
+        msg!("Approving through wallet program {}", caller.program_id);
+
+        let member = ctx.accounts.member.key();
+        record_approval(&mut ctx.accounts.multisig, &mut ctx.accounts.transaction, member, String::new(), false)
+    }
+
+    /// Reserves the next transaction index for the signing member, so a
+    /// client can show it before the proposal lands. The nonce moves past it
+    /// at once; `propose_transaction_with_reservation` later proposes at the
+    /// reserved index. The reservation lapses after `NONCE_RESERVATION_TTL`.
+    pub fn reserve_nonce(ctx: Context<ReserveNonce>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let reserver = ctx.accounts.reserver.key();
+
+        multisig.require_active()?;
+        if !is_signer_in_multisig(&multisig.signers, &reserver) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        let reservation = &mut ctx.accounts.reservation;
+        reservation.multisig = multisig.key();
+        reservation.tx_index = multisig.nonce;
+        reservation.reserver = reserver;
+        reservation.expires_at = Clock::get()?.unix_timestamp + NONCE_RESERVATION_TTL;
+        reservation.bump = *ctx.bumps.get("reservation").unwrap();
+        multisig.nonce += 1;
+
+        emit!(NonceReserved {
+            multisig: reservation.multisig,
+            tx_index: reservation.tx_index,
+            reserver,
+            expires_at: reservation.expires_at,
+        });
+
+        Ok(())
+    }
+
+    /// Proposes a transaction at the index the proposer reserved with
+    /// `reserve_nonce`, closing the reservation and refunding its rent.
+    /// Otherwise as for `propose_transaction`.
+    pub fn propose_transaction_with_reservation(
+        ctx: Context<ProposeTransactionWithReservation>,
+        program_id: Pubkey,
+        accounts: Vec<u8>,
+        instruction_data: Vec<u8>,
+        options: ProposalOptions,
+    ) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let proposer = ctx.accounts.proposer.key();
+        let reservation = &ctx.accounts.reservation;
+
+        multisig.require_active()?;
+
+        // Validate proposer is a signer in the multisig
+        if !is_signer_in_multisig(&multisig.signers, &proposer) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        // Only the reserver may use the reservation, and only while it holds
+        let clock = Clock::get()?;
+        if reservation.reserver != proposer {
+            return err!(MultisigWalletError::ReservationNotHeld);
+        }
+        if reservation.is_expired(clock.unix_timestamp) {
+            return err!(MultisigWalletError::ReservationExpired);
+        }
+
+        // Enforce the per-proposer cooldown
+        multisig.record_proposal(proposer, clock.unix_timestamp)?;
+        note_member_action(multisig, proposer, clock.unix_timestamp);
+
+        let config = load_program_config(&ctx.accounts.program_config)?;
+        propose_opaque(
+            multisig,
+            &ctx.accounts.transaction,
+            &ctx.accounts.rent_payer,
+            &ctx.accounts.system_program.to_account_info(),
+            &config,
+            OpaqueProposal {
+                proposer,
+                program_id,
+                accounts,
+                instruction_data,
+                memo: options.memo,
+                assertions: options.assertions,
+                read_only: options.read_only,
+                priority: options.priority,
+                authority_kind: options.authority_kind,
+                automatable: options.automatable,
+                category: options.category,
+                now: clock.unix_timestamp,
+                slot: clock.slot,
+                ttl_seconds: options.ttl_seconds,
+                joint_with: None,
+                depends_on: options.depends_on,
+                compute: options.compute,
+                sweep: options.sweep,
+                ephemeral_signers: options.ephemeral_signers,
+                reserved_index: Some(reservation.tx_index),
+            },
+        )
+    }
+
+    /// Closes a nonce reservation that lapsed unused, returning its rent to
+    /// the reserver. Anyone can call it. Its index is never proposed at; in
+    /// ordered mode the reservation can only be closed once its index is the
+    /// next to execute, and execution then moves past it.
+    pub fn close_expired_reservation(ctx: Context<CloseExpiredReservation>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let reservation = &ctx.accounts.reservation;
+
+        if !reservation.is_expired(Clock::get()?.unix_timestamp) {
+            return err!(MultisigWalletError::ReservationNotExpired);
+        }
+        if multisig.ordered_execution {
+            if reservation.tx_index != multisig.next_ordered_index() {
+                return err!(MultisigWalletError::OutOfOrderExecution);
+            }
+            multisig.last_executed_index = Some(reservation.tx_index);
+        }
+        multisig.note_proposal_closed(reservation.tx_index);
+        msg!("Released reserved index {}", reservation.tx_index);
+
+        Ok(())
+    }
+
+    /// Confirms the execution of an approved proposal whose instruction data
+    /// is over the multisig's `confirmation_threshold_bytes`, which can't
+    /// execute until then. Any member eligible to approve it may confirm,
+    /// except its proposer.
+    pub fn confirm_execution(ctx: Context<ConfirmExecution>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+        let member = ctx.accounts.member.key();
+
+        assert_executable(multisig, transaction, &Clock::get()?)?;
+        if !transaction.confirmation_required {
+            return err!(MultisigWalletError::ConfirmationNotRequired);
+        }
+        if transaction.confirmer != 0 {
+            return err!(MultisigWalletError::AlreadyConfirmed);
+        }
+        if !multisig.is_approved(transaction) {
+            return err!(MultisigWalletError::InsufficientApprovals);
+        }
+
+        // A second pair of eyes: the proposer can't confirm its own payload
+        if member == transaction.proposer {
+            return err!(MultisigWalletError::SelfConfirmation);
+        }
+        let position = transaction
+            .approver_position(&member)
+            .filter(|_| is_signer_in_multisig(&multisig.signers, &member))
+            .ok_or(MultisigWalletError::SignerNotFound)?;
+        transaction.confirmer = position + 1;
+
+        emit!(ExecutionConfirmed {
+            multisig: multisig.key(),
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            confirmer: member,
+        });
+
+        Ok(())
+    }
+
+    /// Closes an empty associated token account of the vault, crediting its
+    /// rent back to the vault and dropping it from the token registry. Any
+    /// member may call this; accounts holding tokens or with a delegate are
+    /// refused.
+    pub fn close_empty_vault_ata(ctx: Context<CloseEmptyVaultAta>) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        if !is_signer_in_multisig(&multisig.signers, &ctx.accounts.member.key()) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+
+        let vault = ctx.accounts.vault.to_account_info();
+        let token_account = ctx.accounts.token_account.to_account_info();
+        let state = load_token_account(&token_account)?;
+        if state.owner != *vault.key || state.mint != ctx.accounts.mint.key() {
+            return err!(MultisigWalletError::InvalidTokenAccount);
+        }
+        if state.amount > 0 {
+            return err!(MultisigWalletError::TokenAccountNotEmpty);
+        }
+        if state.delegate.is_some() {
+            return err!(MultisigWalletError::TokenAccountDelegated);
+        }
+
+        // The token program has the last word: Token-2022 refuses to close
+        // accounts with withheld fees or pending confidential balances, and
+        // its error is passed through as is
+        let multisig_key = multisig.key();
+        let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[multisig.vault_bump]];
+        let lamports = token_account.lamports();
+        let token_program = ctx.accounts.token_program.key();
+        invoke_signed(
+            &spl_token_2022::instruction::close_account(&token_program, token_account.key, vault.key, vault.key, &[])?,
+            &[token_account.clone(), vault.clone(), ctx.accounts.token_program.to_account_info()],
+            &[&vault_seeds[..]],
+        )
+        .map_err(|error| {
+            msg!("Token program refused to close {}: {}", token_account.key, error);
+            error
+        })?;
+
+        // Drop it from the registry, if there is one
+        let registry = ctx.accounts.registry.to_account_info();
+        if !registry.data_is_empty() {
+            let mut registry = Account::<TokenRegistry>::try_from(&registry)?;
+            registry.unregister(token_account.key);
+            registry.exit(&crate::ID)?;
+        }
+
+        emit!(VaultTokenAccountClosed {
+            multisig: multisig_key,
+            token_account: token_account.key(),
+            mint: state.mint,
+            lamports,
+        });
+
+        Ok(())
+    }
+
+    /// Closes an executed transaction whose `ExecutionReceipt` is kept,
+    /// refunding its rent to the rent payer, or to the vault if the vault
+    /// already reimbursed it at execution. Anyone may call this. Without a
+    /// receipt the transaction account is the only record left besides
+    /// events, so it stays open.
+    pub fn close_executed_transaction(ctx: Context<CloseExecutedTransaction>) -> Result<()> {
+        let transaction = &ctx.accounts.transaction;
+        if !transaction.executed {
+            return err!(MultisigWalletError::TransactionNotExecuted);
+        }
+        if transaction.escrowed {
+            return err!(MultisigWalletError::EscrowStillFunded);
+        }
+
+        let receipt = ctx.accounts.receipt.to_account_info();
+        if *receipt.owner != crate::ID || receipt.data_is_empty() {
+            return err!(MultisigWalletError::ReceiptMissing);
+        }
+        let receipt = Account::<ExecutionReceipt>::try_from(&receipt)?;
+        if receipt.generation != transaction.generation || receipt.transaction_hash != transaction.hash {
+            return err!(MultisigWalletError::ReceiptMissing);
+        }
+
+        // The rent payer was paid once already if the vault reimbursed it
+        let destination = if transaction.rent_reimbursed {
+            ctx.accounts.vault.to_account_info()
+        } else {
+            ctx.accounts.rent_payer.to_account_info()
+        };
+        ctx.accounts.transaction.close(destination)
+    }
+
+    /// Registers an external instruction members can then propose by its
+    /// tag with `propose_registered_action`, creating the action registry
+    /// on first use. Like `configure_multisig`, every current signer must
+    /// approve, passed as signing remaining accounts.
+    pub fn register_external_action(ctx: Context<RegisterExternalAction>, action: RegisteredAction) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        require_all_signers(&multisig.signers, ctx.remaining_accounts)?;
+        action.validate()?;
+
+        let registry = &mut ctx.accounts.registry;
+        registry.multisig = multisig.key();
+        registry.bump = *ctx.bumps.get("registry").unwrap();
+        registry.register(action.clone())?;
+
+        emit!(ExternalActionRegistered {
+            multisig: multisig.key(),
+            tag: action.tag.clone(),
+            program_id: action.program_id,
+            template_hash: action.hash(),
+        });
+
+        Ok(())
+    }
+
+    /// Removes a registered external action. Pending proposals of it can no
+    /// longer execute. Takes every current signer, like
+    /// `register_external_action`.
+    pub fn remove_external_action(ctx: Context<RemoveExternalAction>, tag: String) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        require_all_signers(&multisig.signers, ctx.remaining_accounts)?;
+        ctx.accounts.registry.unregister(&tag)?;
+
+        emit!(ExternalActionRemoved { multisig: multisig.key(), tag });
+
+        Ok(())
+    }
+
+    /// First half of an execution split over two transactions, for
+    /// proposals whose accounts leave no room for the rest of an execution.
+    /// Runs the checks that don't need the instruction's accounts
+    /// (approvals, expiry, confirmation, cluster and dependency) and locks
+    /// the proposal in a stage account at `["stage", transaction]`, which
+    /// chains a hash over the accounts passed so far. Only members may
+    /// stage. The proposal then only executes through `complete_execution`,
+    /// until `release_staged_execution` unlocks it.
+    pub fn stage_execution<'info>(
+        ctx: Context<'_, '_, '_, 'info, StageExecution<'info>>,
+        expected_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        check_expected_hash(&ctx.accounts.transaction, expected_hash)?;
+        let multisig = &ctx.accounts.multisig;
+        let transaction = &mut ctx.accounts.transaction;
+        let executor = ctx.accounts.executor.key();
+        let clock = Clock::get()?;
+
+        if !is_signer_in_multisig(&multisig.signers, &executor) {
+            return err!(MultisigWalletError::SignerNotFound);
+        }
+        assert_executable(multisig, transaction, &clock)?;
+
+        // Joint and read-only proposals execute through their own
+        // instructions
+        if transaction.joint_with.is_some() {
+            return err!(MultisigWalletError::JointExecutionRequired);
+        }
+        if transaction.read_only {
+            return err!(MultisigWalletError::ReadOnlyExecutionRequired);
+        }
+        transaction.check_kind()?;
+        if !multisig.is_approved(transaction) {
+            return err!(MultisigWalletError::InsufficientApprovals);
+        }
+        if transaction.confirmation_required && transaction.confirmer == 0 {
+            return err!(MultisigWalletError::ConfirmationRequired);
+        }
+        check_cluster(transaction, ctx.remaining_accounts)?;
+        check_dependency(multisig, transaction, ctx.remaining_accounts, clock.unix_timestamp)?;
+
+        let staged_accounts: Vec<Pubkey> = ctx.remaining_accounts.iter().map(|account| account.key()).collect();
+        if staged_accounts.len() > MAX_STAGED_ACCOUNTS {
+            return err!(MultisigWalletError::TooManyStagedAccounts);
+        }
+
+        let stage = &mut ctx.accounts.stage;
+        stage.multisig = multisig.key();
+        stage.transaction = transaction.key();
+        stage.executor = executor;
+        stage.staging_hash = ExecutionStage::chain(&transaction.hash, &staged_accounts);
+        stage.staged_accounts = staged_accounts;
+        stage.staged_at = clock.unix_timestamp;
+        stage.bump = *ctx.bumps.get("stage").unwrap();
+        transaction.staged = true;
+
+        emit!(ExecutionStaged {
+            multisig: multisig.key(),
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            executor,
+            staging_hash: stage.staging_hash,
+            staged_at: stage.staged_at,
+        });
+
+        Ok(())
+    }
+
+    /// Second half of a staged execution. The execution's accounts are the
+    /// staged ones followed by the completion's: the runtime only lets a CPI
+    /// touch accounts of the instruction invoking it, so the stager passes
+    /// the staged accounts again, first, then everything else the execution
+    /// needs. The proposal executes as with `execute_transaction`, minus the
+    /// checks staging ran. Fails with `StagingHashMismatch` unless the
+    /// leading accounts chain onto the proposal's hash to the staging hash.
+    /// The stage account is closed, so a proposal completes once.
+    pub fn complete_execution<'info>(ctx: Context<'_, '_, '_, 'info, CompleteExecution<'info>>) -> Result<()> {
+        let stage = &ctx.accounts.stage;
+        let transaction = &mut ctx.accounts.transaction;
+        if ctx.remaining_accounts.len() < stage.staged_accounts.len() {
+            return err!(MultisigWalletError::InsufficientAccounts);
+        }
+        let (staged, completed) = ctx.remaining_accounts.split_at(stage.staged_accounts.len());
+        let staged_accounts: Vec<Pubkey> = staged.iter().map(|account| account.key()).collect();
+        if ExecutionStage::chain(&transaction.hash, &staged_accounts) != stage.staging_hash {
+            return err!(MultisigWalletError::StagingHashMismatch);
+        }
+        let staging_hash = stage.staging_hash;
+
+        let executor = ctx.accounts.executor.to_account_info();
+        let multisig = &mut ctx.accounts.multisig;
+        let prepared =
+            prepare_execution(multisig, transaction, Some(executor.key()), ctx.remaining_accounts, false, true)?;
+        transaction.staged = false;
+        perform_execution(multisig, transaction, Some(executor), ctx.remaining_accounts, prepared)?;
+
+        let completed_accounts: Vec<Pubkey> = completed.iter().map(|account| account.key()).collect();
+        emit!(ExecutionCompleted {
+            multisig: multisig.key(),
+            tx_index: transaction.tx_index,
+            staging_hash,
+            completion_hash: ExecutionStage::chain(&staging_hash, &completed_accounts),
+        });
+
+        Ok(())
+    }
+
+    /// Unlocks a staged proposal that wasn't completed, closing its stage
+    /// account and refunding the rent to the stager. The stager may release
+    /// it at any time, anyone else once `STAGING_TIMEOUT` has passed since
+    /// staging.
+    pub fn release_staged_execution(ctx: Context<ReleaseStagedExecution>) -> Result<()> {
+        let stage = &ctx.accounts.stage;
+        let caller = ctx.accounts.caller.key();
+        if caller != stage.executor && Clock::get()?.unix_timestamp < stage.staged_at.saturating_add(STAGING_TIMEOUT) {
+            return err!(MultisigWalletError::StagingNotExpired);
+        }
+
+        let transaction = &mut ctx.accounts.transaction;
+        transaction.staged = false;
+
+        emit!(StagedExecutionReleased {
+            multisig: ctx.accounts.multisig.key(),
+            tx_index: transaction.tx_index,
+            released_by: caller,
+        });
+
+        Ok(())
+    }
+
+    /// Moves the open-proposal window past proposals that closed out of its
+    /// reach, or before the multisig had one. Anyone may call this. The
+    /// remaining accounts come in pairs for consecutive indices from
+    /// `first_open_index`: the transaction account and the nonce
+    /// reservation account. An index counts as closed when its transaction
+    /// executed, was cancelled or belongs to an earlier generation, or when
+    /// neither account exists.
+    pub fn sync_open_window(ctx: Context<UpdateMultisig>) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let multisig_key = multisig.key();
+        let first_open_index = multisig.open_window.first_open_index;
+
+        for (tx_index, pair) in (first_open_index..multisig.nonce).zip(ctx.remaining_accounts.chunks(2)) {
+            let [transaction, reservation] = pair else {
+                return err!(MultisigWalletError::InsufficientAccounts);
+            };
+            let index = tx_index.to_le_bytes();
+            let (transaction_key, _) =
+                Pubkey::find_program_address(&[b"tx", multisig_key.as_ref(), &index], &crate::ID);
+            let (reservation_key, _) =
+                Pubkey::find_program_address(&[b"reservation", multisig_key.as_ref(), &index], &crate::ID);
+            if transaction.key() != transaction_key || reservation.key() != reservation_key {
+                msg!("Expected the accounts of proposal {}", tx_index);
+                return err!(MultisigWalletError::InvalidTransactionAccount);
+            }
+
+            let closed = if *transaction.owner == crate::ID && !transaction.data_is_empty() {
+                let transaction = Account::<TransactionAccount>::try_from(transaction)?;
+                transaction.executed || transaction.cancelled || transaction.generation != multisig.generation
+            } else {
+                *reservation.owner != crate::ID || reservation.data_is_empty()
+            };
+            if closed {
+                multisig.note_proposal_closed(tx_index);
+            }
+        }
+
+        msg!("First open proposal: {}", multisig.open_window.first_open_index);
+
+        Ok(())
+    }
+}
+
+/// Signer-set lookups shared by both multisig account flavors.
+pub trait Membership {
+    fn is_member(&self, key: &Pubkey) -> bool;
+    fn member_count(&self) -> usize;
+}
+
+impl Membership for MultisigAccount {
+    fn is_member(&self, key: &Pubkey) -> bool {
+        is_signer_in_multisig(&self.signers, key)
+    }
+
+    fn member_count(&self) -> usize {
+        self.signers.len()
+    }
+}
+
+impl Membership for MultisigV2 {
+    fn is_member(&self, key: &Pubkey) -> bool {
+        self.members[..self.member_count as usize].iter().any(|slot| slot.key == *key)
+    }
+
+    fn member_count(&self) -> usize {
+        self.member_count as usize
+    }
+}
+
+/// Multisig state the proposal status checks read, shared by both multisig
+/// account flavors. A zero-copy multisig is always active, never expires,
+/// and has no generations, signer-set invalidation or allowlist.
+pub trait ProposalGate {
+    fn require_active(&self) -> Result<()>;
+    fn generation(&self) -> u64;
+    fn owner_set_seqno(&self) -> u32;
+    fn expiration_timestamp(&self) -> Option<i64>;
+    fn execution_grace_seconds(&self) -> i64;
+    fn check_allowlist(&self, program_id: &Pubkey, data: &[u8]) -> Result<()>;
+}
+
+impl ProposalGate for MultisigAccount {
+    fn require_active(&self) -> Result<()> {
+        MultisigAccount::require_active(self)
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn owner_set_seqno(&self) -> u32 {
+        self.owner_set_seqno
+    }
+
+    fn expiration_timestamp(&self) -> Option<i64> {
+        self.expiration_timestamp
+    }
+
+    fn execution_grace_seconds(&self) -> i64 {
+        self.execution_grace_seconds
+    }
+
+    fn check_allowlist(&self, program_id: &Pubkey, data: &[u8]) -> Result<()> {
+        MultisigAccount::check_allowlist(self, program_id, data)
+    }
+}
+
+impl ProposalGate for MultisigV2 {
+    fn require_active(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    fn owner_set_seqno(&self) -> u32 {
+        0
+    }
+
+    fn expiration_timestamp(&self) -> Option<i64> {
+        None
+    }
+
+    fn execution_grace_seconds(&self) -> i64 {
+        0
+    }
+
+    fn check_allowlist(&self, _program_id: &Pubkey, _data: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T> ProposalGate for Account<'_, T>
+where
+    T: ProposalGate + AccountSerialize + AccountDeserialize + Owner + Clone,
+{
+    fn require_active(&self) -> Result<()> {
+        T::require_active(self)
+    }
+
+    fn generation(&self) -> u64 {
+        T::generation(self)
+    }
+
+    fn owner_set_seqno(&self) -> u32 {
+        T::owner_set_seqno(self)
+    }
+
+    fn expiration_timestamp(&self) -> Option<i64> {
+        T::expiration_timestamp(self)
+    }
+
+    fn execution_grace_seconds(&self) -> i64 {
+        T::execution_grace_seconds(self)
+    }
+
+    fn check_allowlist(&self, program_id: &Pubkey, data: &[u8]) -> Result<()> {
+        T::check_allowlist(self, program_id, data)
+    }
+}
+
+// Helper function to check if a signer is in the multisig
+fn is_signer_in_multisig(signers: &[Pubkey], signer: &Pubkey) -> bool {
+    signers.contains(signer)
+}
+
+// Helper function to recognize a CPI through which a multisig acts towards
+// another one: approving its proposals as a member, or spending a budget it
+// was granted
+fn is_nested_call(data: &[u8], account_metas: &[AccountMeta], multisig: &Pubkey) -> bool {
+    let nested = data.starts_with(&crate::instruction::ApproveTransaction::DISCRIMINATOR)
+        || data.starts_with(&crate::instruction::ApproveTransactionWithMemo::DISCRIMINATOR)
+        || data.starts_with(&crate::instruction::ApproveTransactionIdempotent::DISCRIMINATOR)
+        || data.starts_with(&crate::instruction::ApproveTransactionByIndex::DISCRIMINATOR)
+        || data.starts_with(&crate::instruction::SpendFromBudget::DISCRIMINATOR);
+    nested && account_metas.first().is_some_and(|meta| meta.pubkey != *multisig)
+}
+
+// Helper function to derive an indexed vault PDA of a multisig. Index 0 is
+// the original vault, derived without an index from its stored bump
+fn indexed_vault_address(multisig: &Pubkey, vault_bump: u8, index: u16) -> Result<(Pubkey, u8)> {
+    if index == 0 {
+        return Ok((vault_address(multisig, vault_bump)?, vault_bump));
+    }
+    Ok(Pubkey::find_program_address(&[b"vault", multisig.as_ref(), &index.to_le_bytes()], &crate::ID))
+}
+
+// Helper function to compute the vault PDA of a multisig from its stored
+// bump, without searching for the bump again
+fn vault_address(multisig: &Pubkey, vault_bump: u8) -> Result<Pubkey> {
+    Pubkey::create_program_address(&[b"vault", multisig.as_ref(), &[vault_bump]], &crate::ID)
+        .map_err(|_| error!(MultisigWalletError::InvalidVaultBump))
+}
+
+// Helper function to derive the system-owned vault PDA of a multisig
+fn find_vault_address(multisig: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", multisig.as_ref()], &crate::ID)
+}
+
+// Helper function to derive the transaction PDA of a multisig's proposal
+fn find_transaction_address(multisig: &Pubkey, index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"tx", multisig.as_ref(), &index.to_le_bytes()], &crate::ID)
+}
+
+// Helper function to derive the execution receipt PDA of a multisig's
+// proposal
+fn find_receipt_address(multisig: &Pubkey, index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"receipt", multisig.as_ref(), &index.to_le_bytes()], &crate::ID)
+}
+
+// Helper function to reject account metas handing the multisig, or the
+// proposal being made or one of the `SELF_REFERENCE_LOOKBACK` before it, to
+// a CPI as writable. Older proposals are caught at execution, see
+// build_opaque_instruction
+fn check_self_references(multisig: &Pubkey, nonce: u64, account_metas: &[AccountMeta]) -> Result<()> {
+    let writable: Vec<Pubkey> = account_metas.iter().filter(|meta| meta.is_writable).map(|meta| meta.pubkey).collect();
+    if writable.is_empty() {
+        return Ok(());
+    }
+
+    let recent = nonce.saturating_sub(SELF_REFERENCE_LOOKBACK)..=nonce;
+    let mut proposals = recent.map(|index| find_transaction_address(multisig, index).0);
+    if writable.contains(multisig) || proposals.any(|proposal| writable.contains(&proposal)) {
+        return err!(MultisigWalletError::SelfReferentialProposal);
+    }
+
+    Ok(())
+}
+
+// Helper function to derive the account PDA a typed action creates (stake
+// accounts, mints), seeded by the proposal's transaction account
+fn find_created_account_address(seed: &[u8], transaction: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[seed, transaction.as_ref()], &crate::ID)
+}
+
+/// The `n`th ephemeral signer of the proposal at `transaction`, a PDA the
+/// program signs for when the proposal executes. Clients put it in the
+/// proposal's account metas as a signer wherever the instruction needs a
+/// fresh keypair.
+pub fn find_ephemeral_signer_address(transaction: &Pubkey, n: u8) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"ephemeral", transaction.as_ref(), &n.to_le_bytes()], &crate::ID)
+}
+
+// Helper function to verify at least `threshold` current signers signed the instruction
+fn require_threshold_signers(signers: &[Pubkey], threshold: u8, remaining_accounts: &[AccountInfo]) -> Result<()> {
+    let approvals = signers
+        .iter()
+        .filter(|signer| {
+            remaining_accounts
+                .iter()
+                .any(|account| account.key() == **signer && account.is_signer)
+        })
+        .count();
+    if approvals < threshold as usize {
+        return err!(MultisigWalletError::InsufficientApprovals);
+    }
+
+    Ok(())
+}
+
+// Helper function to check a replacement signer set like an initial one,
+// and against the program config's signer limit
+fn validate_signer_set(multisig: &Account<MultisigAccount>, signers: &[Pubkey], max_signers: u16) -> Result<()> {
+    let vault = vault_address(&multisig.key(), multisig.vault_bump)?;
+    validate_signer_keys(signers, &multisig.key(), &vault)?;
+    if signers.len() > max_signers as usize {
+        return err!(MultisigWalletError::TooManySigners);
+    }
+    Ok(())
+}
+
+// Helper function to install a new signer set along with the side effects of
+// a membership change
+fn replace_signers<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    signers: Vec<Pubkey>,
+    max_signers: u16,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    validate_signer_set(multisig, &signers, max_signers)?;
+
+    // Operational signers can never double as recovery members
+    if signers.iter().any(|signer| multisig.recovery_members.contains(signer)) {
+        return err!(MultisigWalletError::RecoverySetOverlap);
+    }
+
+    multisig.signers = signers;
+
+    // Forget activity and allowances of removed members
+    let members = multisig.signers.clone();
+    multisig.member_activity.retain(|activity| members.contains(&activity.member));
+    multisig.member_allowances.retain(|allowance| members.contains(&allowance.member));
+
+    // Void everything in flight: pending transactions become stale, and
+    // those supplied in remaining accounts are closed with their rent
+    // refunded to the rent payer
+    if multisig.invalidate_on_member_change {
+        multisig.owner_set_seqno = multisig.owner_set_seqno.wrapping_add(1);
+        close_pending_transactions(multisig, remaining_accounts)?;
+    }
+
+    Ok(())
+}
+
+// Helper function to record a member's proposal, approval or execution,
+// reporting when it brings a member flagged inactive back
+fn note_member_action(multisig: &mut Account<MultisigAccount>, member: Pubkey, now: i64) {
+    if multisig.record_action(member, now) {
+        emit!(MemberReactivated { multisig: multisig.key(), member, at: now });
+    }
+}
+
+// Helper function to close every pending transaction of a multisig found in
+// the remaining accounts, refunding its rent to the rent payer (which must be
+// supplied as a writable remaining account too)
+fn close_pending_transactions<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let multisig_key = multisig.key();
+    for info in remaining_accounts {
+        if *info.owner != crate::ID || !info.is_writable {
+            continue;
+        }
+        let Ok(transaction) = Account::<TransactionAccount>::try_from(info) else {
+            continue;
+        };
+        // Escrowed funds and stages must be released before the proposal
+        // can go
+        if transaction.multisig != multisig_key || transaction.executed || transaction.escrowed || transaction.staged {
+            continue;
+        }
+
+        let rent_payer = find_account(remaining_accounts, &transaction.rent_payer)?;
+        close_account_to(info, rent_payer)?;
+        record_terminal_history(multisig, &transaction, HistoryRecord::Closed)?;
+    }
+
+    Ok(())
+}
+
+// Helper function to link a proposal's cancellation or closing into the
+// multisig's history
+fn record_terminal_history(
+    multisig: &mut Account<MultisigAccount>,
+    transaction: &TransactionAccount,
+    record: HistoryRecord,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    multisig.note_proposal_closed(transaction.tx_index);
+    let (history_before, history_after) = multisig.record_history(record, &transaction.hash, transaction.tx_index, now);
+    emit!(ProposalHistoryRecorded {
+        multisig: multisig.key(),
+        tx_index: transaction.tx_index,
+        record,
+        history_before,
+        history_after,
+    });
+
+    Ok(())
+}
+
+// Helper function to close a program-owned account, moving its lamports to `destination`
+fn close_account_to<'info>(info: &AccountInfo<'info>, destination: &AccountInfo<'info>) -> Result<()> {
+    let lamports = info.lamports();
+    **info.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? += lamports;
+    info.assign(&anchor_lang::system_program::ID);
+    info.realloc(0, false)?;
+
+    Ok(())
+}
+
+// Helper function to build the canonical memo recorded on execution
+fn execution_memo(multisig: &Pubkey, tx_index: u64, memo: Option<&str>) -> String {
+    match memo {
+        Some(memo) => format!("multisig={} tx={} {}", multisig, tx_index, memo),
+        None => format!("multisig={} tx={}", multisig, tx_index),
+    }
+}
+
+// Helper function to verify every current signer signed the instruction
+fn require_all_signers(signers: &[Pubkey], remaining_accounts: &[AccountInfo]) -> Result<()> {
+    for signer in signers {
+        let found = remaining_accounts.iter().any(|account| {
+            account.key() == *signer && account.is_signer
+        });
+        if !found {
+            return err!(MultisigWalletError::NotAllSignersApproved);
+        }
+    }
+
+    Ok(())
+}
+
+// Helper function to apply a config change to the multisig, for
+// configure_multisig and executed config change proposals
+fn apply_config_action<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    action: ConfigAction,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    match action {
+        ConfigAction::SetMintCap { mint, amount_per_period, period_seconds } => {
+            if amount_per_period == 0 || period_seconds <= 0 {
+                return err!(MultisigWalletError::InvalidMintCap);
+            }
+
+            if let Some(cap) = multisig.mint_caps.iter_mut().find(|cap| cap.mint == mint) {
+                cap.amount_per_period = amount_per_period;
+                cap.period_seconds = period_seconds;
+            } else {
+                if multisig.mint_caps.len() >= MAX_MINT_CAPS {
+                    return err!(MultisigWalletError::TooManyMintCaps);
+                }
+                multisig.mint_caps.push(MintCap {
+                    mint,
+                    amount_per_period,
+                    period_seconds,
+                    period_start: Clock::get()?.unix_timestamp,
+                    minted_in_period: 0,
+                });
+            }
+        }
+        ConfigAction::RemoveMintCap { mint } => {
+            multisig.mint_caps.retain(|cap| cap.mint != mint);
+        }
+        ConfigAction::SetExecutionMemo { enabled } => {
+            multisig.memo_on_execute = enabled;
+        }
+        ConfigAction::SetProposalCooldown { seconds } => {
+            multisig.proposal_cooldown_seconds = seconds;
+        }
+        ConfigAction::SetEmergencyTimelock { seconds } => {
+            if seconds <= 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.emergency_timelock_seconds = seconds;
+        }
+        ConfigAction::SetRecovery { members, threshold, delay_seconds } => {
+            if members.len() > MAX_RECOVERY_MEMBERS {
+                return err!(MultisigWalletError::TooManyRecoveryMembers);
+            }
+            if members.iter().any(|member| multisig.signers.contains(member)) {
+                return err!(MultisigWalletError::RecoverySetOverlap);
+            }
+            // An empty set with a zero threshold disables recovery
+            if threshold as usize > members.len() || (threshold == 0 && !members.is_empty()) {
+                return err!(MultisigWalletError::InvalidThreshold);
+            }
+            if !members.is_empty() && delay_seconds <= 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.recovery_members = members;
+            multisig.recovery_threshold = threshold;
+            multisig.recovery_delay_seconds = delay_seconds;
+        }
+        ConfigAction::SetApproverProgram { program, max_approvals } => {
+            if program.is_some() && max_approvals == 0 {
+                return err!(MultisigWalletError::InvalidThreshold);
+            }
+            multisig.approver_program = program;
+            multisig.max_external_approvals = max_approvals;
+        }
+        ConfigAction::SetAllowedProgram { program_id, prefixes } => {
+            if prefixes.len() > MAX_INSTRUCTION_PREFIXES
+                || prefixes
+                    .iter()
+                    .any(|prefix| prefix.is_empty() || prefix.len() > MAX_INSTRUCTION_PREFIX_LEN)
+            {
+                return err!(MultisigWalletError::InvalidInstructionPrefix);
+            }
+
+            if let Some(entry) = multisig.allowed_programs.iter_mut().find(|entry| entry.program_id == program_id) {
+                entry.prefixes = prefixes;
+            } else {
+                if multisig.allowed_programs.len() >= MAX_ALLOWED_PROGRAMS {
+                    return err!(MultisigWalletError::TooManyAllowedPrograms);
+                }
+                multisig.allowed_programs.push(AllowedProgram { program_id, prefixes });
+            }
+        }
+        ConfigAction::RemoveAllowedProgram { program_id } => {
+            multisig.allowed_programs.retain(|entry| entry.program_id != program_id);
+        }
+        ConfigAction::SetDefaultProposalTtl { seconds } => {
+            if seconds < 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.default_proposal_ttl_seconds = seconds;
+        }
+        ConfigAction::SetReimburseProposalRent { enabled } => {
+            multisig.reimburse_proposal_rent = enabled;
+        }
+        ConfigAction::SetCleanupBounty { lamports, vault_cap } => {
+            multisig.cleanup_bounty_lamports = lamports;
+            multisig.cleanup_bounty_vault_cap = vault_cap;
+        }
+        ConfigAction::SetMaxAccountMetas { max } => {
+            if max == 0 || max as usize > MAX_ACCOUNT_METAS {
+                return err!(MultisigWalletError::InvalidAccountMetaCap);
+            }
+            multisig.max_account_metas = max;
+        }
+        ConfigAction::CreateSubBudget { child, lamports, mint_budgets, expires_at } => {
+            if mint_budgets.len() > MAX_BUDGET_MINTS {
+                return err!(MultisigWalletError::TooManySubBudgets);
+            }
+            let budget = SubBudget { child, lamports, mint_budgets, expires_at };
+            if let Some(entry) = multisig.sub_budgets.iter_mut().find(|entry| entry.child == child) {
+                *entry = budget;
+            } else {
+                if multisig.sub_budgets.len() >= MAX_SUB_BUDGETS {
+                    return err!(MultisigWalletError::TooManySubBudgets);
+                }
+                multisig.sub_budgets.push(budget);
+            }
+        }
+        ConfigAction::RevokeSubBudget { child } => {
+            multisig.sub_budgets.retain(|entry| entry.child != child);
+        }
+        ConfigAction::SetKeyRotationCooldown { seconds } => {
+            if seconds < 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.key_rotation_cooldown_seconds = seconds;
+        }
+        ConfigAction::SetReassignApprovalsOnRotation { enabled } => {
+            multisig.reassign_approvals_on_rotation = enabled;
+        }
+        ConfigAction::SetRateLimit { mint, max_per_window } => {
+            let now = Clock::get()?.unix_timestamp;
+            if let Some(limit) = multisig.rate_limits.iter_mut().find(|limit| limit.mint == mint) {
+                // Tightening applies at once, raising only after a delay
+                if max_per_window <= limit.current_max(now) {
+                    limit.max_per_window = max_per_window;
+                    limit.pending_max = None;
+                } else {
+                    limit.pending_max = Some(max_per_window);
+                    limit.pending_effective_at = now.saturating_add(RATE_LIMIT_RAISE_DELAY);
+                }
+            } else {
+                if multisig.rate_limits.len() >= MAX_RATE_LIMITS {
+                    return err!(MultisigWalletError::TooManyRateLimits);
+                }
+                multisig.rate_limits.push(RateLimit {
+                    mint,
+                    max_per_window,
+                    window_start: now,
+                    spent_in_window: 0,
+                    pending_max: None,
+                    pending_effective_at: 0,
+                });
+            }
+        }
+        ConfigAction::RemoveRateLimit { mint } => {
+            // Only a limit already raised to unlimited can be removed, so
+            // removal can't skip the raise delay
+            let now = Clock::get()?.unix_timestamp;
+            let limit = multisig
+                .rate_limits
+                .iter_mut()
+                .find(|limit| limit.mint == mint)
+                .ok_or(MultisigWalletError::RateLimitNotFound)?;
+            if limit.current_max(now) != u64::MAX {
+                return err!(MultisigWalletError::RateLimitRaisePending);
+            }
+            multisig.rate_limits.retain(|limit| limit.mint != mint);
+        }
+        ConfigAction::SetPriorityLevel { priority, timelock_seconds, required_threshold } => {
+            // A level may raise the approvals required, never lower them
+            if timelock_seconds < 0
+                || required_threshold < multisig.threshold
+                || required_threshold as usize > multisig.max_threshold(multisig.signers.len())
+            {
+                return err!(MultisigWalletError::InvalidPriorityLevel);
+            }
+            let level = PriorityLevel { priority, timelock_seconds, required_threshold };
+            if let Some(existing) = multisig.priority_levels.iter_mut().find(|level| level.priority == priority) {
+                *existing = level;
+            } else {
+                if multisig.priority_levels.len() >= MAX_PRIORITY_LEVELS {
+                    return err!(MultisigWalletError::TooManyPriorityLevels);
+                }
+                multisig.priority_levels.push(level);
+            }
+        }
+        ConfigAction::RemovePriorityLevel { priority } => {
+            if multisig.priority_level(priority).is_none() {
+                return err!(MultisigWalletError::PriorityLevelNotFound);
+            }
+            multisig.priority_levels.retain(|level| level.priority != priority);
+        }
+        ConfigAction::SetTokenVote { token_vote } => {
+            // A zero quorum would approve every proposal outright
+            if let Some(token_vote) = &token_vote {
+                if token_vote.required_amount == 0 || token_vote.vote_window_seconds <= 0 {
+                    return err!(MultisigWalletError::InvalidTokenVote);
+                }
+            }
+            multisig.token_vote = token_vote;
+        }
+        ConfigAction::SetPruneRemovedApprovals { enabled } => {
+            multisig.prune_removed_approvals = enabled;
+        }
+        ConfigAction::SetExecutionPolicy { policy } => {
+            multisig.execution_policy = policy;
+        }
+        ConfigAction::SetAutomationAuthority { authority } => {
+            multisig.automation_authority = authority;
+        }
+        ConfigAction::SetLiveThreshold { enabled } => {
+            multisig.live_threshold = enabled;
+        }
+        ConfigAction::SetExecutionGrace { seconds } => {
+            if seconds < 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.execution_grace_seconds = seconds;
+        }
+        ConfigAction::SetAllowDangerousSystemInstructions { enabled } => {
+            multisig.allow_dangerous_system_instructions = enabled;
+        }
+        ConfigAction::SetPayoutCap { mint, max_total } => {
+            multisig.payout_caps.retain(|cap| cap.mint != mint);
+            if let Some(max_total) = max_total {
+                if max_total == 0 {
+                    return err!(MultisigWalletError::InvalidAmount);
+                }
+                if multisig.payout_caps.len() >= MAX_PAYOUT_CAPS {
+                    return err!(MultisigWalletError::TooManyPayoutCaps);
+                }
+                multisig.payout_caps.push(PayoutCap { mint, max_total });
+            }
+        }
+        ConfigAction::SetInactivityPeriod { seconds } => {
+            if seconds < 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.inactivity_period_seconds = seconds;
+        }
+        ConfigAction::SetBudgetEpochs { epochs } => {
+            if let Some(epochs) = &epochs {
+                if epochs.length_seconds <= 0 {
+                    return err!(MultisigWalletError::InvalidDuration);
+                }
+            } else if !multisig.epoch_caps.is_empty() {
+                return err!(MultisigWalletError::BudgetEpochsNotSet);
+            }
+            // Renumbering the epochs starts every cap afresh
+            let now = Clock::get()?.unix_timestamp;
+            let epoch = epochs.as_ref().map_or(0, |epochs| epochs.epoch_at(now));
+            for cap in multisig.epoch_caps.iter_mut() {
+                cap.epoch = epoch;
+                cap.spent_in_epoch = 0;
+            }
+            multisig.budget_epochs = epochs;
+        }
+        ConfigAction::SetEpochCap { mint, max_per_epoch } => {
+            let epochs = multisig.budget_epochs.as_ref().ok_or(MultisigWalletError::BudgetEpochsNotSet)?;
+            let epoch = epochs.epoch_at(Clock::get()?.unix_timestamp);
+            match (multisig.epoch_caps.iter_mut().find(|cap| cap.mint == mint), max_per_epoch) {
+                (Some(cap), Some(max_per_epoch)) => cap.max_per_epoch = max_per_epoch,
+                (None, Some(max_per_epoch)) => {
+                    if multisig.epoch_caps.len() >= MAX_EPOCH_CAPS {
+                        return err!(MultisigWalletError::TooManyEpochCaps);
+                    }
+                    multisig.epoch_caps.push(EpochCap { mint, max_per_epoch, epoch, spent_in_epoch: 0 });
+                }
+                (_, None) => multisig.epoch_caps.retain(|cap| cap.mint != mint),
+            }
+        }
+        ConfigAction::SetFeePayer { payer } => {
+            multisig.fee_payer = payer;
+        }
+        ConfigAction::InviteSigner { invitee, roles } => {
+            create_invitation(multisig, invitee, roles, remaining_accounts)?;
+        }
+        ConfigAction::SetTypedOnly { enabled } => {
+            // Forbidding opaque proposals applies at once, allowing them
+            // again only after a delay
+            let now = Clock::get()?.unix_timestamp;
+            if enabled {
+                multisig.typed_only = true;
+                multisig.typed_only_disable_at = 0;
+            } else if multisig.is_typed_only(now) && multisig.typed_only_disable_at == 0 {
+                multisig.typed_only_disable_at = now.saturating_add(TYPED_ONLY_DISABLE_DELAY);
+            }
+        }
+        ConfigAction::SetVoteCooldown { slots } => {
+            multisig.vote_cooldown_slots = slots;
+        }
+        ConfigAction::SetMemberAllowance { member, amount, period_seconds } => {
+            // Changing an allowance refills it but keeps it frozen
+            let frozen = multisig
+                .member_allowances
+                .iter()
+                .any(|allowance| allowance.member == member && allowance.frozen);
+            multisig.member_allowances.retain(|allowance| allowance.member != member);
+            if let Some(amount) = amount {
+                if amount == 0 {
+                    return err!(MultisigWalletError::InvalidAmount);
+                }
+                if period_seconds <= 0 {
+                    return err!(MultisigWalletError::InvalidDuration);
+                }
+                if !is_signer_in_multisig(&multisig.signers, &member) {
+                    return err!(MultisigWalletError::SignerNotFound);
+                }
+                multisig.member_allowances.push(MemberAllowance {
+                    member,
+                    amount,
+                    period_seconds,
+                    remaining: amount,
+                    last_reset: Clock::get()?.unix_timestamp,
+                    frozen,
+                });
+            }
+        }
+        ConfigAction::SetAllowanceFrozen { member, frozen } => {
+            multisig
+                .member_allowances
+                .iter_mut()
+                .find(|allowance| allowance.member == member)
+                .ok_or(MultisigWalletError::AllowanceNotFound)?
+                .frozen = frozen;
+        }
+        ConfigAction::SetConfirmationThreshold { bytes } => {
+            multisig.confirmation_threshold_bytes = bytes;
+        }
+        ConfigAction::SetProposerCounts { enabled } => {
+            multisig.proposer_excluded = !enabled;
+            // The threshold, and any priority level's, must stay reachable
+            // without the proposer
+            let max_threshold = multisig.max_threshold(multisig.signers.len());
+            let highest = multisig.priority_levels.iter().map(|level| level.required_threshold).max();
+            if multisig.threshold.max(highest.unwrap_or(0)) as usize > max_threshold {
+                return err!(MultisigWalletError::InvalidThreshold);
+            }
+        }
+        ConfigAction::TransferAuthority { new_authority } => {
+            multisig.authority = new_authority;
+        }
+        ConfigAction::SetMemberSwapWindow { seconds } => {
+            if seconds <= 0 {
+                return err!(MultisigWalletError::InvalidDuration);
+            }
+            multisig.member_swap_window_seconds = seconds;
+        }
+        ConfigAction::SetInvalidateOnMemberChange { enabled } => {
+            multisig.invalidate_on_member_change = enabled;
+        }
+        ConfigAction::SetOrderedExecution { enabled, next_index } => {
+            if next_index > multisig.nonce {
+                return err!(MultisigWalletError::OutOfOrderExecution);
+            }
+            multisig.ordered_execution = enabled;
+            multisig.last_executed_index = next_index.checked_sub(1);
+        }
+    }
+
+    Ok(())
+}
+
+// Helper function to invite `invitee` to join the multisig, creating their
+// invitation PDA with rent from the vault. The invitation, the vault and the
+// System Program must be passed along with the remaining accounts
+fn create_invitation<'info>(
+    multisig: &Account<'info, MultisigAccount>,
+    invitee: Pubkey,
+    roles: u8,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    if is_signer_in_multisig(&multisig.signers, &invitee) {
+        return err!(MultisigWalletError::AlreadyMember);
+    }
+    if multisig.recovery_members.contains(&invitee) {
+        return err!(MultisigWalletError::RecoverySetOverlap);
+    }
+
+    // One open invitation per key: its address is taken until it's accepted
+    // or closed
+    let multisig_key = multisig.key();
+    let (address, bump) =
+        Pubkey::find_program_address(&[b"invitation", multisig_key.as_ref(), invitee.as_ref()], &crate::ID);
+    let invitation = find_account(remaining_accounts, &address)?;
+    if *invitation.owner == crate::ID && !invitation.data_is_empty() {
+        return err!(MultisigWalletError::InvitationAlreadyExists);
+    }
+
+    let vault_key = vault_address(&multisig_key, multisig.vault_bump)?;
+    let vault = find_account(remaining_accounts, &vault_key)?;
+    let system_program = find_account(remaining_accounts, &anchor_lang::system_program::ID)?;
+    let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[multisig.vault_bump]];
+    let invitation_seeds = &[b"invitation".as_ref(), multisig_key.as_ref(), invitee.as_ref(), &[bump]];
+    let signer_seeds = [&vault_seeds[..], &invitation_seeds[..]];
+    create_program_account(vault, invitation, system_program, Invitation::SPACE, &signer_seeds)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut invitation = Account::<Invitation>::try_from_unchecked(invitation)?;
+    invitation.multisig = multisig_key;
+    invitation.invitee = invitee;
+    invitation.roles = roles;
+    invitation.expires_at = now.saturating_add(INVITATION_WINDOW);
+    invitation.bump = bump;
+    invitation.exit(&crate::ID)?;
+
+    emit!(InvitationCreated { multisig: multisig_key, invitee, roles, expires_at: invitation.expires_at });
+
+    Ok(())
+}
+
+// Helper function to check a multisig expiration is strictly in the future
+// and within `MAX_EXPIRATION_HORIZON`, so it can't brick approvals at once
+fn validate_expiration(expiration: i64, now: i64) -> Result<()> {
+    if expiration <= now || expiration > now.saturating_add(MAX_EXPIRATION_HORIZON) {
+        return err!(MultisigWalletError::InvalidExpiration);
+    }
+
+    Ok(())
+}
+
+// Helper function to read the program config, or the compiled defaults and
+// no protocol fee while it doesn't exist
+fn load_program_config(program_config: &AccountInfo) -> Result<ProgramConfig> {
+    if program_config.data_is_empty() {
+        return Ok(ProgramConfig {
+            admin: Pubkey::default(),
+            limits: ProgramLimits::DEFAULT,
+            fee_lamports: 0,
+            fee_collector: Pubkey::default(),
+            cluster: Cluster::Unspecified,
+            wallet_programs: Vec::new(),
+            execution_receipts: false,
+            bump: 0,
+        });
+    }
+    Ok(Account::<ProgramConfig>::try_from(program_config)?.into_inner())
+}
+
+// Helper function to reject signer lists that leave a multisig unable to
+// act or miscount its quorum: an empty list, a key listed twice, or keys
+// that can never sign for it. The default key is also the System Program's id
+fn validate_signer_keys(signers: &[Pubkey], multisig: &Pubkey, vault: &Pubkey) -> Result<()> {
+    if signers.is_empty() {
+        return err!(MultisigWalletError::EmptySignerList);
+    }
+
+    for (i, signer) in signers.iter().enumerate() {
+        if signers[..i].contains(signer) {
+            return err!(MultisigWalletError::AlreadyMember);
+        }
+        if *signer == Pubkey::default() {
+            return err!(MultisigWalletError::DefaultSignerKey);
+        }
+        if *signer == crate::ID {
+            return err!(MultisigWalletError::ProgramSignerKey);
+        }
+        if signer == multisig || signer == vault {
+            return err!(MultisigWalletError::SelfSignerKey);
+        }
+    }
+
+    Ok(())
+}
+
+// Helper function shared by the typed proposal instructions
+fn propose_action(
+    ctx: Context<ProposeAction>,
+    program_id: Pubkey,
+    action: TypedAction,
+    category: u16,
+    sweep: bool,
+) -> Result<()> {
+    let config = load_program_config(&ctx.accounts.program_config)?;
+    let multisig = &mut ctx.accounts.multisig;
+    let proposer = ctx.accounts.proposer.key();
+
+    multisig.require_active()?;
+
+    // Validate proposer is a signer in the multisig
+    if !is_signer_in_multisig(&multisig.signers, &proposer) {
+        return err!(MultisigWalletError::SignerNotFound);
+    }
+
+    // Enforce the per-proposer cooldown
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    multisig.record_proposal(proposer, now)?;
+    note_member_action(multisig, proposer, now);
+
+    // Create and initialize the transaction account at the current nonce; the
+    // instruction itself is built at execution
+    let (mut transaction, bump) = create_transaction_account(
+        multisig,
+        multisig.nonce,
+        &ctx.accounts.transaction,
+        &ctx.accounts.rent_payer,
+        &ctx.accounts.system_program.to_account_info(),
+        TransactionAccount::space(0, 0, 0, 0, multisig.signers.len()) + action.extra_space(),
+    )?;
+    transaction.multisig = multisig.key();
+    transaction.proposer = proposer;
+    transaction.rent_payer = ctx.accounts.rent_payer.key();
+    transaction.tx_index = multisig.nonce;
+    transaction.program_id = program_id;
+    transaction.accounts = Vec::new();
+    transaction.version = TRANSACTION_VERSION_COMPACT_METAS;
+    transaction.data = Vec::new();
+    transaction.action = Some(action);
+    transaction.memo = None;
+    transaction.assertions = Vec::new();
+    transaction.executed = false;
+    transaction.cancelled = false;
+    transaction.owner_set_seqno = multisig.owner_set_seqno;
+    transaction.generation = multisig.generation;
+    transaction.bump = bump;
+    transaction.signers = vec![proposer]; // Proposer auto-approves
+    transaction.approval_memos = vec![String::new()];
+    transaction.rejections = Vec::new();
+    transaction.rejection_reasons = Vec::new();
+    transaction.external_approvals = 0;
+    transaction.created_at = now;
+    transaction.created_slot = clock.slot;
+    transaction.joint_with = None;
+    transaction.depends_on = None;
+    transaction.compute = None;
+    transaction.sweep = sweep;
+    transaction.ephemeral_signers = 0;
+    transaction.protocol_fee = config.fee_lamports;
+    transaction.cluster = config.cluster;
+    transaction.receipt_required = config.execution_receipts;
+    transaction.proposer_excluded = multisig.proposer_excluded;
+    transaction.staged = false;
+    transaction.rent_reimbursed = false;
+    // The default TTL counts from the proposal's creation
+    transaction.expires_at = multisig.proposal_expiry(transaction.created_at, None)?;
+    transaction.read_only = false;
+    transaction.escrowed = false;
+    transaction.hash = transaction.compute_hash();
+    transaction.priority = 0;
+    transaction.approved_at = None;
+    transaction.required_approvals = multisig.required_approvals(0) as u8;
+    transaction.note_approvals(multisig.required_approvals(0), now);
+    transaction.authority_kind = AuthorityKind::Vault(0);
+    transaction.eligible_approvers = multisig.signers.clone();
+    transaction.approval_ordinal = 0;
+    transaction.approval_indices = Vec::new();
+    transaction.index_approval(&proposer);
+    transaction.last_vote_slots = vec![0; transaction.eligible_approvers.len()];
+    let created_slot = transaction.created_slot;
+    transaction.note_vote(&proposer, created_slot, 0)?;
+    transaction.token_votes = 0;
+    transaction.vote_ends_at = multisig.vote_ends_at(now);
+    transaction.automatable = false;
+    transaction.category = category;
+    transaction.kind = TransactionKind::TypedAction(transaction.action.as_ref().unwrap().kind());
+
+    msg!(
+        "proposal {}: {} via {}",
+        transaction.tx_index,
+        transaction.action.as_ref().unwrap().summary(),
+        program_id
+    );
+
+    let (history_before, history_after) =
+        multisig.record_history(HistoryRecord::Proposed, &transaction.hash, transaction.tx_index, now);
+
+    emit!(TransactionProposed {
+        multisig: multisig.key(),
+        tx_index: transaction.tx_index,
+        transaction_hash: transaction.hash,
+        priority: transaction.priority,
+        proposer,
+        category: transaction.category,
+        created_at: transaction.created_at,
+        created_slot: transaction.created_slot,
+        kind: transaction.kind.clone(),
+        cluster: transaction.cluster,
+        history_before,
+        history_after,
+    });
+    emit_threshold_reached(multisig, &transaction, false);
+    transaction.exit(&crate::ID)?;
+
+    // Increment transaction counter
+    multisig.nonce += 1;
+
+    Ok(())
+}
+
+// Helper function to validate and record an opaque proposal at the
+// multisig's nonce, shared by single and batched proposals
+fn propose_opaque<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    transaction_account: &AccountInfo<'info>,
+    rent_payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    config: &ProgramConfig,
+    proposal: OpaqueProposal,
+) -> Result<()> {
+    let OpaqueProposal {
+        proposer,
+        program_id,
+        accounts,
+        instruction_data,
+        memo,
+        assertions,
+        read_only,
+        priority,
+        authority_kind,
+        automatable,
+        category,
+        now,
+        slot,
+        ttl_seconds,
+        joint_with,
+        depends_on,
+        compute,
+        sweep,
+        ephemeral_signers,
+        reserved_index,
+    } = proposal;
+    let tx_index = reserved_index.unwrap_or(multisig.nonce);
+
+    // Validate the account metas up front, so malformed proposals fail
+    // before anyone approves them
+    let multisig_key = multisig.key();
+    let (vault, _) = match authority_kind {
+        AuthorityKind::Vault(index) => indexed_vault_address(&multisig_key, multisig.vault_bump, index)?,
+        AuthorityKind::Multisig => (vault_address(&multisig_key, multisig.vault_bump)?, multisig.vault_bump),
+    };
+    // A joint proposal may also be signed by the partner multisig or its
+    // main vault
+    let mut signers = vec![multisig_key, vault];
+    if let Some(partner) = joint_with {
+        if partner == multisig_key {
+            return err!(MultisigWalletError::InvalidJointPartner);
+        }
+        signers.push(partner);
+        signers.push(Pubkey::find_program_address(&[b"vault", partner.as_ref()], &crate::ID).0);
+    }
+    // Ephemeral signers may sign too, as fresh keys for accounts the
+    // instruction creates
+    if ephemeral_signers as usize > MAX_EPHEMERAL_SIGNERS {
+        return err!(MultisigWalletError::TooManyEphemeralSigners);
+    }
+    signers.extend((0..ephemeral_signers).map(|n| find_ephemeral_signer_address(transaction_account.key, n).0));
+    let account_metas = parse_account_metas(&accounts, multisig.max_account_metas, &signers)?;
+
+    // A read-only proposal must not list the vault as writable
+    if read_only && account_metas.iter().any(|meta| meta.pubkey == vault && meta.is_writable) {
+        return err!(MultisigWalletError::WritableVaultInReadOnly);
+    }
+
+    // Validate the data against the deployment's limit
+    if instruction_data.len() > config.limits.max_instruction_data_len as usize {
+        return err!(MultisigWalletError::InstructionDataTooLarge);
+    }
+
+    // Validate the target against the allowlist
+    multisig.check_allowlist(&program_id, &instruction_data)?;
+
+    // Config changes apply to the multisig itself rather than through a CPI,
+    // so they can't be read-only or joint
+    let kind = TransactionKind::of(&program_id, &instruction_data, None)?;
+    if matches!(kind, TransactionKind::ConfigChange(_)) && (read_only || joint_with.is_some()) {
+        return err!(MultisigWalletError::TransactionKindMismatch);
+    }
+
+    // Under `typed_only`, config changes are all that may come this way
+    if kind == TransactionKind::General && multisig.is_typed_only(now) {
+        return err!(MultisigWalletError::OpaqueProposalsDisabled);
+    }
+
+    // A CPI handed the multisig or one of its proposals as writable could
+    // only fail late, or worse once owner checks loosen. Config changes
+    // don't CPI, so they may list the multisig
+    if kind == TransactionKind::General {
+        check_self_references(&multisig_key, tx_index, &account_metas)?;
+    }
+
+    // Refuse System Program calls handing one of the multisig's PDAs to
+    // another owner, unless the multisig opted out
+    if !multisig.allow_dangerous_system_instructions {
+        let main_vault = vault_address(&multisig_key, multisig.vault_bump)?;
+        check_system_instruction(&program_id, &instruction_data, &account_metas, &[multisig_key, vault, main_vault])?;
+    }
+
+    // Only configured priority levels may be tagged
+    if priority != 0 && multisig.priority_level(priority).is_none() {
+        return err!(MultisigWalletError::PriorityLevelNotFound);
+    }
+
+    // Validate memo length
+    if memo.as_ref().is_some_and(|memo| memo.len() > MAX_MEMO_LEN) {
+        return err!(MultisigWalletError::MemoTooLong);
+    }
+
+    // Validate balance assertions
+    if assertions.len() > MAX_BALANCE_ASSERTIONS {
+        return err!(MultisigWalletError::TooManyBalanceAssertions);
+    }
+
+    // Only earlier proposals can be depended on, so there are no cycles
+    if depends_on.is_some_and(|index| index >= tx_index) {
+        return err!(MultisigWalletError::InvalidDependency);
+    }
+
+    // Declared compute must be something the runtime could grant
+    if compute.is_some_and(|compute| !compute.is_valid()) {
+        return err!(MultisigWalletError::InvalidComputeDeclaration);
+    }
+
+    // Large payloads to other programs need a second look before executing
+    let threshold_bytes = multisig.confirmation_threshold_bytes as usize;
+    let confirmation_required =
+        kind == TransactionKind::General && threshold_bytes > 0 && instruction_data.len() > threshold_bytes;
+
+    // Create and initialize the transaction account at its index
+    let accounts = encode_compact_account_metas(&account_metas);
+    let space = TransactionAccount::space(
+        accounts.len(),
+        instruction_data.len(),
+        memo.as_ref().map_or(0, |memo| memo.len()),
+        assertions.len(),
+        multisig.signers.len(),
+    ) + kind.extra_space();
+    let (mut transaction, bump) =
+        create_transaction_account(multisig, tx_index, transaction_account, rent_payer, system_program, space)?;
+    transaction.multisig = multisig.key();
+    transaction.proposer = proposer;
+    transaction.rent_payer = rent_payer.key();
+    transaction.tx_index = tx_index;
+    transaction.program_id = program_id;
+    transaction.accounts = accounts;
+    transaction.version = TRANSACTION_VERSION_COMPACT_METAS;
+    transaction.data = instruction_data;
+    transaction.action = None;
+    transaction.memo = memo;
+    transaction.assertions = assertions;
+    transaction.executed = false;
+    transaction.cancelled = false;
+    transaction.owner_set_seqno = multisig.owner_set_seqno;
+    transaction.generation = multisig.generation;
+    transaction.bump = bump;
+    transaction.signers = vec![proposer]; // Proposer auto-approves
+    transaction.approval_memos = vec![String::new()];
+    transaction.rejections = Vec::new();
+    transaction.rejection_reasons = Vec::new();
+    transaction.external_approvals = 0;
+    transaction.created_at = now;
+    transaction.created_slot = slot;
+    // The default TTL counts from the proposal's creation
+    transaction.expires_at = multisig.proposal_expiry(transaction.created_at, ttl_seconds)?;
+    transaction.read_only = read_only;
+    transaction.escrowed = false;
+    transaction.joint_with = joint_with;
+    transaction.depends_on = depends_on;
+    transaction.compute = compute;
+    transaction.sweep = sweep;
+    transaction.ephemeral_signers = ephemeral_signers;
+    transaction.confirmation_required = confirmation_required;
+    transaction.confirmer = 0;
+    transaction.protocol_fee = config.fee_lamports;
+    transaction.cluster = config.cluster;
+    transaction.receipt_required = config.execution_receipts;
+    transaction.proposer_excluded = multisig.proposer_excluded;
+    transaction.staged = false;
+    transaction.rent_reimbursed = false;
+    transaction.hash = transaction.compute_hash();
+    transaction.priority = priority;
+    transaction.approved_at = None;
+    transaction.required_approvals = multisig.required_approvals(priority) as u8;
+    transaction.note_approvals(multisig.required_approvals(priority), now);
+    transaction.authority_kind = authority_kind;
+    transaction.eligible_approvers = multisig.signers.clone();
+    transaction.approval_ordinal = 0;
+    transaction.approval_indices = Vec::new();
+    transaction.index_approval(&proposer);
+    transaction.last_vote_slots = vec![0; transaction.eligible_approvers.len()];
+    let created_slot = transaction.created_slot;
+    transaction.note_vote(&proposer, created_slot, 0)?;
+    transaction.token_votes = 0;
+    transaction.vote_ends_at = multisig.vote_ends_at(now);
+    transaction.automatable = automatable;
+    transaction.category = category;
+    transaction.kind = kind;
+
+    msg!(
+        "proposal {}: program {}, {} accounts, {} data bytes",
+        transaction.tx_index,
+        program_id,
+        account_metas.len(),
+        transaction.data.len()
+    );
+
+    let (history_before, history_after) =
+        multisig.record_history(HistoryRecord::Proposed, &transaction.hash, transaction.tx_index, now);
+
+    emit!(TransactionProposed {
+        multisig: multisig_key,
+        tx_index: transaction.tx_index,
+        transaction_hash: transaction.hash,
+        priority: transaction.priority,
+        proposer,
+        category: transaction.category,
+        created_at: transaction.created_at,
+        created_slot: transaction.created_slot,
+        kind: transaction.kind.clone(),
+        cluster: transaction.cluster,
+        history_before,
+        history_after,
+    });
+    emit_threshold_reached(multisig, &transaction, false);
+    transaction.exit(&crate::ID)?;
+
+    // Increment transaction counter, which a reservation already did
+    if reserved_index.is_none() {
+        multisig.nonce += 1;
+    }
+
+    Ok(())
+}
+
+// Helper function to create the transaction account of the proposal at
+// `tx_index`. Done here rather than with `init`, whose seeds check can't
+// tell a wrong address from a stale one: an existing transaction at the
+// address means another proposal consumed the nonce first
+fn create_transaction_account<'info>(
+    multisig: &Account<'info, MultisigAccount>,
+    tx_index: u64,
+    transaction: &AccountInfo<'info>,
+    rent_payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+) -> Result<(Account<'info, TransactionAccount>, u8)> {
+    let multisig_key = multisig.key();
+    let nonce = tx_index.to_le_bytes();
+    let (address, bump) = Pubkey::find_program_address(&[b"tx", multisig_key.as_ref(), &nonce], &crate::ID);
+    if *transaction.key != address {
+        if *transaction.owner == crate::ID && !transaction.data_is_empty() {
+            return err!(MultisigWalletError::NonceAlreadyUsed);
+        }
+        return err!(MultisigWalletError::InvalidTransactionAccount);
+    }
+
+    let seeds = &[b"tx".as_ref(), multisig_key.as_ref(), &nonce, &[bump]];
+    create_program_account(rent_payer, transaction, system_program, space, &[&seeds[..]])?;
+
+    Ok((Account::try_from_unchecked(transaction)?, bump))
+}
+
+// Helper function to create a rent-exempt PDA owned by this program, paid by
+// `payer`. `signer_seeds` must include the new account's seeds, and the
+// payer's too when it's a PDA
+fn create_program_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    let accounts = [payer.clone(), account.clone(), system_program.clone()];
+    let funded = account.lamports();
+    if funded == 0 {
+        invoke_signed(
+            &system_instruction::create_account(payer.key, account.key, rent, space as u64, &crate::ID),
+            &accounts,
+            signer_seeds,
+        )?;
+    } else {
+        // Someone sent lamports to the address ahead of time; top it up and
+        // take it over instead of failing
+        if funded < rent {
+            let top_up = system_instruction::transfer(payer.key, account.key, rent - funded);
+            invoke_signed(&top_up, &accounts, signer_seeds)?;
+        }
+        invoke_signed(&system_instruction::allocate(account.key, space as u64), &accounts, signer_seeds)?;
+        invoke_signed(&system_instruction::assign(account.key, &crate::ID), &accounts, signer_seeds)?;
+    }
+
+    Ok(())
+}
+
+// Helper function to check a client's expected proposal hash, if given
+fn check_expected_hash(transaction: &TransactionAccount, expected_hash: Option<[u8; 32]>) -> Result<()> {
+    if expected_hash.is_some_and(|expected| expected != transaction.hash) {
+        return err!(MultisigWalletError::HashMismatch);
+    }
+
+    Ok(())
+}
+
+// Helper function with the status checks shared by everything that votes
+// on or executes a proposal, so each new proposal or multisig state is
+// checked in one place: the multisig must be active and unexpired, and the
+// proposal open (neither executed nor cancelled), unexpired and not stale
+fn assert_approvable(multisig: &impl ProposalGate, transaction: &TransactionAccount, clock: &Clock) -> Result<()> {
+    assert_open(multisig, transaction, clock, transaction.is_expired(clock.unix_timestamp))
+}
+
+// Helper function with the checks of `assert_approvable` for execution,
+// where a proposal that reached threshold in time may run out its grace
+// period after expiring
+fn assert_executable(multisig: &impl ProposalGate, transaction: &TransactionAccount, clock: &Clock) -> Result<()> {
+    let expired = transaction.execution_expired(clock.unix_timestamp, multisig.execution_grace_seconds());
+    assert_open(multisig, transaction, clock, expired)
+}
+
+// Helper function behind `assert_approvable` and `assert_executable`, given
+// whether the proposal's own expiry has passed
+fn assert_open(
+    multisig: &impl ProposalGate,
+    transaction: &TransactionAccount,
+    clock: &Clock,
+    expired: bool,
+) -> Result<()> {
+    multisig.require_active()?;
+
+    if transaction.executed {
+        return err!(MultisigWalletError::TransactionAlreadyExecuted);
+    }
+    if transaction.cancelled {
+        return err!(MultisigWalletError::TransactionCancelled);
+    }
+    // A multisig re-created at this address doesn't take over the proposals
+    // of the one closed before it
+    if transaction.generation != multisig.generation() {
+        return err!(MultisigWalletError::GenerationMismatch);
+    }
+
+    // The multisig-wide expiration, then the proposal's own
+    if let Some(expiration) = multisig.expiration_timestamp() {
+        if clock.unix_timestamp > expiration {
+            return err!(MultisigWalletError::TransactionExpired);
+        }
+    }
+    if expired {
+        return err!(MultisigWalletError::TransactionExpired);
+    }
+
+    // The signer set must not have been invalidated since the proposal
+    if transaction.owner_set_seqno != multisig.owner_set_seqno() {
+        return err!(MultisigWalletError::StaleTransaction);
+    }
+
+    Ok(())
+}
+
+// Helper function shared by both approval instructions
+fn record_approval(
+    multisig: &mut Account<MultisigAccount>,
+    transaction: &mut Account<TransactionAccount>,
+    signer: Pubkey,
+    memo: String,
+    idempotent: bool,
+) -> Result<()> {
+    assert_approvable(multisig, transaction, &Clock::get()?)?;
+
+    // Check the signer against the approvers frozen at propose time, so
+    // members added since can't approve, and against the current signers,
+    // so those removed or rotated out since can't either
+    if !transaction.is_eligible_approver(&signer) || !is_signer_in_multisig(&multisig.signers, &signer) {
+        return err!(MultisigWalletError::SignerNotFound);
+    }
+
+    // Check if signer has already approved; a retried idempotent approval
+    // has nothing left to do
+    if transaction.signers.contains(&signer) {
+        if idempotent {
+            msg!("{} already approved proposal {}", signer, transaction.tx_index);
+            return Ok(());
+        }
+        return err!(MultisigWalletError::AlreadyApproved);
+    }
+
+    // A member voting again, after a key rotation dropped its approval,
+    // must wait out the vote cooldown first
+    transaction.note_vote(&signer, Clock::get()?.slot, multisig.vote_cooldown_slots)?;
+
+    // Add signer to approvals
+    let was_approved = multisig.is_approved(transaction);
+    transaction.signers.push(signer);
+    transaction.approval_memos.push(memo.clone());
+    // Proposals made before approvals were indexed stay unindexed
+    if transaction.approval_ordinal > 0 {
+        transaction.index_approval(&signer);
+    }
+
+    let required = multisig.threshold_for(transaction);
+    transaction.note_approvals(required, Clock::get()?.unix_timestamp);
+
+    // Report progress, so wallets simulating the approval can show it
+    let approvals = transaction.approval_count();
+    let remaining = required.saturating_sub(approvals);
+    let expires_in = match transaction.expires_at {
+        Some(expires_at) => Some(expires_at.saturating_sub(Clock::get()?.unix_timestamp)),
+        None => None,
+    };
+    match expires_in {
+        Some(seconds) => msg!(
+            "approval {} of {}, {} more needed, expires in {} seconds",
+            approvals,
+            required,
+            remaining,
+            seconds
+        ),
+        None => msg!("approval {} of {}, {} more needed, no expiry", approvals, required, remaining),
+    }
+    if let Some(action) = &transaction.action {
+        msg!("action: {}", action.summary());
+    }
+
+    emit!(TransactionApproved {
+        multisig: multisig.key(),
+        tx_index: transaction.tx_index,
+        transaction_hash: transaction.hash,
+        priority: transaction.priority,
+        signer,
+        memo,
+        approvals: approvals as u8,
+        threshold: required as u8,
+        expires_in,
+    });
+    emit_threshold_reached(multisig, transaction, was_approved);
+    note_member_action(multisig, signer, Clock::get()?.unix_timestamp);
+
+    Ok(())
+}
+
+// Helper function to emit ThresholdReached when a proposal goes from not
+// approved to approved, by its effective approvals or a token vote quorum.
+// Stateless, so it fires once per crossing: again only if the count first
+// dropped, such as after approvers were removed
+fn emit_threshold_reached(multisig: &MultisigAccount, transaction: &TransactionAccount, was_approved: bool) {
+    if was_approved || !multisig.is_approved(transaction) {
+        return;
+    }
+
+    let required = multisig.threshold_for(transaction);
+    let approvals = multisig.effective_approvals(transaction);
+    let timelock = multisig.priority_level(transaction.priority).map_or(0, |level| level.timelock_seconds);
+    emit!(ThresholdReached {
+        multisig: transaction.multisig,
+        tx_index: transaction.tx_index,
+        transaction_hash: transaction.hash,
+        priority: transaction.priority,
+        approvals: approvals as u8,
+        threshold: required as u8,
+        timelock_ends_at: transaction
+            .approved_at
+            .filter(|_| timelock > 0)
+            .map(|approved_at| approved_at.saturating_add(timelock)),
+        expires_at: transaction.expires_at,
+    });
+}
+
+// Helper function shared by execute_transaction, execute_readonly and
+// execute_transaction_by_index
+fn execute<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    transaction: &mut Account<'info, TransactionAccount>,
+    executor_account: Option<AccountInfo<'info>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    read_only: bool,
+) -> Result<()> {
+    let executor = executor_account.as_ref().map(|executor| *executor.key);
+    let prepared = prepare_execution(multisig, transaction, executor, remaining_accounts, read_only, false)?;
+    perform_execution(multisig, transaction, executor_account, remaining_accounts, prepared)
+}
+
+// Helper function to run every check an execution makes before invoking
+// anything, and build the instructions it will invoke. It changes nothing
+// outside the multisig's in-memory state, so a caller may restore that and
+// treat a failure as a recorded attempt. `staged` is set for
+// complete_execution, whose staging already checked the cluster and the
+// dependency
+fn prepare_execution<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    transaction: &Account<'info, TransactionAccount>,
+    executor: Option<Pubkey>,
+    remaining_accounts: &[AccountInfo<'info>],
+    read_only: bool,
+    staged: bool,
+) -> Result<PreparedExecution> {
+    assert_executable(multisig, transaction, &Clock::get()?)?;
+
+    // A staged proposal is locked for its stager's complete_execution
+    if transaction.staged && !staged {
+        return err!(MultisigWalletError::ExecutionStaged);
+    }
+
+    // Joint proposals need their partner's signature, see execute_joint
+    if transaction.joint_with.is_some() {
+        return err!(MultisigWalletError::JointExecutionRequired);
+    }
+
+    // Execution branches on the stored kind, so it must still describe the
+    // payload
+    transaction.check_kind()?;
+
+    // A proposal stamped with a cluster only runs where the program config
+    // declares that cluster
+    if !staged {
+        check_cluster(transaction, remaining_accounts)?;
+    }
+
+    // Read-only proposals only run through execute_readonly and vice versa,
+    // so approvers get the mode they signed off on
+    match (transaction.read_only, read_only) {
+        (true, false) => return err!(MultisigWalletError::ReadOnlyExecutionRequired),
+        (false, true) => return err!(MultisigWalletError::TransactionNotReadOnly),
+        _ => {}
+    }
+
+    // Check if there are enough approvals for the proposal's priority, or
+    // enough governance tokens voted for it. Approvers removed since the
+    // proposal no longer count
+    if !multisig.is_approved(transaction) {
+        return err!(MultisigWalletError::InsufficientApprovals);
+    }
+
+    // Large payloads also need their confirmation, see confirm_execution
+    if transaction.confirmation_required && transaction.confirmer == 0 {
+        return err!(MultisigWalletError::ConfirmationRequired);
+    }
+
+    // The proposal this one depends on must have executed first
+    if !staged {
+        check_dependency(multisig, transaction, remaining_accounts, Clock::get()?.unix_timestamp)?;
+    }
+
+    // Check the executor against the execution policy. The automation
+    // authority is only checked against the multisig's current setting, so
+    // revoking it applies to pending proposals at once
+    let is_member = executor.is_some_and(|key| is_signer_in_multisig(&multisig.signers, &key));
+    let automated = !is_member && executor.is_some() && executor == multisig.automation_authority;
+    if automated && !transaction.automatable {
+        return err!(MultisigWalletError::NotAutomatable);
+    }
+    if multisig.execution_policy == ExecutionPolicy::SignersOnly && !is_member && !automated {
+        return err!(MultisigWalletError::ExecutorNotAllowed);
+    }
+    if let Some(member) = executor.filter(|_| is_member) {
+        note_member_action(multisig, member, Clock::get()?.unix_timestamp);
+    }
+
+    // Check the priority's timelock has run since the threshold was reached.
+    // The stored priority decides it, never a caller-supplied one.
+    let timelock = multisig.priority_level(transaction.priority).map_or(0, |level| level.timelock_seconds);
+    if timelock > 0 {
+        let approved_at = transaction.approved_at.ok_or(MultisigWalletError::ExecutionTimelockActive)?;
+        if Clock::get()?.unix_timestamp < approved_at.saturating_add(timelock) {
+            return err!(MultisigWalletError::ExecutionTimelockActive);
+        }
+    }
+
+    // In ordered mode, only the next index may execute
+    if multisig.ordered_execution {
+        if transaction.tx_index != multisig.next_ordered_index() {
+            return err!(MultisigWalletError::OutOfOrderExecution);
+        }
+        multisig.last_executed_index = Some(transaction.tx_index);
+    }
+
+    let multisig_key = multisig.key();
+    let transaction_key = transaction.key();
+    let vault_bump = multisig.vault_bump;
+    let vault_key = vault_address(&multisig_key, vault_bump)?;
+    let (authority, authority_bump) = match transaction.authority_kind {
+        AuthorityKind::Multisig => (multisig_key, multisig.bump),
+        AuthorityKind::Vault(index) => indexed_vault_address(&multisig_key, vault_bump, index)?,
+    };
+
+    // Count what typed actions send out of the vault against its rate limits
+    // and epoch caps
+    if let Some((mint, amount)) = transaction.action.as_ref().and_then(TypedAction::outflow) {
+        multisig.consume_outflow(mint, amount, Clock::get()?.unix_timestamp)?;
+    }
+
+    // Typed actions are rebuilt from their stored parameters; opaque
+    // proposals replay the stored instruction against matching accounts.
+    // Config changes invoke nothing, see perform_execution
+    let mut instructions = match (&transaction.kind, &transaction.action) {
+        (TransactionKind::ConfigChange(_), _) => {
+            // Like configure_multisig, a config change needs every current
+            // member, not just the threshold
+            if !multisig.signers.iter().all(|member| transaction.signers.contains(member)) {
+                return err!(MultisigWalletError::NotAllSignersApproved);
+            }
+            Vec::new()
+        }
+        (TransactionKind::TypedAction(_), Some(action)) if action.is_escrowed() => {
+            // Escrowed funds were locked once the threshold was reached, and
+            // are paid out of the escrow rather than the vault
+            if !transaction.escrowed {
+                return err!(MultisigWalletError::EscrowNotFunded);
+            }
+            let (escrow, _) = find_created_account_address(b"escrow", &transaction_key);
+            let escrow_balance = find_account(remaining_accounts, &escrow)?.lamports();
+            build_escrow_payout(action, &escrow, &vault_key, escrow_balance, true)?
+        }
+        (TransactionKind::TypedAction(_), Some(action)) => {
+            check_action_preconditions(action, remaining_accounts, &vault_key)?;
+
+            // Count capped mints against the current period
+            if let TypedAction::MintTo { mint, amount, .. } = action {
+                if let Some(cap) = multisig.mint_caps.iter_mut().find(|cap| cap.mint == *mint) {
+                    cap.consume(*amount, Clock::get()?.unix_timestamp)?;
+                }
+            }
+
+            let instructions =
+                build_action_instructions(action, &multisig_key, &vault_key, &transaction_key, remaining_accounts)?;
+
+            // Like an opaque call, a registered action can't be metered, so
+            // it can't have a rate-limited or capped vault sign or be written
+            let touches_vault = |instruction: &Instruction| {
+                instruction.accounts.iter().any(|meta| meta.pubkey == vault_key && (meta.is_signer || meta.is_writable))
+            };
+            if matches!(action, TypedAction::Registered { .. })
+                && multisig.meters_outflows()
+                && instructions.iter().any(touches_vault)
+            {
+                return err!(MultisigWalletError::OpaqueCallRateLimited);
+            }
+            instructions
+        }
+        (TransactionKind::General, None) => {
+            // Deserialize account metas
+            let account_metas = transaction.account_metas();
+
+            // The allowlist may have changed since the proposal
+            multisig.check_allowlist(&transaction.program_id, &transaction.data)?;
+
+            // Opaque calls can't be metered, so a rate-limited or capped
+            // vault can't sign for one or be handed over as writable
+            let signs_as_vault = matches!(transaction.authority_kind, AuthorityKind::Vault(_));
+            let is_vault = |key: &Pubkey| *key == vault_key || (signs_as_vault && *key == authority);
+            if multisig.meters_outflows()
+                && account_metas
+                    .iter()
+                    .any(|meta| is_vault(&meta.pubkey) && (meta.is_signer || meta.is_writable))
+            {
+                return err!(MultisigWalletError::OpaqueCallRateLimited);
+            }
+
+            // Prevent recursive CPI to this program, except for a nested
+            // multisig approving another multisig's proposal or spending
+            // its budget
+            if transaction.program_id == crate::ID
+                && !is_nested_call(&transaction.data, &account_metas, &multisig_key)
+            {
+                return err!(MultisigWalletError::RecursiveCallNotAllowed);
+            }
+
+            // The proposal's ephemeral signers sign alongside its authority
+            let mut authorities = vec![authority];
+            authorities.extend(
+                (0..transaction.ephemeral_signers).map(|n| find_ephemeral_signer_address(&transaction_key, n).0),
+            );
+            let instruction =
+                build_opaque_instruction(transaction, &account_metas, remaining_accounts, &authorities, read_only)?;
+            vec![instruction]
+        }
+        _ => return err!(MultisigWalletError::TransactionKindMismatch),
+    };
+
+    // Record the execution with the Memo program first, so the audit
+    // trail can't be dropped without failing the whole execution
+    if transaction.memo.is_some() || multisig.memo_on_execute {
+        let memo = execution_memo(&multisig_key, transaction.tx_index, transaction.memo.as_deref());
+        instructions.insert(
+            0,
+            Instruction {
+                program_id: spl_memo::ID,
+                accounts: vec![],
+                data: memo.into_bytes(),
+            },
+        );
+    }
+
+    Ok(PreparedExecution { automated, authority, authority_bump, instructions })
+}
+
+// Helper function to carry out an execution `prepare_execution` checked:
+// collect the protocol fee, invoke the instructions, check the outcome and
+// mark the proposal executed
+fn perform_execution<'info>(
+    multisig: &mut Account<'info, MultisigAccount>,
+    transaction: &mut Account<'info, TransactionAccount>,
+    executor_account: Option<AccountInfo<'info>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    prepared: PreparedExecution,
+) -> Result<()> {
+    let PreparedExecution { automated, authority, authority_bump, instructions } = prepared;
+    let executor = executor_account.as_ref().map(|executor| *executor.key);
+    let multisig_key = multisig.key();
+    let transaction_key = transaction.key();
+    let vault_bump = multisig.vault_bump;
+    let vault_key = vault_address(&multisig_key, vault_bump)?;
+
+    // Get PDA signers: the authority chosen at propose time (the multisig
+    // itself or one of its vaults) and, for actions creating an account, the
+    // new account's PDA
+    let create_key = multisig.create_key;
+    let seeds = &[b"multisig".as_ref(), create_key.as_ref(), &[multisig.bump]];
+    let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[vault_bump]];
+    let vault_index = match transaction.authority_kind {
+        AuthorityKind::Vault(index) => index.to_le_bytes(),
+        AuthorityKind::Multisig => [0; 2],
+    };
+    let indexed_vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &vault_index, &[authority_bump]];
+    let authority_seeds: &[&[u8]] = match transaction.authority_kind {
+        AuthorityKind::Multisig => &seeds[..],
+        AuthorityKind::Vault(0) => &vault_seeds[..],
+        AuthorityKind::Vault(_) => &indexed_vault_seeds[..],
+    };
+    let mut signer_seeds: Vec<&[&[u8]]> = vec![authority_seeds];
+
+    let created_seed = transaction.action.as_ref().and_then(TypedAction::created_account_seed);
+    let created_bump = created_seed
+        .map(|seed| find_created_account_address(seed, &transaction_key).1)
+        .unwrap_or_default();
+    let created_seeds = &[created_seed.unwrap_or_default(), transaction_key.as_ref(), &[created_bump]];
+    if created_seed.is_some() {
+        signer_seeds.push(&created_seeds[..]);
+    }
+
+    // And the proposal's ephemeral signers, by index
+    let ephemeral_bumps: Vec<([u8; 1], [u8; 1])> = (0..transaction.ephemeral_signers)
+        .map(|n| (n.to_le_bytes(), [find_ephemeral_signer_address(&transaction_key, n).1]))
+        .collect();
+    let ephemeral_seeds: Vec<[&[u8]; 4]> = ephemeral_bumps
+        .iter()
+        .map(|(n, bump)| [b"ephemeral".as_ref(), transaction_key.as_ref(), n.as_ref(), bump.as_ref()])
+        .collect();
+    signer_seeds.extend(ephemeral_seeds.iter().map(|seeds| &seeds[..]));
+
+    // Collect the protocol fee before the CPI; if it can't be paid, nothing
+    // runs and the proposal stays pending
+    pay_protocol_fee(multisig, transaction, executor_account.as_ref(), remaining_accounts)?;
+
+    // Snapshot asserted balances before the CPI
+    let balances_before = transaction
+        .assertions
+        .iter()
+        .map(|assertion| assertion.read_balance(remaining_accounts))
+        .collect::<Result<Vec<u64>>>()?;
+
+    // Snapshot the vaults the execution could draw from, to keep them
+    // rent-exempt
+    let mut vaults = vec![vault_key];
+    if authority != vault_key && authority != multisig_key {
+        vaults.push(authority);
+    }
+    let vaults_before = vault_balances(remaining_accounts, &vaults);
+
+    // Execute transaction via CPI, measuring it against the declared compute
+    // where the runtime reports the units left. An executor that didn't
+    // request the declared budget is stopped here rather than deep in the CPI
+    let units_before = remaining_compute_units();
+    if let (Some(compute), Some(remaining)) = (transaction.compute, units_before) {
+        if !compute.has_headroom(remaining) {
+            msg!("{} compute units left, {} declared", remaining, compute.units);
+            return err!(MultisigWalletError::InsufficientComputeRemaining);
+        }
+    }
+    for instruction in &instructions {
+        invoke_signed(instruction, remaining_accounts, &signer_seeds)?;
+    }
+    if let TransactionKind::ConfigChange(action) = &transaction.kind {
+        apply_config_action(multisig, action.clone(), remaining_accounts)?;
+    }
+    if let (Some(compute), Some(before), Some(after)) = (transaction.compute, units_before, remaining_compute_units()) {
+        let consumed_units = before.saturating_sub(after);
+        if compute.is_near_limit(consumed_units) {
+            emit!(ComputeNearLimit {
+                multisig: multisig_key,
+                tx_index: transaction.tx_index,
+                declared_units: compute.units,
+                consumed_units,
+            });
+        }
+    }
+
+    // Fail (and roll back the CPI) if a vault was drawn below rent exemption
+    check_vault_rent(remaining_accounts, &vaults_before, transaction.sweep)?;
+
+    // Fail (and roll back the CPI) if any asserted balance moved too little
+    for (i, (assertion, before)) in transaction.assertions.iter().zip(balances_before).enumerate() {
+        let after = assertion.read_balance(remaining_accounts)?;
+        let delta = after as i128 - before as i128;
+        if delta < assertion.min_delta as i128 {
+            msg!(
+                "Balance assertion {} failed for {}: delta {} below minimum {}",
+                i,
+                assertion.account,
+                delta,
+                assertion.min_delta
+            );
+            return err!(MultisigWalletError::BalanceAssertionFailed);
+        }
+    }
+
+    // Count categorized typed outflows towards their category's total. The
+    // stats account must be passed along so none go unrecorded, but it only
+    // counts once `reset_category_stats` has created it
+    let outflow = transaction.action.as_ref().and_then(TypedAction::outflow);
+    if let Some((mint, amount)) = outflow.filter(|_| transaction.category != 0) {
+        let (stats_key, _) = Pubkey::find_program_address(&[b"category_stats", multisig_key.as_ref()], &crate::ID);
+        let stats = find_account(remaining_accounts, &stats_key)?;
+        if !stats.data_is_empty() {
+            let mut stats = Account::<CategoryStats>::try_from(stats)?;
+            stats.record(transaction.category, mint, amount)?;
+            stats.exit(&crate::ID)?;
+        }
+    }
+
+    // Keep the token registry in step with typed actions opening or closing
+    // the vault's wrapped SOL account, when it's passed along
+    let opens_wsol = match &transaction.action {
+        Some(TypedAction::WrapSol { .. }) => Some(true),
+        Some(TypedAction::UnwrapSol) => Some(false),
+        _ => None,
+    };
+    if let Some(opened) = opens_wsol {
+        let (registry_key, _) =
+            Pubkey::find_program_address(&[b"token_registry", multisig_key.as_ref()], &crate::ID);
+        let registry = remaining_accounts.iter().find(|account| *account.key == registry_key);
+        if let Some(registry) = registry.filter(|registry| !registry.data_is_empty()) {
+            let wsol = get_associated_token_address_with_program_id(&vault_key, &native_mint::ID, &spl_token::ID);
+            let mut registry = Account::<TokenRegistry>::try_from(registry)?;
+            if opened {
+                registry.register(wsol, native_mint::ID)?;
+            } else {
+                registry.unregister(&wsol);
+            }
+            registry.exit(&crate::ID)?;
+        }
+    }
+
+    if let Some(TypedAction::SetAuthority { target, authority_type, new_authority, .. }) = &transaction.action {
+        emit!(AuthorityChanged {
+            multisig: multisig_key,
+            tx_index: transaction.tx_index,
+            transaction_hash: transaction.hash,
+            priority: transaction.priority,
+            target: *target,
+            authority_type: *authority_type,
+            old_authority: vault_key,
+            new_authority: *new_authority,
+        });
+    }
+
+    // Reimburse the proposal's rent from the vault when enabled and
+    // affordable. Only executions qualify, so it can't be farmed by
+    // proposing and cancelling
+    if multisig.reimburse_proposal_rent {
+        let rent_payer = find_account(remaining_accounts, &transaction.rent_payer)?;
+        let vault = find_account(remaining_accounts, &vault_key)?;
+        let amount = transaction.to_account_info().lamports();
+        if vault.lamports() >= amount.saturating_add(Rent::get()?.minimum_balance(vault.data_len())) {
+            invoke_signed(
+                &system_instruction::transfer(&vault_key, rent_payer.key, amount),
+                remaining_accounts,
+                &[&vault_seeds[..]],
+            )?;
+            transaction.rent_reimbursed = true;
+        }
+    }
+
+    // Keep a receipt that outlives the transaction account, if asked for
+    create_execution_receipt(
+        multisig,
+        transaction,
+        executor_account.as_ref(),
+        remaining_accounts,
+        Clock::get()?.unix_timestamp,
+    )?;
+
+    // Mark transaction as executed
+    transaction.executed = true;
+    multisig.note_proposal_closed(transaction.tx_index);
+
+    emit!(TransactionExecuted {
+        multisig: multisig_key,
+        tx_index: transaction.tx_index,
+        transaction_hash: transaction.hash,
+        priority: transaction.priority,
+        executor,
+        automated,
+        category: transaction.category,
+        kind: transaction.kind.clone(),
+        remaining_compute_units: units_before,
+    });
+
+    Ok(())
+}
+
+// Helper function to check a proposal executes on the cluster it was made
+// for, as the program config now declares it. Proposals made while no
+// cluster was declared run anywhere
+fn check_cluster(transaction: &TransactionAccount, remaining_accounts: &[AccountInfo]) -> Result<()> {
+    if transaction.cluster == Cluster::Unspecified {
+        return Ok(());
+    }
+
+    let (config_key, _) = Pubkey::find_program_address(&[b"program_config"], &crate::ID);
+    let config = load_program_config(find_account(remaining_accounts, &config_key)?)?;
+    if config.cluster != transaction.cluster {
+        return err!(MultisigWalletError::ClusterMismatch);
+    }
+
+    Ok(())
+}
+
+// Helper function to pay the protocol fee `transaction` was stamped with to
+// the program config's fee collector, from the vault or the executor as the
+// multisig prefers. The program config, the collector and a paying vault
+// must be passed along with the remaining accounts
+fn pay_protocol_fee<'info>(
+    multisig: &Account<'info, MultisigAccount>,
+    transaction: &TransactionAccount,
+    executor: Option<&AccountInfo<'info>>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let lamports = transaction.protocol_fee;
+    if lamports == 0 {
+        return Ok(());
+    }
+
+    let (config_key, _) = Pubkey::find_program_address(&[b"program_config"], &crate::ID);
+    let config = Account::<ProgramConfig>::try_from(find_account(remaining_accounts, &config_key)?)?;
+    let collector = find_account(remaining_accounts, &config.fee_collector)?;
+
+    let multisig_key = multisig.key();
+    let payer = match multisig.fee_payer {
+        FeePayer::Vault => {
+            let vault_key = vault_address(&multisig_key, multisig.vault_bump)?;
+            let vault = find_account(remaining_accounts, &vault_key)?;
+            let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[multisig.vault_bump]];
+            invoke_signed(
+                &system_instruction::transfer(&vault_key, collector.key, lamports),
+                &[vault.clone(), collector.clone()],
+                &[&vault_seeds[..]],
+            )?;
+            vault_key
+        }
+        FeePayer::Executor => {
+            let executor = executor.ok_or(MultisigWalletError::FeePayerRequired)?;
+            invoke(
+                &system_instruction::transfer(executor.key, collector.key, lamports),
+                &[executor.clone(), collector.clone()],
+            )?;
+            *executor.key
+        }
+    };
+
+    emit!(ProtocolFeePaid {
+        multisig: multisig_key,
+        tx_index: transaction.tx_index,
+        payer,
+        collector: *collector.key,
+        lamports,
+    });
+
+    Ok(())
+}
+
+// Helper function to keep a receipt of `transaction`'s execution when it was
+// proposed under a program config asking for one. Its rent comes from
+// whoever pays the multisig's protocol fee. The receipt PDA, the System
+// Program and a paying vault must be passed along with the remaining accounts
+fn create_execution_receipt<'info>(
+    multisig: &Account<'info, MultisigAccount>,
+    transaction: &TransactionAccount,
+    executor: Option<&AccountInfo<'info>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    executed_at: i64,
+) -> Result<()> {
+    if !transaction.receipt_required {
+        return Ok(());
+    }
+
+    let multisig_key = multisig.key();
+    let index = transaction.tx_index.to_le_bytes();
+    let (receipt_key, bump) = find_receipt_address(&multisig_key, transaction.tx_index);
+    let info = find_account(remaining_accounts, &receipt_key)?;
+    let system_program = find_account(remaining_accounts, &anchor_lang::system_program::ID)?;
+    let receipt_seeds = &[b"receipt".as_ref(), multisig_key.as_ref(), &index, &[bump]];
+
+    // A receipt left by an earlier multisig at this address is written over
+    if info.data_is_empty() {
+        let space = ExecutionReceipt::SPACE;
+        match multisig.fee_payer {
+            FeePayer::Vault => {
+                let vault = find_account(remaining_accounts, &vault_address(&multisig_key, multisig.vault_bump)?)?;
+                let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[multisig.vault_bump]];
+                create_program_account(vault, info, system_program, space, &[&receipt_seeds[..], &vault_seeds[..]])?;
+            }
+            FeePayer::Executor => {
+                let executor = executor.ok_or(MultisigWalletError::FeePayerRequired)?;
+                create_program_account(executor, info, system_program, space, &[&receipt_seeds[..]])?;
+            }
+        }
+    }
+
+    let (mint, amount) = transaction.action.as_ref().and_then(TypedAction::outflow).unwrap_or_default();
+    let mut receipt = Account::<ExecutionReceipt>::try_from_unchecked(info)?;
+    receipt.multisig = multisig_key;
+    receipt.tx_index = transaction.tx_index;
+    receipt.generation = transaction.generation;
+    receipt.transaction_hash = transaction.hash;
+    receipt.executor = executor.map(|executor| *executor.key);
+    receipt.executed_at = executed_at;
+    receipt.category = transaction.category;
+    receipt.mint = mint;
+    receipt.amount = amount;
+    receipt.bump = bump;
+    receipt.exit(&crate::ID)
+}
+
+// Helper function to check the proposal `transaction` depends on has
+// executed. One that never can (cancelled, expired, stale, or closed since)
+// fails with `DependencyFailed` rather than `DependencyNotExecuted`, since
+// retrying won't help
+fn check_dependency<'info>(
+    multisig: &Account<'info, MultisigAccount>,
+    transaction: &TransactionAccount,
+    remaining_accounts: &[AccountInfo<'info>],
+    now: i64,
+) -> Result<()> {
+    let index = match transaction.depends_on {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+    let (key, _) = find_transaction_address(&multisig.key(), index);
+    let info = find_account(remaining_accounts, &key)?;
+
+    // Executed proposals are only closed once they have a receipt, so a
+    // closed one without a receipt failed
+    if *info.owner != crate::ID || info.data_is_empty() {
+        let (receipt_key, _) = find_receipt_address(&multisig.key(), index);
+        let receipt = remaining_accounts.iter().find(|account| *account.key == receipt_key);
+        if let Some(receipt) = receipt.filter(|receipt| *receipt.owner == crate::ID && !receipt.data_is_empty()) {
+            if Account::<ExecutionReceipt>::try_from(receipt)?.generation == multisig.generation {
+                return Ok(());
+            }
+        }
+        msg!("Dependency {} was closed without executing", index);
+        return err!(MultisigWalletError::DependencyFailed);
+    }
+    let dependency = Account::<TransactionAccount>::try_from(info)?;
+    if dependency.executed {
+        return Ok(());
+    }
+    let stale = dependency.owner_set_seqno != multisig.owner_set_seqno || dependency.generation != multisig.generation;
+    if dependency.cancelled || stale || dependency.execution_expired(now, multisig.execution_grace_seconds) {
+        msg!("Dependency {} can no longer execute", index);
+        return err!(MultisigWalletError::DependencyFailed);
+    }
+
+    msg!("Dependency {} hasn't executed yet", index);
+    err!(MultisigWalletError::DependencyNotExecuted)
+}
+
+// Helper function to read the balances of the given vaults among the
+// remaining accounts. A vault that wasn't passed can't be drawn from
+fn vault_balances(remaining_accounts: &[AccountInfo], vaults: &[Pubkey]) -> Vec<(Pubkey, u64)> {
+    remaining_accounts
+        .iter()
+        .filter(|account| vaults.contains(account.key))
+        .map(|account| (*account.key, account.lamports()))
+        .collect()
+}
+
+// Helper function to refuse an execution that drew a vault below the
+// rent-exempt minimum for its data, which would let the runtime reclaim it.
+// A sweep may empty it entirely instead
+fn check_vault_rent(remaining_accounts: &[AccountInfo], before: &[(Pubkey, u64)], sweep: bool) -> Result<()> {
+    let rent = Rent::get()?;
+    for (key, before) in before {
+        let vault = find_account(remaining_accounts, key)?;
+        let after = vault.lamports();
+        let minimum = rent.minimum_balance(vault.data_len());
+        if after < *before && after < minimum && !(sweep && after == 0) {
+            msg!("Vault {} left with {} lamports, below the rent-exempt {}", key, after, minimum);
+            return err!(MultisigWalletError::WouldBreakRentExemption);
+        }
+    }
+    Ok(())
+}
+
+// Helper function to read the compute units left in the transaction. Only
+// the on-chain runtime reports them, so native builds get `None`
+#[cfg(target_os = "solana")]
+fn remaining_compute_units() -> Option<u64> {
+    extern "C" {
+        fn sol_remaining_compute_units() -> u64;
+    }
+    Some(unsafe { sol_remaining_compute_units() })
+}
+
+#[cfg(not(target_os = "solana"))]
+fn remaining_compute_units() -> Option<u64> {
+    None
+}
+
+// Helper function to build an opaque proposal's instruction from its stored
+// metas, checked against the remaining accounts. Only `authorities`, the PDAs
+// signed for, may be signers
+fn build_opaque_instruction(
+    transaction: &TransactionAccount,
+    account_metas: &[AccountMeta],
+    remaining_accounts: &[AccountInfo],
+    authorities: &[Pubkey],
+    read_only: bool,
+) -> Result<Instruction> {
+    // Validate remaining accounts
+    if remaining_accounts.len() < account_metas.len() {
+        msg!(
+            "Insufficient accounts: expected {}, provided {}",
+            account_metas.len(),
+            remaining_accounts.len()
+        );
+        return err!(MultisigWalletError::InsufficientAccounts);
+    }
+
+    // Create remaining accounts array
+    let mut invoke_accounts = Vec::with_capacity(account_metas.len());
+    for (meta, account) in account_metas.iter().zip(remaining_accounts) {
+        if account.key() != meta.pubkey {
+            msg!(
+                "Account meta {} mismatch: expected {}, provided {}",
+                invoke_accounts.len(),
+                meta.pubkey,
+                account.key()
+            );
+            return err!(MultisigWalletError::InvalidAccountMetas);
+        }
+        // Only the authorities chosen at propose time sign
+        if meta.is_signer && !authorities.contains(&meta.pubkey) {
+            msg!(
+                "Account meta {} signer mismatch: proposal signs as {:?}, instruction expects {}",
+                invoke_accounts.len(),
+                authorities,
+                meta.pubkey
+            );
+            return err!(MultisigWalletError::InvalidAccountMetas);
+        }
+        // An account the proposal writes to must come in writable, or the
+        // CPI fails deep inside the target. Signers need no such check: the
+        // only ones are the PDAs signed for
+        if meta.is_writable && !read_only && !account.is_writable {
+            msg!(
+                "Account meta {} ({}) must be writable but was passed read-only",
+                invoke_accounts.len(),
+                meta.pubkey
+            );
+            return err!(MultisigWalletError::AccountPrivilegeMismatch);
+        }
+        // Proposals older than check_self_references looks back are only
+        // recognized by their contents
+        if meta.is_writable && *account.owner == crate::ID {
+            let proposal = Account::<TransactionAccount>::try_from(account);
+            if proposal.is_ok_and(|proposal| proposal.multisig == transaction.multisig) {
+                msg!("Account meta {} ({}) is one of the multisig's proposals", invoke_accounts.len(), meta.pubkey);
+                return err!(MultisigWalletError::SelfReferentialProposal);
+            }
+        }
+        invoke_accounts.push(AccountMeta {
+            pubkey: account.key(),
+            is_signer: meta.is_signer,
+            is_writable: meta.is_writable && !read_only,
+        });
+    }
+
+    Ok(Instruction {
+        program_id: transaction.program_id,
+        accounts: invoke_accounts,
+        data: transaction.data.clone(),
+    })
+}
+
+// Helper function running one side's checks of a joint execution, as
+// `execute` would for a proposal of its own. Returns the side's signing
+// authority and its bump
+fn check_joint_side(
+    multisig: &mut Account<MultisigAccount>,
+    transaction: &TransactionAccount,
+    executor: Option<Pubkey>,
+    clock: &Clock,
+) -> Result<(Pubkey, u8)> {
+    assert_executable(multisig, transaction, clock)?;
+    if !multisig.is_approved(transaction) {
+        return err!(MultisigWalletError::InsufficientApprovals);
+    }
+    if transaction.confirmation_required && transaction.confirmer == 0 {
+        return err!(MultisigWalletError::ConfirmationRequired);
+    }
+
+    // Only general proposals run as one joint CPI
+    transaction.check_kind()?;
+    if transaction.kind != TransactionKind::General {
+        return err!(MultisigWalletError::TransactionKindMismatch);
+    }
+
+    // The automation authority can't execute joint proposals, so under
+    // `SignersOnly` the executor must be a member of this side
+    let is_member = executor.is_some_and(|key| is_signer_in_multisig(&multisig.signers, &key));
+    if multisig.execution_policy == ExecutionPolicy::SignersOnly && !is_member {
+        return err!(MultisigWalletError::ExecutorNotAllowed);
+    }
+    if let Some(member) = executor.filter(|_| is_member) {
+        note_member_action(multisig, member, clock.unix_timestamp);
+    }
+
+    let timelock = multisig.priority_level(transaction.priority).map_or(0, |level| level.timelock_seconds);
+    if timelock > 0 {
+        let approved_at = transaction.approved_at.ok_or(MultisigWalletError::ExecutionTimelockActive)?;
+        if clock.unix_timestamp < approved_at.saturating_add(timelock) {
+            return err!(MultisigWalletError::ExecutionTimelockActive);
+        }
+    }
+
+    if multisig.ordered_execution {
+        if transaction.tx_index != multisig.next_ordered_index() {
+            return err!(MultisigWalletError::OutOfOrderExecution);
+        }
+        multisig.last_executed_index = Some(transaction.tx_index);
+    }
+
+    multisig.check_allowlist(&transaction.program_id, &transaction.data)?;
+
+    let multisig_key = multisig.key();
+    let vault_key = vault_address(&multisig_key, multisig.vault_bump)?;
+    let (authority, authority_bump) = match transaction.authority_kind {
+        AuthorityKind::Multisig => (multisig_key, multisig.bump),
+        AuthorityKind::Vault(index) => indexed_vault_address(&multisig_key, multisig.vault_bump, index)?,
+    };
+    let is_vault = |key: &Pubkey| *key == vault_key || (*key == authority && authority != multisig_key);
+    if multisig.meters_outflows()
+        && transaction.account_metas()
+            .iter()
+            .any(|meta| is_vault(&meta.pubkey) && (meta.is_signer || meta.is_writable))
+    {
+        return err!(MultisigWalletError::OpaqueCallRateLimited);
+    }
+
+    Ok((authority, authority_bump))
+}
+
+// Helper function to build the signer seeds of the PDA a multisig's proposal
+// signs as, given that PDA's bump
+fn authority_seeds(multisig: &Account<MultisigAccount>, kind: AuthorityKind, bump: u8) -> Vec<Vec<u8>> {
+    let multisig_key = multisig.key();
+    match kind {
+        AuthorityKind::Multisig => {
+            vec![b"multisig".to_vec(), multisig.create_key.to_bytes().to_vec(), vec![multisig.bump]]
+        }
+        AuthorityKind::Vault(0) => vec![b"vault".to_vec(), multisig_key.to_bytes().to_vec(), vec![bump]],
+        AuthorityKind::Vault(index) => vec![
+            b"vault".to_vec(),
+            multisig_key.to_bytes().to_vec(),
+            index.to_le_bytes().to_vec(),
+            vec![bump],
+        ],
+    }
+}
+
+// Helper function to locate a specific account among the remaining accounts
+fn find_account<'a, 'info>(remaining_accounts: &'a [AccountInfo<'info>], key: &Pubkey) -> Result<&'a AccountInfo<'info>> {
+    remaining_accounts
+        .iter()
+        .find(|account| account.key == key)
+        .ok_or_else(|| error!(MultisigWalletError::InsufficientAccounts))
+}
+
+// Helper function to read a token account owned by either token program
+fn load_token_account(account: &AccountInfo) -> Result<TokenAccount> {
+    if *account.owner != spl_token::ID && *account.owner != spl_token_2022::ID {
+        return err!(MultisigWalletError::InvalidTokenAccount);
+    }
+    let data = account.try_borrow_data()?;
+    TokenAccount::try_deserialize(&mut &data[..])
+}
+
+// Helper function to read a mint owned by either token program
+fn load_mint(account: &AccountInfo) -> Result<Mint> {
+    if *account.owner != spl_token::ID && *account.owner != spl_token_2022::ID {
+        return err!(MultisigWalletError::InvalidMint);
+    }
+    let data = account.try_borrow_data()?;
+    Mint::try_deserialize(&mut &data[..])
+}
+
+// Helper function to fail unless every account in the vault's token
+// registry is among `listed`. A registry that was never created lists none
+fn require_registered_listed(registry: &AccountInfo, listed: &[Pubkey]) -> Result<()> {
+    if registry.data_is_empty() {
+        return Ok(());
+    }
+
+    let registry = Account::<TokenRegistry>::try_from(registry)?;
+    if let Some(missing) = registry.accounts.iter().find(|entry| !listed.contains(&entry.address)) {
+        msg!("Registered token account {} is not listed", missing.address);
+        return err!(MultisigWalletError::UnlistedTokenAccount);
+    }
+
+    Ok(())
+}
+
+// Helper function to check execution-time state a typed action depends on,
+// so it fails with a precise error rather than inside the CPI
+fn check_action_preconditions(action: &TypedAction, remaining_accounts: &[AccountInfo], vault: &Pubkey) -> Result<()> {
+    match action {
+        TypedAction::WrapSol { amount } => {
+            let vault_account = find_account(remaining_accounts, vault)?;
+            if vault_account.lamports() < *amount {
+                return err!(MultisigWalletError::InsufficientVaultBalance);
+            }
+        }
+        TypedAction::UnwrapSol => {
+            let wsol = get_associated_token_address_with_program_id(vault, &native_mint::ID, &spl_token::ID);
+            let token_account = load_token_account(find_account(remaining_accounts, &wsol)?)?;
+            if token_account.delegate.is_some() || token_account.delegated_amount != 0 {
+                return err!(MultisigWalletError::TokenAccountDelegated);
+            }
+        }
+        // Check the vault covers the whole payout before the first transfer
+        TypedAction::Payout { mint, token_program, .. } => {
+            let (_, total) = action.outflow().unwrap();
+            let available = match mint {
+                None => find_account(remaining_accounts, vault)?.lamports(),
+                Some(mint) => {
+                    let source = get_associated_token_address_with_program_id(vault, mint, token_program);
+                    load_token_account(find_account(remaining_accounts, &source)?)?.amount
+                }
+            };
+            if available < total {
+                msg!("Payout of {} exceeds the vault's {}", total, available);
+                return err!(MultisigWalletError::InsufficientVaultBalance);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Helper function to look up `tag` in the multisig's action registry, which
+// must be among `remaining_accounts`
+fn load_registered_action(
+    remaining_accounts: &[AccountInfo],
+    multisig: &Pubkey,
+    tag: &str,
+) -> Result<RegisteredAction> {
+    let (address, _) = Pubkey::find_program_address(&[b"action_registry", multisig.as_ref()], &crate::ID);
+    let registry = find_account(remaining_accounts, &address)?;
+    let template = if registry.data_is_empty() {
+        None
+    } else {
+        Account::<ActionRegistry>::try_from(registry)?.find(tag).cloned()
+    };
+
+    template.ok_or_else(|| {
+        msg!("No external action is registered as {}", tag);
+        error!(MultisigWalletError::ActionNotRegistered)
+    })
+}
+
+// Helper function to build the CPIs performed by a typed action
+fn build_action_instructions(
+    action: &TypedAction,
+    multisig: &Pubkey,
+    vault: &Pubkey,
+    transaction: &Pubkey,
+    remaining_accounts: &[AccountInfo],
+) -> Result<Vec<Instruction>> {
+    let instructions = match action {
+        TypedAction::DelegateStake { validator, lamports } => {
+            let stake_account = find_created_account_address(b"stake", transaction).0;
+            let authorized = Authorized {
+                staker: *vault,
+                withdrawer: *vault,
+            };
+            let mut instructions = stake::instruction::create_account(
+                vault,
+                &stake_account,
+                &authorized,
+                &Lockup::default(),
+                *lamports,
+            );
+            instructions.push(stake::instruction::delegate_stake(&stake_account, vault, validator));
+            instructions
+        }
+        TypedAction::DeactivateStake { stake_account } => {
+            vec![stake::instruction::deactivate_stake(stake_account, vault)]
+        }
+        TypedAction::WithdrawStake { stake_account, lamports, recipient } => {
+            vec![stake::instruction::withdraw(stake_account, vault, recipient, *lamports, None)]
+        }
+        TypedAction::WrapSol { amount } => {
+            let wsol = get_associated_token_address_with_program_id(vault, &native_mint::ID, &spl_token::ID);
+            vec![
+                create_associated_token_account_idempotent(vault, vault, &native_mint::ID, &spl_token::ID),
+                system_instruction::transfer(vault, &wsol, *amount),
+                spl_token::instruction::sync_native(&spl_token::ID, &wsol)?,
+            ]
+        }
+        TypedAction::UnwrapSol => {
+            let wsol = get_associated_token_address_with_program_id(vault, &native_mint::ID, &spl_token::ID);
+            vec![spl_token::instruction::close_account(&spl_token::ID, &wsol, vault, vault, &[])?]
+        }
+        TypedAction::NftTransfer { mint, recipient, token_program } => {
+            let source = get_associated_token_address_with_program_id(vault, mint, token_program);
+            let destination = get_associated_token_address_with_program_id(recipient, mint, token_program);
+            vec![
+                create_associated_token_account_idempotent(vault, recipient, mint, token_program),
+                spl_token_2022::instruction::transfer_checked(
+                    token_program,
+                    &source,
+                    mint,
+                    &destination,
+                    vault,
+                    &[],
+                    1,
+                    0,
+                )?,
+            ]
+        }
+        TypedAction::CreateMint { decimals, freeze_authority, token_program } => {
+            let mint = find_created_account_address(b"mint", transaction).0;
+            let space = spl_token::state::Mint::LEN;
+            vec![
+                system_instruction::create_account(
+                    vault,
+                    &mint,
+                    Rent::get()?.minimum_balance(space),
+                    space as u64,
+                    token_program,
+                ),
+                spl_token_2022::instruction::initialize_mint2(
+                    token_program,
+                    &mint,
+                    vault,
+                    freeze_authority.as_ref(),
+                    *decimals,
+                )?,
+            ]
+        }
+        TypedAction::MintTo { mint, destination, amount, token_program } => {
+            vec![spl_token_2022::instruction::mint_to(token_program, mint, destination, vault, &[], *amount)?]
+        }
+        TypedAction::SetAuthority { target, authority_type, new_authority, token_program } => {
+            vec![spl_token_2022::instruction::set_authority(
+                token_program,
+                target,
+                new_authority.as_ref(),
+                authority_type.to_spl(),
+                vault,
+                &[],
+            )?]
+        }
+        TypedAction::TokenBurn { mint, amount, decimals, close_if_empty, token_program } => {
+            let source = get_associated_token_address_with_program_id(vault, mint, token_program);
+            let mut instructions = vec![spl_token_2022::instruction::burn_checked(
+                token_program,
+                &source,
+                mint,
+                vault,
+                &[],
+                *amount,
+                *decimals,
+            )?];
+
+            // Reclaim the rent of an account the burn leaves empty
+            if *close_if_empty {
+                let balance = load_token_account(find_account(remaining_accounts, &source)?)?.amount;
+                if balance == *amount {
+                    instructions.push(spl_token_2022::instruction::close_account(
+                        token_program,
+                        &source,
+                        vault,
+                        vault,
+                        &[],
+                    )?);
+                }
+            }
+            instructions
+        }
+        TypedAction::Transfer { recipient, lamports, .. } => {
+            vec![system_instruction::transfer(vault, recipient, *lamports)]
+        }
+        TypedAction::TokenTransfer { mint, recipient, amount, decimals, token_program, .. } => {
+            let source = get_associated_token_address_with_program_id(vault, mint, token_program);
+            let destination = get_associated_token_address_with_program_id(recipient, mint, token_program);
+            vec![
+                create_associated_token_account_idempotent(vault, recipient, mint, token_program),
+                spl_token_2022::instruction::transfer_checked(
+                    token_program,
+                    &source,
+                    mint,
+                    &destination,
+                    vault,
+                    &[],
+                    *amount,
+                    *decimals,
+                )?,
+            ]
+        }
+        TypedAction::Payout { mint: None, entries, .. } => entries
+            .iter()
+            .map(|entry| system_instruction::transfer(vault, &entry.recipient, entry.amount))
+            .collect(),
+        TypedAction::Payout { mint: Some(mint), decimals, token_program, entries } => {
+            let source = get_associated_token_address_with_program_id(vault, mint, token_program);
+            let mut instructions = Vec::with_capacity(entries.len() * 2);
+            for entry in entries {
+                let destination = get_associated_token_address_with_program_id(&entry.recipient, mint, token_program);
+                instructions.push(create_associated_token_account_idempotent(
+                    vault,
+                    &entry.recipient,
+                    mint,
+                    token_program,
+                ));
+                instructions.push(spl_token_2022::instruction::transfer_checked(
+                    token_program,
+                    &source,
+                    mint,
+                    &destination,
+                    vault,
+                    &[],
+                    entry.amount,
+                    *decimals,
+                )?);
+            }
+            instructions
+        }
+        // Built from the template as registered now, so one the board
+        // changed since the proposal was made can't execute
+        TypedAction::Registered { tag, args, template_hash, .. } => {
+            let template = load_registered_action(remaining_accounts, multisig, tag)?;
+            if template.hash() != *template_hash {
+                msg!("External action {} was changed since the proposal", tag);
+                return err!(MultisigWalletError::RegisteredActionChanged);
+            }
+            vec![template.build_instruction(args, vault)?]
+        }
+    };
+
+    Ok(instructions)
+}
+
+// Helper function to build the CPIs moving an escrowed action's funds from
+// the vault into its escrow. SOL escrows also receive a rent reserve, token
+// escrows are token accounts owned by the escrow PDA itself
+fn build_escrow_lock(action: &TypedAction, escrow: &Pubkey, vault: &Pubkey) -> Result<Vec<Instruction>> {
+    match action {
+        TypedAction::Transfer { lamports, .. } => Ok(vec![system_instruction::transfer(
+            vault,
+            escrow,
+            lamports.saturating_add(Rent::get()?.minimum_balance(0)),
+        )]),
+        TypedAction::TokenTransfer { mint, amount, decimals, token_program, .. } => {
+            let source = get_associated_token_address_with_program_id(vault, mint, token_program);
+            let space = spl_token::state::Account::LEN;
+            Ok(vec![
+                system_instruction::create_account(
+                    vault,
+                    escrow,
+                    Rent::get()?.minimum_balance(space),
+                    space as u64,
+                    token_program,
+                ),
+                spl_token_2022::instruction::initialize_account3(token_program, escrow, mint, escrow)?,
+                spl_token_2022::instruction::transfer_checked(
+                    token_program,
+                    &source,
+                    mint,
+                    escrow,
+                    vault,
+                    &[],
+                    *amount,
+                    *decimals,
+                )?,
+            ])
+        }
+        _ => err!(MultisigWalletError::NotEscrowed),
+    }
+}
+
+// Helper function to build the CPIs emptying an escrow: to the recipient on
+// execution, or back to the vault on release. Whatever else the escrow holds
+// (its rent) goes back to the vault
+fn build_escrow_payout(
+    action: &TypedAction,
+    escrow: &Pubkey,
+    vault: &Pubkey,
+    escrow_balance: u64,
+    to_recipient: bool,
+) -> Result<Vec<Instruction>> {
+    match action {
+        TypedAction::Transfer { recipient, lamports, .. } => {
+            if to_recipient {
+                Ok(vec![
+                    system_instruction::transfer(escrow, recipient, *lamports),
+                    system_instruction::transfer(escrow, vault, escrow_balance - lamports),
+                ])
+            } else {
+                Ok(vec![system_instruction::transfer(escrow, vault, escrow_balance)])
+            }
+        }
+        TypedAction::TokenTransfer { mint, recipient, amount, decimals, token_program, .. } => {
+            let mut instructions = Vec::new();
+            let destination = if to_recipient {
+                instructions.push(create_associated_token_account_idempotent(vault, recipient, mint, token_program));
+                get_associated_token_address_with_program_id(recipient, mint, token_program)
+            } else {
+                get_associated_token_address_with_program_id(vault, mint, token_program)
+            };
+            instructions.push(spl_token_2022::instruction::transfer_checked(
+                token_program,
+                escrow,
+                mint,
+                &destination,
+                escrow,
+                &[],
+                *amount,
+                *decimals,
+            )?);
+            instructions.push(spl_token_2022::instruction::close_account(token_program, escrow, vault, escrow, &[])?);
+            Ok(instructions)
+        }
+        _ => err!(MultisigWalletError::NotEscrowed),
+    }
+}
+
+// Helper function to run escrow CPIs, signed by both the vault and the escrow
+fn invoke_escrow_instructions<'info>(
+    ctx: &Context<'_, '_, '_, 'info, EscrowAction<'info>>,
+    instructions: &[Instruction],
+) -> Result<()> {
+    let multisig_key = ctx.accounts.multisig.key();
+    let transaction_key = ctx.accounts.transaction.key();
+    let vault_seeds = &[b"vault".as_ref(), multisig_key.as_ref(), &[ctx.accounts.multisig.vault_bump]];
+    let escrow_seeds = &[b"escrow".as_ref(), transaction_key.as_ref(), &[*ctx.bumps.get("escrow").unwrap()]];
+
+    let mut accounts = ctx.remaining_accounts.to_vec();
+    accounts.extend([
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.escrow.to_account_info(),
+        ctx.accounts.system_program.to_account_info(),
+    ]);
+    for instruction in instructions {
+        invoke_signed(instruction, &accounts, &[&vault_seeds[..], &escrow_seeds[..]])?;
+    }
+
+    Ok(())
+}
+
+// Helper function to read the (staker, withdrawer) authorities of a stake
+// account supplied as the first remaining account. The stake state starts
+// with a u32 tag and the rent-exempt reserve, followed by both authorities.
+fn read_stake_authorities(remaining_accounts: &[AccountInfo], stake_account: &Pubkey) -> Result<(Pubkey, Pubkey)> {
+    let account = remaining_accounts.first().ok_or(MultisigWalletError::InsufficientAccounts)?;
+    if account.key() != *stake_account || *account.owner != stake::program::ID {
+        return err!(MultisigWalletError::InvalidStakeAccount);
+    }
+
+    let data = account.try_borrow_data()?;
+    let tag = data.get(0..4).ok_or(MultisigWalletError::InvalidStakeAccount)?;
+    // 1 = Initialized, 2 = Stake; both carry the authorities
+    if tag != [1, 0, 0, 0] && tag != [2, 0, 0, 0] {
+        return err!(MultisigWalletError::InvalidStakeAccount);
+    }
+    let staker = data.get(12..44).ok_or(MultisigWalletError::InvalidStakeAccount)?;
+    let withdrawer = data.get(44..76).ok_or(MultisigWalletError::InvalidStakeAccount)?;
+
+    Ok((
+        Pubkey::new_from_array(staker.try_into().unwrap()),
+        Pubkey::new_from_array(withdrawer.try_into().unwrap()),
+    ))
+}
+
+// Helper function to parse and validate proposed account metas. Each meta is
+// a 32-byte pubkey followed by a flags byte (bit 0: signer, bit 1: writable).
+// Only the multisig and its vault, which the program signs for, may be
+// signers, each at most once
+fn parse_account_metas(data: &[u8], max_metas: u8, signers: &[Pubkey]) -> Result<Vec<AccountMeta>> {
+    if data.len() % 33 != 0 {
+        return err!(MultisigWalletError::InvalidAccountMetas);
+    }
+    if data.len() / 33 > max_metas as usize {
+        return err!(MultisigWalletError::TooManyAccountMetas);
+    }
+
+    let mut account_metas: Vec<AccountMeta> = Vec::with_capacity(data.len() / 33);
+    for (i, chunk) in data.chunks_exact(33).enumerate() {
+        if chunk[32] & !0b11 != 0 {
+            msg!("Account meta {} has reserved flag bits set: {:#04x}", i, chunk[32]);
+            return err!(MultisigWalletError::InvalidAccountMetaFlags);
+        }
+
+        let meta = decode_account_meta(chunk);
+        if meta.is_signer {
+            if !signers.contains(&meta.pubkey) {
+                msg!("Account meta {} signer {} is not a multisig PDA", i, meta.pubkey);
+                return err!(MultisigWalletError::UnauthorizedSignerMeta);
+            }
+            if account_metas.iter().any(|other| other.is_signer && other.pubkey == meta.pubkey) {
+                return err!(MultisigWalletError::DuplicateSignerMeta);
+            }
+        }
+        account_metas.push(meta);
+    }
+
+    Ok(account_metas)
+}
+
+// Helper function to reject a System Program instruction that changes the
+// owner of one of the `protected` accounts. Data that doesn't decode is
+// rejected too, since what it would do can't be checked
+fn check_system_instruction(
+    program_id: &Pubkey,
+    data: &[u8],
+    account_metas: &[AccountMeta],
+    protected: &[Pubkey],
+) -> Result<()> {
+    if *program_id != anchor_lang::system_program::ID {
+        return Ok(());
+    }
+
+    match SystemCall::decode(data) {
+        None => {
+            msg!("System Program instruction data doesn't decode");
+            err!(MultisigWalletError::DangerousSystemInstruction)
+        }
+        Some(SystemCall::ChangesOwner { target, owner }) => {
+            match account_metas.get(target).filter(|meta| protected.contains(&meta.pubkey)) {
+                Some(meta) => {
+                    msg!("System Program instruction assigns {} to {}", meta.pubkey, owner);
+                    err!(MultisigWalletError::DangerousSystemInstruction)
+                }
+                None => Ok(()),
+            }
+        }
+        Some(SystemCall::Other) => Ok(()),
+    }
+}
+
+// Helper function to deserialize account metas stored in the 33-byte
+// encoding, which were validated by parse_account_metas when proposed
+fn deserialize_account_metas(data: &[u8]) -> Vec<AccountMeta> {
+    data.chunks_exact(33).map(decode_account_meta).collect()
+}
+
+// Helper function to serialize account metas into the 33-byte encoding that
+// parse_account_metas reads
+fn serialize_account_metas(metas: &[AccountMeta]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(metas.len() * 33);
+    for meta in metas {
+        data.extend_from_slice(meta.pubkey.as_ref());
+        data.push((meta.is_signer as u8) | ((meta.is_writable as u8) << 1));
+    }
+    data
+}
+
+/// Encodes account metas the way proposals store them: one byte per meta,
+/// followed by each distinct pubkey once, in order of first use. A meta's
+/// byte holds its flags in bits 0-1 (signer, writable) and its key's position
+/// in bits 2-7. The first meta always uses the first key, so its position
+/// bits hold the number of keys minus one instead. This takes as many bytes
+/// as the 33-byte encoding when no key repeats, and 32 fewer per repeat.
+/// `MAX_ACCOUNT_METAS` keeps positions within 6 bits.
+pub fn encode_compact_account_metas(metas: &[AccountMeta]) -> Vec<u8> {
+    let mut keys: Vec<Pubkey> = Vec::new();
+    let mut data = Vec::with_capacity(metas.len() * 33);
+    for meta in metas {
+        let position = match keys.iter().position(|key| *key == meta.pubkey) {
+            Some(position) => position,
+            None => {
+                keys.push(meta.pubkey);
+                keys.len() - 1
+            }
+        };
+        data.push(((position as u8) << 2) | (meta.is_signer as u8) | ((meta.is_writable as u8) << 1));
+    }
+    if let Some(first) = data.first_mut() {
+        *first |= ((keys.len() - 1) as u8) << 2;
+    }
+    for key in &keys {
+        data.extend_from_slice(key.as_ref());
+    }
+    data
+}
+
+// Helper function to decode account metas written by
+// encode_compact_account_metas
+fn decode_compact_account_metas(data: &[u8]) -> Vec<AccountMeta> {
+    let first = match data.first() {
+        Some(first) => *first,
+        None => return Vec::new(),
+    };
+    let keys_len = (first >> 2) as usize + 1;
+    let (metas, keys) = data.split_at(data.len() - keys_len * 32);
+    metas
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            let position = if i == 0 { 0 } else { (byte >> 2) as usize };
+            AccountMeta {
+                pubkey: Pubkey::new_from_array(keys[position * 32..(position + 1) * 32].try_into().unwrap()),
+                is_signer: (byte & 1) != 0,
+                is_writable: (byte & 2) != 0,
+            }
+        })
+        .collect()
+}
+
+fn decode_account_meta(chunk: &[u8]) -> AccountMeta {
+    AccountMeta {
+        pubkey: Pubkey::new_from_array(chunk[..32].try_into().unwrap()),
+        is_signer: (chunk[32] & 1) != 0,
+        is_writable: (chunk[32] & 2) != 0,
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(initial_signers: Vec<Pubkey>, threshold: u8, expiration_timestamp: Option<i64>)]
+pub struct InitializeMultisig<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = MultisigAccount::space(initial_signers.len()),
+        seeds = [b"multisig", create_key.key().as_ref()],
+        bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// Seed of the multisig PDA: a fresh keypair, or the payer for the
+    /// legacy creator-derived address
+    pub create_key: Signer<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+
+    /// Counts the multisigs created at this address, and outlives them
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = MultisigGeneration::SPACE,
+        seeds = [b"generation", create_key.key().as_ref()],
+        bump
+    )]
+    pub generation: Account<'info, MultisigGeneration>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeTransaction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+    
+    /// CHECK: The proposal's transaction account at `["tx", multisig,
+    /// nonce]`, created once the proposal is validated
+    #[account(mut)]
+    pub transaction: UncheckedAccount<'info>,
+    
+    pub proposer: Signer<'info>,
+
+    /// Pays the proposal's rent and receives it back when it is closed; may
+    /// be the proposer itself
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeTransactionsBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    pub proposer: Signer<'info>,
+
+    /// Pays every proposal's rent and receives it back when they are closed;
+    /// may be the proposer itself
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The proposal's transaction account at `["tx", multisig,
+    /// nonce]`, created once the proposal is validated
+    #[account(mut)]
+    pub transaction: UncheckedAccount<'info>,
+
+    pub proposer: Signer<'info>,
+
+    /// Pays the proposal's rent and receives it back when it is closed; may
+    /// be the proposer itself
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA, whose protocol fee the proposal is
+    /// stamped with
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveTransaction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+    
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(tx_index: u64)]
+pub struct ApproveTransactionByIndex<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// Derived from the index argument rather than the account's own
+    /// `tx_index`
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveExternal<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// CHECK: The instructions sysvar, used to identify the calling program
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredTransaction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key(),
+        close = rent_payer
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// CHECK: Receives the remaining rent; must be the transaction's rent payer
+    #[account(mut, address = transaction.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    /// CHECK: The multisig's system-owned vault, which may top up the bounty
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateTransactionEncoding<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// CHECK: Receives the freed rent; must be the transaction's rent payer
+    #[account(mut, address = transaction.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    pub member: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMultisigAccount<'info> {
+    /// CHECK: A multisig too small for the current layout doesn't
+    /// deserialize; its owner and discriminator are checked in the handler
+    #[account(mut)]
+    pub multisig: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RecordDeposit<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The multisig's vault, only read for its balance
+    #[account(seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DepositLedger::SPACE,
+        seeds = [b"deposits", multisig.key().as_ref()],
+        bump
+    )]
+    pub ledger: Account<'info, DepositLedger>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetPdas<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ExplainTransaction<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SnapshotBalances<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The multisig's vault, only read for its balance
+    #[account(seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = member,
+        space = Snapshot::SPACE,
+        seeds = [b"snapshot", multisig.key().as_ref(), &multisig.snapshot_count.to_le_bytes()],
+        bump
+    )]
+    pub snapshot: Account<'info, Snapshot>,
+
+    /// CHECK: The vault's token registry, empty if never created. Every
+    /// registered account must be snapshotted
+    #[account(seeds = [b"token_registry", multisig.key().as_ref()], bump)]
+    pub registry: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub member: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateVaultAta<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = member,
+        space = TokenRegistry::SPACE,
+        seeds = [b"token_registry", multisig.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, TokenRegistry>,
+
+    /// CHECK: The multisig's vault, owner of the new token account
+    #[account(seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: The token account's mint
+    #[account(owner = token_program.key())]
+    pub mint: UncheckedAccount<'info>,
+
+    /// CHECK: The vault's associated token account for the mint
+    #[account(
+        mut,
+        address = get_associated_token_address_with_program_id(&vault.key(), &mint.key(), &token_program.key())
+    )]
+    pub token_account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub member: Signer<'info>,
+
+    /// CHECK: The mint's token program
+    #[account(constraint = token_program.key() == spl_token::ID || token_program.key() == spl_token_2022::ID)]
+    pub token_program: UncheckedAccount<'info>,
+
+    /// CHECK: The Associated Token Account program
+    #[account(address = anchor_spl::associated_token::ID)]
+    pub associated_token_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PruneTokenRegistry<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_registry", multisig.key().as_ref()],
+        bump = registry.bump
+    )]
+    pub registry: Account<'info, TokenRegistry>,
+}
+
+#[derive(Accounts)]
+pub struct ResetCategoryStats<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = CategoryStats::SPACE,
+        seeds = [b"category_stats", multisig.key().as_ref()],
+        bump
+    )]
+    pub stats: Account<'info, CategoryStats>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SpendFromBudget<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The multisig's system-owned vault the budget is spent from
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// The child multisig holding the budget, signing through its execution
+    pub child: Signer<'info>,
+
+    /// CHECK: Receives the lamports, or the tokens when spending a mint budget
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RotateKey<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    pub member: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EscrowAction<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// CHECK: The multisig's system-owned vault
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: The proposal's escrow, holding its funds from locking until
+    /// execution or release
+    #[account(mut, seeds = [b"escrow", transaction.key().as_ref()], bump)]
+    pub escrow: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VoteWithTokens<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// CHECK: The voter's escrow for this proposal, a token account owned by
+    /// itself; created on the first vote
+    #[account(mut, seeds = [b"vote_escrow", transaction.key().as_ref(), voter.key().as_ref()], bump)]
+    pub escrow: UncheckedAccount<'info>,
+
+    /// CHECK: The governance token mint, checked against the token vote config
+    pub mint: UncheckedAccount<'info>,
+
+    /// CHECK: The voter's token account the tokens are locked from; the
+    /// token program checks it
+    #[account(mut)]
+    pub source: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    /// CHECK: The mint's token program
+    #[account(constraint = token_program.key() == spl_token::ID || token_program.key() == spl_token_2022::ID)]
+    pub token_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockVoteTokens<'info> {
+    /// CHECK: The proposal voted on; may already be closed
+    pub transaction: UncheckedAccount<'info>,
+
+    /// CHECK: The voter's escrow for this proposal
+    #[account(mut, seeds = [b"vote_escrow", transaction.key().as_ref(), voter.key().as_ref()], bump)]
+    pub escrow: UncheckedAccount<'info>,
+
+    /// CHECK: The governance token mint; the token program checks it against
+    /// the escrow
+    pub mint: UncheckedAccount<'info>,
+
+    /// CHECK: A token account of the voter's receiving the tokens
+    #[account(mut)]
+    pub destination: UncheckedAccount<'info>,
+
+    /// Receives the escrow's rent
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    /// CHECK: The mint's token program
+    #[account(constraint = token_program.key() == spl_token::ID || token_program.key() == spl_token_2022::ID)]
+    pub token_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
 pub struct ExecuteTransaction<'info> {
     #[account(
-        seeds = [b"multisig", creator.key().as_ref()],
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// Who executes; required under `ExecutionPolicy::SignersOnly`, where it
+    /// must be a member or the automation authority, and when the multisig
+    /// has the executor pay the protocol fee
+    #[account(mut)]
+    pub executor: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(tx_index: u64)]
+pub struct ExecuteTransactionByIndex<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// Derived from the index argument rather than the account's own
+    /// `tx_index`
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// As for `execute_transaction`
+    #[account(mut)]
+    pub executor: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteJoint<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"multisig", partner_multisig.create_key.as_ref()],
+        bump = partner_multisig.bump
+    )]
+    pub partner_multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", partner_multisig.key().as_ref(), &partner_transaction.tx_index.to_le_bytes()],
+        bump = partner_transaction.bump,
+        constraint = partner_transaction.multisig == partner_multisig.key()
+    )]
+    pub partner_transaction: Account<'info, TransactionAccount>,
+
+    /// Who executes; a side with `ExecutionPolicy::SignersOnly` requires a
+    /// member of that side. Pays the protocol fee of a side that has the
+    /// executor pay
+    #[account(mut)]
+    pub executor: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct SkipTransaction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_signers: Vec<Pubkey>)]
+pub struct ProposeRecovery<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = RecoveryProposal::space(new_signers.len()),
+        seeds = [b"recovery", multisig.key().as_ref()],
+        bump
+    )]
+    pub recovery: Account<'info, RecoveryProposal>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecoveryVote<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"recovery", multisig.key().as_ref()],
+        bump = recovery.bump
+    )]
+    pub recovery: Account<'info, RecoveryProposal>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteRecovery<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"recovery", multisig.key().as_ref()],
+        bump = recovery.bump,
+        close = proposer
+    )]
+    pub recovery: Account<'info, RecoveryProposal>,
+
+    /// CHECK: Receives the recovery account's rent; must be its proposer
+    #[account(mut, address = recovery.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRecovery<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"recovery", multisig.key().as_ref()],
+        bump = recovery.bump,
+        close = proposer
+    )]
+    pub recovery: Account<'info, RecoveryProposal>,
+
+    /// CHECK: Receives the recovery account's rent; must be its proposer
+    #[account(mut, address = recovery.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyThresholdAction<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMultisig<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMultisigWithConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptInitialMembership<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    pub member: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePendingMultisig<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump,
+        close = creator
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// The multisig's creator, which paid its rent
+    #[account(mut, address = multisig.authority)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMultisig<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump,
+        close = receiver
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+    
+    #[account(mut)]
+    pub receiver: AccountInfo<'info>,
+
+    /// CHECK: The multisig's system-owned vault, swept to the receiver
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: The vault's token registry, empty if never created. Every
+    /// registered account must be swept, and the registry is closed
+    #[account(mut, seeds = [b"token_registry", multisig.key().as_ref()], bump)]
+    pub registry: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMultisigV2<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<MultisigV2>(),
+        seeds = [b"multisig_v2", create_key.key().as_ref()],
+        bump
+    )]
+    pub multisig: AccountLoader<'info, MultisigV2>,
+
+    /// Seed of the multisig PDA
+    pub create_key: Signer<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(tx_index: u64, program_id: Pubkey, accounts: Vec<u8>, instruction_data: Vec<u8>)]
+pub struct ProposeTransactionV2<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig_v2", multisig.load()?.create_key.as_ref()],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, MultisigV2>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = TransactionAccount::space(
+            accounts.len().min(DEFAULT_MAX_ACCOUNT_METAS as usize * 33),
+            instruction_data.len(),
+            0,
+            0,
+            multisig.load()?.member_count as usize,
+        ),
+        seeds = [b"tx", multisig.key().as_ref(), &tx_index.to_le_bytes()],
+        bump
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveTransactionV2<'info> {
+    #[account(
+        seeds = [b"multisig_v2", multisig.load()?.create_key.as_ref()],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, MultisigV2>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTransactionV2<'info> {
+    #[account(
+        seeds = [b"multisig_v2", multisig.load()?.create_key.as_ref()],
+        bump = multisig.load()?.bump
+    )]
+    pub multisig: AccountLoader<'info, MultisigV2>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_signers: Vec<Pubkey>)]
+pub struct ProposeMemberSwap<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = MemberSwap::space(new_signers.len(), multisig.signers.len()),
+        seeds = [b"member_swap", multisig.key().as_ref()],
+        bump
+    )]
+    pub member_swap: Account<'info, MemberSwap>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MemberSwapVote<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"member_swap", multisig.key().as_ref()],
+        bump = member_swap.bump
+    )]
+    pub member_swap: Account<'info, MemberSwap>,
+
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveMemberSwap<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"member_swap", multisig.key().as_ref()],
+        bump = member_swap.bump,
+        close = proposer
+    )]
+    pub member_swap: Account<'info, MemberSwap>,
+
+    /// CHECK: Receives the swap account's rent; must be its proposer
+    #[account(mut, address = member_swap.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProgramConfig<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = ProgramConfig::SPACE,
+        seeds = [b"program_config"],
+        bump
+    )]
+    pub program_config: Account<'info, ProgramConfig>,
+
+    /// The program's upgradeable loader data, naming its upgrade authority
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+            @ MultisigWalletError::NotUpgradeAuthority
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProgramConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_config"],
+        bump = program_config.bump,
+        has_one = admin @ MultisigWalletError::NotProgramConfigAdmin
+    )]
+    pub program_config: Account<'info, ProgramConfig>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptInvitation<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump,
+        realloc = MultisigAccount::space(multisig.signers.len() + 1).max(multisig.to_account_info().data_len()),
+        realloc::payer = invitee,
+        realloc::zero = false
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"invitation", multisig.key().as_ref(), invitee.key().as_ref()],
+        bump = invitation.bump,
+        close = vault
+    )]
+    pub invitation: Account<'info, Invitation>,
+
+    /// CHECK: The multisig's vault, which paid the invitation's rent
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub invitee: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredInvitation<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"invitation", multisig.key().as_ref(), invitation.invitee.as_ref()],
+        bump = invitation.bump,
+        close = vault
+    )]
+    pub invitation: Account<'info, Invitation>,
+
+    /// CHECK: The multisig's vault, which paid the invitation's rent
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAllowance<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The multisig's system-owned vault the allowance is paid from
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// The member holding the allowance
+    pub member: Signer<'info>,
+
+    /// CHECK: Receives the lamports
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveFor<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// The smart wallet's PDA, signed for by its program
+    pub member: Signer<'info>,
+
+    /// CHECK: The program config PDA listing the wallet programs, none until
+    /// it exists
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+
+    /// CHECK: The instructions sysvar, used to identify the calling program
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReserveNonce<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        init,
+        payer = reserver,
+        space = NonceReservation::SPACE,
+        seeds = [b"reservation", multisig.key().as_ref(), &multisig.nonce.to_le_bytes()],
+        bump
+    )]
+    pub reservation: Account<'info, NonceReservation>,
+
+    /// The member reserving the index, who pays the reservation's rent
+    #[account(mut)]
+    pub reserver: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeTransactionWithReservation<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
         bump = multisig.bump
     )]
     pub multisig: Account<'info, MultisigAccount>,
-    
+
+    #[account(
+        mut,
+        seeds = [b"reservation", multisig.key().as_ref(), &reservation.tx_index.to_le_bytes()],
+        bump = reservation.bump,
+        constraint = reservation.multisig == multisig.key(),
+        close = proposer
+    )]
+    pub reservation: Account<'info, NonceReservation>,
+
+    /// CHECK: The proposal's transaction account at `["tx", multisig,
+    /// reserved index]`, created once the proposal is validated
+    #[account(mut)]
+    pub transaction: UncheckedAccount<'info>,
+
+    /// The reserver, who gets the reservation's rent back
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    /// Pays the proposal's rent and receives it back when it is closed; may
+    /// be the proposer itself
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: The program config PDA. Its limits apply once it exists, the
+    /// compiled defaults until then
+    #[account(seeds = [b"program_config"], bump)]
+    pub program_config: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseExpiredReservation<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"reservation", multisig.key().as_ref(), &reservation.tx_index.to_le_bytes()],
+        bump = reservation.bump,
+        constraint = reservation.multisig == multisig.key(),
+        close = reserver
+    )]
+    pub reservation: Account<'info, NonceReservation>,
+
+    /// CHECK: The reserver, who paid the reservation's rent
+    #[account(mut, address = reservation.reserver)]
+    pub reserver: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfirmExecution<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
     #[account(
         mut,
         seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
@@ -371,61 +7393,3315 @@ pub struct ExecuteTransaction<'info> {
         constraint = transaction.multisig == multisig.key()
     )]
     pub transaction: Account<'info, TransactionAccount>,
-    
-    /// CHECK: This is just used as a seed for the multisig PDA
-    pub creator: UncheckedAccount<'info>,
+
+    pub member: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEmptyVaultAta<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    /// CHECK: The vault's token registry, empty if never created. The closed
+    /// account is dropped from it
+    #[account(mut, seeds = [b"token_registry", multisig.key().as_ref()], bump)]
+    pub registry: UncheckedAccount<'info>,
+
+    /// CHECK: The multisig's vault, owner of the token account and receiver
+    /// of its rent
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: The token account's mint
+    #[account(owner = token_program.key())]
+    pub mint: UncheckedAccount<'info>,
+
+    /// CHECK: The vault's associated token account for the mint
+    #[account(
+        mut,
+        address = get_associated_token_address_with_program_id(&vault.key(), &mint.key(), &token_program.key())
+    )]
+    pub token_account: UncheckedAccount<'info>,
+
+    pub member: Signer<'info>,
+
+    /// CHECK: The mint's token program
+    #[account(constraint = token_program.key() == spl_token::ID || token_program.key() == spl_token_2022::ID)]
+    pub token_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseExecutedTransaction<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    /// CHECK: Receives the rent unless the vault reimbursed it; must be the
+    /// transaction's rent payer
+    #[account(mut, address = transaction.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+
+    /// CHECK: The multisig's vault, which receives the rent it reimbursed
+    #[account(mut, seeds = [b"vault", multisig.key().as_ref()], bump = multisig.vault_bump)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: The transaction's execution receipt, which must exist
+    #[account(seeds = [b"receipt", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()], bump)]
+    pub receipt: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterExternalAction<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ActionRegistry::SPACE,
+        seeds = [b"action_registry", multisig.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, ActionRegistry>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveExternalAction<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"action_registry", multisig.key().as_ref()],
+        bump = registry.bump
+    )]
+    pub registry: Account<'info, ActionRegistry>,
+}
+
+#[derive(Accounts)]
+pub struct StageExecution<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    #[account(
+        init,
+        payer = executor,
+        space = ExecutionStage::SPACE,
+        seeds = [b"stage", transaction.key().as_ref()],
+        bump
+    )]
+    pub stage: Account<'info, ExecutionStage>,
+
+    /// The member staging the execution, who pays the stage account's rent
+    /// and must complete it
+    #[account(mut)]
+    pub executor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteExecution<'info> {
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stage", transaction.key().as_ref()],
+        bump = stage.bump,
+        has_one = executor,
+        close = executor
+    )]
+    pub stage: Account<'info, ExecutionStage>,
+
+    #[account(mut)]
+    pub executor: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseStagedExecution<'info> {
+    #[account(
+        seeds = [b"multisig", multisig.create_key.as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, MultisigAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"tx", multisig.key().as_ref(), &transaction.tx_index.to_le_bytes()],
+        bump = transaction.bump,
+        constraint = transaction.multisig == multisig.key()
+    )]
+    pub transaction: Account<'info, TransactionAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stage", transaction.key().as_ref()],
+        bump = stage.bump,
+        has_one = executor,
+        close = executor
+    )]
+    pub stage: Account<'info, ExecutionStage>,
+
+    /// CHECK: The stager, refunded the stage account's rent
+    #[account(mut)]
+    pub executor: UncheckedAccount<'info>,
+
+    pub caller: Signer<'info>,
+}
+
+/// Zeroed bytes at the end of every new multisig and transaction account.
+/// A small fixed-size field added later takes its bytes from here, shrinking
+/// the padding by its size, so existing accounts need no realloc. Such a
+/// field must read correctly as all zeros.
+pub const MULTISIG_RESERVED_SPACE: usize = 0;
+pub const TRANSACTION_RESERVED_SPACE: usize = 14;
+
+/// `TransactionAccount::version` of proposals storing their account metas
+/// as 33-byte entries, the encoding clients propose them in
+pub const TRANSACTION_VERSION_LEGACY_METAS: u8 = 0;
+/// `TransactionAccount::version` of proposals storing their account metas
+/// compactly, see `encode_compact_account_metas`
+pub const TRANSACTION_VERSION_COMPACT_METAS: u8 = 1;
+
+#[account]
+pub struct MultisigAccount {
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub expiration_timestamp: Option<i64>,
+    pub nonce: u64,
+    pub bump: u8,
+    pub create_key: Pubkey,
+    pub authority: Pubkey,
+    pub mint_caps: Vec<MintCap>,
+    pub memo_on_execute: bool,
+    pub ordered_execution: bool,
+    pub last_executed_index: Option<u64>,
+    pub owner_set_seqno: u32,
+    pub invalidate_on_member_change: bool,
+    pub proposal_cooldown_seconds: u32,
+    pub member_activity: Vec<MemberActivity>,
+    pub member_swap_window_seconds: i64,
+    pub emergency_timelock_seconds: i64,
+    pub pending_emergency: Option<EmergencyThreshold>,
+    pub recovery_members: Vec<Pubkey>,
+    pub recovery_threshold: u8,
+    pub recovery_delay_seconds: i64,
+    pub approver_program: Option<Pubkey>,
+    pub max_external_approvals: u8,
+    pub allowed_programs: Vec<AllowedProgram>,
+    pub default_proposal_ttl_seconds: i64,
+    pub reimburse_proposal_rent: bool,
+    pub cleanup_bounty_lamports: u64,
+    pub cleanup_bounty_vault_cap: u64,
+    pub snapshot_count: u64,
+    pub max_account_metas: u8,
+    pub sub_budgets: Vec<SubBudget>,
+    pub key_rotation_cooldown_seconds: i64,
+    pub reassign_approvals_on_rotation: bool,
+    pub rate_limits: Vec<RateLimit>,
+    pub status: MultisigStatus,
+    pub required_acceptances: u8,
+    pub activation_deadline: i64,
+    pub accepted_members: Vec<Pubkey>,
+    pub priority_levels: Vec<PriorityLevel>,
+    pub token_vote: Option<TokenVote>,
+    pub vault_bump: u8,
+    pub prune_removed_approvals: bool,
+    pub execution_policy: ExecutionPolicy,
+    pub automation_authority: Option<Pubkey>,
+    pub live_threshold: bool,
+    pub execution_grace_seconds: i64,
+    pub allow_dangerous_system_instructions: bool,
+    pub payout_caps: Vec<PayoutCap>,
+    pub inactivity_period_seconds: i64,
+    pub budget_epochs: Option<BudgetEpochs>,
+    pub epoch_caps: Vec<EpochCap>,
+    /// Who pays the protocol fee on executions
+    pub fee_payer: FeePayer,
+    /// Head of the hash-linked proposal history, see `link_history`
+    pub history_hash: [u8; 32],
+    /// Whether only typed actions and config changes may be proposed, see
+    /// `is_typed_only`
+    pub typed_only: bool,
+    /// When a scheduled switch-off of `typed_only` takes effect, `0` if none
+    pub typed_only_disable_at: i64,
+    /// Slots a member must wait after voting on a proposal before voting on
+    /// it again, `0` for no wait
+    pub vote_cooldown_slots: u64,
+    /// Personal vault allowances of members, see `claim_allowance`
+    pub member_allowances: Vec<MemberAllowance>,
+    /// Which multisig created at this address this is, counted from `1` by
+    /// its `MultisigGeneration`, `0` if created before they were counted
+    pub generation: u64,
+    /// Opaque proposals carrying more instruction data than this many bytes
+    /// need `confirm_execution` before they execute, `0` to disable
+    pub confirmation_threshold_bytes: u16,
+    /// Whether proposers' own approvals don't count towards the threshold,
+    /// see `proposer_counts`. The padding was used up, so this grows the
+    /// account; older ones read it from the unused room of their bounded
+    /// lists
+    pub proposer_excluded: bool,
+    /// Which proposals may still be open, for clients paging through them.
+    /// Grows the account like `proposer_excluded`
+    pub open_window: OpenProposalWindow,
+    /// Padding for future fields, see `MULTISIG_RESERVED_SPACE`
+    pub reserved: [u8; MULTISIG_RESERVED_SPACE],
+}
+
+impl MultisigAccount {
+    pub fn space(signers_len: usize) -> usize {
+        8 + // discriminator
+        4 + (signers_len * 32) + // signers vector
+        1 + // threshold
+        9 + // optional expiration timestamp
+        8 + // nonce
+        1 + // bump
+        32 + // create key
+        32 + // authority
+        4 + (MAX_MINT_CAPS * MintCap::SIZE) + // mint caps (bounded)
+        1 + // memo on execute
+        1 + // ordered execution
+        9 + // optional last executed index
+        4 + // owner set seqno
+        1 + // invalidate on member change
+        4 + // proposal cooldown
+        4 + (signers_len * MemberActivity::SIZE) + // member activity (one per signer)
+        8 + // member swap window
+        8 + // emergency timelock
+        1 + EmergencyThreshold::SIZE + // optional pending emergency threshold
+        4 + (MAX_RECOVERY_MEMBERS * 32) + // recovery members (bounded)
+        1 + // recovery threshold
+        8 + // recovery delay
+        1 + 32 + // optional approver program
+        1 + // max external approvals
+        4 + (MAX_ALLOWED_PROGRAMS * AllowedProgram::SIZE) + // program allowlist (bounded)
+        8 + // default proposal TTL
+        1 + // reimburse proposal rent
+        8 + // cleanup bounty
+        8 + // cleanup bounty vault cap
+        8 + // snapshot count
+        1 + // max account metas
+        4 + (MAX_SUB_BUDGETS * SubBudget::SIZE) + // sub-budgets (bounded)
+        8 + // key rotation cooldown
+        1 + // reassign approvals on rotation
+        4 + (MAX_RATE_LIMITS * RateLimit::SIZE) + // rate limits (bounded)
+        1 + // status
+        1 + // required acceptances
+        8 + // activation deadline
+        4 + (signers_len * 32) + // accepted members (one per signer)
+        4 + (MAX_PRIORITY_LEVELS * PriorityLevel::SIZE) + // priority levels (bounded)
+        1 + TokenVote::SIZE + // optional token vote
+        1 + // vault bump
+        1 + // prune removed approvals
+        1 + // execution policy
+        1 + 32 + // optional automation authority
+        1 + // live threshold
+        8 + // execution grace seconds
+        1 + // allow dangerous system instructions
+        4 + (MAX_PAYOUT_CAPS * PayoutCap::SIZE) + // payout caps
+        8 + // inactivity period
+        1 + BudgetEpochs::SIZE + // optional budget epochs
+        4 + (MAX_EPOCH_CAPS * EpochCap::SIZE) + // epoch caps (bounded)
+        1 + // fee payer
+        32 + // history hash
+        1 + // typed only
+        8 + // typed only disable time
+        8 + // vote cooldown in slots
+        4 + (signers_len * MemberAllowance::SIZE) + // member allowances (at most one per signer)
+        8 + // generation
+        2 + // confirmation threshold in bytes
+        1 + // proposer excluded
+        OpenProposalWindow::SIZE + // open proposal window
+        MULTISIG_RESERVED_SPACE // reserved padding
+    }
+
+    /// Records that proposal `tx_index` executed, was cancelled or closed,
+    /// or that its reserved index was released.
+    pub fn note_proposal_closed(&mut self, tx_index: u64) {
+        let nonce = self.nonce;
+        self.open_window.note_closed(tx_index, nonce);
+    }
+
+    /// Links a record about proposal `tx_index` into the proposal history,
+    /// returning the history heads before and after it.
+    pub fn record_history(
+        &mut self,
+        record: HistoryRecord,
+        transaction_hash: &[u8; 32],
+        tx_index: u64,
+        timestamp: i64,
+    ) -> ([u8; 32], [u8; 32]) {
+        let before = self.history_hash;
+        self.history_hash = link_history(&before, record, transaction_hash, tx_index, timestamp);
+        (before, self.history_hash)
+    }
+
+    /// Records a proposal by `proposer` at `now`, failing while the proposer's
+    /// cooldown from their previous proposal is still running.
+    pub fn record_proposal(&mut self, proposer: Pubkey, now: i64) -> Result<()> {
+        let cooldown = self.proposal_cooldown_seconds as i64;
+        match self.member_activity.iter_mut().find(|activity| activity.member == proposer) {
+            Some(activity) => {
+                if cooldown > 0 && now < activity.last_proposed_at.saturating_add(cooldown) {
+                    return err!(MultisigWalletError::ProposalCooldownActive);
+                }
+                activity.last_proposed_at = now;
+            }
+            None => self.member_activity.push(MemberActivity {
+                member: proposer,
+                last_proposed_at: now,
+                last_rotated_at: 0,
+                last_action_at: 0,
+                inactive: false,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Counts `amount` of `mint` (lamports for `None`) leaving the vault
+    /// against its rate limit and its epoch cap, where there are any.
+    pub fn consume_outflow(&mut self, mint: Option<Pubkey>, amount: u64, now: i64) -> Result<()> {
+        if let Some(limit) = self.rate_limits.iter_mut().find(|limit| limit.mint == mint) {
+            limit.consume(amount, now)?;
+        }
+        if let Some(epochs) = &self.budget_epochs {
+            let epoch = epochs.epoch_at(now);
+            if let Some(cap) = self.epoch_caps.iter_mut().find(|cap| cap.mint == mint) {
+                cap.consume(amount, epoch)?;
+                msg!("epoch {}: {} of {} spent", cap.epoch, cap.spent_in_epoch, cap.max_per_epoch);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether opaque proposals are refused at `now`. A scheduled switch-off
+    /// applies once its delay has passed.
+    pub fn is_typed_only(&self, now: i64) -> bool {
+        self.typed_only && (self.typed_only_disable_at == 0 || now < self.typed_only_disable_at)
+    }
+
+    /// Whether a proposer's own approval counts towards the threshold of
+    /// proposals made from now on.
+    pub fn proposer_counts(&self) -> bool {
+        !self.proposer_excluded
+    }
+
+    /// Highest threshold a set of `signers_len` signers can meet. Without
+    /// the proposer counting, one member is always left out.
+    pub fn max_threshold(&self, signers_len: usize) -> usize {
+        signers_len.saturating_sub(self.proposer_excluded as usize)
+    }
+
+    /// Whether vault outflows are metered, by a rate limit or an epoch cap.
+    /// Opaque calls can't be, so a metered vault can't sign for one.
+    pub fn meters_outflows(&self) -> bool {
+        !self.rate_limits.is_empty() || !self.epoch_caps.is_empty()
+    }
+
+    /// Moves `old_key`'s activity over to `new_key` on a key rotation at
+    /// `now`, failing while the cooldown from the member's previous rotation
+    /// is still running.
+    pub fn record_rotation(&mut self, old_key: Pubkey, new_key: Pubkey, now: i64) -> Result<()> {
+        let cooldown = self.key_rotation_cooldown_seconds;
+        match self.member_activity.iter_mut().find(|activity| activity.member == old_key) {
+            Some(activity) => {
+                if cooldown > 0
+                    && activity.last_rotated_at != 0
+                    && now < activity.last_rotated_at.saturating_add(cooldown)
+                {
+                    return err!(MultisigWalletError::KeyRotationCooldownActive);
+                }
+                activity.member = new_key;
+                activity.last_rotated_at = now;
+            }
+            None => self.member_activity.push(MemberActivity {
+                member: new_key,
+                last_proposed_at: 0,
+                last_rotated_at: now,
+                last_action_at: 0,
+                inactive: false,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Records that `member` proposed, approved or executed at `now`,
+    /// clearing any inactivity flag. Returns whether the member was flagged.
+    pub fn record_action(&mut self, member: Pubkey, now: i64) -> bool {
+        match self.member_activity.iter_mut().find(|activity| activity.member == member) {
+            Some(activity) => {
+                activity.last_action_at = now;
+                std::mem::replace(&mut activity.inactive, false)
+            }
+            None => {
+                self.member_activity.push(MemberActivity {
+                    member,
+                    last_proposed_at: 0,
+                    last_rotated_at: 0,
+                    last_action_at: now,
+                    inactive: false,
+                });
+                false
+            }
+        }
+    }
+
+    /// How many approvals the registered approver program may grant.
+    pub fn external_approval_capacity(&self) -> usize {
+        if self.approver_program.is_some() {
+            self.max_external_approvals as usize
+        } else {
+            0
+        }
+    }
+
+    /// Checks an opaque instruction against the program allowlist. An empty
+    /// allowlist allows everything, and an entry without prefixes allows the
+    /// whole program.
+    pub fn check_allowlist(&self, program_id: &Pubkey, data: &[u8]) -> Result<()> {
+        if self.allowed_programs.is_empty() {
+            return Ok(());
+        }
+
+        let entry = self
+            .allowed_programs
+            .iter()
+            .find(|entry| entry.program_id == *program_id)
+            .ok_or(MultisigWalletError::ProgramNotAllowed)?;
+        if !entry.prefixes.is_empty() && !entry.prefixes.iter().any(|prefix| data.starts_with(prefix)) {
+            return err!(MultisigWalletError::InstructionNotAllowed);
+        }
+
+        Ok(())
+    }
+
+    /// Expiry of a proposal made at `now`: the requested TTL, capped by the
+    /// default TTL when one is configured.
+    pub fn proposal_expiry(&self, now: i64, ttl_seconds: Option<i64>) -> Result<Option<i64>> {
+        if ttl_seconds.is_some_and(|ttl| ttl <= 0) {
+            return err!(MultisigWalletError::InvalidDuration);
+        }
+
+        let default = Some(self.default_proposal_ttl_seconds).filter(|ttl| *ttl > 0);
+        let ttl = match (ttl_seconds, default) {
+            (Some(requested), Some(default)) => Some(requested.min(default)),
+            (requested, default) => requested.or(default),
+        };
+
+        Ok(ttl.map(|ttl| now.saturating_add(ttl)))
+    }
+
+    /// Index the next execution must have in ordered mode.
+    pub fn next_ordered_index(&self) -> u64 {
+        self.last_executed_index.map_or(0, |index| index + 1)
+    }
+
+    /// The most one payout of `mint` (SOL for `None`) may send in total,
+    /// if capped.
+    pub fn payout_cap(&self, mint: Option<Pubkey>) -> Option<u64> {
+        self.payout_caps.iter().find(|cap| cap.mint == mint).map(|cap| cap.max_total)
+    }
+
+    /// The configured level for `priority`, if any.
+    pub fn priority_level(&self, priority: u8) -> Option<&PriorityLevel> {
+        self.priority_levels.iter().find(|level| level.priority == priority)
+    }
+
+    /// Approvals a proposal of `priority` needs: its level's override, but
+    /// never fewer than the base threshold.
+    pub fn required_approvals(&self, priority: u8) -> usize {
+        let required = self.priority_level(priority).map_or(0, |level| level.required_threshold);
+        required.max(self.threshold) as usize
+    }
+
+    /// Approvals `transaction` needs: the count stamped when it was
+    /// proposed, or with `live_threshold` set, what its priority needs now.
+    pub fn threshold_for(&self, transaction: &TransactionAccount) -> usize {
+        if self.live_threshold {
+            self.required_approvals(transaction.priority)
+        } else {
+            transaction.required_approvals as usize
+        }
+    }
+
+    /// Approvals of `transaction` counted at execution. With
+    /// `prune_removed_approvals` set, approvals from members removed since
+    /// the proposal are dropped.
+    pub fn effective_approvals(&self, transaction: &TransactionAccount) -> usize {
+        if self.prune_removed_approvals {
+            transaction.live_approval_count(&self.signers)
+        } else {
+            transaction.approval_count()
+        }
+    }
+
+    /// Whether a proposal may execute: enough effective member approvals
+    /// for its priority, or a token vote quorum reached.
+    pub fn is_approved(&self, transaction: &TransactionAccount) -> bool {
+        let signed = self.effective_approvals(transaction) >= self.threshold_for(transaction);
+        let voted = self
+            .token_vote
+            .as_ref()
+            .is_some_and(|token_vote| transaction.token_votes >= token_vote.required_amount);
+        signed || voted
+    }
+
+    /// End of the token vote window for a proposal made at `now`, if token
+    /// votes are enabled.
+    pub fn vote_ends_at(&self, now: i64) -> Option<i64> {
+        self.token_vote.as_ref().map(|token_vote| now.saturating_add(token_vote.vote_window_seconds))
+    }
+
+    /// Fails while the multisig is still waiting on member acceptances.
+    pub fn require_active(&self) -> Result<()> {
+        if self.status != MultisigStatus::Active {
+            return err!(MultisigWalletError::MultisigNotActive);
+        }
+
+        Ok(())
+    }
+}
+
+/// Lifecycle of a multisig created with an acceptance requirement.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MultisigStatus {
+    /// Waiting for the listed signers to accept membership
+    Pending,
+    Active,
+}
+
+/// Who may call `execute_transaction` and `execute_readonly`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExecutionPolicy {
+    /// Anyone may execute an approved proposal
+    Anyone,
+    /// Only members, or the automation authority for automatable proposals
+    SignersOnly,
+}
+
+/// Who pays the protocol fee when a proposal executes. `Vault` comes first,
+/// so multisigs created before the fee read it from their zeroed padding.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FeePayer {
+    /// The multisig's main vault
+    Vault,
+    /// Whoever executes, who must then sign
+    Executor,
+}
+
+/// Network a deployment declares it runs on. The genesis hash isn't
+/// readable on-chain, so the program config's admin declares it instead.
+/// `Unspecified` comes first, so proposals made before it read it from their
+/// zeroed padding.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cluster {
+    /// None declared, proposals run anywhere
+    Unspecified,
+    Mainnet,
+    Devnet,
+    Testnet,
+    Localnet,
+}
+
+/// The PDA that signs a proposal's instruction at execution.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuthorityKind {
+    /// The multisig account itself, for config-style CPIs
+    Multisig,
+    /// A system-owned vault; `0` is the original `["vault", multisig]` vault
+    /// and others are seeded `["vault", multisig, index]`
+    Vault(u16),
+}
+
+/// Furthest a multisig expiration may be set from the current time, about
+/// ten years.
+pub const MAX_EXPIRATION_HORIZON: i64 = 10 * 365 * 24 * 60 * 60;
+
+/// How `update_multisig` changes the multisig's expiration.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpirationUpdate {
+    Keep,
+    Clear,
+    Set { timestamp: i64 },
+}
+
+impl ExpirationUpdate {
+    /// The expiration to store, or `None` when it stays as `current`.
+    pub fn resolve(self, current: Option<i64>) -> Option<Option<i64>> {
+        let new = match self {
+            ExpirationUpdate::Keep => return None,
+            ExpirationUpdate::Clear => None,
+            ExpirationUpdate::Set { timestamp } => Some(timestamp),
+        };
+        (new != current).then_some(new)
+    }
+}
+
+/// Acceptance requirement for a new multisig, see `initialize_multisig`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Activation {
+    /// How many listed signers must accept; the signer count requires all
+    pub required_acceptances: u8,
+    /// Unix timestamp after which acceptances close and the creator may
+    /// close the multisig
+    pub deadline: i64,
+}
+
+/// Per-member bookkeeping kept on the multisig.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MemberActivity {
+    pub member: Pubkey,
+    pub last_proposed_at: i64,
+    pub last_rotated_at: i64,
+    /// Last proposal, approval or execution by the member; `0` until seen
+    pub last_action_at: i64,
+    /// Set by `flag_inactive_members`, cleared when the member next acts
+    pub inactive: bool,
+}
+
+impl MemberActivity {
+    pub const SIZE: usize = 32 + 8 + 8 + 8 + 1;
+}
+
+/// Upper bound on the configurable account meta cap. A CPI can't pass more
+/// account infos than this.
+pub const MAX_ACCOUNT_METAS: usize = 64;
+
+/// How many account metas a proposed instruction can carry unless configured
+/// otherwise.
+pub const DEFAULT_MAX_ACCOUNT_METAS: u8 = 32;
+
+/// Maximum length in bytes of a proposal memo.
+pub const MAX_MEMO_LEN: usize = 128;
+
+/// Maximum length in bytes of a note left with an approval.
+pub const MAX_APPROVAL_MEMO_LEN: usize = 64;
+
+/// Maximum length in bytes of the reason given with a rejection.
+pub const MAX_REJECTION_REASON_LEN: usize = 64;
+
+/// Maximum number of mints a multisig can cap.
+pub const MAX_MINT_CAPS: usize = 4;
+
+/// Maximum number of programs on the allowlist.
+pub const MAX_ALLOWED_PROGRAMS: usize = 8;
+
+/// Maximum number of instruction prefixes per allowed program.
+pub const MAX_INSTRUCTION_PREFIXES: usize = 4;
+
+/// Maximum length in bytes of an instruction prefix, enough for an Anchor
+/// discriminator.
+pub const MAX_INSTRUCTION_PREFIX_LEN: usize = 8;
+
+/// A program opaque proposals may target, optionally restricted to
+/// instructions whose data starts with one of `prefixes`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct AllowedProgram {
+    pub program_id: Pubkey,
+    pub prefixes: Vec<Vec<u8>>,
+}
+
+impl AllowedProgram {
+    pub const SIZE: usize = 32 + 4 + MAX_INSTRUCTION_PREFIXES * (4 + MAX_INSTRUCTION_PREFIX_LEN);
+}
+
+/// Per-period limit on tokens minted through typed mint_to proposals.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MintCap {
+    pub mint: Pubkey,
+    pub amount_per_period: u64,
+    pub period_seconds: i64,
+    pub period_start: i64,
+    pub minted_in_period: u64,
+}
+
+impl MintCap {
+    pub const SIZE: usize = 32 + 8 + 8 + 8 + 8;
+
+    // Records a mint against the current period, starting a new period once
+    // the previous one has elapsed
+    fn consume(&mut self, amount: u64, now: i64) -> Result<()> {
+        if now >= self.period_start.saturating_add(self.period_seconds) {
+            self.period_start = now;
+            self.minted_in_period = 0;
+        }
+
+        let minted = self
+            .minted_in_period
+            .checked_add(amount)
+            .ok_or(MultisigWalletError::MintCapExceeded)?;
+        if minted > self.amount_per_period {
+            return err!(MultisigWalletError::MintCapExceeded);
+        }
+        self.minted_in_period = minted;
+
+        Ok(())
+    }
+}
+
+/// Token authorities a typed set_authority proposal can change, mirroring
+/// the SPL Token `AuthorityType` values.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenAuthorityType {
+    MintTokens,
+    FreezeAccount,
+    AccountOwner,
+    CloseAccount,
+}
+
+impl TokenAuthorityType {
+    fn to_spl(self) -> spl_token_2022::instruction::AuthorityType {
+        use spl_token_2022::instruction::AuthorityType;
+        match self {
+            TokenAuthorityType::MintTokens => AuthorityType::MintTokens,
+            TokenAuthorityType::FreezeAccount => AuthorityType::FreezeAccount,
+            TokenAuthorityType::AccountOwner => AuthorityType::AccountOwner,
+            TokenAuthorityType::CloseAccount => AuthorityType::CloseAccount,
+        }
+    }
+}
+
+/// Configuration changes applied through `configure_multisig`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum ConfigAction {
+    SetMintCap { mint: Pubkey, amount_per_period: u64, period_seconds: i64 },
+    RemoveMintCap { mint: Pubkey },
+    SetExecutionMemo { enabled: bool },
+    SetOrderedExecution { enabled: bool, next_index: u64 },
+    SetInvalidateOnMemberChange { enabled: bool },
+    SetProposalCooldown { seconds: u32 },
+    SetMemberSwapWindow { seconds: i64 },
+    TransferAuthority { new_authority: Pubkey },
+    SetEmergencyTimelock { seconds: i64 },
+    SetRecovery { members: Vec<Pubkey>, threshold: u8, delay_seconds: i64 },
+    SetApproverProgram { program: Option<Pubkey>, max_approvals: u8 },
+    SetAllowedProgram { program_id: Pubkey, prefixes: Vec<Vec<u8>> },
+    RemoveAllowedProgram { program_id: Pubkey },
+    SetDefaultProposalTtl { seconds: i64 },
+    SetReimburseProposalRent { enabled: bool },
+    SetCleanupBounty { lamports: u64, vault_cap: u64 },
+    SetMaxAccountMetas { max: u8 },
+    CreateSubBudget { child: Pubkey, lamports: u64, mint_budgets: Vec<MintBudget>, expires_at: Option<i64> },
+    RevokeSubBudget { child: Pubkey },
+    SetKeyRotationCooldown { seconds: i64 },
+    SetReassignApprovalsOnRotation { enabled: bool },
+    SetRateLimit { mint: Option<Pubkey>, max_per_window: u64 },
+    RemoveRateLimit { mint: Option<Pubkey> },
+    SetPriorityLevel { priority: u8, timelock_seconds: i64, required_threshold: u8 },
+    RemovePriorityLevel { priority: u8 },
+    SetTokenVote { token_vote: Option<TokenVote> },
+    SetPruneRemovedApprovals { enabled: bool },
+    SetExecutionPolicy { policy: ExecutionPolicy },
+    SetAutomationAuthority { authority: Option<Pubkey> },
+    SetLiveThreshold { enabled: bool },
+    SetExecutionGrace { seconds: i64 },
+    SetAllowDangerousSystemInstructions { enabled: bool },
+    SetPayoutCap { mint: Option<Pubkey>, max_total: Option<u64> },
+    SetInactivityPeriod { seconds: i64 },
+    SetBudgetEpochs { epochs: Option<BudgetEpochs> },
+    SetEpochCap { mint: Option<Pubkey>, max_per_epoch: Option<u64> },
+    SetFeePayer { payer: FeePayer },
+    InviteSigner { invitee: Pubkey, roles: u8 },
+    SetTypedOnly { enabled: bool },
+    SetVoteCooldown { slots: u64 },
+    SetMemberAllowance { member: Pubkey, amount: Option<u64>, period_seconds: i64 },
+    SetAllowanceFrozen { member: Pubkey, frozen: bool },
+    SetConfirmationThreshold { bytes: u16 },
+    SetProposerCounts { enabled: bool },
+}
+
+#[account]
+pub struct TransactionAccount {
+    pub multisig: Pubkey,
+    pub proposer: Pubkey,
+    pub rent_payer: Pubkey,
+    pub tx_index: u64,
+    pub program_id: Pubkey,
+    pub accounts: Vec<u8>,
+    pub data: Vec<u8>,
+    pub action: Option<TypedAction>,
+    pub memo: Option<String>,
+    pub assertions: Vec<BalanceAssertion>,
+    pub signers: Vec<Pubkey>,
+    pub approval_memos: Vec<String>,
+    pub rejections: Vec<Pubkey>,
+    pub rejection_reasons: Vec<String>,
+    pub external_approvals: u8,
+    pub expires_at: Option<i64>,
+    pub read_only: bool,
+    pub escrowed: bool,
+    pub hash: [u8; 32],
+    pub priority: u8,
+    pub approved_at: Option<i64>,
+    pub authority_kind: AuthorityKind,
+    pub eligible_approvers: Vec<Pubkey>,
+    pub token_votes: u64,
+    pub vote_ends_at: Option<i64>,
+    pub automatable: bool,
+    pub required_approvals: u8,
+    pub category: u16,
+    pub created_at: i64,
+    pub created_slot: u64,
+    /// The partner multisig of a joint proposal, see `execute_joint`
+    pub joint_with: Option<Pubkey>,
+    /// An earlier proposal that must have executed first
+    pub depends_on: Option<u64>,
+    /// Compute the proposer expects the execution to need
+    pub compute: Option<ComputeDeclaration>,
+    /// Whether the execution may empty a vault rather than leave it
+    /// rent-exempt
+    pub sweep: bool,
+    pub executed: bool,
+    pub cancelled: bool,
+    pub owner_set_seqno: u32,
+    pub bump: u8,
+    /// Protocol fee stamped from the program config at proposal, paid on
+    /// execution
+    pub protocol_fee: u64,
+    /// Attempts `try_execute_transaction` recorded as failed
+    pub failed_attempts: u32,
+    /// Error of the last failed attempt, as the runtime reports it, `0` if
+    /// none failed
+    pub last_error_code: u64,
+    /// What the proposal does, derived from its payload at proposal and
+    /// checked against it again at execution
+    pub kind: TransactionKind,
+    /// Cluster stamped from the program config at proposal, checked again at
+    /// execution
+    pub cluster: Cluster,
+    /// Ordinal of the latest approval in `approval_indices`, `0` on
+    /// proposals made before approvals were indexed
+    pub approval_ordinal: u16,
+    /// Every approval as the approver's position in `eligible_approvers` and
+    /// its ordinal, sorted by position
+    pub approval_indices: Vec<ApprovalIndex>,
+    /// Slot of each eligible approver's latest vote, parallel to
+    /// `eligible_approvers`, `0` if it hasn't voted
+    pub last_vote_slots: Vec<u64>,
+    /// Number of ephemeral signer PDAs signed for at execution, see
+    /// `find_ephemeral_signer_address`
+    pub ephemeral_signers: u8,
+    /// Generation of the multisig stamped at proposal, checked at approval
+    /// and execution
+    pub generation: u64,
+    /// Encoding of `accounts`, `TRANSACTION_VERSION_LEGACY_METAS` on
+    /// proposals made before the compact encoding and not yet migrated
+    pub version: u8,
+    /// Whether the payload is large enough that execution waits for
+    /// `confirm_execution`, stamped at proposal
+    pub confirmation_required: bool,
+    /// Position in `eligible_approvers` of the member who confirmed the
+    /// execution, plus one; `0` until confirmed
+    pub confirmer: u16,
+    /// Whether executing it keeps an `ExecutionReceipt`, stamped at proposal
+    /// from the program config
+    pub receipt_required: bool,
+    /// Whether the proposer's approval is left out of `approval_count`,
+    /// stamped at proposal from the multisig's `proposer_excluded`
+    pub proposer_excluded: bool,
+    /// Whether the proposal is locked by `stage_execution`
+    pub staged: bool,
+    /// Whether the vault reimbursed the proposal's rent at execution, taken
+    /// from the reserved padding
+    pub rent_reimbursed: bool,
+    /// Padding for future fields, see `TRANSACTION_RESERVED_SPACE`
+    pub reserved: [u8; TRANSACTION_RESERVED_SPACE],
+}
+
+impl TransactionAccount {
+    pub fn space(
+        accounts_len: usize,
+        data_len: usize,
+        memo_len: usize,
+        assertions_len: usize,
+        max_signers: usize,
+    ) -> usize {
+        8 + // discriminator
+        32 + // multisig pubkey
+        32 + // proposer pubkey
+        32 + // rent payer pubkey
+        8 +  // tx_index
+        32 + // program_id
+        4 + accounts_len + // accounts vector
+        4 + data_len + // data vector
+        1 + TypedAction::SPACE + // optional typed action
+        1 + 4 + memo_len + // optional memo
+        4 + (assertions_len * BalanceAssertion::SIZE) + // balance assertions
+        4 + (max_signers * 32) + // signers vector (dynamic)
+        4 + (max_signers * (4 + MAX_APPROVAL_MEMO_LEN)) + // approval memos, parallel to signers
+        4 + (max_signers * 32) + // rejections
+        4 + (max_signers * (4 + MAX_REJECTION_REASON_LEN)) + // rejection reasons, parallel to rejections
+        1 + // executed
+        1 + // cancelled
+        4 + // owner set seqno at proposal
+        1 + // external approvals
+        9 + // optional expiry
+        1 + // read only
+        1 + // escrowed
+        32 + // canonical hash
+        1 + // priority
+        9 + // optional threshold reached time
+        3 + // authority kind
+        4 + (max_signers * 32) + // eligible approvers, snapshotted at proposal
+        8 + // governance tokens voted
+        9 + // optional end of the token vote window
+        1 + // automatable
+        1 + // required approvals, stamped at proposal
+        2 + // category
+        8 + // created at
+        8 + // created slot
+        1 + 32 + // optional joint partner
+        9 + // optional dependency
+        1 + ComputeDeclaration::SIZE + // optional compute declaration
+        1 + // sweep
+        1 + // bump
+        8 + // protocol fee
+        4 + // failed attempts
+        8 + // last error code
+        TransactionKind::SPACE + // kind, a config change's action comes on top
+        1 + // cluster
+        2 + // approval ordinal
+        4 + (max_signers * ApprovalIndex::SIZE) + // approval indices
+        4 + (max_signers * 8) + // last vote slots, parallel to eligible approvers
+        1 + // ephemeral signers
+        8 + // generation
+        1 + // version
+        1 + // confirmation required
+        2 + // confirmer
+        1 + // receipt required
+        1 + // proposer excluded
+        1 + // staged
+        1 + // rent reimbursed
+        TRANSACTION_RESERVED_SPACE // reserved padding
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now > expires_at)
+    }
+
+    /// Whether the proposal is past executing. A proposal that reached its
+    /// threshold before expiring stays executable for `grace_seconds` more.
+    pub fn execution_expired(&self, now: i64, grace_seconds: i64) -> bool {
+        let expires_at = match self.expires_at {
+            Some(expires_at) => expires_at,
+            None => return false,
+        };
+        let in_grace = self.approved_at.is_some_and(|approved_at| approved_at <= expires_at)
+            && now <= expires_at.saturating_add(grace_seconds);
+        now > expires_at && !in_grace
+    }
+
+    /// The proposal's account metas, decoded from whichever encoding
+    /// `version` says they're stored in.
+    pub fn account_metas(&self) -> Vec<AccountMeta> {
+        match self.version {
+            TRANSACTION_VERSION_LEGACY_METAS => deserialize_account_metas(&self.accounts),
+            _ => decode_compact_account_metas(&self.accounts),
+        }
+    }
+
+    /// The account metas in the 33-byte encoding they were proposed in. The
+    /// hashes cover this form, so they don't change when a proposal is
+    /// migrated to the compact encoding.
+    pub fn proposed_accounts(&self) -> Vec<u8> {
+        match self.version {
+            TRANSACTION_VERSION_LEGACY_METAS => self.accounts.clone(),
+            _ => serialize_account_metas(&self.account_metas()),
+        }
+    }
+
+    /// Canonical sha256 of what the proposal does: the target program,
+    /// account metas, instruction data, typed action, memo, expiry, joint
+    /// partner and dependency, Borsh-serialized so field boundaries are
+    /// unambiguous.
+    pub fn compute_hash(&self) -> [u8; 32] {
+        let content = (
+            &self.program_id,
+            &self.proposed_accounts(),
+            &self.data,
+            &self.action,
+            &self.memo,
+            &self.expires_at,
+            &self.joint_with,
+            &self.depends_on,
+            &self.sweep,
+            &self.cluster,
+            &self.generation,
+        );
+        hash(&content.try_to_vec().unwrap()).to_bytes()
+    }
+
+    /// For a joint proposal, the sha256 both sides share: the two multisigs
+    /// in key order, and the target program, account metas and instruction
+    /// data.
+    pub fn joint_operation_hash(&self) -> Option<[u8; 32]> {
+        let partner = self.joint_with?;
+        let pair = if self.multisig < partner { (self.multisig, partner) } else { (partner, self.multisig) };
+        let content = (&pair, &self.program_id, &self.proposed_accounts(), &self.data);
+        Some(hash(&content.try_to_vec().unwrap()).to_bytes())
+    }
+
+    /// Moves a rotated member's votes to their new key. Without `reassign`
+    /// the approval is dropped instead, so the new key has to approve again.
+    pub fn rotate_member(&mut self, old_key: &Pubkey, new_key: Pubkey, reassign: bool) {
+        if let Some(i) = self.signers.iter().position(|signer| signer == old_key) {
+            if reassign {
+                self.signers[i] = new_key;
+            } else {
+                self.signers.remove(i);
+                self.approval_memos.remove(i);
+                if let Some(member) = self.approver_position(old_key) {
+                    self.approval_indices.retain(|index| index.member != member);
+                }
+            }
+        }
+        if let Some(i) = self.rejections.iter().position(|signer| signer == old_key) {
+            self.rejections[i] = new_key;
+        }
+        if let Some(i) = self.eligible_approvers.iter().position(|approver| approver == old_key) {
+            self.eligible_approvers[i] = new_key;
+        }
+    }
+
+    /// `key`'s position in `eligible_approvers`, which `approval_indices`
+    /// refers to.
+    pub fn approver_position(&self, key: &Pubkey) -> Option<u16> {
+        self.eligible_approvers.iter().position(|approver| approver == key).map(|i| i as u16)
+    }
+
+    /// The member who confirmed the execution, see `confirm_execution`.
+    pub fn confirmed_by(&self) -> Option<Pubkey> {
+        let position = self.confirmer.checked_sub(1)?;
+        self.eligible_approvers.get(position as usize).copied()
+    }
+
+    /// Records `signer`'s approval in `approval_indices` with the next
+    /// ordinal, keeping the list sorted by position.
+    pub fn index_approval(&mut self, signer: &Pubkey) {
+        if let Some(member) = self.approver_position(signer) {
+            self.approval_ordinal = self.approval_ordinal.saturating_add(1);
+            let at = self.approval_indices.partition_point(|index| index.member < member);
+            self.approval_indices.insert(at, ApprovalIndex { member, ordinal: self.approval_ordinal });
+        }
+    }
+
+    /// Records `key`'s vote at `slot`, failing with `VoteTooSoon` if its
+    /// previous vote on the proposal was less than `cooldown_slots` before.
+    /// Proposals made before votes were tracked have no slots to check.
+    pub fn note_vote(&mut self, key: &Pubkey, slot: u64, cooldown_slots: u64) -> Result<()> {
+        let position = self.approver_position(key);
+        if let Some(last) = position.and_then(|i| self.last_vote_slots.get_mut(i as usize)) {
+            if *last > 0 && slot < last.saturating_add(cooldown_slots) {
+                return err!(MultisigWalletError::VoteTooSoon);
+            }
+            *last = slot;
+        }
+        Ok(())
+    }
+
+    /// Whether `key` was a member when the proposal was made.
+    pub fn is_eligible_approver(&self, key: &Pubkey) -> bool {
+        is_signer_in_multisig(&self.eligible_approvers, key)
+    }
+
+    /// Approvals counted at execution: those from approvers who are still
+    /// members, plus those granted by the registered approver program.
+    pub fn live_approval_count(&self, members: &[Pubkey]) -> usize {
+        let live = self
+            .signers
+            .iter()
+            .filter(|signer| is_signer_in_multisig(members, signer) && self.counts_approval_of(signer))
+            .count();
+        live + self.external_approvals as usize
+    }
+
+    /// Approvals counted towards the threshold: member approvals plus those
+    /// granted by the registered approver program. A proposer stamped
+    /// `proposer_excluded` stays among `signers` but isn't counted.
+    pub fn approval_count(&self) -> usize {
+        let uncounted = self.signers.iter().filter(|signer| !self.counts_approval_of(signer)).count();
+        self.signers.len() - uncounted + self.external_approvals as usize
+    }
+
+    /// Whether `signer`'s approval counts towards the threshold.
+    pub fn counts_approval_of(&self, signer: &Pubkey) -> bool {
+        !self.proposer_excluded || *signer != self.proposer
+    }
+
+    /// Stamps when the proposal first reached `required` approvals, which
+    /// starts its priority's timelock.
+    pub fn note_approvals(&mut self, required: usize, now: i64) {
+        if self.approved_at.is_none() && self.approval_count() >= required {
+            self.approved_at = Some(now);
+        }
+    }
+
+    /// Fails unless the stored kind is the one the payload describes.
+    pub fn check_kind(&self) -> Result<()> {
+        if TransactionKind::of(&self.program_id, &self.data, self.action.as_ref())? != self.kind {
+            return err!(MultisigWalletError::TransactionKindMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// What the proposal does, for `explain_transaction`, going by its kind.
+    pub fn explain(&self) -> Explanation {
+        match (&self.kind, &self.action) {
+            (TransactionKind::TypedAction(_), Some(action)) => Explanation::Action(action.clone()),
+            (TransactionKind::ConfigChange(action), _) => Explanation::Config(action.clone()),
+            _ => Explanation::Opaque {
+                program_id: self.program_id,
+                data_hash: hash(&self.data).to_bytes(),
+                compute: self.compute,
+            },
+        }
+    }
+}
+
+/// A proposal decoded for display, returned by `explain_transaction`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum Explanation {
+    /// A typed action: transfers, stake operations, mints and so on
+    Action(TypedAction),
+    /// A `configure_multisig` call on this program
+    Config(ConfigAction),
+    /// Any other instruction, identified by its program and a sha256 of its
+    /// data, with the compute its proposer declared
+    Opaque { program_id: Pubkey, data_hash: [u8; 32], compute: Option<ComputeDeclaration> },
+}
+
+/// Most compute units a transaction may request.
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Smallest and largest heap frame a transaction may request, in bytes.
+pub const MIN_HEAP_FRAME_BYTES: u32 = 32 * 1024;
+pub const MAX_HEAP_FRAME_BYTES: u32 = 256 * 1024;
+
+/// Compute an opaque proposal's execution is expected to need, so the
+/// executor's client can attach matching ComputeBudget instructions. The
+/// program doesn't request it itself; it's advisory.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ComputeDeclaration {
+    /// Units for `SetComputeUnitLimit`
+    pub units: u32,
+    /// Bytes for `RequestHeapFrame`, `0` for the default heap
+    pub heap_bytes: u32,
+}
+
+impl ComputeDeclaration {
+    pub const SIZE: usize = 4 + 4;
+
+    /// Whether the runtime could grant this: a non-zero unit limit within
+    /// the maximum, and either the default heap or a frame in 1 KiB steps
+    /// within the allowed range.
+    pub fn is_valid(&self) -> bool {
+        let heap_valid = self.heap_bytes == 0
+            || ((MIN_HEAP_FRAME_BYTES..=MAX_HEAP_FRAME_BYTES).contains(&self.heap_bytes)
+                && self.heap_bytes.is_multiple_of(1024));
+        self.units > 0 && self.units <= MAX_COMPUTE_UNITS && heap_valid
+    }
+
+    /// Whether `remaining_units` leave room for the execution. The declared
+    /// units cover the whole transaction, this program's own work included,
+    /// so only less than half of them left counts as clearly too little.
+    pub fn has_headroom(&self, remaining_units: u64) -> bool {
+        remaining_units.saturating_mul(2) >= u64::from(self.units)
+    }
+
+    /// Whether `consumed_units` came within 10% of the declared units.
+    pub fn is_near_limit(&self, consumed_units: u64) -> bool {
+        consumed_units.saturating_mul(10) >= u64::from(self.units) * 9
+    }
+}
+
+/// Domain tag opening every canonical approval message, so the bytes can't be
+/// mistaken for a Solana transaction or another protocol's message.
+pub const APPROVAL_MESSAGE_DOMAIN: &[u8] = b"multisig-wallet:approve";
+
+/// Version of the canonical approval message layout.
+pub const APPROVAL_MESSAGE_VERSION: u8 = 1;
+
+/// The bytes a member signs off-chain to approve `transaction` of `multisig`.
+///
+/// Version 1 layout, integers little-endian:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 1     | domain length (23) |
+/// | 23    | `APPROVAL_MESSAGE_DOMAIN` |
+/// | 1     | `APPROVAL_MESSAGE_VERSION` |
+/// | 2     | body length (81) |
+/// | 32    | multisig key |
+/// | 8     | `tx_index` |
+/// | 32    | transaction hash |
+/// | 1     | 1 if the proposal expires, else 0 |
+/// | 8     | `expires_at`, 0 if it doesn't expire |
+pub fn canonical_approval_message(multisig: &Pubkey, transaction: &TransactionAccount) -> Vec<u8> {
+    encode_approval_message(multisig, transaction.tx_index, &transaction.hash, transaction.expires_at)
+}
+
+/// `canonical_approval_message` built from its fields, for callers without a
+/// deserialized `TransactionAccount`.
+pub fn encode_approval_message(
+    multisig: &Pubkey,
+    tx_index: u64,
+    transaction_hash: &[u8; 32],
+    expires_at: Option<i64>,
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(81);
+    body.extend_from_slice(multisig.as_ref());
+    body.extend_from_slice(&tx_index.to_le_bytes());
+    body.extend_from_slice(transaction_hash);
+    body.push(u8::from(expires_at.is_some()));
+    body.extend_from_slice(&expires_at.unwrap_or(0).to_le_bytes());
+
+    let mut message = Vec::with_capacity(4 + APPROVAL_MESSAGE_DOMAIN.len() + body.len());
+    message.push(APPROVAL_MESSAGE_DOMAIN.len() as u8);
+    message.extend_from_slice(APPROVAL_MESSAGE_DOMAIN);
+    message.push(APPROVAL_MESSAGE_VERSION);
+    message.extend_from_slice(&(body.len() as u16).to_le_bytes());
+    message.extend_from_slice(&body);
+    message
+}
+
+/// What happened to a proposal, as linked into a multisig's history.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HistoryRecord {
+    Proposed,
+    Cancelled,
+    Closed,
+}
+
+/// The multisig's history head after linking `record` onto `previous`: the
+/// sha256 of `previous`, the transaction hash, and `tx_index` and `timestamp`
+/// as little-endian integers. Cancellations and closings append a tag byte,
+/// 1 and 2, so they can't be mistaken for a proposal. A new multisig's
+/// history starts at all zeros.
+pub fn link_history(
+    previous: &[u8; 32],
+    record: HistoryRecord,
+    transaction_hash: &[u8; 32],
+    tx_index: u64,
+    timestamp: i64,
+) -> [u8; 32] {
+    let mut bytes = Vec::with_capacity(81);
+    bytes.extend_from_slice(previous);
+    bytes.extend_from_slice(transaction_hash);
+    bytes.extend_from_slice(&tx_index.to_le_bytes());
+    bytes.extend_from_slice(&timestamp.to_le_bytes());
+    match record {
+        HistoryRecord::Proposed => {}
+        HistoryRecord::Cancelled => bytes.push(1),
+        HistoryRecord::Closed => bytes.push(2),
+    }
+    hash(&bytes).to_bytes()
+}
+
+/// Maximum number of vault token accounts the deposit ledger tracks.
+pub const MAX_TRACKED_TOKEN_ACCOUNTS: usize = 8;
+
+/// The vault balances last seen by `record_deposit`, which reports deposits
+/// as the change since this snapshot.
+#[account]
+pub struct DepositLedger {
+    pub multisig: Pubkey,
+    pub lamports: u64,
+    pub tokens: Vec<TrackedBalance>,
+    pub last_recorded_at: i64,
+    pub bump: u8,
+}
+
+impl DepositLedger {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        8 + // vault lamports
+        4 + (MAX_TRACKED_TOKEN_ACCOUNTS * TrackedBalance::SIZE) + // token balances
+        8 + // last recorded at
+        1; // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TrackedBalance {
+    pub account: Pubkey,
+    pub amount: u64,
+}
+
+impl TrackedBalance {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Maximum number of token accounts a single snapshot records.
+pub const MAX_SNAPSHOT_TOKEN_ACCOUNTS: usize = 8;
+
+/// Treasury holdings at a point in time, see `snapshot_balances`.
+#[account]
+pub struct Snapshot {
+    pub multisig: Pubkey,
+    pub index: u64,
+    pub lamports: u64,
+    pub holdings: Vec<TokenHolding>,
+    pub taken_at: i64,
+    pub bump: u8,
+}
+
+impl Snapshot {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        8 + // snapshot index
+        8 + // vault lamports
+        4 + (MAX_SNAPSHOT_TOKEN_ACCOUNTS * TokenHolding::SIZE) + // token holdings
+        8 + // taken at
+        1; // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokenHolding {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl TokenHolding {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Maximum number of `(category, mint)` totals tracked per period.
+pub const MAX_CATEGORY_OUTFLOWS: usize = 16;
+
+/// Outflows of categorized typed transfers since the period started, per
+/// category and mint. Category meanings are up to clients.
+#[account]
+pub struct CategoryStats {
+    pub multisig: Pubkey,
+    pub outflows: Vec<CategoryOutflow>,
+    pub period_started_at: i64,
+    pub bump: u8,
+}
+
+impl CategoryStats {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        4 + (MAX_CATEGORY_OUTFLOWS * CategoryOutflow::SIZE) + // totals
+        8 + // period start
+        1; // bump
+
+    /// Adds an outflow of `mint` (`None` for SOL) to the category's total.
+    pub fn record(&mut self, category: u16, mint: Option<Pubkey>, amount: u64) -> Result<()> {
+        match self.outflows.iter_mut().find(|total| total.category == category && total.mint == mint) {
+            Some(total) => total.amount = total.amount.saturating_add(amount),
+            None => {
+                if self.outflows.len() >= MAX_CATEGORY_OUTFLOWS {
+                    return err!(MultisigWalletError::TooManyCategoryOutflows);
+                }
+                self.outflows.push(CategoryOutflow { category, mint, amount });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct CategoryOutflow {
+    pub category: u16,
+    /// `None` for SOL
+    pub mint: Option<Pubkey>,
+    pub amount: u64,
+}
+
+impl CategoryOutflow {
+    pub const SIZE: usize = 2 + 33 + 8;
+}
+
+/// How many multisigs were created at the address seeded by `create_key`,
+/// at `["generation", create_key]`. `close_multisig` leaves it behind, so a
+/// multisig re-created at the same address gets a new generation.
+#[account]
+pub struct MultisigGeneration {
+    pub generation: u64,
+    pub bump: u8,
+}
+
+impl MultisigGeneration {
+    pub const SPACE: usize = 8 + // discriminator
+        8 + // generation
+        1; // bump
+}
+
+/// Which of a multisig's proposals may still be open, so clients can fetch
+/// the transaction accounts in `[first_open_index, nonce)` instead of
+/// scanning them all, and skip those known to be closed. All zeros, as on
+/// multisigs created before it, means any index may be open.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct OpenProposalWindow {
+    /// Every proposal below it executed, was cancelled or closed, or had
+    /// its reserved index released
+    pub first_open_index: u64,
+    /// Bit `i` is set when proposal `first_open_index + i` is known to be
+    /// closed; a clear bit means it may be open
+    pub closed: u64,
+}
+
+impl OpenProposalWindow {
+    pub const SIZE: usize = 8 + 8;
+
+    /// Indices tracked from `first_open_index`.
+    pub const WIDTH: u64 = 64;
+
+    /// Records that proposal `tx_index` is closed, then moves the window
+    /// past the closed indices at its front, never beyond `nonce`. An index
+    /// beyond the window isn't recorded; `sync_open_window` catches up on it
+    /// once the window gets there.
+    pub fn note_closed(&mut self, tx_index: u64, nonce: u64) {
+        let offset = tx_index.wrapping_sub(self.first_open_index);
+        if tx_index >= self.first_open_index && offset < Self::WIDTH {
+            self.closed |= 1 << offset;
+        }
+
+        let advance = u64::from(self.closed.trailing_ones()).min(nonce.saturating_sub(self.first_open_index));
+        self.closed = self.closed.checked_shr(advance as u32).unwrap_or(0);
+        self.first_open_index += advance;
+    }
+
+    /// Whether proposal `tx_index` may still be open, for a multisig at
+    /// `nonce`.
+    pub fn may_be_open(&self, tx_index: u64, nonce: u64) -> bool {
+        if tx_index < self.first_open_index || tx_index >= nonce {
+            return false;
+        }
+        let offset = tx_index - self.first_open_index;
+        offset >= Self::WIDTH || self.closed & (1 << offset) == 0
+    }
+}
+
+/// Maximum number of vault token accounts the registry lists, so a snapshot
+/// can always cover all of them.
+pub const MAX_REGISTERED_TOKEN_ACCOUNTS: usize = MAX_SNAPSHOT_TOKEN_ACCOUNTS;
+
+/// The vault's token accounts known to the program, filled by
+/// `create_vault_ata` and wrapping SOL. `close_multisig` and
+/// `snapshot_balances` refuse to leave any of them out.
+#[account]
+pub struct TokenRegistry {
+    pub multisig: Pubkey,
+    pub accounts: Vec<RegisteredTokenAccount>,
+    pub bump: u8,
+}
+
+impl TokenRegistry {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        4 + (MAX_REGISTERED_TOKEN_ACCOUNTS * RegisteredTokenAccount::SIZE) + // registered accounts
+        1; // bump
+
+    /// Whether `address` is registered.
+    pub fn contains(&self, address: &Pubkey) -> bool {
+        self.accounts.iter().any(|entry| entry.address == *address)
+    }
+
+    /// Registers a token account, once.
+    pub fn register(&mut self, address: Pubkey, mint: Pubkey) -> Result<()> {
+        if self.contains(&address) {
+            return Ok(());
+        }
+        if self.accounts.len() >= MAX_REGISTERED_TOKEN_ACCOUNTS {
+            return err!(MultisigWalletError::TokenRegistryFull);
+        }
+        self.accounts.push(RegisteredTokenAccount { address, mint });
+
+        Ok(())
+    }
+
+    /// Drops a token account from the registry, if listed.
+    pub fn unregister(&mut self, address: &Pubkey) {
+        self.accounts.retain(|entry| entry.address != *address);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RegisteredTokenAccount {
+    pub address: Pubkey,
+    pub mint: Pubkey,
+}
+
+impl RegisteredTokenAccount {
+    pub const SIZE: usize = 32 + 32;
+}
+
+/// Maximum number of external actions a multisig can register.
+pub const MAX_REGISTERED_ACTIONS: usize = 8;
+
+/// Longest tag a registered action can have, in bytes.
+pub const MAX_ACTION_TAG_LEN: usize = 32;
+
+/// Longest discriminator a registered action's instruction data can start
+/// with.
+pub const MAX_ACTION_DISCRIMINATOR_LEN: usize = 8;
+
+/// Most arguments and accounts a registered action's template can declare.
+pub const MAX_ACTION_ARGS: usize = 6;
+pub const MAX_ACTION_ACCOUNTS: usize = 8;
+
+/// External instructions the board vetted for `propose_registered_action`,
+/// at `["action_registry", multisig]`. Filled by `register_external_action`
+/// and emptied by `remove_external_action`.
+#[account]
+pub struct ActionRegistry {
+    pub multisig: Pubkey,
+    pub actions: Vec<RegisteredAction>,
+    pub bump: u8,
+}
+
+impl ActionRegistry {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        4 + (MAX_REGISTERED_ACTIONS * RegisteredAction::MAX_SIZE) + // registered actions
+        1; // bump
+
+    /// The action registered as `tag`, if any.
+    pub fn find(&self, tag: &str) -> Option<&RegisteredAction> {
+        self.actions.iter().find(|action| action.tag == tag)
+    }
+
+    /// Registers an action under a tag not taken yet.
+    pub fn register(&mut self, action: RegisteredAction) -> Result<()> {
+        if self.find(&action.tag).is_some() {
+            return err!(MultisigWalletError::ActionAlreadyRegistered);
+        }
+        if self.actions.len() >= MAX_REGISTERED_ACTIONS {
+            return err!(MultisigWalletError::ActionRegistryFull);
+        }
+        self.actions.push(action);
+
+        Ok(())
+    }
+
+    /// Drops the action registered as `tag`.
+    pub fn unregister(&mut self, tag: &str) -> Result<()> {
+        if self.find(tag).is_none() {
+            return err!(MultisigWalletError::ActionNotRegistered);
+        }
+        self.actions.retain(|action| action.tag != tag);
+
+        Ok(())
+    }
+}
+
+/// An external instruction template: the instruction data is the
+/// discriminator followed by the arguments, each serialized like Borsh
+/// does, and the accounts are resolved from the vault, fixed addresses and
+/// the arguments.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RegisteredAction {
+    /// Name proposals refer to the action by
+    pub tag: String,
+    pub program_id: Pubkey,
+    pub discriminator: Vec<u8>,
+    /// Types of the arguments a proposal supplies, in order
+    pub args: Vec<ArgKind>,
+    /// The instruction's accounts, in order
+    pub accounts: Vec<TemplateAccount>,
+}
+
+impl RegisteredAction {
+    pub const MAX_SIZE: usize = 4 + MAX_ACTION_TAG_LEN + // tag
+        32 + // program id
+        4 + MAX_ACTION_DISCRIMINATOR_LEN + // discriminator
+        4 + MAX_ACTION_ARGS + // argument types
+        4 + (MAX_ACTION_ACCOUNTS * TemplateAccount::SIZE); // accounts
+
+    /// A sha256 of the whole template, pinned by the proposals made from
+    /// it.
+    pub fn hash(&self) -> [u8; 32] {
+        hash(&self.try_to_vec().unwrap()).to_bytes()
+    }
+
+    /// Checks the template is within bounds, doesn't target this program,
+    /// and that every account taken from an argument names a pubkey one.
+    pub fn validate(&self) -> Result<()> {
+        if self.tag.is_empty() || self.tag.len() > MAX_ACTION_TAG_LEN {
+            return err!(MultisigWalletError::InvalidActionTemplate);
+        }
+        if self.program_id == crate::ID {
+            return err!(MultisigWalletError::RecursiveCallNotAllowed);
+        }
+        if self.discriminator.is_empty()
+            || self.discriminator.len() > MAX_ACTION_DISCRIMINATOR_LEN
+            || self.args.len() > MAX_ACTION_ARGS
+            || self.accounts.len() > MAX_ACTION_ACCOUNTS
+        {
+            return err!(MultisigWalletError::InvalidActionTemplate);
+        }
+
+        for (i, account) in self.accounts.iter().enumerate() {
+            let arg = match account.source {
+                AccountSource::Arg(arg) | AccountSource::VaultTokenAccount { mint_arg: arg, .. } => arg,
+                AccountSource::Fixed(_) | AccountSource::Vault => continue,
+            };
+            if self.args.get(usize::from(arg)) != Some(&ArgKind::Pubkey) {
+                msg!("Account {} takes argument {}, which isn't a pubkey", i, arg);
+                return err!(MultisigWalletError::InvalidActionTemplate);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks `args` match the declared argument types one for one.
+    pub fn check_args(&self, args: &[ActionArg]) -> Result<()> {
+        if args.len() != self.args.len() {
+            msg!("{} takes {} arguments, got {}", self.tag, self.args.len(), args.len());
+            return err!(MultisigWalletError::InvalidActionArgs);
+        }
+        if let Some(i) = args.iter().zip(&self.args).position(|(arg, kind)| arg.kind() != *kind) {
+            msg!("Argument {} of {} should be {:?}", i, self.tag, self.args[i]);
+            return err!(MultisigWalletError::InvalidActionArgs);
+        }
+
+        Ok(())
+    }
+
+    /// The instruction `args` make of the template, with `vault` signing.
+    pub fn build_instruction(&self, args: &[ActionArg], vault: &Pubkey) -> Result<Instruction> {
+        self.check_args(args)?;
+
+        let mut data = self.discriminator.clone();
+        for arg in args {
+            arg.encode(&mut data);
+        }
+
+        let pubkey_arg = |index: u8| match args[usize::from(index)] {
+            ActionArg::Pubkey(key) => key,
+            // validate() only lets accounts name pubkey arguments
+            _ => unreachable!(),
+        };
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|account| {
+                let (pubkey, is_signer) = match &account.source {
+                    AccountSource::Fixed(key) => (*key, false),
+                    AccountSource::Vault => (*vault, true),
+                    AccountSource::VaultTokenAccount { mint_arg, token_program } => {
+                        let mint = pubkey_arg(*mint_arg);
+                        (get_associated_token_address_with_program_id(vault, &mint, token_program), false)
+                    }
+                    AccountSource::Arg(index) => (pubkey_arg(*index), false),
+                };
+                AccountMeta { pubkey, is_signer, is_writable: account.is_writable }
+            })
+            .collect();
+
+        Ok(Instruction { program_id: self.program_id, accounts, data })
+    }
+}
+
+/// The type of a registered action's argument.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    Bool,
+    Pubkey,
+}
+
+/// A proposal's value for a registered action's argument.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ActionArg {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Bool(bool),
+    Pubkey(Pubkey),
+}
+
+impl ActionArg {
+    pub const MAX_SIZE: usize = 1 + 32;
+
+    pub fn kind(&self) -> ArgKind {
+        match self {
+            ActionArg::U8(_) => ArgKind::U8,
+            ActionArg::U16(_) => ArgKind::U16,
+            ActionArg::U32(_) => ArgKind::U32,
+            ActionArg::U64(_) => ArgKind::U64,
+            ActionArg::Bool(_) => ArgKind::Bool,
+            ActionArg::Pubkey(_) => ArgKind::Pubkey,
+        }
+    }
+
+    /// Appends the value to instruction data the way Borsh serializes it.
+    pub fn encode(&self, data: &mut Vec<u8>) {
+        match self {
+            ActionArg::U8(value) => data.push(*value),
+            ActionArg::U16(value) => data.extend(value.to_le_bytes()),
+            ActionArg::U32(value) => data.extend(value.to_le_bytes()),
+            ActionArg::U64(value) => data.extend(value.to_le_bytes()),
+            ActionArg::Bool(value) => data.push(u8::from(*value)),
+            ActionArg::Pubkey(value) => data.extend(value.to_bytes()),
+        }
+    }
+}
+
+/// One account of a registered action's instruction.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TemplateAccount {
+    pub source: AccountSource,
+    pub is_writable: bool,
+}
+
+impl TemplateAccount {
+    pub const SIZE: usize = AccountSource::MAX_SIZE + 1;
+}
+
+/// Where a template account's address comes from.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum AccountSource {
+    /// Always this address, like the target program's state
+    Fixed(Pubkey),
+    /// The vault, signing
+    Vault,
+    /// The vault's associated token account for the mint in the given
+    /// argument
+    VaultTokenAccount { mint_arg: u8, token_program: Pubkey },
+    /// The pubkey in the given argument
+    Arg(u8),
+}
+
+impl AccountSource {
+    pub const MAX_SIZE: usize = 1 + 1 + 32;
+}
+
+/// Maximum number of child multisigs holding a budget.
+pub const MAX_SUB_BUDGETS: usize = 4;
+
+/// Maximum number of mints a single budget covers.
+pub const MAX_BUDGET_MINTS: usize = 2;
+
+/// Funds a child multisig may spend from the vault without the parent's
+/// quorum, see `spend_from_budget`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SubBudget {
+    pub child: Pubkey,
+    pub lamports: u64,
+    pub mint_budgets: Vec<MintBudget>,
+    pub expires_at: Option<i64>,
+}
+
+impl SubBudget {
+    pub const SIZE: usize = 32 + 8 + 4 + (MAX_BUDGET_MINTS * MintBudget::SIZE) + 9;
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now > expires_at)
+    }
+}
+
+/// A member's personal allowance from the vault, claimed alone with
+/// `claim_allowance`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MemberAllowance {
+    pub member: Pubkey,
+    /// Lamports the member may claim per period
+    pub amount: u64,
+    pub period_seconds: i64,
+    /// Lamports left to claim in the current period
+    pub remaining: u64,
+    /// Start of the current period
+    pub last_reset: i64,
+    /// Whether claims are suspended, see `SetAllowanceFrozen`
+    pub frozen: bool,
+}
+
+impl MemberAllowance {
+    pub const SIZE: usize = 32 + 8 + 8 + 8 + 8 + 1;
+
+    // Takes a claim from the current period, refilling the allowance once
+    // the previous period has elapsed
+    fn claim(&mut self, amount: u64, now: i64) -> Result<()> {
+        if now >= self.last_reset.saturating_add(self.period_seconds) {
+            self.last_reset = now;
+            self.remaining = self.amount;
+        }
+
+        self.remaining = self
+            .remaining
+            .checked_sub(amount)
+            .ok_or(MultisigWalletError::AllowanceExceeded)?;
+
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MintBudget {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl MintBudget {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Maximum number of assets with a payout cap.
+pub const MAX_PAYOUT_CAPS: usize = 4;
+
+/// The most one `propose_payout` may send in total of an asset (SOL when
+/// `mint` is `None`).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct PayoutCap {
+    pub mint: Option<Pubkey>,
+    pub max_total: u64,
+}
+
+impl PayoutCap {
+    pub const SIZE: usize = 33 + 8;
+}
+
+/// Maximum number of recipients in one payout.
+pub const MAX_PAYOUT_ENTRIES: usize = 20;
+
+/// One recipient of a payout and the lamports or token base units it gets.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PayoutEntry {
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+impl PayoutEntry {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Maximum number of rate-limited assets.
+pub const MAX_RATE_LIMITS: usize = 4;
+
+/// Length of a rate limit window.
+pub const RATE_LIMIT_WINDOW: i64 = 24 * 60 * 60;
+
+/// How long a raised (or removed) rate limit waits before taking effect.
+pub const RATE_LIMIT_RAISE_DELAY: i64 = 2 * 24 * 60 * 60;
+
+/// How long switching `typed_only` off waits before taking effect.
+pub const TYPED_ONLY_DISABLE_DELAY: i64 = 2 * 24 * 60 * 60;
+
+/// The most of one asset (SOL when `mint` is `None`) that may leave the
+/// vault per window, regardless of approvals.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RateLimit {
+    pub mint: Option<Pubkey>,
+    pub max_per_window: u64,
+    pub window_start: i64,
+    pub spent_in_window: u64,
+    pub pending_max: Option<u64>,
+    pub pending_effective_at: i64,
+}
+
+impl RateLimit {
+    pub const SIZE: usize = 33 + 8 + 8 + 8 + 9 + 8;
+
+    // Applies a scheduled raise once its delay has passed
+    fn current_max(&mut self, now: i64) -> u64 {
+        if let Some(max) = self.pending_max {
+            if now >= self.pending_effective_at {
+                self.max_per_window = max;
+                self.pending_max = None;
+            }
+        }
+        self.max_per_window
+    }
+
+    // Records an outflow against the current window, starting a new window
+    // once the previous one has elapsed
+    fn consume(&mut self, amount: u64, now: i64) -> Result<()> {
+        let max = self.current_max(now);
+        if now >= self.window_start.saturating_add(RATE_LIMIT_WINDOW) {
+            self.window_start = now;
+            self.spent_in_window = 0;
+        }
+
+        let spent = self
+            .spent_in_window
+            .checked_add(amount)
+            .ok_or(MultisigWalletError::RateLimitExceeded)?;
+        if spent > max {
+            return err!(MultisigWalletError::RateLimitExceeded);
+        }
+        self.spent_in_window = spent;
+
+        Ok(())
+    }
+}
+
+/// Fixed-length budget epochs counted from `start`, such as quarters.
+/// Which epoch a moment falls in follows from the clock alone, so caps roll
+/// over without anyone resetting them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BudgetEpochs {
+    pub start: i64,
+    pub length_seconds: i64,
+}
+
+impl BudgetEpochs {
+    pub const SIZE: usize = 8 + 8;
+
+    /// The epoch `now` falls in. Times before `start` count towards the
+    /// first epoch.
+    pub fn epoch_at(&self, now: i64) -> u64 {
+        (now.saturating_sub(self.start).max(0) / self.length_seconds) as u64
+    }
+}
+
+/// Maximum number of assets with an epoch cap.
+pub const MAX_EPOCH_CAPS: usize = 4;
+
+/// The most of one asset (SOL when `mint` is `None`) that may leave the
+/// vault per budget epoch. Allowance left at the end of an epoch lapses.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct EpochCap {
+    pub mint: Option<Pubkey>,
+    pub max_per_epoch: u64,
+    /// The epoch `spent_in_epoch` belongs to
+    pub epoch: u64,
+    pub spent_in_epoch: u64,
+}
+
+impl EpochCap {
+    pub const SIZE: usize = 33 + 8 + 8 + 8;
+
+    /// Records an outflow in `epoch`, starting from zero in a later epoch.
+    /// A clock reading behind the recorded epoch, from skew around a
+    /// boundary, counts towards the recorded epoch, so the previous epoch's
+    /// allowance can't be reopened.
+    pub fn consume(&mut self, amount: u64, epoch: u64) -> Result<()> {
+        if epoch > self.epoch {
+            self.epoch = epoch;
+            self.spent_in_epoch = 0;
+        }
+
+        let spent = self
+            .spent_in_epoch
+            .checked_add(amount)
+            .ok_or(MultisigWalletError::EpochCapExceeded)?;
+        if spent > self.max_per_epoch {
+            return err!(MultisigWalletError::EpochCapExceeded);
+        }
+        self.spent_in_epoch = spent;
+
+        Ok(())
+    }
+}
+
+/// Maximum number of configured priority levels.
+pub const MAX_PRIORITY_LEVELS: usize = 4;
+
+/// Timelock and approval requirement for proposals tagged with `priority`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct PriorityLevel {
+    pub priority: u8,
+    /// Delay between reaching the threshold and execution
+    pub timelock_seconds: i64,
+    /// Approvals required; never below the base threshold
+    pub required_threshold: u8,
+}
+
+impl PriorityLevel {
+    pub const SIZE: usize = 1 + 8 + 1;
+}
+
+/// Token-weighted approval, the alternative to member signatures: a
+/// proposal also passes once `required_amount` of `mint` is locked for it
+/// within `vote_window_seconds` of its proposal.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokenVote {
+    pub mint: Pubkey,
+    pub required_amount: u64,
+    pub vote_window_seconds: i64,
+}
+
+impl TokenVote {
+    pub const SIZE: usize = 32 + 8 + 8;
+}
+
+/// Maximum number of recovery members.
+pub const MAX_RECOVERY_MEMBERS: usize = 5;
+
+/// A pending replacement of the signer set by the recovery members, see
+/// `propose_recovery`.
+#[account]
+pub struct RecoveryProposal {
+    pub multisig: Pubkey,
+    pub proposer: Pubkey,
+    pub new_signers: Vec<Pubkey>,
+    pub new_threshold: u8,
+    pub approvals: Vec<Pubkey>,
+    pub approved_at: Option<i64>,
+    pub bump: u8,
+}
+
+impl RecoveryProposal {
+    pub fn space(new_signers_len: usize) -> usize {
+        8 + // discriminator
+        32 + // multisig pubkey
+        32 + // proposer pubkey
+        4 + (new_signers_len * 32) + // new signers
+        1 + // new threshold
+        4 + (MAX_RECOVERY_MEMBERS * 32) + // approvals by recovery members
+        9 + // optional approval time
+        1 // bump
+    }
+
+    /// Starts the recovery delay once the recovery threshold is reached.
+    pub fn check_approved(&mut self, threshold: u8, now: i64) {
+        if self.approved_at.is_none() && self.approvals.len() >= threshold as usize {
+            self.approved_at = Some(now);
+        }
+    }
+}
+
+/// How long an emergency threshold reduction is timelocked unless configured
+/// otherwise.
+pub const DEFAULT_EMERGENCY_TIMELOCK: i64 = 30 * 24 * 60 * 60;
+
+/// A pending emergency threshold reduction.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct EmergencyThreshold {
+    pub new_threshold: u8,
+    pub initiator: Pubkey,
+    pub unlocks_at: i64,
+}
+
+impl EmergencyThreshold {
+    pub const SIZE: usize = 1 + 32 + 8;
+}
+
+/// How long a member swap stays open unless configured otherwise.
+pub const DEFAULT_MEMBER_SWAP_WINDOW: i64 = 7 * 24 * 60 * 60;
+
+/// A pending replacement of the signer set, see `propose_member_swap`.
+#[account]
+pub struct MemberSwap {
+    pub multisig: Pubkey,
+    pub proposer: Pubkey,
+    pub new_signers: Vec<Pubkey>,
+    pub new_threshold: u8,
+    pub approvals: Vec<Pubkey>,
+    pub accepted: Vec<Pubkey>,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl MemberSwap {
+    pub fn space(new_signers_len: usize, max_signers: usize) -> usize {
+        8 + // discriminator
+        32 + // multisig pubkey
+        32 + // proposer pubkey
+        4 + (new_signers_len * 32) + // new signers
+        1 + // new threshold
+        4 + (max_signers * 32) + // approvals by current signers
+        4 + (new_signers_len * 32) + // acceptances by incoming signers
+        8 + // expires at
+        1 // bump
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        now > self.expires_at
+    }
+
+    /// Signers joining the multisig, who must accept before finalization.
+    pub fn incoming<'a>(&'a self, current: &'a [Pubkey]) -> impl Iterator<Item = &'a Pubkey> + 'a {
+        self.new_signers.iter().filter(move |signer| !current.contains(signer))
+    }
+}
+
+/// How long an invitation stays open.
+pub const INVITATION_WINDOW: i64 = 72 * 60 * 60;
+
+/// An open invitation to join the multisig, see `accept_invitation`.
+#[account]
+pub struct Invitation {
+    pub multisig: Pubkey,
+    pub invitee: Pubkey,
+    /// Role flags for the new member, kept for off-chain tooling; the
+    /// program doesn't interpret them
+    pub roles: u8,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl Invitation {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        32 + // invitee pubkey
+        1 + // roles
+        8 + // expires at
+        1; // bump
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        now > self.expires_at
+    }
+}
+
+/// How long a nonce reservation holds, in seconds.
+pub const NONCE_RESERVATION_TTL: i64 = 10 * 60;
+
+/// A transaction index set aside by `reserve_nonce` for
+/// `propose_transaction_with_reservation`.
+#[account]
+pub struct NonceReservation {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub reserver: Pubkey,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl NonceReservation {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        8 + // reserved index
+        32 + // reserver pubkey
+        8 + // expires at
+        1; // bump
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        now > self.expires_at
+    }
+}
+
+/// A lasting record of an executed proposal at `["receipt", multisig,
+/// tx_index]`, kept when the program config asks for one. Small enough to
+/// keep after the transaction account is closed.
+#[account]
+pub struct ExecutionReceipt {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    /// The multisig's generation the proposal was made for
+    pub generation: u64,
+    pub transaction_hash: [u8; 32],
+    pub executor: Option<Pubkey>,
+    pub executed_at: i64,
+    pub category: u16,
+    /// Mint a typed action moved, `None` for SOL or when nothing moved
+    pub mint: Option<Pubkey>,
+    /// Amount a typed action moved, `0` for other proposals
+    pub amount: u64,
+    pub bump: u8,
+}
+
+impl ExecutionReceipt {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        8 + // transaction index
+        8 + // generation
+        32 + // transaction hash
+        1 + 32 + // optional executor
+        8 + // executed at
+        2 + // category
+        1 + 32 + // optional mint
+        8 + // amount
+        1; // bump
+}
+
+/// Most accounts `stage_execution` takes, enough for the program config and
+/// a dependency with room to spare.
+pub const MAX_STAGED_ACCOUNTS: usize = 4;
+
+/// Seconds after staging until anyone may release a staged execution.
+pub const STAGING_TIMEOUT: i64 = 10 * 60;
+
+/// A proposal locked by `stage_execution` at `["stage", transaction]`,
+/// closed when the execution completes or is released.
+#[account]
+pub struct ExecutionStage {
+    pub multisig: Pubkey,
+    pub transaction: Pubkey,
+    /// The member who staged it, the only one who may complete it
+    pub executor: Pubkey,
+    /// `chain` of the proposal's hash and `staged_accounts`
+    pub staging_hash: [u8; 32],
+    /// The accounts passed to `stage_execution`, in order
+    pub staged_accounts: Vec<Pubkey>,
+    pub staged_at: i64,
+    pub bump: u8,
+}
+
+impl ExecutionStage {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // multisig pubkey
+        32 + // transaction pubkey
+        32 + // executor
+        32 + // staging hash
+        4 + (MAX_STAGED_ACCOUNTS * 32) + // staged accounts
+        8 + // staged at
+        1; // bump
+
+    /// One link of the staging hash chain: a sha256 of the previous link
+    /// and the accounts passed in the next step.
+    pub fn chain(previous: &[u8; 32], accounts: &[Pubkey]) -> [u8; 32] {
+        hash(&(previous, accounts).try_to_vec().unwrap()).to_bytes()
+    }
+}
+
+/// Most proposals `propose_transactions_batch` accepts; more wouldn't fit in
+/// a transaction anyway.
+pub const MAX_BATCH_PROPOSALS: usize = 5;
+
+/// Most ephemeral signers a proposal may declare.
+pub const MAX_EPHEMERAL_SIGNERS: usize = 8;
+
+/// How many proposals before a new one are checked for being handed to its
+/// CPI as writable, bounding the PDA derivations a proposal costs.
+pub const SELF_REFERENCE_LOOKBACK: u64 = 8;
+
+/// One proposal of `propose_transactions_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BatchProposal {
+    pub program_id: Pubkey,
+    pub accounts: Vec<u8>,
+    pub instruction_data: Vec<u8>,
+    pub memo: Option<String>,
+    pub category: u16,
+}
+
+/// The optional parameters of `propose_transaction` and
+/// `propose_transaction_with_reservation`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ProposalOptions {
+    /// Optional note recorded through the Memo program on execution
+    pub memo: Option<String>,
+    /// Balance changes that must hold after the CPI, or the execution is
+    /// rolled back
+    pub assertions: Vec<BalanceAssertion>,
+    /// Optional lifetime of the proposal, capped by the multisig's default
+    /// proposal TTL
+    pub ttl_seconds: Option<i64>,
+    /// Whether the proposal must run through `execute_readonly`, with every
+    /// account passed to the target as read-only
+    pub read_only: bool,
+    /// Priority level of the proposal; `0` is normal, any other level must
+    /// be configured with `SetPriorityLevel`
+    pub priority: u8,
+    /// The PDA that signs the instruction at execution, either the multisig
+    /// itself or one of its vaults
+    pub authority_kind: AuthorityKind,
+    /// Whether the registered automation authority may execute the proposal
+    /// once approved
+    pub automatable: bool,
+    /// Budget category code for reporting, `0` for none
+    pub category: u16,
+    /// Optional index of an earlier proposal that must have executed before
+    /// this one can
+    pub depends_on: Option<u64>,
+    /// Optional compute units and heap size the execution is expected to
+    /// need, for the executor's ComputeBudget instructions
+    pub compute: Option<ComputeDeclaration>,
+    /// Whether the execution may empty a vault entirely rather than leave it
+    /// rent-exempt
+    pub sweep: bool,
+    /// Number of ephemeral signer PDAs the program signs for at execution,
+    /// for instructions that need a fresh keypair such as creating an account
+    pub ephemeral_signers: u8,
+}
+
+impl Default for ProposalOptions {
+    /// The defaults batched proposals use: no memo or assertions, the
+    /// multisig's default TTL, normal priority, signed by the vault and not
+    /// automatable.
+    fn default() -> Self {
+        Self {
+            memo: None,
+            assertions: Vec::new(),
+            ttl_seconds: None,
+            read_only: false,
+            priority: 0,
+            authority_kind: AuthorityKind::Vault(0),
+            automatable: false,
+            category: 0,
+            depends_on: None,
+            compute: None,
+            sweep: false,
+            ephemeral_signers: 0,
+        }
+    }
+}
+
+/// Everything an opaque proposal records, gathered by `propose_transaction`
+/// and `propose_transactions_batch`.
+struct OpaqueProposal {
+    proposer: Pubkey,
+    program_id: Pubkey,
+    accounts: Vec<u8>,
+    instruction_data: Vec<u8>,
+    memo: Option<String>,
+    assertions: Vec<BalanceAssertion>,
+    read_only: bool,
+    priority: u8,
+    authority_kind: AuthorityKind,
+    automatable: bool,
+    category: u16,
+    now: i64,
+    slot: u64,
+    ttl_seconds: Option<i64>,
+    joint_with: Option<Pubkey>,
+    depends_on: Option<u64>,
+    compute: Option<ComputeDeclaration>,
+    sweep: bool,
+    ephemeral_signers: u8,
+    /// Index of a consumed nonce reservation, or `None` to take the live
+    /// nonce
+    reserved_index: Option<u64>,
+}
+
+/// An execution that passed its checks, ready to be carried out.
+struct PreparedExecution {
+    automated: bool,
+    authority: Pubkey,
+    authority_bump: u8,
+    instructions: Vec<Instruction>,
+}
+
+/// A System Program instruction, as far as the proposal guard cares: whether
+/// it hands an account to a new owner.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SystemCall {
+    /// Assigns the account at index `target` of the instruction's accounts
+    /// to `owner`: `CreateAccount`, `Assign`, `CreateAccountWithSeed`,
+    /// `AllocateWithSeed` and `AssignWithSeed`.
+    ChangesOwner { target: usize, owner: Pubkey },
+    /// Any other known instruction.
+    Other,
+}
+
+impl SystemCall {
+    /// Decodes System Program instruction data, laid out by bincode: a u32
+    /// variant tag, then the fields with u64 lengths before strings. Returns
+    /// `None` for an unknown tag or data too short for the variant's fields.
+    /// Trailing bytes are ignored, as the System Program does.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = SystemDataReader { data };
+        let call = match reader.u32()? {
+            // CreateAccount { lamports, space, owner }
+            0 => {
+                reader.skip(16)?;
+                SystemCall::ChangesOwner { target: 1, owner: reader.pubkey()? }
+            }
+            // Assign { owner }
+            1 => SystemCall::ChangesOwner { target: 0, owner: reader.pubkey()? },
+            // CreateAccountWithSeed { base, seed, lamports, space, owner }
+            3 => {
+                reader.skip(32)?;
+                reader.string()?;
+                reader.skip(16)?;
+                SystemCall::ChangesOwner { target: 1, owner: reader.pubkey()? }
+            }
+            // AllocateWithSeed { base, seed, space, owner }
+            9 => {
+                reader.skip(32)?;
+                reader.string()?;
+                reader.skip(8)?;
+                SystemCall::ChangesOwner { target: 0, owner: reader.pubkey()? }
+            }
+            // AssignWithSeed { base, seed, owner }
+            10 => {
+                reader.skip(32)?;
+                reader.string()?;
+                SystemCall::ChangesOwner { target: 0, owner: reader.pubkey()? }
+            }
+            // Transfer, WithdrawNonceAccount and Allocate take a u64
+            2 | 5 | 8 => {
+                reader.skip(8)?;
+                SystemCall::Other
+            }
+            // AdvanceNonceAccount and UpgradeNonceAccount take nothing
+            4 | 12 => SystemCall::Other,
+            // InitializeNonceAccount and AuthorizeNonceAccount take a pubkey
+            6 | 7 => {
+                reader.pubkey()?;
+                SystemCall::Other
+            }
+            // TransferWithSeed { lamports, from_seed, from_owner }
+            11 => {
+                reader.skip(8)?;
+                reader.string()?;
+                reader.pubkey()?;
+                SystemCall::Other
+            }
+            _ => return None,
+        };
+
+        Some(call)
+    }
+}
+
+/// Reads bincode fields off the front of System Program instruction data.
+struct SystemDataReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SystemDataReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn pubkey(&mut self) -> Option<Pubkey> {
+        Some(Pubkey::new_from_array(self.take(32)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        let len = u64::from_le_bytes(self.take(8)?.try_into().ok()?);
+        std::str::from_utf8(self.take(usize::try_from(len).ok()?)?).ok()
+    }
 }
 
-#[derive(Accounts)]
-pub struct UpdateMultisig<'info> {
-    #[account(
-        mut,
-        seeds = [b"multisig", creator.key().as_ref()],
-        bump = multisig.bump
-    )]
-    pub multisig: Account<'info, MultisigAccount>,
-    
-    /// CHECK: This is just used as a seed for the multisig PDA
-    pub creator: UncheckedAccount<'info>,
+/// Maximum number of balance assertions a proposal can carry.
+pub const MAX_BALANCE_ASSERTIONS: usize = 4;
+
+/// One approval of a proposal, for rendering who approved and in what order
+/// without looking each approver up.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ApprovalIndex {
+    /// The approver's position in the proposal's `eligible_approvers`
+    pub member: u16,
+    /// `1` for the proposer's approval, counting up with each one after
+    pub ordinal: u16,
 }
 
-#[derive(Accounts)]
-pub struct CloseMultisig<'info> {
-    #[account(
-        mut,
-        seeds = [b"multisig", creator.key().as_ref()],
-        bump = multisig.bump,
-        close = receiver
-    )]
-    pub multisig: Account<'info, MultisigAccount>,
-    
-    /// CHECK: This is just used as a seed for the multisig PDA
-    pub creator: UncheckedAccount<'info>,
-    
-    #[account(mut)]
-    pub receiver: AccountInfo<'info>,
+impl ApprovalIndex {
+    pub const SIZE: usize = 2 + 2;
+}
+
+/// A minimum balance change an execution must produce on one account.
+/// `min_delta` may be negative to bound how much an account may lose.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BalanceAssertion {
+    pub account: Pubkey,
+    pub kind: BalanceKind,
+    pub min_delta: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BalanceKind {
+    Lamports,
+    Token,
+}
+
+impl BalanceAssertion {
+    pub const SIZE: usize = 32 + 1 + 8;
+
+    // Reads the asserted balance from the remaining accounts. A token account
+    // that doesn't exist (yet, or any more) counts as an empty balance.
+    fn read_balance(&self, remaining_accounts: &[AccountInfo]) -> Result<u64> {
+        let account = find_account(remaining_accounts, &self.account)?;
+        match self.kind {
+            BalanceKind::Lamports => Ok(account.lamports()),
+            BalanceKind::Token => {
+                if account.data_is_empty() && *account.owner == anchor_lang::system_program::ID {
+                    return Ok(0);
+                }
+                Ok(load_token_account(account)?.amount)
+            }
+        }
+    }
+}
+
+/// Proposal payloads the program builds and validates itself, instead of
+/// replaying opaque instruction bytes.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum TypedAction {
+    DelegateStake { validator: Pubkey, lamports: u64 },
+    DeactivateStake { stake_account: Pubkey },
+    WithdrawStake { stake_account: Pubkey, lamports: u64, recipient: Pubkey },
+    WrapSol { amount: u64 },
+    UnwrapSol,
+    NftTransfer { mint: Pubkey, recipient: Pubkey, token_program: Pubkey },
+    CreateMint { decimals: u8, freeze_authority: Option<Pubkey>, token_program: Pubkey },
+    MintTo { mint: Pubkey, destination: Pubkey, amount: u64, token_program: Pubkey },
+    SetAuthority {
+        target: Pubkey,
+        authority_type: TokenAuthorityType,
+        new_authority: Option<Pubkey>,
+        token_program: Pubkey,
+    },
+    TokenBurn { mint: Pubkey, amount: u64, decimals: u8, close_if_empty: bool, token_program: Pubkey },
+    Transfer { recipient: Pubkey, lamports: u64, escrow: bool },
+    TokenTransfer {
+        mint: Pubkey,
+        recipient: Pubkey,
+        amount: u64,
+        decimals: u8,
+        token_program: Pubkey,
+        escrow: bool,
+    },
+    Payout { mint: Option<Pubkey>, decimals: u8, token_program: Pubkey, entries: Vec<PayoutEntry> },
+    /// An external instruction built from the registered template hashing
+    /// to `template_hash`
+    Registered { tag: String, program_id: Pubkey, args: Vec<ActionArg>, template_hash: [u8; 32] },
+}
+
+impl TypedAction {
+    // Tag plus the largest fixed-size variant (TokenTransfer). A payout's
+    // entries come on top, see `extra_space`
+    pub const SPACE: usize = 1 + 32 + 32 + 8 + 1 + 32 + 1;
+
+    /// Space the action needs beyond `SPACE`: a payout's entries, or a
+    /// registered action's tag and arguments.
+    pub fn extra_space(&self) -> usize {
+        match self {
+            TypedAction::Payout { entries, .. } => 4 + entries.len() * PayoutEntry::SIZE,
+            TypedAction::Registered { tag, args, .. } => 4 + tag.len() + 4 + args.len() * ActionArg::MAX_SIZE,
+            _ => 0,
+        }
+    }
+
+    /// Which variant the action is, for its proposal's `TransactionKind`.
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            TypedAction::DelegateStake { .. } => ActionKind::DelegateStake,
+            TypedAction::DeactivateStake { .. } => ActionKind::DeactivateStake,
+            TypedAction::WithdrawStake { .. } => ActionKind::WithdrawStake,
+            TypedAction::WrapSol { .. } => ActionKind::WrapSol,
+            TypedAction::UnwrapSol => ActionKind::UnwrapSol,
+            TypedAction::NftTransfer { .. } => ActionKind::NftTransfer,
+            TypedAction::CreateMint { .. } => ActionKind::CreateMint,
+            TypedAction::MintTo { .. } => ActionKind::MintTo,
+            TypedAction::SetAuthority { .. } => ActionKind::SetAuthority,
+            TypedAction::TokenBurn { .. } => ActionKind::TokenBurn,
+            TypedAction::Transfer { .. } => ActionKind::Transfer,
+            TypedAction::TokenTransfer { .. } => ActionKind::TokenTransfer,
+            TypedAction::Payout { .. } => ActionKind::Payout,
+            TypedAction::Registered { .. } => ActionKind::Registered,
+        }
+    }
+
+    /// Seed prefix of the PDA this action creates and must sign for, if any.
+    pub fn created_account_seed(&self) -> Option<&'static [u8]> {
+        match self {
+            TypedAction::DelegateStake { .. } => Some(b"stake"),
+            TypedAction::CreateMint { .. } => Some(b"mint"),
+            _ if self.is_escrowed() => Some(b"escrow"),
+            _ => None,
+        }
+    }
+
+    /// The asset (SOL for `None`) and amount the action sends out of the
+    /// vault, for rate limiting.
+    pub fn outflow(&self) -> Option<(Option<Pubkey>, u64)> {
+        match self {
+            TypedAction::Transfer { lamports, .. } => Some((None, *lamports)),
+            TypedAction::TokenTransfer { mint, amount, .. } => Some((Some(*mint), *amount)),
+            TypedAction::NftTransfer { mint, .. } => Some((Some(*mint), 1)),
+            TypedAction::Payout { mint, entries, .. } => {
+                Some((*mint, entries.iter().fold(0u64, |total, entry| total.saturating_add(entry.amount))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the action's funds are locked in an escrow before execution.
+    pub fn is_escrowed(&self) -> bool {
+        matches!(
+            self,
+            TypedAction::Transfer { escrow: true, .. } | TypedAction::TokenTransfer { escrow: true, .. }
+        )
+    }
+
+    /// A one-line description of the action for logs. Every variant has a
+    /// fixed number of fields and payouts are described by their total, so
+    /// formatting it costs bounded compute.
+    pub fn summary(&self) -> String {
+        match self {
+            TypedAction::DelegateStake { validator, lamports } => {
+                format!("delegate {} lamports of stake to {}", lamports, validator)
+            }
+            TypedAction::DeactivateStake { stake_account } => format!("deactivate stake {}", stake_account),
+            TypedAction::WithdrawStake { stake_account, lamports, recipient } => {
+                format!("withdraw {} lamports from stake {} to {}", lamports, stake_account, recipient)
+            }
+            TypedAction::WrapSol { amount } => format!("wrap {} lamports", amount),
+            TypedAction::UnwrapSol => "unwrap all wrapped SOL".to_string(),
+            TypedAction::NftTransfer { mint, recipient, .. } => format!("transfer NFT {} to {}", mint, recipient),
+            TypedAction::CreateMint { decimals, .. } => format!("create a mint with {} decimals", decimals),
+            TypedAction::MintTo { mint, destination, amount, .. } => {
+                format!("mint {} of {} to {}", amount, mint, destination)
+            }
+            TypedAction::SetAuthority { target, authority_type, new_authority, .. } => match new_authority {
+                Some(new_authority) => format!("set {:?} authority of {} to {}", authority_type, target, new_authority),
+                None => format!("remove {:?} authority of {}", authority_type, target),
+            },
+            TypedAction::TokenBurn { mint, amount, .. } => format!("burn {} of {}", amount, mint),
+            TypedAction::Transfer { recipient, lamports, escrow } => {
+                format!("send {} lamports to {}{}", lamports, recipient, if *escrow { " (escrowed)" } else { "" })
+            }
+            TypedAction::TokenTransfer { mint, recipient, amount, escrow, .. } => format!(
+                "send {} of {} to {}{}",
+                amount,
+                mint,
+                recipient,
+                if *escrow { " (escrowed)" } else { "" }
+            ),
+            TypedAction::Payout { mint, entries, .. } => {
+                let total = entries.iter().fold(0u64, |total, entry| total.saturating_add(entry.amount));
+                match mint {
+                    Some(mint) => format!("pay out {} of {} to {} recipients", total, mint, entries.len()),
+                    None => format!("pay out {} lamports to {} recipients", total, entries.len()),
+                }
+            }
+            TypedAction::Registered { tag, program_id, args, .. } => {
+                format!("registered action {} on {} with {} arguments", tag, program_id, args.len())
+            }
+        }
+    }
+}
+
+/// Which `TypedAction` a proposal carries, without its parameters.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ActionKind {
+    DelegateStake,
+    DeactivateStake,
+    WithdrawStake,
+    WrapSol,
+    UnwrapSol,
+    NftTransfer,
+    CreateMint,
+    MintTo,
+    SetAuthority,
+    TokenBurn,
+    Transfer,
+    TokenTransfer,
+    Payout,
+    Registered,
 }
 
+/// What a proposal does: an opaque instruction, a change to the multisig's
+/// own configuration, or a typed action. Execution branches on it.
+/// `General` comes first, so accounts proposed before kinds existed read as
+/// general proposals; those carrying a typed action then fail the kind check
+/// and have to be proposed again.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum TransactionKind {
+    General,
+    ConfigChange(ConfigAction),
+    TypedAction(ActionKind),
+}
+
+impl TransactionKind {
+    // Tag plus an action kind. A config change's action comes on top, see
+    // `extra_space`
+    pub const SPACE: usize = 1 + 1;
+
+    /// Space the kind needs beyond `SPACE`: a config change's action.
+    pub fn extra_space(&self) -> usize {
+        match self {
+            TransactionKind::ConfigChange(action) => action.try_to_vec().map_or(0, |bytes| bytes.len()),
+            _ => 0,
+        }
+    }
+
+    /// The kind of a payload: typed actions by their variant, calls to this
+    /// program's `configure_multisig` by their config change, and anything
+    /// else as general. A `configure_multisig` call whose action doesn't
+    /// decode is refused rather than treated as general.
+    pub fn of(program_id: &Pubkey, data: &[u8], action: Option<&TypedAction>) -> Result<Self> {
+        if let Some(action) = action {
+            return Ok(TransactionKind::TypedAction(action.kind()));
+        }
+
+        if *program_id == crate::ID && data.starts_with(&crate::instruction::ConfigureMultisig::DISCRIMINATOR) {
+            let action = ConfigAction::try_from_slice(&data[8..])
+                .map_err(|_| error!(MultisigWalletError::TransactionKindMismatch))?;
+            return Ok(TransactionKind::ConfigChange(action));
+        }
+
+        Ok(TransactionKind::General)
+    }
+}
+
+/// A program address with its canonical bump.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pda {
+    pub address: Pubkey,
+    pub bump: u8,
+}
+
+/// A multisig's PDAs, returned by `get_pdas`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Pdas {
+    pub multisig: Pda,
+    pub vault: Pda,
+    /// The transaction account of the next proposal, at `next_tx_index`
+    pub next_transaction: Pda,
+    pub next_tx_index: u64,
+}
+
+/// Deployment-wide settings at `["program_config"]`, created by the upgrade
+/// authority with `initialize_program_config`.
 #[account]
-pub struct MultisigAccount {
-    pub signers: Vec<Pubkey>,
-    pub threshold: u8,
-    pub expiration_timestamp: Option<u64>,
+pub struct ProgramConfig {
+    /// May change the config with `update_program_config`
+    pub admin: Pubkey,
+    pub limits: ProgramLimits,
+    /// Charged per execution of proposals made while it's set, `0` for none
+    pub fee_lamports: u64,
+    /// Receives the protocol fee
+    pub fee_collector: Pubkey,
+    /// Network this deployment runs on, stamped on every proposal
+    pub cluster: Cluster,
+    /// Smart wallet programs allowed to approve for their PDAs with
+    /// `approve_for`
+    pub wallet_programs: Vec<Pubkey>,
+    /// Whether proposals made while it's set keep an `ExecutionReceipt`
+    /// when they execute
+    pub execution_receipts: bool,
+    pub bump: u8,
+}
+
+impl ProgramConfig {
+    pub const SPACE: usize = 8 + // discriminator
+        32 + // admin
+        ProgramLimits::SIZE + // limits
+        8 + // fee lamports
+        32 + // fee collector
+        1 + // cluster
+        4 + (MAX_WALLET_PROGRAMS * 32) + // wallet programs (bounded)
+        1 + // execution receipts
+        1; // bump
+}
+
+/// Maximum number of wallet programs the program config registers.
+pub const MAX_WALLET_PROGRAMS: usize = 4;
+
+/// Limits a deployment can tune without a redeploy.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ProgramLimits {
+    /// Most signers a multisig may have
+    pub max_signers: u16,
+    /// Most bytes of instruction data an opaque proposal may carry
+    pub max_instruction_data_len: u32,
+    /// `default_proposal_ttl_seconds` new multisigs start with, `0` for none
+    pub default_proposal_ttl_seconds: i64,
+    /// `max_account_metas` new multisigs start with
+    pub default_max_account_metas: u8,
+}
+
+impl ProgramLimits {
+    pub const SIZE: usize = 2 + 4 + 8 + 1;
+
+    /// The limits while no program config exists. Data is capped at a
+    /// transaction's packet size, which no proposal can exceed anyway.
+    pub const DEFAULT: Self = Self {
+        max_signers: 64,
+        max_instruction_data_len: 1232,
+        default_proposal_ttl_seconds: 0,
+        default_max_account_metas: DEFAULT_MAX_ACCOUNT_METAS,
+    };
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_signers == 0
+            || self.max_instruction_data_len == 0
+            || self.default_proposal_ttl_seconds < 0
+            || self.default_max_account_metas == 0
+            || self.default_max_account_metas as usize > MAX_ACCOUNT_METAS
+        {
+            return err!(MultisigWalletError::InvalidProgramLimits);
+        }
+        Ok(())
+    }
+}
+
+/// The program config fields `update_program_config` replaces; those left
+/// `None` are kept.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ProgramConfigUpdate {
+    pub limits: Option<ProgramLimits>,
+    pub new_admin: Option<Pubkey>,
+    pub fee_lamports: Option<u64>,
+    pub fee_collector: Option<Pubkey>,
+    pub cluster: Option<Cluster>,
+    pub wallet_programs: Option<Vec<Pubkey>>,
+    pub execution_receipts: Option<bool>,
+}
+
+/// Most members a zero-copy multisig holds.
+pub const MAX_MEMBERS_V2: usize = 16;
+
+/// A zero-copy multisig, read in place instead of deserialized. Its members
+/// are the first `member_count` slots.
+#[account(zero_copy)]
+pub struct MultisigV2 {
     pub nonce: u64,
+    pub members: [MemberSlot; MAX_MEMBERS_V2],
+    pub create_key: Pubkey,
+    pub member_count: u8,
+    pub threshold: u8,
     pub bump: u8,
+    pub vault_bump: u8,
+    pub _padding: [u8; 4],
 }
 
-#[account]
-pub struct TransactionAccount {
+/// One member of a zero-copy multisig.
+#[zero_copy]
+pub struct MemberSlot {
+    pub key: Pubkey,
+}
+
+/// SPL Memo program, invoked to record executions.
+pub mod spl_memo {
+    use super::*;
+    declare_id!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+}
+
+/// Helpers for off-chain clients.
+#[cfg(feature = "client")]
+pub mod client {
+    use super::*;
+
+    pub use crate::{
+        canonical_approval_message, encode_approval_message, find_ephemeral_signer_address, link_history, ActionArg,
+        ActionKind, Cluster, ComputeDeclaration, ConfigAction, Explanation, HistoryRecord, OpenProposalWindow, Pda,
+        Pdas, ProgramLimits, TransactionKind, TypedAction, APPROVAL_MESSAGE_DOMAIN, APPROVAL_MESSAGE_VERSION,
+    };
+
+    /// Decodes the return data of a simulated `explain_transaction`.
+    pub fn decode_explanation(return_data: &[u8]) -> std::io::Result<Explanation> {
+        Explanation::try_from_slice(return_data)
+    }
+
+    /// Decodes the return data of a simulated `get_pdas`.
+    pub fn decode_pdas(return_data: &[u8]) -> std::io::Result<Pdas> {
+        Pdas::try_from_slice(return_data)
+    }
+}
+
+#[event]
+pub struct TransactionProposed {
     pub multisig: Pubkey,
+    /// The nonce the proposal consumed
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
     pub proposer: Pubkey,
+    /// Budget category code, `0` for none
+    pub category: u16,
+    pub created_at: i64,
+    pub created_slot: u64,
+    pub kind: TransactionKind,
+    pub cluster: Cluster,
+    /// Head of the multisig's proposal history before and after this
+    /// proposal, see `link_history`
+    pub history_before: [u8; 32],
+    pub history_after: [u8; 32],
+}
+
+#[event]
+pub struct ExecutionAttemptFailed {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub executor: Option<Pubkey>,
+    pub error_code: u64,
+    pub failed_attempts: u32,
+}
+
+#[event]
+pub struct ProposalHistoryRecorded {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub record: HistoryRecord,
+    pub history_before: [u8; 32],
+    pub history_after: [u8; 32],
+}
+
+#[event]
+pub struct TransactionApproved {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub signer: Pubkey,
+    pub memo: String,
+    pub approvals: u8,
+    pub threshold: u8,
+    pub expires_in: Option<i64>,
+}
+
+#[event]
+pub struct ThresholdReached {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub approvals: u8,
+    pub threshold: u8,
+    /// When the priority's timelock lets the proposal execute, if it has one
+    pub timelock_ends_at: Option<i64>,
+    pub expires_at: Option<i64>,
+}
+
+#[event]
+pub struct TransactionExecuted {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub executor: Option<Pubkey>,
+    /// Whether the automation authority executed it
+    pub automated: bool,
+    pub category: u16,
+    pub kind: TransactionKind,
+    /// Compute units left right before the CPI, `None` where the runtime
+    /// doesn't report them
+    pub remaining_compute_units: Option<u64>,
+}
+
+#[event]
+pub struct TransactionRejected {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub signer: Pubkey,
+    pub reason: String,
+    pub cancelled: bool,
+}
+
+#[event]
+pub struct EmergencyThresholdInitiated {
+    pub multisig: Pubkey,
+    pub initiator: Pubkey,
+    pub new_threshold: u8,
+    pub unlocks_at: i64,
+}
+
+#[event]
+pub struct EmergencyThresholdVetoed {
+    pub multisig: Pubkey,
+    pub vetoed_by: Pubkey,
+    pub new_threshold: u8,
+}
+
+#[event]
+pub struct EmergencyThresholdApplied {
+    pub multisig: Pubkey,
+    pub new_threshold: u8,
+}
+
+#[event]
+pub struct ExternalApprovalRecorded {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub approver_program: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct DepositRecorded {
+    pub multisig: Pubkey,
+    pub recorded_by: Pubkey,
+    pub lamports_delta: i128,
+    pub token_deltas: Vec<TokenDelta>,
+    pub memo: String,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TokenDelta {
+    pub account: Pubkey,
+    pub mint: Pubkey,
+    pub delta: i128,
+}
+
+#[event]
+pub struct BalancesSnapshotted {
+    pub multisig: Pubkey,
+    pub snapshot: Pubkey,
+    pub index: u64,
+    pub lamports: u64,
+    pub holdings: Vec<TokenHolding>,
+    pub taken_at: i64,
+}
+
+#[event]
+pub struct CategoryStatsReset {
+    pub multisig: Pubkey,
+    /// The totals of the period that ended
+    pub outflows: Vec<CategoryOutflow>,
+    pub period_started_at: i64,
+    pub period_ended_at: i64,
+}
+
+#[event]
+pub struct MultisigActivated {
+    pub multisig: Pubkey,
+    pub accepted_members: Vec<Pubkey>,
+}
+
+#[event]
+pub struct KeyRotated {
+    pub multisig: Pubkey,
+    pub old_key: Pubkey,
+    pub new_key: Pubkey,
+    pub approvals_reassigned: bool,
+}
+
+#[event]
+pub struct AuthorityChanged {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub target: Pubkey,
+    pub authority_type: TokenAuthorityType,
+    pub old_authority: Pubkey,
+    pub new_authority: Option<Pubkey>,
+}
+
+#[event]
+pub struct TokenVoteRecorded {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub priority: u8,
+    pub voter: Pubkey,
+    pub amount: u64,
+    pub total: u64,
+    pub required: u64,
+}
+
+#[event]
+pub struct VoteTokensUnlocked {
+    pub transaction: Pubkey,
+    pub voter: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct JointTransactionExecuted {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub partner_multisig: Pubkey,
+    pub partner_tx_index: u64,
+    pub operation_hash: [u8; 32],
+}
+
+#[event]
+pub struct MemberFlaggedInactive {
+    pub multisig: Pubkey,
+    pub member: Pubkey,
+    pub last_action_at: i64,
+}
+
+#[event]
+pub struct MemberReactivated {
+    pub multisig: Pubkey,
+    pub member: Pubkey,
+    pub at: i64,
+}
+
+#[event]
+pub struct ComputeNearLimit {
+    pub multisig: Pubkey,
     pub tx_index: u64,
+    pub declared_units: u32,
+    pub consumed_units: u64,
+}
+
+#[event]
+pub struct VaultTokenAccountClosed {
+    pub multisig: Pubkey,
+    pub token_account: Pubkey,
+    pub mint: Pubkey,
+    /// Rent returned to the vault
+    pub lamports: u64,
+}
+
+#[event]
+pub struct ExternalActionRegistered {
+    pub multisig: Pubkey,
+    pub tag: String,
     pub program_id: Pubkey,
-    pub accounts: Vec<u8>,
-    pub data: Vec<u8>,
-    pub signers: Vec<Pubkey>,
-    pub executed: bool,
-    pub bump: u8,
+    /// Pinned by proposals made from the template
+    pub template_hash: [u8; 32],
+}
+
+#[event]
+pub struct ExternalActionRemoved {
+    pub multisig: Pubkey,
+    pub tag: String,
+}
+
+#[event]
+pub struct ExecutionStaged {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub executor: Pubkey,
+    pub staging_hash: [u8; 32],
+    pub staged_at: i64,
+}
+
+#[event]
+pub struct ExecutionCompleted {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub staging_hash: [u8; 32],
+    /// `ExecutionStage::chain` of the staging hash and the accounts passed
+    /// to `complete_execution`
+    pub completion_hash: [u8; 32],
+}
+
+#[event]
+pub struct StagedExecutionReleased {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub released_by: Pubkey,
+}
+
+#[event]
+pub struct ProgramConfigUpdated {
+    pub admin: Pubkey,
+    pub limits: ProgramLimits,
+    pub fee_lamports: u64,
+    pub fee_collector: Pubkey,
+    pub cluster: Cluster,
+    pub wallet_programs: Vec<Pubkey>,
+    pub execution_receipts: bool,
+}
+
+#[event]
+pub struct ProtocolFeePaid {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub payer: Pubkey,
+    pub collector: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct MultisigMigrated {
+    pub multisig: Pubkey,
+    pub old_len: u32,
+    pub new_len: u32,
+    /// Rent the payer added
+    pub rent: u64,
+}
+
+#[event]
+pub struct TransactionMigrated {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    /// The proposal's `version` after the migration
+    pub version: u8,
+    pub freed_bytes: u32,
+    /// Rent returned to the rent payer
+    pub refund: u64,
+}
+
+#[event]
+pub struct InvitationCreated {
+    pub multisig: Pubkey,
+    pub invitee: Pubkey,
+    pub roles: u8,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct InvitationAccepted {
+    pub multisig: Pubkey,
+    pub invitee: Pubkey,
+    pub roles: u8,
+}
+
+#[event]
+pub struct ExecutionConfirmed {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub transaction_hash: [u8; 32],
+    pub confirmer: Pubkey,
+}
+
+#[event]
+pub struct NonceReserved {
+    pub multisig: Pubkey,
+    pub tx_index: u64,
+    pub reserver: Pubkey,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct AllowanceClaimed {
+    pub multisig: Pubkey,
+    pub member: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    /// Left to claim in the current period
+    pub remaining: u64,
 }
 
 #[error_code]
@@ -450,4 +10726,330 @@ pub enum MultisigWalletError {
     InsufficientAccounts,
     #[msg("Recursive CPI calls are not allowed")]
     RecursiveCallNotAllowed,
-}
\ No newline at end of file
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+    #[msg("Account is not a vote account")]
+    InvalidVoteAccount,
+    #[msg("Stake account is not controlled by the vault")]
+    InvalidStakeAccount,
+    #[msg("Vault balance is too low for this action")]
+    InsufficientVaultBalance,
+    #[msg("Account is not a valid token account")]
+    InvalidTokenAccount,
+    #[msg("Token account has an active delegate")]
+    TokenAccountDelegated,
+    #[msg("Account is not a valid mint for this action")]
+    InvalidMint,
+    #[msg("Mint authority is not the vault")]
+    InvalidMintAuthority,
+    #[msg("Mint cap requires a positive amount and period")]
+    InvalidMintCap,
+    #[msg("Maximum number of mint caps reached")]
+    TooManyMintCaps,
+    #[msg("Mint would exceed the mint cap for the current period")]
+    MintCapExceeded,
+    #[msg("The vault does not hold this authority or it cannot be changed this way")]
+    InvalidAuthorityChange,
+    #[msg("Removing an authority is irrevocable and must be explicitly confirmed")]
+    IrrevocableChangeNotConfirmed,
+    #[msg("Memo exceeds the maximum length")]
+    MemoTooLong,
+    #[msg("Too many balance assertions")]
+    TooManyBalanceAssertions,
+    #[msg("Balance assertion failed after execution")]
+    BalanceAssertionFailed,
+    #[msg("Transaction has been cancelled")]
+    TransactionCancelled,
+    #[msg("Transaction is not next in the execution order")]
+    OutOfOrderExecution,
+    #[msg("Signer set changed since this transaction was proposed")]
+    StaleTransaction,
+    #[msg("Proposer must wait for the proposal cooldown to elapse")]
+    ProposalCooldownActive,
+    #[msg("Signer has already rejected this transaction")]
+    AlreadyRejected,
+    #[msg("Duration must be greater than zero")]
+    InvalidDuration,
+    #[msg("Member swap has expired")]
+    MemberSwapExpired,
+    #[msg("Signer is not joining the multisig in this member swap")]
+    NotIncomingSigner,
+    #[msg("Signer has already accepted membership")]
+    AlreadyAccepted,
+    #[msg("Not every incoming signer has accepted membership")]
+    MembershipNotAccepted,
+    #[msg("An emergency threshold reduction is already pending")]
+    EmergencyAlreadyPending,
+    #[msg("No emergency threshold reduction is pending")]
+    NoEmergencyPending,
+    #[msg("Emergency timelock has not elapsed")]
+    EmergencyTimelockActive,
+    #[msg("Signer is not a recovery member")]
+    NotRecoveryMember,
+    #[msg("Maximum number of recovery members exceeded")]
+    TooManyRecoveryMembers,
+    #[msg("Recovery members and signers must be distinct")]
+    RecoverySetOverlap,
+    #[msg("Recovery delay has not elapsed")]
+    RecoveryDelayActive,
+    #[msg("Caller is not the registered approver program")]
+    InvalidApproverProgram,
+    #[msg("External approvals were already recorded for this transaction")]
+    ExternalApprovalAlreadyRecorded,
+    #[msg("External approvals must be positive and within the configured cap")]
+    ExternalApprovalsExceedCap,
+    #[msg("Target program is not on the allowlist")]
+    ProgramNotAllowed,
+    #[msg("Instruction is not allowed for this program")]
+    InstructionNotAllowed,
+    #[msg("Maximum number of allowed programs reached")]
+    TooManyAllowedPrograms,
+    #[msg("Instruction prefixes must be 1 to 8 bytes, at most 4 per program")]
+    InvalidInstructionPrefix,
+    #[msg("Transaction can still be approved or executed")]
+    TransactionStillActive,
+    #[msg("Deposit ledger already tracks the maximum number of token accounts")]
+    TooManyTrackedTokenAccounts,
+    #[msg("Too many token accounts for a single snapshot")]
+    TooManySnapshotAccounts,
+    #[msg("Read-only proposals must be executed with execute_readonly")]
+    ReadOnlyExecutionRequired,
+    #[msg("Transaction was not proposed as read-only")]
+    TransactionNotReadOnly,
+    #[msg("Read-only proposals cannot list the vault as writable")]
+    WritableVaultInReadOnly,
+    #[msg("Too many account metas in the proposed instruction")]
+    TooManyAccountMetas,
+    #[msg("Account meta flags may only use the signer and writable bits")]
+    InvalidAccountMetaFlags,
+    #[msg("Only the multisig and its vault may be signers of a proposed instruction")]
+    UnauthorizedSignerMeta,
+    #[msg("Signer listed more than once in the proposed account metas")]
+    DuplicateSignerMeta,
+    #[msg("Account meta cap must be between 1 and 64")]
+    InvalidAccountMetaCap,
+    #[msg("No budget granted to this child multisig")]
+    SubBudgetNotFound,
+    #[msg("Budget has expired")]
+    SubBudgetExpired,
+    #[msg("Spend exceeds the remaining budget")]
+    SubBudgetExhausted,
+    #[msg("Too many budgets or budgeted mints")]
+    TooManySubBudgets,
+    #[msg("Key is already a member of the multisig")]
+    AlreadyMember,
+    #[msg("Key rotation cooldown has not elapsed")]
+    KeyRotationCooldownActive,
+    #[msg("Transaction is not an escrowed transfer")]
+    NotEscrowed,
+    #[msg("Escrow is already funded")]
+    EscrowAlreadyFunded,
+    #[msg("Escrow has not been funded")]
+    EscrowNotFunded,
+    #[msg("Escrowed funds must be released first")]
+    EscrowStillFunded,
+    #[msg("Withdrawal exceeds the vault's rate limit")]
+    RateLimitExceeded,
+    #[msg("Opaque calls cannot use the vault while rate limits are set")]
+    OpaqueCallRateLimited,
+    #[msg("Too many rate limits")]
+    TooManyRateLimits,
+    #[msg("No rate limit set for this asset")]
+    RateLimitNotFound,
+    #[msg("Rate limit must be raised to unlimited before it can be removed")]
+    RateLimitRaisePending,
+    #[msg("Transaction does not match the expected hash")]
+    HashMismatch,
+    #[msg("Multisig is pending member acceptance")]
+    MultisigNotActive,
+    #[msg("Multisig is not pending activation")]
+    MultisigNotPending,
+    #[msg("Invalid activation requirement")]
+    InvalidActivation,
+    #[msg("Activation deadline has passed")]
+    ActivationDeadlinePassed,
+    #[msg("Activation deadline has not passed yet")]
+    ActivationDeadlineNotReached,
+    #[msg("Invalid priority level")]
+    InvalidPriorityLevel,
+    #[msg("Too many priority levels")]
+    TooManyPriorityLevels,
+    #[msg("Priority level is not configured")]
+    PriorityLevelNotFound,
+    #[msg("Execution timelock has not elapsed")]
+    ExecutionTimelockActive,
+    #[msg("Too many members for a zero-copy multisig")]
+    TooManyMembers,
+    #[msg("Transaction index does not match the multisig's nonce")]
+    InvalidTransactionIndex,
+    #[msg("Update would not change the multisig")]
+    NoOpUpdate,
+    #[msg("Token voting is not enabled for this proposal")]
+    TokenVoteNotConfigured,
+    #[msg("Invalid token vote configuration")]
+    InvalidTokenVote,
+    #[msg("The proposal's token vote window has closed")]
+    VoteWindowClosed,
+    #[msg("Vote tokens stay locked until the vote window ends or the proposal is settled")]
+    VoteTokensLocked,
+    #[msg("Invalid transaction account")]
+    InvalidTransactionAccount,
+    #[msg("Stored vault bump does not produce a valid address")]
+    InvalidVaultBump,
+    #[msg("Token account is frozen or delegated and can't be swept")]
+    UnsweepableTokenAccount,
+    #[msg("Signer list is empty")]
+    EmptySignerList,
+    #[msg("Signer list contains the default (System Program) key")]
+    DefaultSignerKey,
+    #[msg("Signer list contains this program's id")]
+    ProgramSignerKey,
+    #[msg("Signer list contains the multisig or its vault")]
+    SelfSignerKey,
+    #[msg("Expiration must be in the future and within the maximum horizon")]
+    InvalidExpiration,
+    #[msg("Another proposal already used this transaction index")]
+    NonceAlreadyUsed,
+    #[msg("The executor is not allowed by the execution policy")]
+    ExecutorNotAllowed,
+    #[msg("The proposal was not marked automatable")]
+    NotAutomatable,
+    #[msg("A batch must hold between 2 and 5 proposals")]
+    InvalidBatchSize,
+    #[msg("The token registry is full")]
+    TokenRegistryFull,
+    #[msg("A registered vault token account was not listed")]
+    UnlistedTokenAccount,
+    #[msg("Token account is not in the registry")]
+    TokenAccountNotRegistered,
+    #[msg("Token account is still open and can't be pruned")]
+    TokenAccountStillOpen,
+    #[msg("Too many category totals in this period")]
+    TooManyCategoryOutflows,
+    #[msg("System Program instruction would reassign a multisig account")]
+    DangerousSystemInstruction,
+    #[msg("An account was passed with fewer privileges than the proposal requires")]
+    AccountPrivilegeMismatch,
+    #[msg("A payout must have between 1 and 20 recipients")]
+    InvalidPayoutSize,
+    #[msg("Payout lists a recipient more than once")]
+    DuplicatePayoutRecipient,
+    #[msg("Payout total exceeds the configured cap")]
+    PayoutCapExceeded,
+    #[msg("Too many payout caps")]
+    TooManyPayoutCaps,
+    #[msg("No inactivity period is configured")]
+    InactivityPeriodNotSet,
+    #[msg("A joint proposal's partner must be another multisig")]
+    InvalidJointPartner,
+    #[msg("Joint proposals execute through execute_joint")]
+    JointExecutionRequired,
+    #[msg("The joint proposals don't name each other or describe different operations")]
+    JointProposalMismatch,
+    #[msg("Budget epochs are not configured, or epoch caps still depend on them")]
+    BudgetEpochsNotSet,
+    #[msg("Too many epoch caps")]
+    TooManyEpochCaps,
+    #[msg("Outflow exceeds the budget epoch's cap")]
+    EpochCapExceeded,
+    #[msg("A proposal can only depend on an earlier one")]
+    InvalidDependency,
+    #[msg("The proposal this one depends on hasn't executed yet")]
+    DependencyNotExecuted,
+    #[msg("The proposal this one depends on can no longer execute")]
+    DependencyFailed,
+    #[msg("Declared compute units or heap size out of bounds")]
+    InvalidComputeDeclaration,
+    #[msg("The execution would leave a vault below rent exemption")]
+    WouldBreakRentExemption,
+    #[msg("More signers than the program config allows")]
+    TooManySigners,
+    #[msg("Instruction data longer than the program config allows")]
+    InstructionDataTooLarge,
+    #[msg("Program limits out of bounds")]
+    InvalidProgramLimits,
+    #[msg("Only the program's upgrade authority can create the program config")]
+    NotUpgradeAuthority,
+    #[msg("Only the program config's admin can change it")]
+    NotProgramConfigAdmin,
+    #[msg("The multisig has the executor pay the protocol fee, but no executor signed")]
+    FeePayerRequired,
+    #[msg("The proposal's kind doesn't match its payload")]
+    TransactionKindMismatch,
+    #[msg("The key already has an open invitation")]
+    InvitationAlreadyExists,
+    #[msg("The invitation has expired")]
+    InvitationExpired,
+    #[msg("The invitation hasn't expired yet")]
+    InvitationNotExpired,
+    #[msg("The multisig only accepts typed proposals and config changes")]
+    OpaqueProposalsDisabled,
+    #[msg("The proposal was made for another cluster")]
+    ClusterMismatch,
+    #[msg("The member voted on this proposal too recently")]
+    VoteTooSoon,
+    #[msg("More ephemeral signers than a proposal may declare")]
+    TooManyEphemeralSigners,
+    #[msg("The proposal writes to the multisig or one of its proposals")]
+    SelfReferentialProposal,
+    #[msg("The member has no allowance")]
+    AllowanceNotFound,
+    #[msg("The member's allowance is frozen")]
+    AllowanceFrozen,
+    #[msg("The claim exceeds what's left of the allowance this period")]
+    AllowanceExceeded,
+    #[msg("The proposal was made for an earlier multisig at this address")]
+    GenerationMismatch,
+    #[msg("approve_for must be called directly by a registered wallet program")]
+    UnregisteredWalletProgram,
+    #[msg("More wallet programs than the program config can register")]
+    TooManyWalletPrograms,
+    #[msg("The proposal already uses the compact account meta encoding")]
+    TransactionAlreadyMigrated,
+    #[msg("The nonce reservation belongs to another member")]
+    ReservationNotHeld,
+    #[msg("The nonce reservation has expired")]
+    ReservationExpired,
+    #[msg("The nonce reservation hasn't expired yet")]
+    ReservationNotExpired,
+    #[msg("The proposal's payload must be confirmed with confirm_execution first")]
+    ConfirmationRequired,
+    #[msg("The proposal doesn't need a confirmation")]
+    ConfirmationNotRequired,
+    #[msg("The proposal's execution is already confirmed")]
+    AlreadyConfirmed,
+    #[msg("The proposer can't confirm its own proposal")]
+    SelfConfirmation,
+    #[msg("Too few compute units left for the proposal's declared compute")]
+    InsufficientComputeRemaining,
+    #[msg("Token account still holds tokens")]
+    TokenAccountNotEmpty,
+    #[msg("The transaction has no execution receipt")]
+    ReceiptMissing,
+    #[msg("The transaction hasn't executed")]
+    TransactionNotExecuted,
+    #[msg("Invalid external action template")]
+    InvalidActionTemplate,
+    #[msg("An external action is already registered under this tag")]
+    ActionAlreadyRegistered,
+    #[msg("The action registry is full")]
+    ActionRegistryFull,
+    #[msg("No external action is registered under this tag")]
+    ActionNotRegistered,
+    #[msg("Arguments don't match the registered action's template")]
+    InvalidActionArgs,
+    #[msg("The registered action changed since the proposal")]
+    RegisteredActionChanged,
+    #[msg("The proposal is staged for complete_execution")]
+    ExecutionStaged,
+    #[msg("More accounts than stage_execution takes")]
+    TooManyStagedAccounts,
+    #[msg("The proposal no longer matches its staging hash")]
+    StagingHashMismatch,
+    #[msg("Only the stager may release the staged execution before its timeout")]
+    StagingNotExpired,
+    #[msg("The account isn't a multisig of this program")]
+    InvalidMultisigAccount,
+    #[msg("The multisig already has the current layout")]
+    MultisigAlreadyMigrated,
+}
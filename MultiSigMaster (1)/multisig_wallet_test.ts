@@ -20,6 +20,14 @@ describe("multisig_wallet", () => {
     [Buffer.from("multisig"), payer.publicKey.toBuffer()],
     program.programId
   );
+  const [programConfigPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("program_config")],
+    program.programId
+  );
+  const [generationPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("generation"), payer.publicKey.toBuffer()],
+    program.programId
+  );
 
   let txIndex = 0;
   let txPda: PublicKey;
@@ -48,13 +56,27 @@ describe("multisig_wallet", () => {
     const accountMetas = serializeAccountMetas(instruction.keys);
 
     const tx = await program.methods
-      .proposeTransaction(instruction.programId, Buffer.from(accountMetas), instruction.data)
+      .proposeTransaction(instruction.programId, Buffer.from(accountMetas), instruction.data, {
+        memo: null,
+        assertions: [],
+        ttlSeconds: null,
+        readOnly: false,
+        priority: 0,
+        authorityKind: { multisig: {} },
+        automatable: false,
+        category: 0,
+        dependsOn: null,
+        compute: null,
+        sweep: false,
+        ephemeralSigners: 0,
+      })
       .accounts({
         multisig: multisigPda,
         transaction: txPda,
-        creator: payer.publicKey,
         proposer,
+        rentPayer: proposer,
         systemProgram: SystemProgram.programId,
+        programConfig: programConfigPda,
       })
       .signers(signer ? [signer] : [])
       .rpc();
@@ -88,12 +110,22 @@ describe("multisig_wallet", () => {
   after(async () => {
     try {
       const multisigAccount = await program.account.multisigAccount.fetch(multisigPda);
+      const [vaultPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("vault"), multisigPda.toBuffer()],
+        program.programId
+      );
+      const [registryPda] = PublicKey.findProgramAddressSync(
+        [Buffer.from("token_registry"), multisigPda.toBuffer()],
+        program.programId
+      );
       const tx = await program.methods
-        .closeMultisig()
+        .closeMultisig(0)
         .accounts({
           multisig: multisigPda,
-          creator: payer.publicKey,
           receiver: payer.publicKey,
+          vault: vaultPda,
+          registry: registryPda,
+          systemProgram: SystemProgram.programId,
         })
         .remainingAccounts(multisigAccount.signers.map(pubkey => ({
           pubkey,
@@ -112,12 +144,14 @@ describe("multisig_wallet", () => {
     const initialSigners = [payer.publicKey, signer1.publicKey, signer2.publicKey];
 
     const tx = await program.methods
-      .initializeMultisig(initialSigners, 2, null)
+      .initializeMultisig(initialSigners, 2, null, null)
       .accounts({
         multisig: multisigPda,
+        createKey: payer.publicKey,
         payer: payer.publicKey,
         systemProgram: SystemProgram.programId,
-        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        programConfig: programConfigPda,
+        generation: generationPda,
       })
       .rpc();
 
@@ -136,9 +170,11 @@ describe("multisig_wallet", () => {
         .initializeMultisig([payer.publicKey, signer1.publicKey], 3)
         .accounts({
           multisig: multisigPda,
+          createKey: payer.publicKey,
           payer: payer.publicKey,
           systemProgram: SystemProgram.programId,
-          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          programConfig: programConfigPda,
+          generation: generationPda,
         })
         .rpc();
       expect.fail("Should have thrown an error");
@@ -187,11 +223,10 @@ describe("multisig_wallet", () => {
 
   it("Approves a transaction by a second signer", async () => {
     const tx = await program.methods
-      .approveTransaction()
+      .approveTransaction(null)
       .accounts({
         multisig: multisigPda,
         transaction: txPda,
-        creator: payer.publicKey,
         signer: signer1.publicKey,
       })
       .signers([signer1])
@@ -207,11 +242,10 @@ describe("multisig_wallet", () => {
   it("Rejects approval by non-signer", async () => {
     try {
       const tx = await program.methods
-        .approveTransaction()
+        .approveTransaction(null)
         .accounts({
           multisig: multisigPda,
           transaction: txPda,
-          creator: payer.publicKey,
           signer: newSigner.publicKey,
         })
         .signers([newSigner])
@@ -247,11 +281,11 @@ describe("multisig_wallet", () => {
     }));
 
     const tx = await program.methods
-      .executeTransaction()
+      .executeTransaction(null)
       .accounts({
         multisig: multisigPda,
         transaction: txPda,
-        creator: payer.publicKey,
+        executor: null,
       })
       .remainingAccounts(remainingAccounts)
       .rpc();
@@ -293,11 +327,11 @@ describe("multisig_wallet", () => {
 
     try {
       const tx = await program.methods
-        .executeTransaction()
+        .executeTransaction(null)
         .accounts({
           multisig: multisigPda,
           transaction: txPda,
-          creator: payer.publicKey,
+          executor: null,
         })
         .remainingAccounts(remainingAccounts)
         .rpc();
@@ -332,11 +366,11 @@ describe("multisig_wallet", () => {
 
     try {
       const tx = await program.methods
-        .executeTransaction()
+        .executeTransaction(null)
         .accounts({
           multisig: multisigPda,
           transaction: txPda,
-          creator: payer.publicKey,
+          executor: null,
         })
         .remainingAccounts(remainingAccounts)
         .rpc();
@@ -348,51 +382,30 @@ describe("multisig_wallet", () => {
     }
   });
 
-  it("Tests expiration logic with past timestamp", async () => {
+  it("Rejects an expiration in the past", async () => {
     const slot = await provider.connection.getSlot();
     const timestamp = await provider.connection.getBlockTime(slot);
     const expiration = timestamp - 1000;
 
-    const tx = await program.methods
-      .updateMultisig(null, null, new anchor.BN(expiration))
-      .accounts({
-        multisig: multisigPda,
-        creator: payer.publicKey,
-      })
-      .remainingAccounts([
-        { pubkey: payer.publicKey, isSigner: true, isWritable: false },
-        { pubkey: signer1.publicKey, isSigner: true, isWritable: false },
-        { pubkey: signer2.publicKey, isSigner: true, isWritable: false },
-      ])
-      .signers([signer1, signer2])
-      .rpc();
-
-    await provider.connection.confirmTransaction(tx);
-
-    const transferInstruction = SystemProgram.transfer({
-      fromPubkey: multisigPda,
-      toPubkey: signer3.publicKey,
-      lamports: LAMPORTS_PER_SOL / 100,
-    });
-
-    await proposeTransaction(transferInstruction, payer.publicKey);
-
     try {
       const tx = await program.methods
-        .approveTransaction()
+        .updateMultisig(null, null, { set: { timestamp: new anchor.BN(expiration) } })
         .accounts({
           multisig: multisigPda,
-          transaction: txPda,
-          creator: payer.publicKey,
-          signer: signer1.publicKey,
+          programConfig: programConfigPda,
         })
-        .signers([signer1])
+        .remainingAccounts([
+          { pubkey: payer.publicKey, isSigner: true, isWritable: false },
+          { pubkey: signer1.publicKey, isSigner: true, isWritable: false },
+          { pubkey: signer2.publicKey, isSigner: true, isWritable: false },
+        ])
+        .signers([signer1, signer2])
         .rpc();
       expect.fail("Should have thrown an error");
     } catch (error) {
       const programError = ProgramError.parse(error, program.idl.errors);
       expect(programError).to.not.be.null;
-      expect(programError.name).to.equal("TransactionExpired");
+      expect(programError.name).to.equal("InvalidExpiration");
     }
   });
 
@@ -404,10 +417,10 @@ describe("multisig_wallet", () => {
     );
 
     const tx = await program.methods
-      .updateMultisig(newSigners, 2, null)
+      .updateMultisig(newSigners, 2, { keep: {} })
       .accounts({
         multisig: multisigPda,
-        creator: payer.publicKey,
+        programConfig: programConfigPda,
       })
       .remainingAccounts(currentSigners.map(pubkey => ({
         pubkey,
@@ -433,10 +446,10 @@ describe("multisig_wallet", () => {
 
     try {
       const tx = await program.methods
-        .updateMultisig(null, 1, null)
+        .updateMultisig(null, 1, { keep: {} })
         .accounts({
           multisig: multisigPda,
-          creator: payer.publicKey,
+          programConfig: programConfigPda,
         })
         .remainingAccounts([
           { pubkey: payer.publicKey, isSigner: true, isWritable: false },
@@ -0,0 +1,6272 @@
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::program::{invoke, invoke_signed};
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::{
+    AccountDeserialize, AccountSerialize, AnchorDeserialize, AnchorSerialize, InstructionData, ToAccountMetas,
+};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token::spl_token;
+use multisig_wallet::{
+    APPROVAL_MESSAGE_DOMAIN, APPROVAL_MESSAGE_VERSION, AccountSource, ActionArg, ActionKind, Activation, ApprovalIndex,
+    ArgKind, AuthorityKind, BalanceAssertion, BalanceKind, BatchProposal, BudgetEpochs, CategoryOutflow, CategoryStats,
+    Cluster, ComputeDeclaration, ConfigAction, DepositLedger, EpochCap, ExecutionPolicy, ExecutionReceipt,
+    ExpirationUpdate, Explanation, FeePayer, HistoryRecord, INVITATION_WINDOW, Invitation, MAX_COMPUTE_UNITS,
+    MAX_EPHEMERAL_SIGNERS, MAX_EXPIRATION_HORIZON, MAX_HEAP_FRAME_BYTES, MAX_WALLET_PROGRAMS, MULTISIG_RESERVED_SPACE,
+    MultisigAccount, MultisigStatus, MultisigWalletError, NONCE_RESERVATION_TTL, NonceReservation, OpenProposalWindow,
+    PayoutEntry, Pda, Pdas, ProgramConfig, ProgramConfigUpdate, ProgramLimits, ProposalOptions, RegisteredAction,
+    RegisteredTokenAccount, SELF_REFERENCE_LOOKBACK, STAGING_TIMEOUT, Snapshot, SystemCall, TRANSACTION_RESERVED_SPACE,
+    TRANSACTION_VERSION_COMPACT_METAS, TRANSACTION_VERSION_LEGACY_METAS, TemplateAccount, TokenRegistry, TokenVote,
+    TransactionAccount, TransactionKind, TypedAction, canonical_approval_message, encode_approval_message,
+    find_ephemeral_signer_address, link_history,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{keypair_from_seed, Keypair, Signer},
+    system_instruction, system_program, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+
+const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+// Anchor's generated entrypoint ties the accounts slice and its elements to a
+// single lifetime, which the program-test builtin signature can't satisfy.
+// Leaking a copy of the slice lets both lifetimes line up.
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    multisig_wallet::entry(program_id, accounts, data)
+}
+
+// Stand-in for an external governance program: forwards its instruction to
+// the multisig program, so the call arrives as a CPI from this program.
+fn process_governance(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: accounts[1..]
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: data.to_vec(),
+    };
+    invoke(&ix, accounts)
+}
+
+fn governance_id() -> Pubkey {
+    Pubkey::new_from_array([7; 32])
+}
+
+// Stand-in for a program that is itself a multisig member, like a streaming
+// payments program: forwards its instruction to the multisig program with
+// its authority PDA signing, the way the generated `cpi` clients do.
+fn process_streamer(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (authority, bump) = streamer_authority();
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: accounts[1..]
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer || *account.key == authority,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: data.to_vec(),
+    };
+    invoke_signed(&ix, accounts, &[&[b"streamer", &[bump]]])
+}
+
+fn streamer_id() -> Pubkey {
+    Pubkey::new_from_array([8; 32])
+}
+
+fn streamer_authority() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"streamer"], &streamer_id())
+}
+
+/// Routes a multisig instruction through the stand-in streaming program,
+/// which signs for its authority PDA.
+fn via_streamer(mut ix: Instruction) -> Instruction {
+    let (authority, _) = streamer_authority();
+    for meta in ix.accounts.iter_mut().filter(|meta| meta.pubkey == authority) {
+        meta.is_signer = false;
+    }
+    ix.accounts.insert(0, AccountMeta::new_readonly(multisig_wallet::ID, false));
+    ix.program_id = streamer_id();
+    ix
+}
+
+fn program_test() -> ProgramTest {
+    let mut test = ProgramTest::new(
+        "multisig_wallet",
+        multisig_wallet::ID,
+        processor!(process_instruction),
+    );
+    test.add_program("governance", governance_id(), processor!(process_governance));
+    test.add_program("streamer", streamer_id(), processor!(process_streamer));
+
+    // Builtins have no program data account, so stand one in: the
+    // UpgradeableLoaderState::ProgramData layout with an upgrade authority.
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend(0u64.to_le_bytes());
+    data.push(1);
+    data.extend(upgrade_authority().pubkey().to_bytes());
+    test.add_account(
+        program_data_pda(),
+        solana_sdk::account::Account {
+            lamports: LAMPORTS_PER_SOL,
+            data,
+            owner: bpf_loader_upgradeable::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    test
+}
+
+fn multisig_pda(create_key: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"multisig", create_key.as_ref()], &multisig_wallet::ID).0
+}
+
+fn vault_pda(multisig: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", multisig.as_ref()], &multisig_wallet::ID).0
+}
+
+fn registry_pda(multisig: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"token_registry", multisig.as_ref()], &multisig_wallet::ID).0
+}
+
+fn action_registry_pda(multisig: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"action_registry", multisig.as_ref()], &multisig_wallet::ID).0
+}
+
+fn stage_pda(transaction: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"stage", transaction.as_ref()], &multisig_wallet::ID).0
+}
+
+fn generation_pda(create_key: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"generation", create_key.as_ref()], &multisig_wallet::ID).0
+}
+
+fn transaction_pda(multisig: &Pubkey, index: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"tx", multisig.as_ref(), &index.to_le_bytes()],
+        &multisig_wallet::ID,
+    )
+    .0
+}
+
+fn member_swap_pda(multisig: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"member_swap", multisig.as_ref()], &multisig_wallet::ID).0
+}
+
+fn recovery_pda(multisig: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"recovery", multisig.as_ref()], &multisig_wallet::ID).0
+}
+
+fn invitation_pda(multisig: &Pubkey, invitee: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"invitation", multisig.as_ref(), invitee.as_ref()], &multisig_wallet::ID).0
+}
+
+fn program_config_pda() -> Pubkey {
+    Pubkey::find_program_address(&[b"program_config"], &multisig_wallet::ID).0
+}
+
+fn program_data_pda() -> Pubkey {
+    Pubkey::find_program_address(&[multisig_wallet::ID.as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+// The upgrade authority recorded in the program data account that
+// `program_test` installs, so tests can sign as it.
+fn upgrade_authority() -> Keypair {
+    keypair_from_seed(&[7; 32]).unwrap()
+}
+
+// A `configure_multisig` call to propose, which executes as a config change.
+fn config_change(action: ConfigAction) -> Instruction {
+    Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: vec![],
+        data: multisig_wallet::instruction::ConfigureMultisig { action }.data(),
+    }
+}
+
+fn serialize_account_metas(metas: &[AccountMeta]) -> Vec<u8> {
+    metas
+        .iter()
+        .flat_map(|meta| {
+            let flags = (meta.is_signer as u8) | ((meta.is_writable as u8) << 1);
+            meta.pubkey.to_bytes().into_iter().chain(std::iter::once(flags))
+        })
+        .collect()
+}
+
+struct Fixture {
+    context: ProgramTestContext,
+    create_key: Keypair,
+    creator: Keypair,
+    members: Vec<Keypair>,
+    multisig: Pubkey,
+    vault: Pubkey,
+}
+
+impl Fixture {
+    /// Creates a multisig owned by a fresh creator whose members are the
+    /// creator followed by `extra_members` funded keypairs, expiring
+    /// `expires_in` seconds after creation if given.
+    async fn new(extra_members: usize, threshold: u8, expires_in: Option<i64>) -> Self {
+        let mut context = program_test().start_with_context().await;
+        let creator = Keypair::new();
+        let members: Vec<Keypair> = (0..extra_members).map(|_| Keypair::new()).collect();
+
+        let mut funding = vec![system_instruction::transfer(
+            &context.payer.pubkey(),
+            &creator.pubkey(),
+            10 * LAMPORTS_PER_SOL,
+        )];
+        for member in &members {
+            funding.push(system_instruction::transfer(
+                &context.payer.pubkey(),
+                &member.pubkey(),
+                LAMPORTS_PER_SOL,
+            ));
+        }
+        send(&mut context, &funding, &[]).await.unwrap();
+
+        let create_key = Keypair::new();
+        let multisig = multisig_pda(&create_key.pubkey());
+        let mut fixture = Self {
+            context,
+            vault: vault_pda(&multisig),
+            create_key,
+            creator,
+            members,
+            multisig,
+        };
+
+        let mut signers = vec![fixture.creator.pubkey()];
+        signers.extend(fixture.members.iter().map(|member| member.pubkey()));
+        let clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+        let expiration = expires_in.map(|seconds| clock.unix_timestamp + seconds);
+        let ix = fixture.initialize_ix(signers, threshold, expiration);
+        let creator = fixture.creator.insecure_clone();
+        let create_key = fixture.create_key.insecure_clone();
+        fixture.send(&[ix], &[&creator, &create_key]).await.unwrap();
+        fixture
+    }
+
+    fn initialize_ix(&self, signers: Vec<Pubkey>, threshold: u8, expiration: Option<i64>) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::InitializeMultisig {
+                multisig: self.multisig,
+                create_key: self.create_key.pubkey(),
+                payer: self.creator.pubkey(),
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+                generation: generation_pda(&self.create_key.pubkey()),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::InitializeMultisig {
+                initial_signers: signers,
+                threshold,
+                expiration_timestamp: expiration,
+                activation: None,
+            }
+            .data(),
+        }
+    }
+
+    /// Creates another multisig in the same bank, paid by the creator, and
+    /// returns its address.
+    async fn create_multisig(&mut self, signers: Vec<Pubkey>, threshold: u8) -> Pubkey {
+        let create_key = Keypair::new();
+        let multisig = multisig_pda(&create_key.pubkey());
+        let mut ix = self.initialize_ix(signers, threshold, None);
+        ix.accounts[0].pubkey = multisig;
+        ix.accounts[1].pubkey = create_key.pubkey();
+        ix.accounts[5].pubkey = generation_pda(&create_key.pubkey());
+        let creator = self.creator.insecure_clone();
+        self.send(&[ix], &[&creator, &create_key]).await.unwrap();
+        multisig
+    }
+
+    /// Points the instruction builders at another multisig.
+    fn select(&mut self, multisig: Pubkey) {
+        self.multisig = multisig;
+        self.vault = vault_pda(&multisig);
+    }
+
+    fn propose_ix(&self, proposer: &Pubkey, index: u64, inner: &Instruction) -> Instruction {
+        self.propose_raw_ix(proposer, index, inner.program_id, serialize_account_metas(&inner.accounts), inner.data.clone(), None, vec![])
+    }
+
+    fn propose_raw_ix(
+        &self,
+        proposer: &Pubkey,
+        index: u64,
+        program_id: Pubkey,
+        accounts: Vec<u8>,
+        instruction_data: Vec<u8>,
+        memo: Option<String>,
+        assertions: Vec<BalanceAssertion>,
+    ) -> Instruction {
+        // Proposals listing the multisig as a signer execute as the multisig
+        let authority_kind = if accounts
+            .chunks(33)
+            .any(|chunk| chunk[..32] == self.multisig.to_bytes()[..] && chunk[32] & 1 != 0)
+        {
+            AuthorityKind::Multisig
+        } else {
+            AuthorityKind::Vault(0)
+        };
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeTransaction {
+                multisig: self.multisig,
+                transaction: transaction_pda(&self.multisig, index),
+                proposer: *proposer,
+                rent_payer: *proposer,
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeTransaction {
+                program_id,
+                accounts,
+                instruction_data,
+                options: ProposalOptions { memo, assertions, authority_kind, ..Default::default() },
+            }
+            .data(),
+        }
+    }
+
+    fn approve_ix(&self, signer: &Pubkey, index: u64) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ApproveTransaction {
+                multisig: self.multisig,
+                transaction: transaction_pda(&self.multisig, index),
+                signer: *signer,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ApproveTransaction { expected_hash: None }.data(),
+        }
+    }
+
+    fn reject_ix(&self, signer: &Pubkey, index: u64, reason: &str) -> Instruction {
+        let mut ix = self.approve_ix(signer, index);
+        ix.data = multisig_wallet::instruction::RejectTransaction { reason: reason.to_string() }.data();
+        ix
+    }
+
+    fn approve_with_memo_ix(&self, signer: &Pubkey, index: u64, memo: &str) -> Instruction {
+        let mut ix = self.approve_ix(signer, index);
+        ix.data = multisig_wallet::instruction::ApproveTransactionWithMemo { memo: memo.to_string() }.data();
+        ix
+    }
+
+    /// Builds an execute instruction forwarding the inner instruction's
+    /// accounts (PDA signers are supplied through invoke_signed, so none of
+    /// them are marked as signers here) followed by the target program.
+    fn execute_ix(&self, index: u64, inner: &Instruction) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::ExecuteTransaction {
+            multisig: self.multisig,
+            transaction: transaction_pda(&self.multisig, index),
+            executor: None,
+        }
+        .to_account_metas(None);
+        accounts.extend(inner.accounts.iter().map(|meta| AccountMeta {
+            pubkey: meta.pubkey,
+            is_signer: false,
+            is_writable: meta.is_writable,
+        }));
+        accounts.push(AccountMeta::new_readonly(inner.program_id, false));
+
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ExecuteTransaction { expected_hash: None }.data(),
+        }
+    }
+
+    fn update_ix(
+        &self,
+        approvers: &[Pubkey],
+        new_signers: Option<Vec<Pubkey>>,
+        new_threshold: Option<u8>,
+        new_expiration: ExpirationUpdate,
+    ) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::UpdateMultisigWithConfig {
+            multisig: self.multisig,
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None);
+        accounts.extend(approvers.iter().map(|key| AccountMeta::new_readonly(*key, true)));
+
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::UpdateMultisig {
+                new_signers,
+                new_threshold,
+                new_expiration,
+            }
+            .data(),
+        }
+    }
+
+    fn configure_ix(&self, approvers: &[Pubkey], action: ConfigAction) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::UpdateMultisig {
+            multisig: self.multisig,
+        }
+        .to_account_metas(None);
+        accounts.extend(approvers.iter().map(|key| AccountMeta::new_readonly(*key, true)));
+
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ConfigureMultisig { action }.data(),
+        }
+    }
+
+    /// Applies a config action signed by every member.
+    async fn configure(&mut self, action: ConfigAction) -> Result<(), BanksClientError> {
+        let mut keypairs = vec![self.creator.insecure_clone()];
+        keypairs.extend(self.members.iter().map(|member| member.insecure_clone()));
+        let approvers: Vec<Pubkey> = keypairs.iter().map(|keypair| keypair.pubkey()).collect();
+        let ix = self.configure_ix(&approvers, action);
+        let signers: Vec<&Keypair> = keypairs.iter().collect();
+        self.send(&[ix], &signers).await
+    }
+
+    fn propose_member_swap_ix(&self, proposer: &Pubkey, new_signers: Vec<Pubkey>, new_threshold: u8) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeMemberSwap {
+                multisig: self.multisig,
+                member_swap: member_swap_pda(&self.multisig),
+                proposer: *proposer,
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeMemberSwap { new_signers, new_threshold }.data(),
+        }
+    }
+
+    fn member_swap_vote_ix(&self, signer: &Pubkey, data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::MemberSwapVote {
+                multisig: self.multisig,
+                member_swap: member_swap_pda(&self.multisig),
+                signer: *signer,
+            }
+            .to_account_metas(None),
+            data,
+        }
+    }
+
+    fn resolve_member_swap_ix(&self, proposer: &Pubkey, approvers: &[Pubkey], data: Vec<u8>) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::ResolveMemberSwap {
+            multisig: self.multisig,
+            member_swap: member_swap_pda(&self.multisig),
+            proposer: *proposer,
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None);
+        accounts.extend(approvers.iter().map(|key| AccountMeta::new_readonly(*key, true)));
+
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data,
+        }
+    }
+
+    fn propose_recovery_ix(&self, proposer: &Pubkey, new_signers: Vec<Pubkey>, new_threshold: u8) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeRecovery {
+                multisig: self.multisig,
+                recovery: recovery_pda(&self.multisig),
+                proposer: *proposer,
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeRecovery { new_signers, new_threshold }.data(),
+        }
+    }
+
+    fn emergency_ix(&self, signer: &Pubkey, data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::EmergencyThresholdAction {
+                multisig: self.multisig,
+                signer: *signer,
+            }
+            .to_account_metas(None),
+            data,
+        }
+    }
+
+    fn approve_external_ix(&self, index: u64, approvals: u8) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ApproveExternal {
+                multisig: self.multisig,
+                transaction: transaction_pda(&self.multisig, index),
+                instructions: sysvar::instructions::ID,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ApproveExternal { approvals }.data(),
+        }
+    }
+
+    /// Routes `approve_external` through the stand-in governance program.
+    fn governance_approve_ix(&self, index: u64, approvals: u8) -> Instruction {
+        let inner = self.approve_external_ix(index, approvals);
+        let mut accounts = vec![AccountMeta::new_readonly(multisig_wallet::ID, false)];
+        accounts.extend(inner.accounts);
+        Instruction {
+            program_id: governance_id(),
+            accounts,
+            data: inner.data,
+        }
+    }
+
+    fn vault_transfer(&self, to: &Pubkey, lamports: u64) -> Instruction {
+        system_instruction::transfer(&self.vault, to, lamports)
+    }
+
+    async fn fund_vault(&mut self, lamports: u64) {
+        let ix = system_instruction::transfer(&self.context.payer.pubkey(), &self.vault, lamports);
+        self.send(&[ix], &[]).await.unwrap();
+    }
+
+    // A `configure_multisig` call inviting `invitee`, with the accounts
+    // creating the invitation
+    fn invite_signer(&self, invitee: &Pubkey, roles: u8) -> Instruction {
+        let mut inner = config_change(ConfigAction::InviteSigner { invitee: *invitee, roles });
+        inner.accounts = vec![
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new(invitation_pda(&self.multisig, invitee), false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ];
+        inner
+    }
+
+    fn accept_invitation_ix(&self, invitee: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::AcceptInvitation {
+                multisig: self.multisig,
+                invitation: invitation_pda(&self.multisig, invitee),
+                vault: self.vault,
+                invitee: *invitee,
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::AcceptInvitation {}.data(),
+        }
+    }
+
+    async fn send(&mut self, ixs: &[Instruction], signers: &[&Keypair]) -> Result<(), BanksClientError> {
+        send(&mut self.context, ixs, signers).await
+    }
+
+    async fn balance(&mut self, key: &Pubkey) -> u64 {
+        self.context.banks_client.get_balance(*key).await.unwrap()
+    }
+
+    async fn multisig_account(&mut self) -> MultisigAccount {
+        fetch(&mut self.context, &self.multisig).await
+    }
+
+    async fn transaction_account(&mut self, index: u64) -> TransactionAccount {
+        let key = transaction_pda(&self.multisig, index);
+        fetch(&mut self.context, &key).await
+    }
+
+    /// Proposes the inner instruction as the creator and returns its index.
+    async fn propose(&mut self, inner: &Instruction) -> u64 {
+        let index = self.multisig_account().await.nonce;
+        let creator = self.creator.insecure_clone();
+        let ix = self.propose_ix(&creator.pubkey(), index, inner);
+        self.send(&[ix], &[&creator]).await.unwrap();
+        index
+    }
+
+    async fn approve(&mut self, member: usize, index: u64) -> Result<(), BanksClientError> {
+        let member = self.members[member].insecure_clone();
+        let ix = self.approve_ix(&member.pubkey(), index);
+        self.send(&[ix], &[&member]).await
+    }
+
+    async fn execute(&mut self, index: u64, inner: &Instruction) -> Result<(), BanksClientError> {
+        let ix = self.execute_ix(index, inner);
+        self.send(&[ix], &[]).await
+    }
+}
+
+async fn send(
+    context: &mut ProgramTestContext,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    // A fresh blockhash keeps retried instructions from being deduplicated.
+    let blockhash = context.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers: Vec<&Keypair> = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(ixs, Some(&context.payer.pubkey()), &all_signers, blockhash);
+    context.banks_client.process_transaction(tx).await
+}
+
+async fn fetch<T: AccountDeserialize>(context: &mut ProgramTestContext, key: &Pubkey) -> T {
+    let account = context
+        .banks_client
+        .get_account(*key)
+        .await
+        .unwrap()
+        .expect("account not found");
+    T::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+async fn simulate_logs(context: &mut ProgramTestContext, ixs: &[Instruction], signers: &[&Keypair]) -> Vec<String> {
+    let blockhash = context.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers: Vec<&Keypair> = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(ixs, Some(&context.payer.pubkey()), &all_signers, blockhash);
+    let result = context.banks_client.simulate_transaction(tx).await.unwrap();
+    result.simulation_details.expect("simulation details").logs
+}
+
+// Checks the account at `key` was allocated `space` bytes, and that its
+// serialized layout fits with `reserved` zeroed bytes of padding at the end
+async fn assert_padded<T: AccountDeserialize + AnchorSerialize>(
+    context: &mut ProgramTestContext,
+    key: &Pubkey,
+    space: usize,
+    reserved: usize,
+) {
+    let account = context.banks_client.get_account(*key).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), space);
+    let decoded = T::try_deserialize(&mut account.data.as_slice()).unwrap();
+    let serialized = 8 + decoded.try_to_vec().unwrap().len();
+    assert!(serialized <= space);
+    assert!(account.data[serialized - reserved..serialized].iter().all(|byte| *byte == 0));
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn assert_error(result: Result<(), BanksClientError>, expected: MultisigWalletError) {
+    let code = u32::from(expected);
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(actual),
+        ))) => assert_eq!(actual, code, "unexpected program error"),
+        other => panic!("expected custom error {code}, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn initialize_records_configuration() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    let multisig = fixture.multisig_account().await;
+
+    assert_eq!(multisig.signers.len(), 3);
+    assert_eq!(multisig.signers[0], fixture.creator.pubkey());
+    assert_eq!(multisig.threshold, 2);
+    assert_eq!(multisig.expiration_timestamp, None);
+    assert_eq!(multisig.nonce, 0);
+    assert_eq!(multisig.create_key, fixture.create_key.pubkey());
+    assert_eq!(multisig.authority, fixture.creator.pubkey());
+}
+
+#[tokio::test]
+async fn transfer_authority_requires_every_signer() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let new_authority = Pubkey::new_unique();
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.configure_ix(&[creator.pubkey()], ConfigAction::TransferAuthority { new_authority });
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::NotAllSignersApproved);
+
+    fixture
+        .configure(ConfigAction::TransferAuthority { new_authority })
+        .await
+        .unwrap();
+    let multisig = fixture.multisig_account().await;
+    assert_eq!(multisig.authority, new_authority);
+    assert_eq!(multisig.create_key, fixture.create_key.pubkey());
+}
+
+#[tokio::test]
+async fn initialize_rejects_invalid_threshold() {
+    let mut context = program_test().start_with_context().await;
+    let creator = Keypair::new();
+    let fund = system_instruction::transfer(&context.payer.pubkey(), &creator.pubkey(), LAMPORTS_PER_SOL);
+    send(&mut context, &[fund], &[]).await.unwrap();
+
+    for threshold in [0, 3] {
+        let ix = Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::InitializeMultisig {
+                // The payer doubles as the create key: the legacy address
+                multisig: multisig_pda(&creator.pubkey()),
+                create_key: creator.pubkey(),
+                payer: creator.pubkey(),
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+                generation: generation_pda(&creator.pubkey()),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::InitializeMultisig {
+                initial_signers: vec![creator.pubkey(), Pubkey::new_unique()],
+                threshold,
+                expiration_timestamp: None,
+                activation: None,
+            }
+            .data(),
+        };
+        let result = send(&mut context, &[ix], &[&creator]).await;
+        assert_error(result, MultisigWalletError::InvalidThreshold);
+    }
+}
+
+// Initializes a multisig at the creator's legacy address with `signers`
+async fn initialize_with_signers(signers: impl FnOnce(&Pubkey) -> Vec<Pubkey>) -> Result<(), BanksClientError> {
+    let mut context = program_test().start_with_context().await;
+    let creator = Keypair::new();
+    let fund = system_instruction::transfer(&context.payer.pubkey(), &creator.pubkey(), LAMPORTS_PER_SOL);
+    send(&mut context, &[fund], &[]).await.unwrap();
+
+    let multisig = multisig_pda(&creator.pubkey());
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::InitializeMultisig {
+            multisig,
+            create_key: creator.pubkey(),
+            payer: creator.pubkey(),
+            system_program: system_program::ID,
+            program_config: program_config_pda(),
+            generation: generation_pda(&creator.pubkey()),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::InitializeMultisig {
+            initial_signers: signers(&multisig),
+            threshold: 1,
+            expiration_timestamp: None,
+            activation: None,
+        }
+        .data(),
+    };
+    send(&mut context, &[ix], &[&creator]).await
+}
+
+#[tokio::test]
+async fn initialize_rejects_empty_signer_list() {
+    let result = initialize_with_signers(|_| vec![]).await;
+    assert_error(result, MultisigWalletError::EmptySignerList);
+}
+
+#[tokio::test]
+async fn initialize_rejects_duplicate_signers() {
+    let signer = Pubkey::new_unique();
+    let result = initialize_with_signers(|_| vec![signer, signer]).await;
+    assert_error(result, MultisigWalletError::AlreadyMember);
+}
+
+#[tokio::test]
+async fn initialize_rejects_default_signer_key() {
+    let result = initialize_with_signers(|_| vec![Pubkey::new_unique(), Pubkey::default()]).await;
+    assert_error(result, MultisigWalletError::DefaultSignerKey);
+}
+
+#[tokio::test]
+async fn initialize_rejects_program_signer_key() {
+    let result = initialize_with_signers(|_| vec![Pubkey::new_unique(), multisig_wallet::ID]).await;
+    assert_error(result, MultisigWalletError::ProgramSignerKey);
+}
+
+#[tokio::test]
+async fn initialize_rejects_multisig_and_vault_as_signers() {
+    let result = initialize_with_signers(|multisig| vec![Pubkey::new_unique(), *multisig]).await;
+    assert_error(result, MultisigWalletError::SelfSignerKey);
+    let result = initialize_with_signers(|multisig| vec![Pubkey::new_unique(), vault_pda(multisig)]).await;
+    assert_error(result, MultisigWalletError::SelfSignerKey);
+}
+
+#[tokio::test]
+async fn executes_vault_transfer_after_threshold() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(2 * LAMPORTS_PER_SOL).await;
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 4);
+
+    let index = fixture.propose(&inner).await;
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.signers, vec![fixture.creator.pubkey()]);
+    assert_eq!(fixture.multisig_account().await.nonce, index + 1);
+
+    fixture.approve(0, index).await.unwrap();
+    let vault = fixture.vault;
+    let vault_before = fixture.balance(&vault).await;
+    fixture.execute(index, &inner).await.unwrap();
+
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 4);
+    assert_eq!(fixture.balance(&vault).await, vault_before - LAMPORTS_PER_SOL / 4);
+    assert!(fixture.transaction_account(index).await.executed);
+}
+
+#[tokio::test]
+async fn propose_rejects_non_member() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let outsider = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &outsider.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let ix = fixture.propose_ix(&outsider.pubkey(), 0, &inner);
+    let result = fixture.send(&[ix], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+}
+
+#[tokio::test]
+async fn propose_rejects_malformed_account_metas() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.propose_raw_ix(&creator.pubkey(), 0, system_program::ID, vec![0; 34], vec![], None, vec![]);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InvalidAccountMetas);
+}
+
+#[tokio::test]
+async fn propose_validates_account_meta_flags_and_signers() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let vault = fixture.vault;
+    let cases = [
+        (vec![AccountMeta { pubkey: vault, is_signer: false, is_writable: true }], 0b100, MultisigWalletError::InvalidAccountMetaFlags),
+        (vec![AccountMeta::new(creator.pubkey(), true)], 0, MultisigWalletError::UnauthorizedSignerMeta),
+        (vec![AccountMeta::new(vault, true), AccountMeta::new_readonly(vault, true)], 0, MultisigWalletError::DuplicateSignerMeta),
+        (vec![AccountMeta::new_readonly(Pubkey::new_unique(), false); 33], 0, MultisigWalletError::TooManyAccountMetas),
+    ];
+    for (metas, extra_flags, expected) in cases {
+        let mut accounts = serialize_account_metas(&metas);
+        accounts[32] |= extra_flags;
+        let ix = fixture.propose_raw_ix(&creator.pubkey(), 0, system_program::ID, accounts, vec![], None, vec![]);
+        let result = fixture.send(&[ix], &[&creator]).await;
+        assert_error(result, expected);
+    }
+}
+
+#[tokio::test]
+async fn propose_rejects_oversized_memo() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let memo = "x".repeat(multisig_wallet::MAX_MEMO_LEN + 1);
+    let ix = fixture.propose_raw_ix(&creator.pubkey(), 0, system_program::ID, vec![], vec![], Some(memo), vec![]);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::MemoTooLong);
+}
+
+#[tokio::test]
+async fn executes_with_memo() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 10);
+
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.propose_raw_ix(
+        &creator.pubkey(),
+        0,
+        inner.program_id,
+        serialize_account_metas(&inner.accounts),
+        inner.data.clone(),
+        Some("payroll march".to_string()),
+        vec![],
+    );
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+
+    let mut ix = fixture.execute_ix(0, &inner);
+    ix.accounts.push(AccountMeta::new_readonly(multisig_wallet::spl_memo::ID, false));
+    fixture.send(&[ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 10);
+    assert_eq!(fixture.transaction_account(0).await.memo.as_deref(), Some("payroll march"));
+}
+
+#[tokio::test]
+async fn approve_rejects_non_member() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    let outsider = Keypair::new();
+    let ix = fixture.approve_ix(&outsider.pubkey(), index);
+    let result = fixture.send(&[ix], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+}
+
+#[tokio::test]
+async fn approve_rejects_double_approval() {
+    let mut fixture = Fixture::new(2, 3, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    fixture.approve(0, index).await.unwrap();
+    let result = fixture.approve(0, index).await;
+    assert_error(result, MultisigWalletError::AlreadyApproved);
+
+    // The proposer's automatic approval counts as well.
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.approve_ix(&creator.pubkey(), index);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::AlreadyApproved);
+}
+
+#[tokio::test]
+async fn execute_rejects_demoted_writable_accounts() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 10);
+    let index = fixture.propose(&inner).await;
+
+    // The vault and the recipient follow the multisig, transaction and
+    // executor accounts
+    for position in [3, 4] {
+        let mut ix = fixture.execute_ix(index, &inner);
+        ix.accounts[position].is_writable = false;
+        let logs = simulate_logs(&mut fixture.context, &[ix.clone()], &[]).await;
+        let expected = format!("Account meta {} ({}) must be writable", position - 3, ix.accounts[position].pubkey);
+        assert!(logs.iter().any(|log| log.contains(&expected)), "{:?}", logs);
+        let result = fixture.send(&[ix], &[]).await;
+        assert_error(result, MultisigWalletError::AccountPrivilegeMismatch);
+    }
+
+    fixture.execute(index, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 10);
+}
+
+#[tokio::test]
+async fn idempotent_approve_tolerates_retries_only() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), LAMPORTS_PER_SOL / 10);
+    let index = fixture.propose(&inner).await;
+    let member = fixture.members[0].insecure_clone();
+    let idempotent_ix = |fixture: &Fixture, signer: &Pubkey| {
+        let mut ix = fixture.approve_ix(signer, index);
+        ix.data = multisig_wallet::instruction::ApproveTransactionIdempotent { expected_hash: None }.data();
+        ix
+    };
+
+    // The first call approves like approve_transaction
+    let ix = idempotent_ix(&fixture, &member.pubkey());
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let approved = fixture.context.banks_client.get_account(transaction_pda(&fixture.multisig, index)).await;
+    assert_eq!(fixture.transaction_account(index).await.signers.len(), 2);
+
+    // A retry succeeds without touching the proposal or emitting events
+    let ix = idempotent_ix(&fixture, &member.pubkey());
+    let logs = simulate_logs(&mut fixture.context, &[ix.clone()], &[&member]).await;
+    assert!(!logs.iter().any(|log| log.starts_with("Program data: ")));
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let retried = fixture.context.banks_client.get_account(transaction_pda(&fixture.multisig, index)).await;
+    assert_eq!(retried.unwrap(), approved.unwrap());
+
+    // Other failures still surface
+    let outsider = Keypair::new();
+    let ix = idempotent_ix(&fixture, &outsider.pubkey());
+    let result = fixture.send(&[ix], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+    fixture.execute(index, &inner).await.unwrap();
+    let ix = idempotent_ix(&fixture, &member.pubkey());
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::TransactionAlreadyExecuted);
+}
+
+#[tokio::test]
+async fn approve_rejects_expired_multisig() {
+    let mut fixture = Fixture::new(1, 2, Some(60)).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 61;
+    fixture.context.set_sysvar(&clock);
+    let result = fixture.approve(0, index).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+}
+
+#[tokio::test]
+async fn execute_rejects_below_threshold() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+}
+
+#[tokio::test]
+async fn execute_rejects_second_execution() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), LAMPORTS_PER_SOL / 10);
+    let index = fixture.propose(&inner).await;
+
+    fixture.execute(index, &inner).await.unwrap();
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::TransactionAlreadyExecuted);
+}
+
+#[tokio::test]
+async fn execute_rejects_mismatched_accounts() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    let substituted = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let ix = fixture.execute_ix(index, &substituted);
+    let logs = simulate_logs(&mut fixture.context, &[ix], &[]).await;
+    let expected = format!(
+        "Account meta 1 mismatch: expected {}, provided {}",
+        inner.accounts[1].pubkey, substituted.accounts[1].pubkey
+    );
+    assert!(logs.iter().any(|log| log.ends_with(&expected)), "{logs:?}");
+
+    let result = fixture.execute(index, &substituted).await;
+    assert_error(result, MultisigWalletError::InvalidAccountMetas);
+}
+
+#[tokio::test]
+async fn execute_rejects_missing_accounts() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    let mut ix = fixture.execute_ix(index, &inner);
+    ix.accounts.truncate(3);
+    let logs = simulate_logs(&mut fixture.context, &[ix.clone()], &[]).await;
+    assert!(
+        logs.iter().any(|log| log.ends_with("Insufficient accounts: expected 2, provided 0")),
+        "{logs:?}"
+    );
+
+    let result = fixture.send(&[ix], &[]).await;
+    assert_error(result, MultisigWalletError::InsufficientAccounts);
+}
+
+#[tokio::test]
+async fn execute_rejects_recursive_call() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let inner = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: vec![],
+        data: vec![],
+    };
+    let index = fixture.propose(&inner).await;
+
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::RecursiveCallNotAllowed);
+}
+
+#[tokio::test]
+async fn update_requires_every_current_signer() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+
+    let ix = fixture.update_ix(&[creator.pubkey(), member.pubkey()], None, Some(1), ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::NotAllSignersApproved);
+
+    let other = fixture.members[1].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey(), other.pubkey()];
+    let ix = fixture.update_ix(&approvers, None, Some(3), ExpirationUpdate::Keep);
+    fixture.send(&[ix], &[&creator, &member, &other]).await.unwrap();
+    assert_eq!(fixture.multisig_account().await.threshold, 3);
+
+    let ix = fixture.update_ix(&approvers, None, Some(4), ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member, &other]).await;
+    assert_error(result, MultisigWalletError::InvalidThreshold);
+}
+
+#[tokio::test]
+async fn close_returns_rent_to_receiver() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let receiver = Pubkey::new_unique();
+    let multisig = fixture.multisig;
+    let rent = fixture.balance(&multisig).await;
+
+    let ix = fixture.close_ix(&receiver, &[], &[]);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+
+    assert_eq!(fixture.balance(&receiver).await, rent);
+    let closed = fixture.context.banks_client.get_account(multisig).await.unwrap();
+    assert!(closed.is_none());
+}
+
+impl Fixture {
+    /// Builds a `close_multisig` signed by all members, sweeping the given
+    /// `[token account, mint, destination]` triples of the vault.
+    fn close_ix(&self, receiver: &Pubkey, sweeps: &[[Pubkey; 3]], extra: &[AccountMeta]) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::CloseMultisig {
+            multisig: self.multisig,
+            receiver: *receiver,
+            vault: self.vault,
+            registry: registry_pda(&self.multisig),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new_readonly(self.creator.pubkey(), true));
+        for member in &self.members {
+            accounts.push(AccountMeta::new_readonly(member.pubkey(), true));
+        }
+        for [token_account, mint, destination] in sweeps {
+            accounts.push(AccountMeta::new(*token_account, false));
+            accounts.push(AccountMeta::new_readonly(*mint, false));
+            accounts.push(AccountMeta::new(*destination, false));
+        }
+        accounts.extend_from_slice(extra);
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::CloseMultisig { token_accounts: sweeps.len() as u8 }.data(),
+        }
+    }
+
+    /// Creates a mint with the payer as mint and freeze authority, and a
+    /// token account of it owned by `owner` holding `amount`.
+    async fn create_token_account(&mut self, owner: &Pubkey, amount: u64) -> (Pubkey, Pubkey) {
+        let mint = Keypair::new();
+        let account = Keypair::new();
+        let payer = self.context.payer.pubkey();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let ixs = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint2(&spl_token::ID, &mint.pubkey(), &payer, Some(&payer), 0)
+                .unwrap(),
+            system_instruction::create_account(
+                &payer,
+                &account.pubkey(),
+                rent.minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_account3(&spl_token::ID, &account.pubkey(), &mint.pubkey(), owner)
+                .unwrap(),
+            spl_token::instruction::mint_to(&spl_token::ID, &mint.pubkey(), &account.pubkey(), &payer, &[], amount)
+                .unwrap(),
+        ];
+        self.send(&ixs, &[&mint, &account]).await.unwrap();
+        (mint.pubkey(), account.pubkey())
+    }
+}
+
+#[tokio::test]
+async fn registered_token_accounts_must_be_listed() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let vault = fixture.vault;
+    let multisig = fixture.multisig;
+    let registry = registry_pda(&multisig);
+    let payer = fixture.context.payer.pubkey();
+    let (mint, _) = fixture.create_token_account(&payer, 0).await;
+    let ata = get_associated_token_address_with_program_id(&vault, &mint, &spl_token::ID);
+
+    let create_ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CreateVaultAta {
+            multisig,
+            registry,
+            vault,
+            mint,
+            token_account: ata,
+            member: creator.pubkey(),
+            token_program: spl_token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CreateVaultAta {}.data(),
+    };
+    fixture.send(&[create_ix.clone()], &[&creator]).await.unwrap();
+    fixture.send(&[create_ix], &[&creator]).await.unwrap();
+    let recorded: TokenRegistry = fetch(&mut fixture.context, &registry).await;
+    assert_eq!(recorded.accounts, vec![RegisteredTokenAccount { address: ata, mint }]);
+
+    // A snapshot or close leaving out the registered account is refused
+    let snapshot_ix = |accounts: &[AccountMeta]| {
+        let (snapshot, _) =
+            Pubkey::find_program_address(&[b"snapshot", multisig.as_ref(), &0u64.to_le_bytes()], &multisig_wallet::ID);
+        let mut metas = multisig_wallet::accounts::SnapshotBalances {
+            multisig,
+            vault,
+            snapshot,
+            registry,
+            member: creator.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        metas.extend_from_slice(accounts);
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: metas,
+            data: multisig_wallet::instruction::SnapshotBalances {}.data(),
+        }
+    };
+    let result = fixture.send(&[snapshot_ix(&[])], &[&creator]).await;
+    assert_error(result, MultisigWalletError::UnlistedTokenAccount);
+    let receiver = Pubkey::new_unique();
+    let ix = fixture.close_ix(&receiver, &[], &[]);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::UnlistedTokenAccount);
+
+    let ix = snapshot_ix(&[AccountMeta::new_readonly(ata, false)]);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+
+    // Pruning waits until the account is closed
+    let prune_ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: {
+            let mut metas =
+                multisig_wallet::accounts::PruneTokenRegistry { multisig, registry }.to_account_metas(None);
+            metas.push(AccountMeta::new_readonly(ata, false));
+            metas
+        },
+        data: multisig_wallet::instruction::PruneTokenRegistry {}.data(),
+    };
+    let result = fixture.send(&[prune_ix.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::TokenAccountStillOpen);
+
+    let close_ata = spl_token::instruction::close_account(&spl_token::ID, &ata, &vault, &vault, &[]).unwrap();
+    let index = fixture.propose(&close_ata).await;
+    fixture.execute(index, &close_ata).await.unwrap();
+    fixture.send(&[prune_ix.clone()], &[]).await.unwrap();
+    let recorded: TokenRegistry = fetch(&mut fixture.context, &registry).await;
+    assert!(recorded.accounts.is_empty());
+    let result = fixture.send(&[prune_ix], &[]).await;
+    assert_error(result, MultisigWalletError::TokenAccountNotRegistered);
+
+    // With nothing left registered the close goes through and takes the
+    // registry with it
+    let ix = fixture.close_ix(&receiver, &[], &[]);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert!(fixture.context.banks_client.get_account(registry).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn close_sweeps_vault_sol_and_token_accounts() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let vault = fixture.vault;
+    let (mint, vault_tokens) = fixture.create_token_account(&vault, 500).await;
+    let (_, destination) = fixture.create_token_account(&creator.pubkey(), 0).await;
+    let receiver = Pubkey::new_unique();
+
+    // A frozen token account can't be emptied, so the close fails rather
+    // than leave it behind
+    let payer = fixture.context.payer.insecure_clone();
+    let freeze = spl_token::instruction::freeze_account(&spl_token::ID, &vault_tokens, &mint, &payer.pubkey(), &[])
+        .unwrap();
+    fixture.send(&[freeze], &[]).await.unwrap();
+    let token_program = [AccountMeta::new_readonly(spl_token::ID, false)];
+    let ix = fixture.close_ix(&receiver, &[[vault_tokens, mint, destination]], &token_program);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::UnsweepableTokenAccount);
+
+    let thaw = spl_token::instruction::thaw_account(&spl_token::ID, &vault_tokens, &mint, &payer.pubkey(), &[])
+        .unwrap();
+    fixture.send(&[thaw], &[]).await.unwrap();
+    let token_rent = fixture.balance(&vault_tokens).await;
+    let vault_lamports = fixture.balance(&vault).await;
+    let multisig = fixture.multisig;
+    let multisig_rent = fixture.balance(&multisig).await;
+    let ix = fixture.close_ix(&receiver, &[[vault_tokens, mint, destination]], &token_program);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+
+    assert_eq!(token_balance(&mut fixture.context, &destination).await, 500);
+    assert_eq!(fixture.balance(&vault_tokens).await, 0);
+    assert_eq!(fixture.balance(&vault).await, 0);
+    assert_eq!(fixture.balance(&receiver).await, token_rent + vault_lamports + multisig_rent);
+}
+
+#[tokio::test]
+async fn execute_enforces_balance_assertions() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 10);
+    let creator = fixture.creator.insecure_clone();
+
+    for (index, min_delta) in [(0, LAMPORTS_PER_SOL as i64), (1, (LAMPORTS_PER_SOL / 10) as i64)] {
+        let assertion = BalanceAssertion {
+            account: recipient,
+            kind: BalanceKind::Lamports,
+            min_delta,
+        };
+        let ix = fixture.propose_raw_ix(
+            &creator.pubkey(),
+            index,
+            inner.program_id,
+            serialize_account_metas(&inner.accounts),
+            inner.data.clone(),
+            None,
+            vec![assertion],
+        );
+        fixture.send(&[ix], &[&creator]).await.unwrap();
+    }
+
+    // The recipient gains less than asserted, so the transfer is rolled back
+    let result = fixture.execute(0, &inner).await;
+    assert_error(result, MultisigWalletError::BalanceAssertionFailed);
+    assert_eq!(fixture.balance(&recipient).await, 0);
+
+    fixture.execute(1, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 10);
+}
+
+#[tokio::test]
+async fn ordered_execution_requires_skipping_gaps() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetOrderedExecution { enabled: true, next_index: 0 })
+        .await
+        .unwrap();
+
+    let first = fixture.vault_transfer(&Pubkey::new_unique(), LAMPORTS_PER_SOL / 10);
+    let second = fixture.vault_transfer(&Pubkey::new_unique(), LAMPORTS_PER_SOL / 10);
+    let first_index = fixture.propose(&first).await;
+    let second_index = fixture.propose(&second).await;
+    fixture.approve(0, second_index).await.unwrap();
+
+    let result = fixture.execute(second_index, &second).await;
+    assert_error(result, MultisigWalletError::OutOfOrderExecution);
+
+    // Skipping needs a threshold of signers, here both members
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let mut accounts = multisig_wallet::accounts::SkipTransaction {
+        multisig: fixture.multisig,
+        transaction: transaction_pda(&fixture.multisig, first_index),
+    }
+    .to_account_metas(None);
+    accounts.push(AccountMeta::new_readonly(creator.pubkey(), true));
+    let skip = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: accounts.clone(),
+        data: multisig_wallet::instruction::SkipTransaction {}.data(),
+    };
+    let result = fixture.send(&[skip.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+
+    let mut skip = skip;
+    skip.accounts.push(AccountMeta::new_readonly(member.pubkey(), true));
+    fixture.send(&[skip], &[&creator, &member]).await.unwrap();
+    assert!(fixture.transaction_account(first_index).await.cancelled);
+
+    fixture.execute(second_index, &second).await.unwrap();
+    assert_eq!(fixture.multisig_account().await.last_executed_index, Some(second_index));
+}
+
+#[tokio::test]
+async fn member_change_invalidates_pending_transactions() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture
+        .configure(ConfigAction::SetInvalidateOnMemberChange { enabled: true })
+        .await
+        .unwrap();
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let supplied = fixture.propose(&inner).await;
+    let unsupplied = fixture.propose(&inner).await;
+
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let replacement = Pubkey::new_unique();
+    let mut ix = fixture.update_ix(
+        &[member.pubkey()],
+        Some(vec![creator.pubkey(), member.pubkey(), replacement]),
+        None,
+        ExpirationUpdate::Keep,
+    );
+    // The proposer signs as a current signer and, as rent payer, receives the
+    // refunded rent
+    ix.accounts.push(AccountMeta::new(creator.pubkey(), true));
+    ix.accounts.push(AccountMeta::new(transaction_pda(&fixture.multisig, supplied), false));
+    let creator_before = fixture.balance(&creator.pubkey()).await;
+    let rent = fixture.balance(&transaction_pda(&fixture.multisig, supplied)).await;
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+
+    assert_eq!(fixture.balance(&creator.pubkey()).await, creator_before + rent);
+    let closed = fixture
+        .context
+        .banks_client
+        .get_account(transaction_pda(&fixture.multisig, supplied))
+        .await
+        .unwrap();
+    assert!(closed.is_none());
+
+    let result = fixture.approve(0, unsupplied).await;
+    assert_error(result, MultisigWalletError::StaleTransaction);
+    let result = fixture.send(&[fixture.reject_ix(&member.pubkey(), unsupplied, "")], &[&member]).await;
+    assert_error(result, MultisigWalletError::StaleTransaction);
+}
+
+#[tokio::test]
+async fn proposal_cooldown_limits_repeat_proposals() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture
+        .configure(ConfigAction::SetProposalCooldown { seconds: 3_600 })
+        .await
+        .unwrap();
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    fixture.propose(&inner).await;
+
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.propose_ix(&creator.pubkey(), 1, &inner);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::ProposalCooldownActive);
+
+    // Other members have their own cooldown
+    let member = fixture.members[0].insecure_clone();
+    let ix = fixture.propose_ix(&member.pubkey(), 1, &inner);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+}
+
+#[tokio::test]
+async fn approve_with_memo_records_note() {
+    let mut fixture = Fixture::new(2, 3, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    let member = fixture.members[0].insecure_clone();
+    let memo = "x".repeat(multisig_wallet::MAX_APPROVAL_MEMO_LEN + 1);
+    let ix = fixture.approve_with_memo_ix(&member.pubkey(), index, &memo);
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::MemoTooLong);
+
+    let ix = fixture.approve_with_memo_ix(&member.pubkey(), index, "pending annex B");
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    fixture.approve(1, index).await.unwrap();
+
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.signers.len(), 3);
+    assert_eq!(transaction.approval_memos, vec!["", "pending annex B", ""]);
+}
+
+#[tokio::test]
+async fn rejections_record_reasons_and_cancel() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    let first = fixture.members[0].insecure_clone();
+    let ix = fixture.reject_ix(&first.pubkey(), index, "");
+    fixture.send(&[ix], &[&first]).await.unwrap();
+    let result = fixture.send(&[fixture.reject_ix(&first.pubkey(), index, "again")], &[&first]).await;
+    assert_error(result, MultisigWalletError::AlreadyRejected);
+    assert!(!fixture.transaction_account(index).await.cancelled);
+
+    let second = fixture.members[1].insecure_clone();
+    let ix = fixture.reject_ix(&second.pubkey(), index, "wrong recipient");
+    fixture.send(&[ix], &[&second]).await.unwrap();
+
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.rejections, vec![first.pubkey(), second.pubkey()]);
+    assert_eq!(transaction.rejection_reasons, vec!["", "wrong recipient"]);
+    assert!(transaction.cancelled);
+}
+
+#[tokio::test]
+async fn settled_or_expired_proposals_reject_votes_and_execution() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let first = fixture.members[0].insecure_clone();
+    let second = fixture.members[1].insecure_clone();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+
+    // Executed: no further approvals or rejections
+    let executed = fixture.propose(&inner).await;
+    fixture.approve(0, executed).await.unwrap();
+    fixture.execute(executed, &inner).await.unwrap();
+    let result = fixture.approve(1, executed).await;
+    assert_error(result, MultisigWalletError::TransactionAlreadyExecuted);
+    let result = fixture.send(&[fixture.reject_ix(&second.pubkey(), executed, "")], &[&second]).await;
+    assert_error(result, MultisigWalletError::TransactionAlreadyExecuted);
+
+    // Cancelled by rejections: approving or executing fails
+    let cancelled = fixture.propose(&inner).await;
+    fixture.send(&[fixture.reject_ix(&first.pubkey(), cancelled, "")], &[&first]).await.unwrap();
+    fixture.send(&[fixture.reject_ix(&second.pubkey(), cancelled, "")], &[&second]).await.unwrap();
+    let result = fixture.approve(0, cancelled).await;
+    assert_error(result, MultisigWalletError::TransactionCancelled);
+    let result = fixture.execute(cancelled, &inner).await;
+    assert_error(result, MultisigWalletError::TransactionCancelled);
+
+    // Expired: rejecting fails like approving
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 60 })
+        .await
+        .unwrap();
+    let expired = fixture.propose(&inner).await;
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 61;
+    fixture.context.set_sysvar(&clock);
+    let result = fixture.send(&[fixture.reject_ix(&first.pubkey(), expired, "")], &[&first]).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+    let result = fixture.approve(0, expired).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+}
+
+#[tokio::test]
+async fn member_swap_requires_acceptance_by_incoming_signers() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let incoming = Keypair::new();
+    let new_signers = vec![creator.pubkey(), incoming.pubkey()];
+
+    let ix = fixture.propose_member_swap_ix(&creator.pubkey(), new_signers.clone(), 2);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+
+    // The incoming key hasn't proven control yet
+    let finalize = multisig_wallet::instruction::FinalizeMemberSwap {}.data();
+    let approve = multisig_wallet::instruction::ApproveMemberSwap {}.data();
+    let ix = fixture.member_swap_vote_ix(&member.pubkey(), approve);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let ix = fixture.resolve_member_swap_ix(&creator.pubkey(), &[], finalize.clone());
+    let result = fixture.send(&[ix], &[]).await;
+    assert_error(result, MultisigWalletError::MembershipNotAccepted);
+
+    // Current members aren't joining, so they can't accept
+    let accept = multisig_wallet::instruction::AcceptMembership {}.data();
+    let ix = fixture.member_swap_vote_ix(&member.pubkey(), accept.clone());
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::NotIncomingSigner);
+
+    let ix = fixture.member_swap_vote_ix(&incoming.pubkey(), accept);
+    fixture.send(&[ix], &[&incoming]).await.unwrap();
+    let ix = fixture.resolve_member_swap_ix(&creator.pubkey(), &[], finalize);
+    fixture.send(&[ix], &[]).await.unwrap();
+
+    let multisig = fixture.multisig_account().await;
+    assert_eq!(multisig.signers, new_signers);
+    assert_eq!(multisig.threshold, 2);
+    let swap = fixture.context.banks_client.get_account(member_swap_pda(&fixture.multisig)).await.unwrap();
+    assert!(swap.is_none());
+}
+
+#[tokio::test]
+async fn member_swap_cancel_requires_quorum() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let ix = fixture.propose_member_swap_ix(&creator.pubkey(), vec![creator.pubkey(), Pubkey::new_unique()], 1);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+
+    let cancel = multisig_wallet::instruction::CancelMemberSwap {}.data();
+    let ix = fixture.resolve_member_swap_ix(&creator.pubkey(), &[creator.pubkey()], cancel.clone());
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+
+    let ix = fixture.resolve_member_swap_ix(&creator.pubkey(), &[creator.pubkey(), member.pubkey()], cancel);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let swap = fixture.context.banks_client.get_account(member_swap_pda(&fixture.multisig)).await.unwrap();
+    assert!(swap.is_none());
+}
+
+#[tokio::test]
+async fn emergency_threshold_applies_after_timelock() {
+    let mut fixture = Fixture::new(2, 3, None).await;
+    fixture
+        .configure(ConfigAction::SetEmergencyTimelock { seconds: 1 })
+        .await
+        .unwrap();
+    let member = fixture.members[0].insecure_clone();
+    let initiate = multisig_wallet::instruction::InitiateEmergencyThreshold { new_threshold: 1 }.data();
+    let execute = multisig_wallet::instruction::ExecuteEmergencyThreshold {}.data();
+
+    // A veto clears the pending reduction
+    let ix = fixture.emergency_ix(&member.pubkey(), initiate.clone());
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let other = fixture.members[1].insecure_clone();
+    let ix = fixture.emergency_ix(&other.pubkey(), multisig_wallet::instruction::VetoEmergencyThreshold {}.data());
+    fixture.send(&[ix], &[&other]).await.unwrap();
+    assert_eq!(fixture.multisig_account().await.pending_emergency, None);
+
+    let ix = fixture.emergency_ix(&member.pubkey(), initiate);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let unlocks_at = fixture.multisig_account().await.pending_emergency.unwrap().unlocks_at;
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unlocks_at;
+    fixture.context.set_sysvar(&clock);
+
+    let ix = fixture.emergency_ix(&member.pubkey(), execute);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let multisig = fixture.multisig_account().await;
+    assert_eq!(multisig.threshold, 1);
+    assert_eq!(multisig.pending_emergency, None);
+}
+
+#[tokio::test]
+async fn emergency_threshold_waits_for_timelock() {
+    let mut fixture = Fixture::new(2, 3, None).await;
+    let member = fixture.members[0].insecure_clone();
+    let ix = fixture.emergency_ix(
+        &member.pubkey(),
+        multisig_wallet::instruction::InitiateEmergencyThreshold { new_threshold: 2 }.data(),
+    );
+    fixture.send(&[ix], &[&member]).await.unwrap();
+
+    let ix = fixture.emergency_ix(&member.pubkey(), multisig_wallet::instruction::ExecuteEmergencyThreshold {}.data());
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::EmergencyTimelockActive);
+}
+
+#[tokio::test]
+async fn recovery_replaces_signers_after_delay() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let guardians = [Keypair::new(), Keypair::new()];
+    let fund: Vec<Instruction> = guardians
+        .iter()
+        .map(|guardian| system_instruction::transfer(&fixture.context.payer.pubkey(), &guardian.pubkey(), LAMPORTS_PER_SOL))
+        .collect();
+    fixture.send(&fund, &[]).await.unwrap();
+
+    // Signers can't double as recovery members
+    let creator = fixture.creator.pubkey();
+    let result = fixture
+        .configure(ConfigAction::SetRecovery { members: vec![creator], threshold: 1, delay_seconds: 60 })
+        .await;
+    assert_error(result, MultisigWalletError::RecoverySetOverlap);
+
+    let members = guardians.iter().map(|guardian| guardian.pubkey()).collect();
+    fixture
+        .configure(ConfigAction::SetRecovery { members, threshold: 2, delay_seconds: 60 })
+        .await
+        .unwrap();
+
+    let new_signer = Pubkey::new_unique();
+    let ix = fixture.propose_recovery_ix(&guardians[0].pubkey(), vec![new_signer], 1);
+    fixture.send(&[ix], &[&guardians[0]]).await.unwrap();
+
+    let recovery = recovery_pda(&fixture.multisig);
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::RecoveryVote {
+            multisig: fixture.multisig,
+            recovery,
+            signer: guardians[1].pubkey(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ApproveRecovery {}.data(),
+    };
+    fixture.send(&[ix], &[&guardians[1]]).await.unwrap();
+
+    let execute = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ExecuteRecovery {
+            multisig: fixture.multisig,
+            recovery,
+            proposer: guardians[0].pubkey(),
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ExecuteRecovery {}.data(),
+    };
+    let result = fixture.send(&[execute.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::RecoveryDelayActive);
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 60;
+    fixture.context.set_sysvar(&clock);
+    fixture.send(&[execute], &[]).await.unwrap();
+
+    let multisig = fixture.multisig_account().await;
+    assert_eq!(multisig.signers, vec![new_signer]);
+    assert_eq!(multisig.threshold, 1);
+}
+
+#[tokio::test]
+async fn recovery_cancellable_by_any_signer() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let guardian = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &guardian.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    fixture
+        .configure(ConfigAction::SetRecovery { members: vec![guardian.pubkey()], threshold: 1, delay_seconds: 60 })
+        .await
+        .unwrap();
+
+    let ix = fixture.propose_recovery_ix(&guardian.pubkey(), vec![Pubkey::new_unique()], 1);
+    fixture.send(&[ix], &[&guardian]).await.unwrap();
+
+    let member = fixture.members[0].insecure_clone();
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CancelRecovery {
+            multisig: fixture.multisig,
+            recovery: recovery_pda(&fixture.multisig),
+            proposer: guardian.pubkey(),
+            signer: member.pubkey(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CancelRecovery {}.data(),
+    };
+    fixture.send(&[ix], &[&member]).await.unwrap();
+
+    let recovery = fixture.context.banks_client.get_account(recovery_pda(&fixture.multisig)).await.unwrap();
+    assert!(recovery.is_none());
+    assert_eq!(fixture.multisig_account().await.signers.len(), 2);
+}
+
+#[tokio::test]
+async fn recovery_checks_signers_at_proposal_and_threshold_at_execution() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let guardian = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &guardian.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    fixture
+        .configure(ConfigAction::SetRecovery { members: vec![guardian.pubkey()], threshold: 1, delay_seconds: 60 })
+        .await
+        .unwrap();
+
+    let new_signer = Pubkey::new_unique();
+    let ix = fixture.propose_recovery_ix(&guardian.pubkey(), vec![new_signer, new_signer], 2);
+    let result = fixture.send(&[ix], &[&guardian]).await;
+    assert_error(result, MultisigWalletError::AlreadyMember);
+    let ix = fixture.propose_recovery_ix(&guardian.pubkey(), vec![new_signer, fixture.multisig], 1);
+    let result = fixture.send(&[ix], &[&guardian]).await;
+    assert_error(result, MultisigWalletError::SelfSignerKey);
+
+    // Excluding proposers during the delay leaves a 2-of-2 recovery
+    // unreachable, so it can't be applied
+    let ix = fixture.propose_recovery_ix(&guardian.pubkey(), vec![new_signer, Pubkey::new_unique()], 2);
+    fixture.send(&[ix], &[&guardian]).await.unwrap();
+    fixture
+        .configure(ConfigAction::SetProposerCounts { enabled: false })
+        .await
+        .unwrap();
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 60;
+    fixture.context.set_sysvar(&clock);
+    let execute = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ExecuteRecovery {
+            multisig: fixture.multisig,
+            recovery: recovery_pda(&fixture.multisig),
+            proposer: guardian.pubkey(),
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ExecuteRecovery {}.data(),
+    };
+    let result = fixture.send(&[execute], &[]).await;
+    assert_error(result, MultisigWalletError::InvalidThreshold);
+}
+
+#[tokio::test]
+async fn nested_multisig_approves_parent_proposal() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let child = fixture.multisig;
+    let parent = fixture.create_multisig(vec![fixture.creator.pubkey(), child], 2).await;
+
+    // The creator proposes on the parent, then the child approves through CPI
+    fixture.select(parent);
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let recipient = Pubkey::new_unique();
+    let transfer = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 10);
+    let parent_index = fixture.propose(&transfer).await;
+    let approval = fixture.approve_ix(&child, parent_index);
+
+    fixture.select(child);
+    let child_index = fixture.propose(&approval).await;
+    fixture.execute(child_index, &approval).await.unwrap();
+
+    fixture.select(parent);
+    let transaction = fixture.transaction_account(parent_index).await;
+    assert_eq!(transaction.signers, vec![fixture.creator.pubkey(), child]);
+    fixture.execute(parent_index, &transfer).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 10);
+}
+
+#[tokio::test]
+async fn nested_multisig_approves_through_every_approval_flavor() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let child = fixture.multisig;
+    let parent = fixture.create_multisig(vec![fixture.creator.pubkey(), child], 2).await;
+
+    fixture.select(parent);
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let transfer = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let idempotent_index = fixture.propose(&transfer).await;
+    let by_index_index = fixture.propose(&transfer).await;
+    let mut idempotent = fixture.approve_ix(&child, idempotent_index);
+    idempotent.data = multisig_wallet::instruction::ApproveTransactionIdempotent { expected_hash: None }.data();
+    let by_index = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ApproveTransactionByIndex {
+            multisig: parent,
+            transaction: transaction_pda(&parent, by_index_index),
+            signer: child,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ApproveTransactionByIndex { tx_index: by_index_index, expected_hash: None }
+            .data(),
+    };
+
+    fixture.select(child);
+    for approval in [&idempotent, &by_index] {
+        let index = fixture.propose(approval).await;
+        fixture.execute(index, approval).await.unwrap();
+    }
+
+    fixture.select(parent);
+    for index in [idempotent_index, by_index_index] {
+        assert_eq!(fixture.transaction_account(index).await.signers, vec![fixture.creator.pubkey(), child]);
+    }
+}
+
+#[tokio::test]
+async fn nested_approval_of_own_proposal_is_self_referential() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let transfer = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&transfer).await;
+
+    // The approval writes to the multisig's own proposal, so it can't even
+    // be proposed
+    let approval = fixture.approve_ix(&fixture.multisig, index);
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.propose_ix(&creator.pubkey(), index + 1, &approval);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::SelfReferentialProposal);
+}
+
+#[tokio::test]
+async fn governance_program_grants_capped_approvals() {
+    let mut fixture = Fixture::new(1, 3, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetApproverProgram { program: Some(governance_id()), max_approvals: 2 })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    // Only a CPI from the registered program counts
+    let result = fixture.send(&[fixture.approve_external_ix(index, 2)], &[]).await;
+    assert_error(result, MultisigWalletError::InvalidApproverProgram);
+
+    let result = fixture.send(&[fixture.governance_approve_ix(index, 3)], &[]).await;
+    assert_error(result, MultisigWalletError::ExternalApprovalsExceedCap);
+
+    fixture.send(&[fixture.governance_approve_ix(index, 2)], &[]).await.unwrap();
+    let result = fixture.send(&[fixture.governance_approve_ix(index, 1)], &[]).await;
+    assert_error(result, MultisigWalletError::ExternalApprovalAlreadyRecorded);
+
+    assert_eq!(fixture.transaction_account(index).await.external_approvals, 2);
+    fixture.execute(index, &inner).await.unwrap();
+}
+
+#[tokio::test]
+async fn allowlist_restricts_instruction_prefixes() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let transfer = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+
+    // Only system transfers (instruction 2) are allowed
+    fixture
+        .configure(ConfigAction::SetAllowedProgram {
+            program_id: system_program::ID,
+            prefixes: vec![transfer.data[..4].to_vec()],
+        })
+        .await
+        .unwrap();
+
+    let creator = fixture.creator.insecure_clone();
+    let assign = system_instruction::assign(&fixture.vault, &Pubkey::new_unique());
+    let ix = fixture.propose_ix(&creator.pubkey(), 0, &assign);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InstructionNotAllowed);
+
+    let memo = Instruction {
+        program_id: multisig_wallet::spl_memo::ID,
+        accounts: vec![],
+        data: b"hi".to_vec(),
+    };
+    let ix = fixture.propose_ix(&creator.pubkey(), 0, &memo);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::ProgramNotAllowed);
+
+    let index = fixture.propose(&transfer).await;
+
+    // Removing the program afterwards blocks execution
+    fixture
+        .configure(ConfigAction::SetAllowedProgram { program_id: multisig_wallet::spl_memo::ID, prefixes: vec![] })
+        .await
+        .unwrap();
+    fixture
+        .configure(ConfigAction::RemoveAllowedProgram { program_id: system_program::ID })
+        .await
+        .unwrap();
+    let result = fixture.execute(index, &transfer).await;
+    assert_error(result, MultisigWalletError::ProgramNotAllowed);
+}
+
+#[tokio::test]
+async fn default_ttl_expires_proposals() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 60 })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    // A shorter TTL requested by the proposer wins
+    let creator = fixture.creator.insecure_clone();
+    let mut ix = fixture.propose_ix(&creator.pubkey(), index + 1, &inner);
+    ix.data = multisig_wallet::instruction::ProposeTransaction {
+        program_id: inner.program_id,
+        accounts: serialize_account_metas(&inner.accounts),
+        instruction_data: inner.data.clone(),
+        options: ProposalOptions { ttl_seconds: Some(10), ..Default::default() },
+    }
+    .data();
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let first = fixture.transaction_account(index).await.expires_at.unwrap();
+    let second = fixture.transaction_account(index + 1).await.expires_at.unwrap();
+    assert_eq!(first - second, 50);
+
+    // Changing the default doesn't touch existing proposals
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 0 })
+        .await
+        .unwrap();
+    assert_eq!(fixture.transaction_account(index).await.expires_at, Some(first));
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = first + 1;
+    fixture.context.set_sysvar(&clock);
+    let result = fixture.approve(0, index).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+}
+
+#[tokio::test]
+async fn proposals_record_creation_time_and_slot() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 60 })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 1_000;
+    fixture.context.set_sysvar(&clock);
+    let index = fixture.propose(&inner).await;
+    let proposed = fixture.transaction_account(index).await;
+    assert_eq!(proposed.created_at, clock.unix_timestamp);
+    assert!(proposed.created_slot >= clock.slot);
+    assert_eq!(proposed.expires_at, Some(proposed.created_at + 60));
+
+    fixture.context.warp_to_slot(proposed.created_slot + 10).unwrap();
+    let index = fixture.propose(&inner).await;
+    let later = fixture.transaction_account(index).await;
+    assert!(later.created_slot >= proposed.created_slot + 10);
+    assert_eq!(later.expires_at, Some(later.created_at + 60));
+}
+
+#[tokio::test]
+async fn grace_period_only_covers_proposals_approved_in_time() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    for action in [
+        ConfigAction::SetDefaultProposalTtl { seconds: 60 },
+        ConfigAction::SetExecutionGrace { seconds: 30 },
+        ConfigAction::SetPriorityLevel { priority: 1, timelock_seconds: 0, required_threshold: 2 },
+    ] {
+        fixture.configure(action).await.unwrap();
+    }
+    let result = fixture.configure(ConfigAction::SetExecutionGrace { seconds: -1 }).await;
+    assert_error(result, MultisigWalletError::InvalidDuration);
+
+    // Priority 0 proposals reach threshold as they're proposed; priority 1
+    // ones need a second approval
+    let creator = fixture.creator.insecure_clone();
+    let mut proposals = vec![];
+    for priority in [0, 0, 1, 1] {
+        let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+        let index = fixture.multisig_account().await.nonce;
+        let mut ix = fixture.propose_ix(&creator.pubkey(), index, &inner);
+        ix.data = multisig_wallet::instruction::ProposeTransaction {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            options: ProposalOptions { priority, ..Default::default() },
+        }
+        .data();
+        fixture.send(&[ix], &[&creator]).await.unwrap();
+        proposals.push((index, inner));
+    }
+    let expires_at = fixture.transaction_account(proposals[0].0).await.expires_at.unwrap();
+
+    // After expiry no approval lands, even inside the grace period
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = expires_at + 10;
+    fixture.context.set_sysvar(&clock);
+    let result = fixture.approve(0, proposals[2].0).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+
+    // Dropping the level makes the priority 1 proposals executable, but
+    // only after they expired, so the grace period doesn't cover them
+    let actions = [ConfigAction::SetLiveThreshold { enabled: true }, ConfigAction::RemovePriorityLevel { priority: 1 }];
+    for action in actions {
+        fixture.configure(action).await.unwrap();
+    }
+    let (index, inner) = &proposals[2];
+    let result = fixture.execute(*index, inner).await;
+    assert_error(result, MultisigWalletError::TransactionExpired);
+
+    // Approved in time and executed inside the grace period
+    let (index, inner) = &proposals[0];
+    fixture.execute(*index, inner).await.unwrap();
+    assert!(fixture.transaction_account(*index).await.executed);
+
+    // Past the grace period nothing runs
+    clock.unix_timestamp = expires_at + 31;
+    fixture.context.set_sysvar(&clock);
+    for (index, inner) in [&proposals[1], &proposals[3]] {
+        let result = fixture.execute(*index, inner).await;
+        assert_error(result, MultisigWalletError::TransactionExpired);
+    }
+}
+
+#[test]
+fn system_call_decodes_every_variant() {
+    let (a, b, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let changes_owner = |target| Some(SystemCall::ChangesOwner { target, owner });
+    let cases = [
+        (system_instruction::create_account(&a, &b, 1, 2, &owner), changes_owner(1)),
+        (system_instruction::assign(&a, &owner), changes_owner(0)),
+        (system_instruction::create_account_with_seed(&a, &b, &a, "seed", 1, 2, &owner), changes_owner(1)),
+        (system_instruction::allocate_with_seed(&b, &a, "seed", 2, &owner), changes_owner(0)),
+        (system_instruction::assign_with_seed(&b, &a, "seed", &owner), changes_owner(0)),
+        (system_instruction::transfer(&a, &b, 1), Some(SystemCall::Other)),
+        (system_instruction::allocate(&a, 2), Some(SystemCall::Other)),
+        (system_instruction::advance_nonce_account(&a, &b), Some(SystemCall::Other)),
+        (system_instruction::withdraw_nonce_account(&a, &b, &a, 1), Some(SystemCall::Other)),
+        (system_instruction::authorize_nonce_account(&a, &b, &owner), Some(SystemCall::Other)),
+        (system_instruction::upgrade_nonce_account(a), Some(SystemCall::Other)),
+        (system_instruction::transfer_with_seed(&a, &b, "seed".into(), &owner, &b, 1), Some(SystemCall::Other)),
+        (system_instruction::create_nonce_account(&a, &b, &a, 1)[1].clone(), Some(SystemCall::Other)),
+    ];
+    for (ix, expected) in cases {
+        assert_eq!(SystemCall::decode(&ix.data), expected, "{:?}", ix.data);
+    }
+
+    // Short, unknown and malformed data doesn't decode
+    let assign = system_instruction::assign(&a, &owner).data;
+    let assign_with_seed = system_instruction::assign_with_seed(&b, &a, "seed", &owner).data;
+    let mut bad_utf8 = assign_with_seed.clone();
+    bad_utf8[44] = 0xff;
+    let mut huge_seed = assign_with_seed.clone();
+    huge_seed[36..44].copy_from_slice(&u64::MAX.to_le_bytes());
+    for data in [
+        vec![],
+        vec![1, 0, 0],
+        13u32.to_le_bytes().to_vec(),
+        assign[..20].to_vec(),
+        assign_with_seed[..assign_with_seed.len() - 1].to_vec(),
+        bad_utf8,
+        huge_seed,
+    ] {
+        assert_eq!(SystemCall::decode(&data), None, "{:?}", data);
+    }
+}
+
+#[tokio::test]
+async fn system_instructions_reassigning_multisig_accounts_are_refused() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let (vault, multisig) = (fixture.vault, fixture.multisig);
+    let attacker = Pubkey::new_unique();
+
+    let assign_vault = system_instruction::assign(&vault, &attacker);
+    let create_vault = system_instruction::create_account(&multisig, &vault, 1, 0, &attacker);
+    let assign_multisig = system_instruction::assign(&multisig, &attacker);
+    let mut malformed = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    malformed.data.truncate(6);
+    for inner in [&assign_vault, &create_vault, &assign_multisig, &malformed] {
+        let index = fixture.multisig_account().await.nonce;
+        let ix = fixture.propose_ix(&creator.pubkey(), index, inner);
+        let result = fixture.send(&[ix], &[&creator]).await;
+        assert_error(result, MultisigWalletError::DangerousSystemInstruction);
+    }
+
+    // Other owners' accounts and ordinary transfers are fine
+    let mut assign_other = system_instruction::assign(&Pubkey::new_unique(), &attacker);
+    assign_other.accounts[0].is_signer = false;
+    fixture.propose(&assign_other).await;
+    let transfer = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    fixture.propose(&transfer).await;
+
+    // The override lets the multisig propose them anyway
+    fixture
+        .configure(ConfigAction::SetAllowDangerousSystemInstructions { enabled: true })
+        .await
+        .unwrap();
+    fixture.propose(&assign_vault).await;
+}
+
+#[tokio::test]
+async fn separate_rent_payer_funds_proposal() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let member = fixture.members[0].insecure_clone();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let ops = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &ops.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    let mut ix = fixture.propose_ix(&member.pubkey(), 0, &inner);
+    ix.accounts[3] = AccountMeta::new(ops.pubkey(), true);
+    let member_before = fixture.balance(&member.pubkey()).await;
+    fixture.send(&[ix], &[&member, &ops]).await.unwrap();
+
+    let transaction = fixture.transaction_account(0).await;
+    assert_eq!(transaction.proposer, member.pubkey());
+    assert_eq!(transaction.rent_payer, ops.pubkey());
+    assert_eq!(fixture.balance(&member.pubkey()).await, member_before);
+    assert!(fixture.balance(&ops.pubkey()).await < LAMPORTS_PER_SOL);
+}
+
+#[tokio::test]
+async fn execution_reimburses_proposal_rent_from_vault() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetReimburseProposalRent { enabled: true })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), LAMPORTS_PER_SOL / 10);
+    let index = fixture.propose(&inner).await;
+
+    let creator = fixture.creator.pubkey();
+    let rent = fixture.balance(&transaction_pda(&fixture.multisig, index)).await;
+    let creator_before = fixture.balance(&creator).await;
+    let mut ix = fixture.execute_ix(index, &inner);
+    ix.accounts.push(AccountMeta::new(creator, false));
+    fixture.send(&[ix], &[]).await.unwrap();
+
+    assert_eq!(fixture.balance(&creator).await, creator_before + rent);
+    let vault = fixture.vault;
+    assert_eq!(fixture.balance(&vault).await, LAMPORTS_PER_SOL - LAMPORTS_PER_SOL / 10 - rent);
+}
+
+#[tokio::test]
+async fn cleanup_bounty_paid_for_closing_expired_proposal() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 60 })
+        .await
+        .unwrap();
+    fixture
+        .configure(ConfigAction::SetCleanupBounty { lamports: 10_000, vault_cap: 0 })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    let cranker = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &cranker.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let transaction = transaction_pda(&fixture.multisig, index);
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CloseExpiredTransaction {
+            multisig: fixture.multisig,
+            transaction,
+            rent_payer: fixture.creator.pubkey(),
+            vault: fixture.vault,
+            cranker: cranker.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CloseExpiredTransaction {}.data(),
+    };
+    let result = fixture.send(&[ix.clone()], &[&cranker]).await;
+    assert_error(result, MultisigWalletError::TransactionStillActive);
+
+    let expires_at = fixture.transaction_account(index).await.expires_at.unwrap();
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = expires_at + 1;
+    fixture.context.set_sysvar(&clock);
+
+    let creator = fixture.creator.pubkey();
+    let rent = fixture.balance(&transaction).await;
+    let creator_before = fixture.balance(&creator).await;
+    fixture.send(&[ix], &[&cranker]).await.unwrap();
+
+    assert_eq!(fixture.balance(&cranker.pubkey()).await, LAMPORTS_PER_SOL + 10_000);
+    assert_eq!(fixture.balance(&creator).await, creator_before + rent - 10_000);
+    assert!(fixture.context.banks_client.get_account(transaction).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn record_deposit_tracks_vault_balance() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let (ledger, _) = Pubkey::find_program_address(&[b"deposits", fixture.multisig.as_ref()], &multisig_wallet::ID);
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::RecordDeposit {
+            multisig: fixture.multisig,
+            vault: fixture.vault,
+            ledger,
+            payer: fixture.context.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::RecordDeposit { memo: "invoice 42".to_string() }.data(),
+    };
+
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture.send(&[ix.clone()], &[]).await.unwrap();
+    let recorded: DepositLedger = fetch(&mut fixture.context, &ledger).await;
+    assert_eq!(recorded.lamports, LAMPORTS_PER_SOL);
+
+    fixture.fund_vault(5_000).await;
+    let logs = simulate_logs(&mut fixture.context, &[ix.clone()], &[]).await;
+    assert!(logs.iter().any(|log| log.starts_with("Program data: ")));
+    fixture.send(&[ix], &[]).await.unwrap();
+    let recorded: DepositLedger = fetch(&mut fixture.context, &ledger).await;
+    assert_eq!(recorded.lamports, LAMPORTS_PER_SOL + 5_000);
+}
+
+#[tokio::test]
+async fn snapshot_records_vault_balance() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let snapshot_ix = |fixture: &Fixture, member: &Pubkey, index: u64| {
+        let (snapshot, _) = Pubkey::find_program_address(
+            &[b"snapshot", fixture.multisig.as_ref(), &index.to_le_bytes()],
+            &multisig_wallet::ID,
+        );
+        let ix = Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::SnapshotBalances {
+                multisig: fixture.multisig,
+                vault: fixture.vault,
+                snapshot,
+                registry: registry_pda(&fixture.multisig),
+                member: *member,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::SnapshotBalances {}.data(),
+        };
+        (snapshot, ix)
+    };
+
+    let outsider = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &outsider.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let (_, ix) = snapshot_ix(&fixture, &outsider.pubkey(), 0);
+    let result = fixture.send(&[ix], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+
+    let creator = fixture.creator.insecure_clone();
+    let (snapshot, ix) = snapshot_ix(&fixture, &creator.pubkey(), 0);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let recorded: Snapshot = fetch(&mut fixture.context, &snapshot).await;
+    assert_eq!(recorded.index, 0);
+    assert_eq!(recorded.lamports, LAMPORTS_PER_SOL);
+    assert!(recorded.holdings.is_empty());
+    assert_eq!(fixture.multisig_account().await.snapshot_count, 1);
+}
+
+#[tokio::test]
+async fn read_only_proposal_runs_through_execute_readonly() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let inner = Instruction {
+        program_id: multisig_wallet::spl_memo::ID,
+        accounts: vec![AccountMeta::new_readonly(fixture.vault, true)],
+        data: b"attestation".to_vec(),
+    };
+    let creator = fixture.creator.insecure_clone();
+    let propose_read_only = |fixture: &Fixture, index: u64, inner: &Instruction| {
+        let mut ix = fixture.propose_ix(&creator.pubkey(), index, inner);
+        ix.data = multisig_wallet::instruction::ProposeTransaction {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            options: ProposalOptions { read_only: true, ..Default::default() },
+        }
+        .data();
+        ix
+    };
+
+    // The vault can't be handed over as writable
+    let mut writable = inner.clone();
+    writable.accounts[0].is_writable = true;
+    let ix = propose_read_only(&fixture, 0, &writable);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::WritableVaultInReadOnly);
+
+    let ix = propose_read_only(&fixture, 0, &inner);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+
+    let result = fixture.execute(0, &inner).await;
+    assert_error(result, MultisigWalletError::ReadOnlyExecutionRequired);
+
+    let mut ix = fixture.execute_ix(0, &inner);
+    ix.data = multisig_wallet::instruction::ExecuteReadonly { expected_hash: None }.data();
+    fixture.send(&[ix], &[]).await.unwrap();
+    assert!(fixture.transaction_account(0).await.executed);
+}
+
+#[tokio::test]
+async fn configurable_account_meta_cap() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let result = fixture.configure(ConfigAction::SetMaxAccountMetas { max: 65 }).await;
+    assert_error(result, MultisigWalletError::InvalidAccountMetaCap);
+    fixture.configure(ConfigAction::SetMaxAccountMetas { max: 2 }).await.unwrap();
+
+    let creator = fixture.creator.insecure_clone();
+    let metas = vec![AccountMeta::new_readonly(Pubkey::new_unique(), false); 3];
+    let ix = fixture.propose_raw_ix(&creator.pubkey(), 0, system_program::ID, serialize_account_metas(&metas), vec![], None, vec![]);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::TooManyAccountMetas);
+
+    let ix = fixture.propose_raw_ix(&creator.pubkey(), 0, system_program::ID, serialize_account_metas(&metas[..2]), vec![], None, vec![]);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+}
+
+#[tokio::test]
+async fn child_multisig_spends_delegated_budget() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let child = fixture.multisig;
+    let parent = fixture.create_multisig(vec![fixture.creator.pubkey()], 1).await;
+
+    fixture.select(parent);
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::CreateSubBudget {
+            child,
+            lamports: LAMPORTS_PER_SOL / 2,
+            mint_budgets: vec![],
+            expires_at: None,
+        })
+        .await
+        .unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let spend = |amount: u64| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::SpendFromBudget {
+            multisig: parent,
+            vault: vault_pda(&parent),
+            child,
+            recipient,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::SpendFromBudget { mint: None, amount }.data(),
+    };
+
+    // The child's quorum approves spends through its own executions
+    fixture.select(child);
+    let first = spend(LAMPORTS_PER_SOL / 5);
+    let index = fixture.propose(&first).await;
+    fixture.execute(index, &first).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 5);
+
+    let overspend = spend(LAMPORTS_PER_SOL / 2);
+    let index = fixture.propose(&overspend).await;
+    let result = fixture.execute(index, &overspend).await;
+    assert_error(result, MultisigWalletError::SubBudgetExhausted);
+
+    // Revoking leaves nothing to spend
+    fixture.select(parent);
+    assert_eq!(fixture.multisig_account().await.sub_budgets[0].lamports, LAMPORTS_PER_SOL * 3 / 10);
+    fixture.configure(ConfigAction::RevokeSubBudget { child }).await.unwrap();
+    fixture.select(child);
+    let index = fixture.propose(&first).await;
+    let result = fixture.execute(index, &first).await;
+    assert_error(result, MultisigWalletError::SubBudgetNotFound);
+}
+
+#[tokio::test]
+async fn approval_logs_progress() {
+    let mut fixture = Fixture::new(2, 3, None).await;
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 600 })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    let member = fixture.members[0].insecure_clone();
+    let ix = fixture.approve_ix(&member.pubkey(), index);
+    let logs = simulate_logs(&mut fixture.context, &[ix], &[&member]).await;
+    assert!(logs.iter().any(|log| log.contains("approval 2 of 3, 1 more needed, expires in ")));
+}
+
+fn rotate_key_ix(multisig: &Pubkey, member: &Pubkey, new_key: Pubkey, pending: &[Pubkey]) -> Instruction {
+    let mut accounts = multisig_wallet::accounts::RotateKey {
+        multisig: *multisig,
+        member: *member,
+    }
+    .to_account_metas(None);
+    accounts.extend(pending.iter().map(|key| AccountMeta::new(*key, false)));
+    Instruction {
+        program_id: multisig_wallet::ID,
+        accounts,
+        data: multisig_wallet::instruction::RotateMyKey { new_key }.data(),
+    }
+}
+
+#[tokio::test]
+async fn rotate_my_key_drops_pending_approvals_by_default() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture
+        .configure(ConfigAction::SetKeyRotationCooldown { seconds: 3600 })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+    fixture.approve(0, index).await.unwrap();
+
+    let member = fixture.members[0].insecure_clone();
+    let creator = fixture.creator.pubkey();
+    let pending = transaction_pda(&fixture.multisig, index);
+    let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), creator, &[]);
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::AlreadyMember);
+
+    let new_key = Keypair::new();
+    let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), new_key.pubkey(), &[pending]);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+
+    assert_eq!(fixture.multisig_account().await.signers, vec![creator, new_key.pubkey()]);
+    assert_eq!(fixture.transaction_account(index).await.signers, vec![creator]);
+
+    // The cooldown stops the new key from rotating straight away
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &new_key.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let ix = rotate_key_ix(&fixture.multisig, &new_key.pubkey(), Pubkey::new_unique(), &[]);
+    let result = fixture.send(&[ix], &[&new_key]).await;
+    assert_error(result, MultisigWalletError::KeyRotationCooldownActive);
+}
+
+#[tokio::test]
+async fn rotate_my_key_can_reassign_pending_approvals() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture
+        .configure(ConfigAction::SetReassignApprovalsOnRotation { enabled: true })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+    fixture.approve(0, index).await.unwrap();
+
+    let member = fixture.members[0].insecure_clone();
+    let new_key = Pubkey::new_unique();
+    let pending = transaction_pda(&fixture.multisig, index);
+    let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), new_key, &[pending]);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+
+    let creator = fixture.creator.pubkey();
+    assert_eq!(fixture.transaction_account(index).await.signers, vec![creator, new_key]);
+}
+
+#[tokio::test]
+async fn rotate_my_key_stales_proposals_it_is_not_given() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let supplied = fixture.propose(&inner).await;
+    let left_out = fixture.propose(&inner).await;
+    let member = fixture.members[0].insecure_clone();
+
+    // The new key must be usable as a signer
+    let vault = fixture.vault;
+    for (new_key, error) in [
+        (Pubkey::default(), MultisigWalletError::DefaultSignerKey),
+        (multisig_wallet::ID, MultisigWalletError::ProgramSignerKey),
+        (vault, MultisigWalletError::SelfSignerKey),
+    ] {
+        let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), new_key, &[]);
+        let result = fixture.send(&[ix], &[&member]).await;
+        assert_error(result, error);
+    }
+
+    let new_key = Keypair::new();
+    let pending = transaction_pda(&fixture.multisig, supplied);
+    let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), new_key.pubkey(), &[pending]);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &new_key.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    // The proposal left out still lists the old key, so neither key can
+    // approve it
+    let result = fixture.send(&[fixture.approve_ix(&member.pubkey(), left_out)], &[&member]).await;
+    assert_error(result, MultisigWalletError::StaleTransaction);
+    let result = fixture.send(&[fixture.approve_ix(&new_key.pubkey(), left_out)], &[&new_key]).await;
+    assert_error(result, MultisigWalletError::StaleTransaction);
+
+    // The supplied one carries over to the new key
+    let result = fixture.send(&[fixture.approve_ix(&member.pubkey(), supplied)], &[&member]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+    fixture.send(&[fixture.approve_ix(&new_key.pubkey(), supplied)], &[&new_key]).await.unwrap();
+    fixture.execute(supplied, &inner).await.unwrap();
+}
+
+fn escrow_pda(transaction: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"escrow", transaction.as_ref()], &multisig_wallet::ID).0
+}
+
+impl Fixture {
+    /// Proposes a typed SOL transfer as the creator and returns its index.
+    async fn propose_transfer(&mut self, recipient: Pubkey, lamports: u64, escrow: bool, category: u16) -> u64 {
+        let index = self.multisig_account().await.nonce;
+        let creator = self.creator.insecure_clone();
+        let ix = Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeAction {
+                multisig: self.multisig,
+                transaction: transaction_pda(&self.multisig, index),
+                proposer: creator.pubkey(),
+                rent_payer: creator.pubkey(),
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeTransfer { recipient, lamports, escrow, category, sweep: false }
+                .data(),
+        };
+        self.send(&[ix], &[&creator]).await.unwrap();
+        index
+    }
+
+    fn escrow_ix(&self, index: u64, data: Vec<u8>) -> Instruction {
+        let transaction = transaction_pda(&self.multisig, index);
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::EscrowAction {
+                multisig: self.multisig,
+                transaction,
+                vault: self.vault,
+                escrow: escrow_pda(&transaction),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data,
+        }
+    }
+}
+
+#[tokio::test]
+async fn escrowed_transfer_pays_out_of_escrow() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let recipient = Pubkey::new_unique();
+    let index = fixture.propose_transfer(recipient, LAMPORTS_PER_SOL / 10, true, 0).await;
+    let escrow = escrow_pda(&transaction_pda(&fixture.multisig, index));
+    let accounts = Instruction {
+        program_id: system_program::ID,
+        accounts: vec![
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(recipient, false),
+            AccountMeta::new(fixture.vault, false),
+        ],
+        data: vec![],
+    };
+
+    // Executing before the funds are locked fails
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::EscrowNotFunded);
+
+    let lock = fixture.escrow_ix(index, multisig_wallet::instruction::LockEscrow {}.data());
+    fixture.send(&[lock.clone()], &[]).await.unwrap();
+    let reserve = fixture.context.banks_client.get_rent().await.unwrap().minimum_balance(0);
+    assert_eq!(fixture.balance(&escrow).await, LAMPORTS_PER_SOL / 10 + reserve);
+    let result = fixture.send(&[lock], &[]).await;
+    assert_error(result, MultisigWalletError::EscrowAlreadyFunded);
+
+    fixture.execute(index, &accounts).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 10);
+    assert_eq!(fixture.balance(&escrow).await, 0);
+    let vault = fixture.vault;
+    assert_eq!(fixture.balance(&vault).await, LAMPORTS_PER_SOL - LAMPORTS_PER_SOL / 10);
+}
+
+#[tokio::test]
+async fn categorized_transfers_roll_up_per_period() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let (stats, _) =
+        Pubkey::find_program_address(&[b"category_stats", fixture.multisig.as_ref()], &multisig_wallet::ID);
+    let reset_ix = |fixture: &Fixture, approvers: &[&Keypair]| {
+        let mut accounts = multisig_wallet::accounts::ResetCategoryStats {
+            multisig: fixture.multisig,
+            stats,
+            payer: fixture.context.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(approvers.iter().map(|approver| AccountMeta::new_readonly(approver.pubkey(), true)));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ResetCategoryStats {}.data(),
+        }
+    };
+
+    // Resetting takes the config threshold
+    let ix = reset_ix(&fixture, &[&creator]);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::NotAllSignersApproved);
+    let ix = reset_ix(&fixture, &[&creator, &member]);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let mut accounts = Instruction {
+        program_id: system_program::ID,
+        accounts: vec![AccountMeta::new(fixture.vault, false), AccountMeta::new(recipient, false)],
+        data: vec![],
+    };
+
+    // A categorized transfer can't execute without the stats account
+    let index = fixture.propose_transfer(recipient, 10_000_000, false, 7).await;
+    assert_eq!(fixture.transaction_account(index).await.category, 7);
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::InsufficientAccounts);
+    accounts.accounts.push(AccountMeta::new(stats, false));
+    fixture.execute(index, &accounts).await.unwrap();
+    let index = fixture.propose_transfer(recipient, 5_000_000, false, 7).await;
+    fixture.execute(index, &accounts).await.unwrap();
+    let index = fixture.propose_transfer(recipient, 2_500_000, false, 0).await;
+    fixture.execute(index, &accounts).await.unwrap();
+
+    let recorded: CategoryStats = fetch(&mut fixture.context, &stats).await;
+    assert_eq!(recorded.outflows, vec![CategoryOutflow { category: 7, mint: None, amount: 15_000_000 }]);
+
+    // A reset reports the closing period and starts from zero
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 3_600;
+    fixture.context.set_sysvar(&clock);
+    let ix = reset_ix(&fixture, &[&creator, &member]);
+    let logs = simulate_logs(&mut fixture.context, &[ix.clone()], &[&creator, &member]).await;
+    assert!(logs.iter().any(|log| log.starts_with("Program data: ")));
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let reset: CategoryStats = fetch(&mut fixture.context, &stats).await;
+    assert!(reset.outflows.is_empty());
+    assert_eq!(reset.period_started_at, clock.unix_timestamp);
+}
+
+#[tokio::test]
+async fn expired_escrow_releases_to_vault() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetDefaultProposalTtl { seconds: 60 })
+        .await
+        .unwrap();
+    let index = fixture.propose_transfer(Pubkey::new_unique(), LAMPORTS_PER_SOL / 10, true, 0).await;
+    let lock = fixture.escrow_ix(index, multisig_wallet::instruction::LockEscrow {}.data());
+    fixture.send(&[lock], &[]).await.unwrap();
+
+    let release = fixture.escrow_ix(index, multisig_wallet::instruction::ReleaseEscrow {}.data());
+    let result = fixture.send(&[release.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::TransactionStillActive);
+
+    let expires_at = fixture.transaction_account(index).await.expires_at.unwrap();
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = expires_at + 1;
+    fixture.context.set_sysvar(&clock);
+
+    fixture.send(&[release], &[]).await.unwrap();
+    let vault = fixture.vault;
+    assert_eq!(fixture.balance(&vault).await, LAMPORTS_PER_SOL);
+    assert!(!fixture.transaction_account(index).await.escrowed);
+}
+
+#[tokio::test]
+async fn rate_limit_caps_outflow_per_window() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetRateLimit { mint: None, max_per_window: LAMPORTS_PER_SOL / 10 })
+        .await
+        .unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let accounts = Instruction {
+        program_id: system_program::ID,
+        accounts: vec![AccountMeta::new(fixture.vault, false), AccountMeta::new(recipient, false)],
+        data: vec![],
+    };
+    let index = fixture.propose_transfer(recipient, LAMPORTS_PER_SOL / 20, false, 0).await;
+    fixture.execute(index, &accounts).await.unwrap();
+    let index = fixture.propose_transfer(recipient, LAMPORTS_PER_SOL / 10, false, 0).await;
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::RateLimitExceeded);
+
+    // Opaque transfers from the vault can't be metered
+    let inner = fixture.vault_transfer(&recipient, 1);
+    let opaque = fixture.propose(&inner).await;
+    let result = fixture.execute(opaque, &inner).await;
+    assert_error(result, MultisigWalletError::OpaqueCallRateLimited);
+
+    // Raising the limit only takes effect after the delay
+    fixture
+        .configure(ConfigAction::SetRateLimit { mint: None, max_per_window: LAMPORTS_PER_SOL })
+        .await
+        .unwrap();
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::RateLimitExceeded);
+    let result = fixture.configure(ConfigAction::RemoveRateLimit { mint: None }).await;
+    assert_error(result, MultisigWalletError::RateLimitRaisePending);
+
+    let limit = &fixture.multisig_account().await.rate_limits[0];
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = limit.pending_effective_at;
+    fixture.context.set_sysvar(&clock);
+    fixture.execute(index, &accounts).await.unwrap();
+}
+
+#[tokio::test]
+async fn payout_pays_every_recipient_or_none() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let payout_ix = |fixture: &Fixture, index: u64, entries: Vec<PayoutEntry>| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ProposeAction {
+            multisig: fixture.multisig,
+            transaction: transaction_pda(&fixture.multisig, index),
+            proposer: fixture.creator.pubkey(),
+            rent_payer: fixture.creator.pubkey(),
+            system_program: system_program::ID,
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ProposePayout { mint: None, entries, category: 0 }.data(),
+    };
+    let recipients: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let entries: Vec<PayoutEntry> =
+        recipients.iter().map(|recipient| PayoutEntry { recipient: *recipient, amount: 10_000_000 }).collect();
+
+    // Recipients are listed once each, with something to receive
+    let mut duplicated = entries.clone();
+    duplicated.push(entries[0]);
+    let ix = payout_ix(&fixture, 0, duplicated);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::DuplicatePayoutRecipient);
+    let ix = payout_ix(&fixture, 0, vec![PayoutEntry { recipient: recipients[0], amount: 0 }]);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InvalidAmount);
+    let ix = payout_ix(&fixture, 0, vec![]);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InvalidPayoutSize);
+
+    // The configured cap bounds the total
+    fixture
+        .configure(ConfigAction::SetPayoutCap { mint: None, max_total: Some(25_000_000) })
+        .await
+        .unwrap();
+    let ix = payout_ix(&fixture, 0, entries.clone());
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::PayoutCapExceeded);
+    fixture.configure(ConfigAction::SetPayoutCap { mint: None, max_total: None }).await.unwrap();
+
+    let mut accounts = Instruction {
+        program_id: system_program::ID,
+        accounts: vec![AccountMeta::new(fixture.vault, false)],
+        data: vec![],
+    };
+    accounts.accounts.extend(recipients.iter().map(|recipient| AccountMeta::new(*recipient, false)));
+
+    // A payout the vault can't fully cover sends nothing
+    let oversized = vec![
+        PayoutEntry { recipient: recipients[0], amount: 10_000_000 },
+        PayoutEntry { recipient: recipients[1], amount: 2 * LAMPORTS_PER_SOL },
+    ];
+    let index = fixture.multisig_account().await.nonce;
+    let ix = payout_ix(&fixture, index, oversized);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::InsufficientVaultBalance);
+    assert_eq!(fixture.balance(&recipients[0]).await, 0);
+
+    let index = fixture.multisig_account().await.nonce;
+    let ix = payout_ix(&fixture, index, entries);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    fixture.execute(index, &accounts).await.unwrap();
+    for recipient in &recipients {
+        assert_eq!(fixture.balance(recipient).await, 10_000_000);
+    }
+}
+
+#[tokio::test]
+async fn dormant_members_are_flagged_until_they_act() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let member = fixture.members[0].pubkey();
+    let flag_ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::UpdateMultisig { multisig: fixture.multisig }.to_account_metas(None),
+        data: multisig_wallet::instruction::FlagInactiveMembers {}.data(),
+    };
+    let result = fixture.send(&[flag_ix.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::InactivityPeriodNotSet);
+
+    fixture.configure(ConfigAction::SetInactivityPeriod { seconds: 100 }).await.unwrap();
+    let inactive = |multisig: &MultisigAccount, key: &Pubkey| {
+        multisig.member_activity.iter().any(|activity| activity.member == *key && activity.inactive)
+    };
+
+    // The first call starts everyone's clock
+    fixture.send(&[flag_ix.clone()], &[]).await.unwrap();
+    assert!(!inactive(&fixture.multisig_account().await, &member));
+
+    // Only the member who didn't act in the period gets flagged
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 100;
+    fixture.context.set_sysvar(&clock);
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+    fixture.send(&[flag_ix], &[]).await.unwrap();
+    let multisig = fixture.multisig_account().await;
+    assert!(inactive(&multisig, &member));
+    assert!(!inactive(&multisig, &fixture.creator.pubkey()));
+
+    // Acting again clears the flag
+    fixture.approve(0, index).await.unwrap();
+    let multisig = fixture.multisig_account().await;
+    assert!(!inactive(&multisig, &member));
+    let activity = multisig.member_activity.iter().find(|activity| activity.member == member).unwrap();
+    assert_eq!(activity.last_action_at, clock.unix_timestamp);
+}
+
+#[tokio::test]
+async fn joint_proposal_executes_with_both_signatures() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let first = fixture.multisig;
+    let second = fixture.create_multisig(vec![fixture.creator.pubkey()], 1).await;
+    let creator = fixture.creator.insecure_clone();
+    let inner = Instruction {
+        program_id: multisig_wallet::spl_memo::ID,
+        accounts: vec![
+            AccountMeta::new_readonly(vault_pda(&first), true),
+            AccountMeta::new_readonly(vault_pda(&second), true),
+        ],
+        data: b"joint venture".to_vec(),
+    };
+    let propose_joint = |fixture: &Fixture, multisig: Pubkey, partner: Pubkey, index: u64, data: &[u8]| {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeTransaction {
+                multisig,
+                transaction: transaction_pda(&multisig, index),
+                proposer: fixture.creator.pubkey(),
+                rent_payer: fixture.creator.pubkey(),
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeJointTransaction {
+                partner,
+                program_id: inner.program_id,
+                accounts: serialize_account_metas(&inner.accounts),
+                instruction_data: data.to_vec(),
+                memo: None,
+                authority_kind: AuthorityKind::Vault(0),
+            }
+            .data(),
+        }
+    };
+    let execute_joint = |first_index: u64, second_index: u64| {
+        let mut accounts = multisig_wallet::accounts::ExecuteJoint {
+            multisig: first,
+            transaction: transaction_pda(&first, first_index),
+            partner_multisig: second,
+            partner_transaction: transaction_pda(&second, second_index),
+            executor: None,
+        }
+        .to_account_metas(None);
+        accounts.extend(inner.accounts.iter().map(|meta| AccountMeta::new_readonly(meta.pubkey, false)));
+        accounts.push(AccountMeta::new_readonly(inner.program_id, false));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ExecuteJoint {}.data(),
+        }
+    };
+
+    let ix = propose_joint(&fixture, first, second, 0, &inner.data);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let ix = propose_joint(&fixture, second, first, 0, &inner.data);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let ix = propose_joint(&fixture, second, first, 1, b"something else");
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+
+    // Neither half runs on its own
+    fixture.select(second);
+    let result = fixture.execute(0, &inner).await;
+    assert_error(result, MultisigWalletError::JointExecutionRequired);
+
+    // Both halves must be approved
+    let result = fixture.send(&[execute_joint(0, 0)], &[]).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+    fixture.select(first);
+    fixture.approve(0, 0).await.unwrap();
+
+    // And describe the same operation
+    let result = fixture.send(&[execute_joint(0, 1)], &[]).await;
+    assert_error(result, MultisigWalletError::JointProposalMismatch);
+
+    fixture.send(&[execute_joint(0, 0)], &[]).await.unwrap();
+    assert!(fixture.transaction_account(0).await.executed);
+    fixture.select(second);
+    assert!(fixture.transaction_account(0).await.executed);
+}
+
+#[tokio::test]
+async fn joint_executions_keep_both_vaults_rent_exempt() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let first = fixture.multisig;
+    let second = fixture.create_multisig(vec![fixture.creator.pubkey()], 1).await;
+    let creator = fixture.creator.insecure_clone();
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let vault = vault_pda(&first);
+    let recipient = Pubkey::new_unique();
+    // The first vault pays, the second co-signs
+    let transfer = |lamports: u64| {
+        let mut inner = system_instruction::transfer(&vault, &recipient, lamports);
+        inner.accounts.push(AccountMeta::new_readonly(vault_pda(&second), true));
+        inner
+    };
+    let propose_joint = |fixture: &Fixture, multisig: Pubkey, partner: Pubkey, index: u64, inner: &Instruction| {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeTransaction {
+                multisig,
+                transaction: transaction_pda(&multisig, index),
+                proposer: fixture.creator.pubkey(),
+                rent_payer: fixture.creator.pubkey(),
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeJointTransaction {
+                partner,
+                program_id: inner.program_id,
+                accounts: serialize_account_metas(&inner.accounts),
+                instruction_data: inner.data.clone(),
+                memo: None,
+                authority_kind: AuthorityKind::Vault(0),
+            }
+            .data(),
+        }
+    };
+    let execute_joint = |index: u64, inner: &Instruction| {
+        let mut accounts = multisig_wallet::accounts::ExecuteJoint {
+            multisig: first,
+            transaction: transaction_pda(&first, index),
+            partner_multisig: second,
+            partner_transaction: transaction_pda(&second, index),
+            executor: None,
+        }
+        .to_account_metas(None);
+        accounts.extend(inner.accounts.iter().map(|meta| AccountMeta {
+            pubkey: meta.pubkey,
+            is_signer: false,
+            is_writable: meta.is_writable,
+        }));
+        accounts.push(AccountMeta::new_readonly(inner.program_id, false));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ExecuteJoint {}.data(),
+        }
+    };
+
+    // The paying vault may be left with exactly the rent-exempt minimum, and
+    // not a lamport less
+    let minimum = fixture.context.banks_client.get_rent().await.unwrap().minimum_balance(0);
+    let spendable = fixture.balance(&vault).await - minimum;
+    for (index, lamports) in [(0, spendable + 1), (1, spendable)] {
+        let inner = transfer(lamports);
+        let ix = propose_joint(&fixture, first, second, index, &inner);
+        fixture.send(&[ix], &[&creator]).await.unwrap();
+        let ix = propose_joint(&fixture, second, first, index, &inner);
+        fixture.send(&[ix], &[&creator]).await.unwrap();
+    }
+    let result = fixture.send(&[execute_joint(0, &transfer(spendable + 1))], &[]).await;
+    assert_error(result, MultisigWalletError::WouldBreakRentExemption);
+    fixture.send(&[execute_joint(1, &transfer(spendable))], &[]).await.unwrap();
+    assert_eq!(fixture.balance(&vault).await, minimum);
+}
+
+#[tokio::test]
+async fn epoch_caps_roll_over_with_the_clock() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    let start = clock.unix_timestamp;
+
+    let cap = ConfigAction::SetEpochCap { mint: None, max_per_epoch: Some(30_000_000) };
+    let result = fixture.configure(cap.clone()).await;
+    assert_error(result, MultisigWalletError::BudgetEpochsNotSet);
+    let epochs = BudgetEpochs { start, length_seconds: 1_000 };
+    fixture.configure(ConfigAction::SetBudgetEpochs { epochs: Some(epochs) }).await.unwrap();
+    fixture.configure(cap).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let accounts = Instruction {
+        program_id: system_program::ID,
+        accounts: vec![AccountMeta::new(fixture.vault, false), AccountMeta::new(recipient, false)],
+        data: vec![],
+    };
+    let index = fixture.propose_transfer(recipient, 20_000_000, false, 0).await;
+    fixture.execute(index, &accounts).await.unwrap();
+
+    // A transfer approved in one epoch but executed in the next counts
+    // towards the epoch it executes in, with no reset call in between
+    let index = fixture.propose_transfer(recipient, 20_000_000, false, 0).await;
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::EpochCapExceeded);
+    clock.unix_timestamp = start + 1_000;
+    fixture.context.set_sysvar(&clock);
+    fixture.execute(index, &accounts).await.unwrap();
+
+    let cap = &fixture.multisig_account().await.epoch_caps[0];
+    assert_eq!((cap.epoch, cap.spent_in_epoch), (1, 20_000_000));
+}
+
+#[test]
+fn epoch_cap_tolerates_clock_skew_at_boundaries() {
+    let epochs = BudgetEpochs { start: 1_000, length_seconds: 100 };
+    assert_eq!(epochs.epoch_at(900), 0);
+    assert_eq!(epochs.epoch_at(1_099), 0);
+    assert_eq!(epochs.epoch_at(1_100), 1);
+
+    // The first outflow at the rollover gets the new epoch's full allowance
+    let mut cap = EpochCap { mint: None, max_per_epoch: 10, epoch: 0, spent_in_epoch: 8 };
+    cap.consume(10, epochs.epoch_at(1_100)).unwrap();
+
+    // A clock reading slipping back behind the boundary stays in the new
+    // epoch rather than reopening the old one
+    assert!(cap.consume(1, epochs.epoch_at(1_099)).is_err());
+    assert_eq!((cap.epoch, cap.spent_in_epoch), (1, 10));
+}
+
+#[tokio::test]
+async fn dependent_proposal_waits_for_its_dependency() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000_000);
+    let propose_dependent = |fixture: &Fixture, index: u64, ttl_seconds: Option<i64>, depends_on: Option<u64>| {
+        let mut ix = fixture.propose_ix(&creator.pubkey(), index, &inner);
+        ix.data = multisig_wallet::instruction::ProposeTransaction {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            options: ProposalOptions { ttl_seconds, depends_on, ..Default::default() },
+        }
+        .data();
+        ix
+    };
+    let execute_after = |fixture: &Fixture, index: u64, dependency: u64| {
+        let mut ix = fixture.execute_ix(index, &inner);
+        ix.accounts.push(AccountMeta::new_readonly(transaction_pda(&fixture.multisig, dependency), false));
+        ix
+    };
+
+    // Only earlier proposals can be depended on
+    let ix = propose_dependent(&fixture, 0, None, Some(0));
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InvalidDependency);
+
+    let first = fixture.propose(&inner).await;
+    let ix = propose_dependent(&fixture, first + 1, None, Some(first));
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let result = fixture.send(&[execute_after(&fixture, first + 1, first)], &[]).await;
+    assert_error(result, MultisigWalletError::DependencyNotExecuted);
+    fixture.execute(first, &inner).await.unwrap();
+    fixture.send(&[execute_after(&fixture, first + 1, first)], &[]).await.unwrap();
+
+    // A dependency that expired fails its dependents for good
+    let ix = propose_dependent(&fixture, first + 2, Some(60), None);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let ix = propose_dependent(&fixture, first + 3, None, Some(first + 2));
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 61;
+    fixture.context.set_sysvar(&clock);
+    let result = fixture.send(&[execute_after(&fixture, first + 3, first + 2)], &[]).await;
+    assert_error(result, MultisigWalletError::DependencyFailed);
+}
+
+#[test]
+fn approval_message_matches_golden_vectors() {
+    // Independent implementations must reproduce these bytes exactly
+    let hash: Vec<u8> = (0..32).collect();
+    let message = encode_approval_message(
+        &Pubkey::new_from_array([1; 32]),
+        7,
+        &hash.try_into().unwrap(),
+        Some(1_700_000_000),
+    );
+    assert_eq!(
+        hex(&message),
+        concat!(
+            "17", "6d756c74697369672d77616c6c65743a617070726f7665", "01", "5100",
+            "0101010101010101010101010101010101010101010101010101010101010101",
+            "0700000000000000",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "01", "00f1536500000000",
+        )
+    );
+
+    let message = encode_approval_message(&Pubkey::new_from_array([0xff; 32]), u64::MAX, &[0xab; 32], None);
+    assert_eq!(
+        hex(&message),
+        concat!(
+            "17", "6d756c74697369672d77616c6c65743a617070726f7665", "01", "5100",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "ffffffffffffffff",
+            "abababababababababababababababababababababababababababababababab",
+            "00", "0000000000000000",
+        )
+    );
+}
+
+#[tokio::test]
+async fn approval_message_covers_the_stored_proposal() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.configure(ConfigAction::SetDefaultProposalTtl { seconds: 60 }).await.unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+    let transaction = fixture.transaction_account(index).await;
+    assert!(transaction.expires_at.is_some());
+
+    let message = canonical_approval_message(&fixture.multisig, &transaction);
+    assert_eq!(
+        message,
+        encode_approval_message(&fixture.multisig, index, &transaction.hash, transaction.expires_at)
+    );
+    assert_eq!(&message[1..1 + APPROVAL_MESSAGE_DOMAIN.len()], APPROVAL_MESSAGE_DOMAIN);
+    assert_eq!(message[1 + APPROVAL_MESSAGE_DOMAIN.len()], APPROVAL_MESSAGE_VERSION);
+}
+
+#[tokio::test]
+async fn proposals_declare_their_compute() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000_000);
+    let propose_with = |fixture: &Fixture, index: u64, compute: ComputeDeclaration| {
+        let mut ix = fixture.propose_ix(&creator.pubkey(), index, &inner);
+        ix.data = multisig_wallet::instruction::ProposeTransaction {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            options: ProposalOptions { compute: Some(compute), ..Default::default() },
+        }
+        .data();
+        ix
+    };
+
+    // Declarations the runtime couldn't grant are refused
+    for compute in [
+        ComputeDeclaration { units: 0, heap_bytes: 0 },
+        ComputeDeclaration { units: MAX_COMPUTE_UNITS + 1, heap_bytes: 0 },
+        ComputeDeclaration { units: 400_000, heap_bytes: 16 * 1024 },
+        ComputeDeclaration { units: 400_000, heap_bytes: 64 * 1024 + 1 },
+        ComputeDeclaration { units: 400_000, heap_bytes: MAX_HEAP_FRAME_BYTES + 1024 },
+    ] {
+        let ix = propose_with(&fixture, 0, compute);
+        assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InvalidComputeDeclaration);
+    }
+
+    // A sane one is stored, surfaced by explain_transaction, and doesn't
+    // change how the proposal executes
+    let compute = ComputeDeclaration { units: MAX_COMPUTE_UNITS, heap_bytes: MAX_HEAP_FRAME_BYTES };
+    let ix = propose_with(&fixture, 0, compute);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert_eq!(fixture.transaction_account(0).await.compute, Some(compute));
+    assert_eq!(
+        fixture.transaction_account(0).await.explain(),
+        Explanation::Opaque {
+            program_id: inner.program_id,
+            data_hash: hash(&inner.data).to_bytes(),
+            compute: Some(compute),
+        }
+    );
+    fixture.execute(0, &inner).await.unwrap();
+    assert!(fixture.transaction_account(0).await.executed);
+}
+
+#[test]
+fn compute_near_limit_is_within_ten_percent() {
+    let compute = ComputeDeclaration { units: 200_000, heap_bytes: 0 };
+    assert!(!compute.is_near_limit(179_999));
+    assert!(compute.is_near_limit(180_000));
+    assert!(compute.is_near_limit(250_000));
+}
+
+#[test]
+fn compute_headroom_is_half_the_declared_units() {
+    let compute = ComputeDeclaration { units: 400_000, heap_bytes: 0 };
+    assert!(compute.has_headroom(1_400_000));
+    assert!(compute.has_headroom(200_000));
+    assert!(!compute.has_headroom(199_999));
+    // The default 200k limit falls well short of a large declaration
+    let compute = ComputeDeclaration { units: MAX_COMPUTE_UNITS, heap_bytes: 0 };
+    assert!(!compute.has_headroom(200_000));
+}
+
+#[tokio::test]
+async fn executions_keep_the_vault_rent_exempt() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let vault = fixture.vault;
+    let recipient = Pubkey::new_unique();
+    let minimum = fixture.context.banks_client.get_rent().await.unwrap().minimum_balance(0);
+    let transfer = |lamports: u64| system_instruction::transfer(&vault, &recipient, lamports);
+
+    // A typed transfer may leave exactly the rent-exempt minimum, and not a
+    // lamport less
+    let spendable = fixture.balance(&vault).await - minimum;
+    let index = fixture.propose_transfer(recipient, spendable + 1, false, 0).await;
+    let result = fixture.execute(index, &transfer(0)).await;
+    assert_error(result, MultisigWalletError::WouldBreakRentExemption);
+    let index = fixture.propose_transfer(recipient, spendable, false, 0).await;
+    fixture.execute(index, &transfer(0)).await.unwrap();
+    assert_eq!(fixture.balance(&vault).await, minimum);
+
+    // The same holds for an opaque CPI, which may only empty the vault
+    // entirely when proposed as a sweep
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let balance = fixture.balance(&vault).await;
+    let index = fixture.propose(&transfer(balance - minimum + 1)).await;
+    let result = fixture.execute(index, &transfer(balance - minimum + 1)).await;
+    assert_error(result, MultisigWalletError::WouldBreakRentExemption);
+    let index = fixture.propose(&transfer(balance)).await;
+    let result = fixture.execute(index, &transfer(balance)).await;
+    assert_error(result, MultisigWalletError::WouldBreakRentExemption);
+
+    let sweep = transfer(balance);
+    let index = fixture.multisig_account().await.nonce;
+    let mut ix = fixture.propose_ix(&creator.pubkey(), index, &sweep);
+    ix.data = multisig_wallet::instruction::ProposeTransaction {
+        program_id: sweep.program_id,
+        accounts: serialize_account_metas(&sweep.accounts),
+        instruction_data: sweep.data.clone(),
+        options: ProposalOptions { sweep: true, ..Default::default() },
+    }
+    .data();
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    fixture.execute(index, &sweep).await.unwrap();
+    assert_eq!(fixture.balance(&vault).await, 0);
+}
+
+#[tokio::test]
+async fn by_index_instructions_derive_the_transaction_address() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let member = fixture.members[0].insecure_clone();
+    let multisig = fixture.multisig;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000_000);
+    let first = fixture.propose(&inner).await;
+    let second = fixture.propose(&inner).await;
+    let approve = |transaction: Pubkey, tx_index: u64, expected_hash: Option<[u8; 32]>| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ApproveTransactionByIndex {
+            multisig,
+            transaction,
+            signer: member.pubkey(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ApproveTransactionByIndex { tx_index, expected_hash }.data(),
+    };
+    let is_seeds_error = |result: Result<(), BanksClientError>| {
+        let code = anchor_lang::error::ErrorCode::ConstraintSeeds as u32;
+        matches!(
+            result,
+            Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(actual),
+            ))) if actual == code
+        )
+    };
+
+    // A transaction account that isn't the index's PDA is refused
+    let result = fixture.send(&[approve(transaction_pda(&multisig, second), first, None)], &[&member]).await;
+    assert!(is_seeds_error(result));
+
+    // The expected hash is checked as for approve_transaction
+    let first_pda = transaction_pda(&multisig, first);
+    let result = fixture.send(&[approve(first_pda, first, Some([7; 32]))], &[&member]).await;
+    assert_error(result, MultisigWalletError::HashMismatch);
+    let hash = fixture.transaction_account(first).await.hash;
+    fixture.send(&[approve(first_pda, first, Some(hash))], &[&member]).await.unwrap();
+    assert!(fixture.transaction_account(first).await.signers.contains(&member.pubkey()));
+
+    // Executing by index takes the same accounts as execute_transaction
+    let mut ix = fixture.execute_ix(first, &inner);
+    ix.data = multisig_wallet::instruction::ExecuteTransactionByIndex { tx_index: second, expected_hash: None }.data();
+    assert!(is_seeds_error(fixture.send(&[ix.clone()], &[]).await));
+    ix.data = multisig_wallet::instruction::ExecuteTransactionByIndex { tx_index: first, expected_hash: Some([7; 32]) }.data();
+    assert_error(fixture.send(&[ix.clone()], &[]).await, MultisigWalletError::HashMismatch);
+    ix.data = multisig_wallet::instruction::ExecuteTransactionByIndex { tx_index: first, expected_hash: Some(hash) }.data();
+    fixture.send(&[ix], &[]).await.unwrap();
+    assert!(fixture.transaction_account(first).await.executed);
+}
+
+#[tokio::test]
+async fn new_accounts_reserve_padding() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    let multisig = fixture.multisig;
+    let space = MultisigAccount::space(3);
+    assert_padded::<MultisigAccount>(&mut fixture.context, &multisig, space, MULTISIG_RESERVED_SPACE).await;
+
+    let memo = "padding".to_string();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let accounts = serialize_account_metas(&inner.accounts);
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.propose_raw_ix(
+        &creator.pubkey(),
+        0,
+        inner.program_id,
+        accounts.clone(),
+        inner.data.clone(),
+        Some(memo.clone()),
+        vec![],
+    );
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let space = TransactionAccount::space(accounts.len(), inner.data.len(), memo.len(), 0, 3);
+    let transaction = transaction_pda(&multisig, 0);
+    assert_padded::<TransactionAccount>(&mut fixture.context, &transaction, space, TRANSACTION_RESERVED_SPACE).await;
+    assert_eq!(fixture.transaction_account(0).await.reserved, [0; TRANSACTION_RESERVED_SPACE]);
+}
+
+#[tokio::test]
+async fn migrate_transaction_encoding_compacts_legacy_proposals() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let multisig = fixture.multisig;
+    let recipient = Pubkey::new_unique();
+    // Listing the recipient twice gives the compact encoding a key to share
+    let mut inner = fixture.vault_transfer(&recipient, 1_000_000);
+    inner.accounts.push(AccountMeta::new_readonly(recipient, false));
+    let index = fixture.propose(&inner).await;
+    let key = transaction_pda(&multisig, index);
+
+    // New proposals are stored compactly, and hash the metas as proposed
+    let proposed = serialize_account_metas(&inner.accounts);
+    let compact = fixture.transaction_account(index).await;
+    assert_eq!(compact.version, TRANSACTION_VERSION_COMPACT_METAS);
+    assert_eq!(compact.accounts.len(), proposed.len() - 32);
+    assert_eq!(compact.account_metas(), inner.accounts);
+    assert_eq!(compact.proposed_accounts(), proposed);
+
+    // Store it the way proposals made before the compact encoding are
+    let mut legacy = compact.clone();
+    legacy.version = TRANSACTION_VERSION_LEGACY_METAS;
+    legacy.accounts = proposed;
+    assert_eq!(legacy.compute_hash(), compact.hash);
+    let rent = fixture.context.banks_client.get_rent().await.unwrap();
+    let mut account = fixture.context.banks_client.get_account(key).await.unwrap().unwrap();
+    let space = account.data.len();
+    account.data.resize(space + 32, 0);
+    legacy.try_serialize(&mut account.data.as_mut_slice()).unwrap();
+    account.lamports = rent.minimum_balance(space + 32);
+    fixture.context.set_account(&key, &account.into());
+    assert_eq!(fixture.transaction_account(index).await.account_metas(), inner.accounts);
+
+    let member = fixture.members[0].insecure_clone();
+    let outsider = Keypair::new();
+    let migrate = |member: Pubkey| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::MigrateTransactionEncoding {
+            multisig,
+            transaction: key,
+            rent_payer: legacy.rent_payer,
+            member,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::MigrateTransactionEncoding {}.data(),
+    };
+    let result = fixture.send(&[migrate(outsider.pubkey())], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+
+    // Migrating shrinks the account back and refunds the rent it frees,
+    // keeping the hash and approvals
+    let rent_payer_balance = fixture.balance(&legacy.rent_payer).await;
+    fixture.send(&[migrate(member.pubkey())], &[&member]).await.unwrap();
+    let migrated = fixture.transaction_account(index).await;
+    assert_eq!(migrated.version, TRANSACTION_VERSION_COMPACT_METAS);
+    assert_eq!(migrated.accounts, compact.accounts);
+    assert_eq!(migrated.hash, compact.hash);
+    assert_eq!(migrated.signers, legacy.signers);
+    let account = fixture.context.banks_client.get_account(key).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), space);
+    assert_eq!(
+        fixture.balance(&legacy.rent_payer).await - rent_payer_balance,
+        rent.minimum_balance(space + 32) - rent.minimum_balance(space)
+    );
+
+    let result = fixture.send(&[migrate(member.pubkey())], &[&member]).await;
+    assert_error(result, MultisigWalletError::TransactionAlreadyMigrated);
+
+    // The migrated proposal still approves against its hash and executes
+    let mut approve = fixture.approve_ix(&member.pubkey(), index);
+    approve.data = multisig_wallet::instruction::ApproveTransaction { expected_hash: Some(compact.hash) }.data();
+    fixture.send(&[approve], &[&member]).await.unwrap();
+    fixture.execute(index, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, 1_000_000);
+}
+
+#[tokio::test]
+async fn migrate_multisig_account_grows_pre_upgrade_multisigs() {
+    let mut fixture = Fixture::new(2, 3, None).await;
+    let multisig = fixture.multisig;
+    let migrate = |multisig: Pubkey, payer: Pubkey| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::MigrateMultisigAccount {
+            multisig,
+            payer,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::MigrateMultisigAccount {}.data(),
+    };
+    let payer = fixture.context.payer.pubkey();
+    let result = fixture.send(&[migrate(multisig, payer)], &[]).await;
+    assert_error(result, MultisigWalletError::MultisigAlreadyMigrated);
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+    let result = fixture.send(&[migrate(transaction_pda(&multisig, index), payer)], &[]).await;
+    assert_error(result, MultisigWalletError::InvalidMultisigAccount);
+    let result = fixture.send(&[migrate(Pubkey::new_unique(), payer)], &[]).await;
+    assert_error(result, MultisigWalletError::InvalidMultisigAccount);
+
+    // Cut the account off where a multisig made before `proposer_excluded`
+    // and `open_window`, with no unused room left, would end
+    let rent = fixture.context.banks_client.get_rent().await.unwrap();
+    let before = fixture.multisig_account().await;
+    let mut account = fixture.context.banks_client.get_account(multisig).await.unwrap().unwrap();
+    let space = account.data.len();
+    assert_eq!(space, MultisigAccount::space(before.signers.len()));
+    let old_len = 8 + before.try_to_vec().unwrap().len() - 1 - OpenProposalWindow::SIZE;
+    account.data.truncate(old_len);
+    account.lamports = rent.minimum_balance(old_len);
+    fixture.context.set_account(&multisig, &account.into());
+    let account = fixture.context.banks_client.get_account(multisig).await.unwrap().unwrap();
+    assert!(MultisigAccount::try_deserialize(&mut account.data.as_slice()).is_err());
+
+    // Migrating grows it back, zero-filled and rent-exempt
+    fixture.send(&[migrate(multisig, payer)], &[]).await.unwrap();
+    let account = fixture.context.banks_client.get_account(multisig).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), space);
+    assert_eq!(account.lamports, rent.minimum_balance(space));
+    let after = fixture.multisig_account().await;
+    assert_eq!(after.signers, before.signers);
+    assert_eq!(after.threshold, before.threshold);
+    assert!(!after.proposer_excluded);
+    assert_eq!(after.open_window, OpenProposalWindow::default());
+
+    let result = fixture.send(&[migrate(multisig, payer)], &[]).await;
+    assert_error(result, MultisigWalletError::MultisigAlreadyMigrated);
+}
+
+fn initialize_program_config_ix(authority: &Pubkey, limits: ProgramLimits) -> Instruction {
+    Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::InitializeProgramConfig {
+            program_config: program_config_pda(),
+            program_data: program_data_pda(),
+            authority: *authority,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::InitializeProgramConfig { limits }.data(),
+    }
+}
+
+fn update_program_config_ix(admin: &Pubkey, update: ProgramConfigUpdate) -> Instruction {
+    Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::UpdateProgramConfig { program_config: program_config_pda(), admin: *admin }
+            .to_account_metas(None),
+        data: multisig_wallet::instruction::UpdateProgramConfig { update }.data(),
+    }
+}
+
+impl Fixture {
+    /// Creates the program config as the upgrade authority, who becomes its
+    /// admin.
+    async fn initialize_program_config(&mut self, limits: ProgramLimits) {
+        let authority = upgrade_authority();
+        let fund = system_instruction::transfer(&self.context.payer.pubkey(), &authority.pubkey(), LAMPORTS_PER_SOL);
+        let ix = initialize_program_config_ix(&authority.pubkey(), limits);
+        self.send(&[fund, ix], &[&authority]).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn program_config_is_created_by_the_upgrade_authority() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let limits = ProgramLimits { max_signers: 10, ..ProgramLimits::DEFAULT };
+
+    let ix = initialize_program_config_ix(&creator.pubkey(), limits);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::NotUpgradeAuthority);
+
+    let authority = upgrade_authority();
+    let invalid = ProgramLimits { max_signers: 0, ..limits };
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &authority.pubkey(), LAMPORTS_PER_SOL);
+    let ix = initialize_program_config_ix(&authority.pubkey(), invalid);
+    let result = fixture.send(&[fund, ix], &[&authority]).await;
+    assert_error(result, MultisigWalletError::InvalidProgramLimits);
+
+    fixture.initialize_program_config(limits).await;
+    let config: ProgramConfig = fetch(&mut fixture.context, &program_config_pda()).await;
+    assert_eq!(config.admin, authority.pubkey());
+    assert_eq!(config.limits, limits);
+}
+
+#[tokio::test]
+async fn program_config_is_updated_by_its_admin() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.initialize_program_config(ProgramLimits::DEFAULT).await;
+    let authority = upgrade_authority();
+    let creator = fixture.creator.insecure_clone();
+    let limits = ProgramLimits { max_instruction_data_len: 512, ..ProgramLimits::DEFAULT };
+
+    let ix = update_program_config_ix(
+        &creator.pubkey(),
+        ProgramConfigUpdate { limits: Some(limits), ..Default::default() },
+    );
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::NotProgramConfigAdmin);
+
+    let ix = update_program_config_ix(&authority.pubkey(), ProgramConfigUpdate::default());
+    let result = fixture.send(&[ix], &[&authority]).await;
+    assert_error(result, MultisigWalletError::NoOpUpdate);
+
+    let invalid = ProgramLimits { default_max_account_metas: 0, ..limits };
+    let ix = update_program_config_ix(
+        &authority.pubkey(),
+        ProgramConfigUpdate { limits: Some(invalid), ..Default::default() },
+    );
+    let result = fixture.send(&[ix], &[&authority]).await;
+    assert_error(result, MultisigWalletError::InvalidProgramLimits);
+
+    // Handing over leaves the upgrade authority with no say
+    let ix = update_program_config_ix(
+        &authority.pubkey(),
+        ProgramConfigUpdate { limits: Some(limits), new_admin: Some(creator.pubkey()), ..Default::default() },
+    );
+    fixture.send(&[ix], &[&authority]).await.unwrap();
+    let ix = update_program_config_ix(
+        &authority.pubkey(),
+        ProgramConfigUpdate { limits: Some(ProgramLimits::DEFAULT), ..Default::default() },
+    );
+    let result = fixture.send(&[ix], &[&authority]).await;
+    assert_error(result, MultisigWalletError::NotProgramConfigAdmin);
+
+    let config: ProgramConfig = fetch(&mut fixture.context, &program_config_pda()).await;
+    assert_eq!(config.admin, creator.pubkey());
+    assert_eq!(config.limits, limits);
+}
+
+#[tokio::test]
+async fn program_limits_apply_once_configured() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey()];
+    let limits = ProgramLimits {
+        max_signers: 3,
+        max_instruction_data_len: 16,
+        default_proposal_ttl_seconds: 3_600,
+        default_max_account_metas: 8,
+    };
+    fixture.initialize_program_config(limits).await;
+
+    let too_many: Vec<Pubkey> = approvers.iter().copied().chain((0..2).map(|_| Pubkey::new_unique())).collect();
+    let create_key = Keypair::new();
+    let mut ix = fixture.initialize_ix(too_many.clone(), 1, None);
+    ix.accounts[0].pubkey = multisig_pda(&create_key.pubkey());
+    ix.accounts[1].pubkey = create_key.pubkey();
+    ix.accounts[5].pubkey = generation_pda(&create_key.pubkey());
+    let result = fixture.send(&[ix], &[&creator, &create_key]).await;
+    assert_error(result, MultisigWalletError::TooManySigners);
+
+    let ix = fixture.update_ix(&approvers, Some(too_many), None, ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::TooManySigners);
+
+    // A multisig created before the config keeps the compiled defaults
+    let existing = fixture.multisig_account().await;
+    assert_eq!(existing.default_proposal_ttl_seconds, 0);
+    assert_eq!(existing.max_account_metas, ProgramLimits::DEFAULT.default_max_account_metas);
+
+    let multisig = fixture.create_multisig(approvers.to_vec(), 1).await;
+    fixture.select(multisig);
+    let created = fixture.multisig_account().await;
+    assert_eq!(created.default_proposal_ttl_seconds, 3_600);
+    assert_eq!(created.max_account_metas, 8);
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let mut data = inner.data.clone();
+    data.resize(17, 0);
+    let accounts = serialize_account_metas(&inner.accounts);
+    let ix = fixture.propose_raw_ix(&creator.pubkey(), 0, inner.program_id, accounts, data, None, vec![]);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InstructionDataTooLarge);
+
+    let index = fixture.propose(&inner).await;
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.expires_at, Some(transaction.created_at + 3_600));
+}
+
+#[tokio::test]
+async fn protocol_fee_is_stamped_at_proposal_and_paid_on_execution() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture.initialize_program_config(ProgramLimits::DEFAULT).await;
+    let authority = upgrade_authority();
+    let collector = Pubkey::new_unique();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &collector, LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let set_fee = |lamports: u64| {
+        update_program_config_ix(
+            &authority.pubkey(),
+            ProgramConfigUpdate {
+                fee_lamports: Some(lamports),
+                fee_collector: Some(collector),
+                ..Default::default()
+            },
+        )
+    };
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let free = fixture.propose(&inner).await;
+    fixture.send(&[set_fee(5_000)], &[&authority]).await.unwrap();
+    let charged = fixture.propose(&inner).await;
+    // Raising the fee doesn't reach proposals already made
+    fixture.send(&[set_fee(9_000)], &[&authority]).await.unwrap();
+    assert_eq!(fixture.transaction_account(free).await.protocol_fee, 0);
+    assert_eq!(fixture.transaction_account(charged).await.protocol_fee, 5_000);
+
+    // Without the program config and collector the fee can't be paid, and
+    // the proposal stays pending
+    let result = fixture.execute(charged, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientAccounts);
+    assert!(!fixture.transaction_account(charged).await.executed);
+
+    let vault = fixture.vault;
+    let vault_before = fixture.balance(&vault).await;
+    let collector_before = fixture.balance(&collector).await;
+    let mut ix = fixture.execute_ix(charged, &inner);
+    ix.accounts.push(AccountMeta::new_readonly(program_config_pda(), false));
+    ix.accounts.push(AccountMeta::new(collector, false));
+    fixture.send(&[ix], &[]).await.unwrap();
+    assert_eq!(fixture.balance(&collector).await, collector_before + 5_000);
+    assert_eq!(fixture.balance(&vault).await, vault_before - 5_000 - 1_000);
+
+    fixture.execute(free, &inner).await.unwrap();
+
+    // Executors pay when the multisig prefers it
+    fixture.configure(ConfigAction::SetFeePayer { payer: FeePayer::Executor }).await.unwrap();
+    let index = fixture.propose(&inner).await;
+    let mut ix = fixture.execute_ix(index, &inner);
+    ix.accounts.push(AccountMeta::new_readonly(program_config_pda(), false));
+    ix.accounts.push(AccountMeta::new(collector, false));
+    let result = fixture.send(&[ix.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::FeePayerRequired);
+
+    let member = fixture.members[0].insecure_clone();
+    ix.accounts[2] = AccountMeta::new(member.pubkey(), true);
+    let member_before = fixture.balance(&member.pubkey()).await;
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    assert_eq!(fixture.balance(&member.pubkey()).await, member_before - 9_000);
+    assert!(fixture.transaction_account(index).await.executed);
+}
+
+#[tokio::test]
+async fn proposal_history_links_proposals_and_terminal_states() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    assert_eq!(fixture.multisig_account().await.history_hash, [0; 32]);
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = 1_700_000_000;
+    fixture.context.set_sysvar(&clock);
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let kept = fixture.propose(&inner).await;
+    let cancelled = fixture.propose(&inner).await;
+    let kept_hash = fixture.transaction_account(kept).await.hash;
+    let cancelled_hash = fixture.transaction_account(cancelled).await.hash;
+    let members: Vec<Keypair> = fixture.members.iter().map(Keypair::insecure_clone).collect();
+    for member in &members {
+        let ix = fixture.reject_ix(&member.pubkey(), cancelled, "");
+        fixture.send(&[ix], &[member]).await.unwrap();
+    }
+
+    let cranker = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &cranker.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let close = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CloseExpiredTransaction {
+            multisig: fixture.multisig,
+            transaction: transaction_pda(&fixture.multisig, cancelled),
+            rent_payer: fixture.creator.pubkey(),
+            vault: fixture.vault,
+            cranker: cranker.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CloseExpiredTransaction {}.data(),
+    };
+    fixture.send(&[close], &[&cranker]).await.unwrap();
+
+    // Replaying the records from genesis reaches the stored head
+    let now = clock.unix_timestamp;
+    let mut head = [0; 32];
+    head = link_history(&head, HistoryRecord::Proposed, &kept_hash, kept, now);
+    head = link_history(&head, HistoryRecord::Proposed, &cancelled_hash, cancelled, now);
+    head = link_history(&head, HistoryRecord::Cancelled, &cancelled_hash, cancelled, now);
+    head = link_history(&head, HistoryRecord::Closed, &cancelled_hash, cancelled, now);
+    assert_eq!(fixture.multisig_account().await.history_hash, head);
+}
+
+#[tokio::test]
+async fn try_execute_records_failed_checks() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture
+        .configure(ConfigAction::SetRateLimit { mint: None, max_per_window: LAMPORTS_PER_SOL / 10 })
+        .await
+        .unwrap();
+    fixture.configure(ConfigAction::SetOrderedExecution { enabled: true, next_index: 0 }).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let accounts = Instruction {
+        program_id: system_program::ID,
+        accounts: vec![AccountMeta::new(fixture.vault, false), AccountMeta::new(recipient, false)],
+        data: vec![],
+    };
+    let index = fixture.propose_transfer(recipient, LAMPORTS_PER_SOL / 5, false, 0).await;
+    let mut try_execute = fixture.execute_ix(index, &accounts);
+    try_execute.data = multisig_wallet::instruction::TryExecuteTransaction { expected_hash: None }.data();
+
+    // Over the rate limit: the attempt is recorded and the call succeeds,
+    // without the ordered index it advanced before failing
+    for attempt in 1..=2 {
+        fixture.send(&[try_execute.clone()], &[]).await.unwrap();
+        let transaction = fixture.transaction_account(index).await;
+        assert_eq!(transaction.failed_attempts, attempt);
+        assert_eq!(transaction.last_error_code, u64::from(u32::from(MultisigWalletError::RateLimitExceeded)));
+        assert!(!transaction.executed);
+    }
+    assert_eq!(fixture.multisig_account().await.last_executed_index, None);
+
+    fixture
+        .configure(ConfigAction::SetRateLimit { mint: None, max_per_window: LAMPORTS_PER_SOL })
+        .await
+        .unwrap();
+    let limit = &fixture.multisig_account().await.rate_limits[0];
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = limit.pending_effective_at;
+    fixture.context.set_sysvar(&clock);
+    fixture.send(&[try_execute], &[]).await.unwrap();
+    let transaction = fixture.transaction_account(index).await;
+    assert!(transaction.executed);
+    assert_eq!(transaction.failed_attempts, 2);
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 5);
+    assert_eq!(fixture.multisig_account().await.last_executed_index, Some(index));
+}
+
+#[tokio::test]
+async fn config_change_proposal_applies_on_execution() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let recipient = Pubkey::new_unique();
+    let typed = fixture.propose_transfer(recipient, 1_000, false, 0).await;
+    let transfer = fixture.vault_transfer(&recipient, 1);
+    let opaque = fixture.propose(&transfer).await;
+    let action = ConfigAction::SetProposalCooldown { seconds: 60 };
+    let inner = config_change(action.clone());
+    let config = fixture.propose(&inner).await;
+
+    assert_eq!(fixture.transaction_account(typed).await.kind, TransactionKind::TypedAction(ActionKind::Transfer));
+    assert_eq!(fixture.transaction_account(opaque).await.kind, TransactionKind::General);
+    assert_eq!(fixture.transaction_account(config).await.kind, TransactionKind::ConfigChange(action));
+
+    // The threshold of one is met, but a config change needs every member
+    let result = fixture.execute(config, &inner).await;
+    assert_error(result, MultisigWalletError::NotAllSignersApproved);
+
+    fixture.approve(0, config).await.unwrap();
+    fixture.execute(config, &inner).await.unwrap();
+    assert!(fixture.transaction_account(config).await.executed);
+    assert_eq!(fixture.multisig_account().await.proposal_cooldown_seconds, 60);
+}
+
+#[tokio::test]
+async fn undecodable_config_change_is_refused() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let mut inner = config_change(ConfigAction::SetProposalCooldown { seconds: 60 });
+    inner.data.truncate(10);
+    let index = fixture.multisig_account().await.nonce;
+    let creator = fixture.creator.insecure_clone();
+    let propose = fixture.propose_ix(&creator.pubkey(), index, &inner);
+    let result = fixture.send(&[propose], &[&creator]).await;
+    assert_error(result, MultisigWalletError::TransactionKindMismatch);
+}
+
+#[tokio::test]
+async fn invited_signer_joins_by_accepting() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let invitee = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &invitee.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    // The invitation is made by a config change proposal, with its rent
+    // paid from the vault
+    let invite = fixture.invite_signer(&invitee.pubkey(), 1);
+    let index = fixture.propose(&invite).await;
+    fixture.approve(0, index).await.unwrap();
+    fixture.execute(index, &invite).await.unwrap();
+    let key = invitation_pda(&fixture.multisig, &invitee.pubkey());
+    let invitation: Invitation = fetch(&mut fixture.context, &key).await;
+    assert_eq!(invitation.invitee, invitee.pubkey());
+    assert_eq!(invitation.roles, 1);
+
+    let index = fixture.propose(&invite).await;
+    fixture.approve(0, index).await.unwrap();
+    let result = fixture.execute(index, &invite).await;
+    assert_error(result, MultisigWalletError::InvitationAlreadyExists);
+
+    let vault = fixture.vault;
+    let vault_before = fixture.balance(&vault).await;
+    let accept = fixture.accept_invitation_ix(&invitee.pubkey());
+    fixture.send(&[accept], &[&invitee]).await.unwrap();
+    let signers = fixture.multisig_account().await.signers;
+    assert_eq!(signers.len(), 3);
+    assert_eq!(signers[2], invitee.pubkey());
+    assert!(fixture.context.banks_client.get_account(key).await.unwrap().is_none());
+    assert!(fixture.balance(&vault).await > vault_before);
+}
+
+#[tokio::test]
+async fn expired_invitation_lapses() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let invitee = Keypair::new();
+    let invite = fixture.invite_signer(&invitee.pubkey(), 0);
+    let creator = fixture.creator.insecure_clone();
+    let action = ConfigAction::InviteSigner { invitee: invitee.pubkey(), roles: 0 };
+    let mut configure = fixture.configure_ix(&[creator.pubkey()], action);
+    configure.accounts.extend(invite.accounts);
+    fixture.send(&[configure], &[&creator]).await.unwrap();
+
+    let key = invitation_pda(&fixture.multisig, &invitee.pubkey());
+    let close = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CloseExpiredInvitation {
+            multisig: fixture.multisig,
+            invitation: key,
+            vault: fixture.vault,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CloseExpiredInvitation {}.data(),
+    };
+    let result = fixture.send(&[close.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::InvitationNotExpired);
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += INVITATION_WINDOW + 1;
+    fixture.context.set_sysvar(&clock);
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &invitee.pubkey(), LAMPORTS_PER_SOL);
+    let accept = fixture.accept_invitation_ix(&invitee.pubkey());
+    let result = fixture.send(&[fund, accept], &[&invitee]).await;
+    assert_error(result, MultisigWalletError::InvitationExpired);
+
+    fixture.send(&[close], &[]).await.unwrap();
+    assert!(fixture.context.banks_client.get_account(key).await.unwrap().is_none());
+    assert_eq!(fixture.multisig_account().await.signers.len(), 1);
+}
+
+#[tokio::test]
+async fn typed_only_refuses_opaque_proposals() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.configure(ConfigAction::SetTypedOnly { enabled: true }).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, 1);
+    let creator = fixture.creator.insecure_clone();
+    let index = fixture.multisig_account().await.nonce;
+    let propose = fixture.propose_ix(&creator.pubkey(), index, &inner);
+    let result = fixture.send(&[propose.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::OpaqueProposalsDisabled);
+
+    // Typed actions and config changes still go through
+    fixture.propose_transfer(recipient, 1, false, 0).await;
+    fixture.propose(&config_change(ConfigAction::SetProposalCooldown { seconds: 0 })).await;
+
+    // Switching it off waits out the delay
+    fixture.configure(ConfigAction::SetTypedOnly { enabled: false }).await.unwrap();
+    let propose = fixture.propose_ix(&creator.pubkey(), index + 2, &inner);
+    let result = fixture.send(&[propose.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::OpaqueProposalsDisabled);
+
+    let disable_at = fixture.multisig_account().await.typed_only_disable_at;
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = disable_at;
+    fixture.context.set_sysvar(&clock);
+    fixture.send(&[propose], &[&creator]).await.unwrap();
+}
+
+#[tokio::test]
+async fn proposals_only_execute_on_the_cluster_they_were_made_for() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture.initialize_program_config(ProgramLimits::DEFAULT).await;
+    let authority = upgrade_authority();
+    let set_cluster = |cluster: Cluster| {
+        update_program_config_ix(
+            &authority.pubkey(),
+            ProgramConfigUpdate { cluster: Some(cluster), ..Default::default() },
+        )
+    };
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let undeclared = fixture.propose(&inner).await;
+    fixture.send(&[set_cluster(Cluster::Devnet)], &[&authority]).await.unwrap();
+    let devnet = fixture.propose(&inner).await;
+    let transaction = fixture.transaction_account(devnet).await;
+    assert_eq!(transaction.cluster, Cluster::Devnet);
+    assert_eq!(transaction.hash, transaction.compute_hash());
+    assert_eq!(fixture.transaction_account(undeclared).await.cluster, Cluster::Unspecified);
+
+    // Checking the cluster needs the program config
+    let result = fixture.execute(devnet, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientAccounts);
+
+    let mut ix = fixture.execute_ix(devnet, &inner);
+    ix.accounts.push(AccountMeta::new_readonly(program_config_pda(), false));
+    fixture.send(&[set_cluster(Cluster::Mainnet)], &[&authority]).await.unwrap();
+    let result = fixture.send(&[ix.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::ClusterMismatch);
+    assert!(!fixture.transaction_account(devnet).await.executed);
+
+    fixture.send(&[set_cluster(Cluster::Devnet)], &[&authority]).await.unwrap();
+    fixture.send(&[ix], &[]).await.unwrap();
+    assert!(fixture.transaction_account(devnet).await.executed);
+
+    // Proposals made before a cluster was declared run anywhere
+    fixture.execute(undeclared, &inner).await.unwrap();
+}
+
+#[tokio::test]
+async fn approvals_are_indexed_by_member_position_and_order() {
+    let mut fixture = Fixture::new(3, 4, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+    fixture.approve(2, index).await.unwrap();
+    fixture.approve(0, index).await.unwrap();
+
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.approval_ordinal, 3);
+    assert_eq!(
+        transaction.approval_indices,
+        vec![
+            ApprovalIndex { member: 0, ordinal: 1 },
+            ApprovalIndex { member: 1, ordinal: 3 },
+            ApprovalIndex { member: 3, ordinal: 2 },
+        ]
+    );
+
+    // A rotated member's approval is dropped along with its index
+    let member = fixture.members[0].insecure_clone();
+    let pending = transaction_pda(&fixture.multisig, index);
+    let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), Pubkey::new_unique(), &[pending]);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(
+        transaction.approval_indices,
+        vec![ApprovalIndex { member: 0, ordinal: 1 }, ApprovalIndex { member: 3, ordinal: 2 }]
+    );
+}
+
+#[tokio::test]
+async fn votes_on_the_same_proposal_wait_out_the_cooldown() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.configure(ConfigAction::SetVoteCooldown { slots: 100 }).await.unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    // A first vote is never throttled
+    fixture.approve(0, index).await.unwrap();
+    let voted = fixture.transaction_account(index).await.last_vote_slots[1];
+    assert!(voted > 0);
+
+    // The rotated key votes for the same member, so it waits
+    let member = fixture.members[0].insecure_clone();
+    let new_key = Keypair::new();
+    let pending = transaction_pda(&fixture.multisig, index);
+    let ix = rotate_key_ix(&fixture.multisig, &member.pubkey(), new_key.pubkey(), &[pending]);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+
+    fixture.context.warp_to_slot(voted + 99).unwrap();
+    let ix = fixture.approve_ix(&new_key.pubkey(), index);
+    let result = fixture.send(&[ix.clone()], &[&new_key]).await;
+    assert_error(result, MultisigWalletError::VoteTooSoon);
+
+    fixture.context.warp_to_slot(voted + 100).unwrap();
+    fixture.send(&[ix], &[&new_key]).await.unwrap();
+    assert_eq!(fixture.transaction_account(index).await.last_vote_slots[1], voted + 100);
+}
+
+#[tokio::test]
+async fn ephemeral_signers_create_accounts_at_execution() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let vault = fixture.vault;
+    let index = fixture.multisig_account().await.nonce;
+    let transaction = transaction_pda(&fixture.multisig, index);
+    let (ephemeral, _) = find_ephemeral_signer_address(&transaction, 0);
+    let owner = Pubkey::new_unique();
+    let create = system_instruction::create_account(&vault, &ephemeral, 10_000_000, 16, &owner);
+
+    let mut ix = fixture.propose_ix(&creator.pubkey(), index, &create);
+    let proposal = |ephemeral_signers: u8| multisig_wallet::instruction::ProposeTransaction {
+        program_id: create.program_id,
+        accounts: serialize_account_metas(&create.accounts),
+        instruction_data: create.data.clone(),
+        options: ProposalOptions { ephemeral_signers, ..Default::default() },
+    };
+
+    // Undeclared, the new account can't be listed as a signer
+    let result = fixture.send(&[ix.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::UnauthorizedSignerMeta);
+    ix.data = proposal(MAX_EPHEMERAL_SIGNERS as u8 + 1).data();
+    let result = fixture.send(&[ix.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::TooManyEphemeralSigners);
+
+    ix.data = proposal(1).data();
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert_eq!(fixture.transaction_account(index).await.ephemeral_signers, 1);
+    fixture.execute(index, &create).await.unwrap();
+
+    let account = fixture.context.banks_client.get_account(ephemeral).await.unwrap().unwrap();
+    assert_eq!(account.owner, owner);
+    assert_eq!(account.data.len(), 16);
+}
+
+#[tokio::test]
+async fn proposals_cannot_write_to_the_multisig_or_its_proposals() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let multisig = fixture.multisig;
+    let unrelated = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let first = fixture.propose(&unrelated).await;
+
+    let mut targets = vec![multisig, transaction_pda(&multisig, first)];
+    let index = fixture.multisig_account().await.nonce;
+    targets.push(transaction_pda(&multisig, index));
+    for target in targets {
+        let ix = fixture.propose_ix(&creator.pubkey(), index, &fixture.vault_transfer(&target, 1));
+        let result = fixture.send(&[ix], &[&creator]).await;
+        assert_error(result, MultisigWalletError::SelfReferentialProposal);
+    }
+
+    // Read-only references are fine
+    let mut inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    inner.accounts.push(AccountMeta::new_readonly(multisig, false));
+    fixture.propose(&inner).await;
+
+    // Proposals past the lookback are recognized at execution
+    while fixture.multisig_account().await.nonce <= first + SELF_REFERENCE_LOOKBACK + 1 {
+        fixture.propose(&unrelated).await;
+    }
+    let inner = fixture.vault_transfer(&transaction_pda(&multisig, first), 1);
+    let index = fixture.propose(&inner).await;
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::SelfReferentialProposal);
+}
+
+fn claim_allowance_ix(multisig: &Pubkey, member: &Pubkey, recipient: &Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ClaimAllowance {
+            multisig: *multisig,
+            vault: vault_pda(multisig),
+            member: *member,
+            recipient: *recipient,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ClaimAllowance { amount }.data(),
+    }
+}
+
+#[tokio::test]
+async fn members_claim_their_allowance_alone() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let multisig = fixture.multisig;
+    let member = fixture.members[0].insecure_clone();
+    let recipient = Pubkey::new_unique();
+    let claim = |amount: u64| claim_allowance_ix(&multisig, &member.pubkey(), &recipient, amount);
+
+    let result = fixture.send(&[claim(1_000_000)], &[&member]).await;
+    assert_error(result, MultisigWalletError::AllowanceNotFound);
+
+    let period_seconds = 30 * 24 * 60 * 60;
+    let allowance = |amount| ConfigAction::SetMemberAllowance { member: member.pubkey(), amount, period_seconds };
+    let result = fixture.configure(allowance(Some(0))).await;
+    assert_error(result, MultisigWalletError::InvalidAmount);
+    fixture.configure(allowance(Some(LAMPORTS_PER_SOL / 2))).await.unwrap();
+
+    fixture.send(&[claim(LAMPORTS_PER_SOL / 5)], &[&member]).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 5);
+    let result = fixture.send(&[claim(LAMPORTS_PER_SOL / 2)], &[&member]).await;
+    assert_error(result, MultisigWalletError::AllowanceExceeded);
+
+    fixture.configure(ConfigAction::SetAllowanceFrozen { member: member.pubkey(), frozen: true }).await.unwrap();
+    let result = fixture.send(&[claim(1_000_000)], &[&member]).await;
+    assert_error(result, MultisigWalletError::AllowanceFrozen);
+    fixture.configure(ConfigAction::SetAllowanceFrozen { member: member.pubkey(), frozen: false }).await.unwrap();
+
+    // A new period refills the allowance, without carrying over what's left
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += period_seconds;
+    fixture.context.set_sysvar(&clock);
+    let result = fixture.send(&[claim(LAMPORTS_PER_SOL / 2 + 1)], &[&member]).await;
+    assert_error(result, MultisigWalletError::AllowanceExceeded);
+    fixture.send(&[claim(LAMPORTS_PER_SOL / 2)], &[&member]).await.unwrap();
+    let claimed = fixture.multisig_account().await.member_allowances[0].clone();
+    assert_eq!(claimed.remaining, 0);
+    assert!(claimed.last_reset >= clock.unix_timestamp);
+
+    // Removing the member deletes the allowance
+    let creator = fixture.creator.insecure_clone();
+    let ix = fixture.update_ix(
+        &[creator.pubkey(), member.pubkey()],
+        Some(vec![creator.pubkey()]),
+        Some(1),
+        ExpirationUpdate::Keep,
+    );
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    assert!(fixture.multisig_account().await.member_allowances.is_empty());
+}
+
+#[tokio::test]
+async fn recreated_multisig_rejects_stale_proposals() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let create_key = fixture.create_key.insecure_clone();
+    let vault = fixture.vault;
+    assert_eq!(fixture.multisig_account().await.generation, 1);
+
+    let transfer = system_instruction::transfer(&vault, &Pubkey::new_unique(), LAMPORTS_PER_SOL / 10);
+    let index = fixture.propose(&transfer).await;
+    assert_eq!(fixture.transaction_account(index).await.generation, 1);
+
+    // Close the multisig, leaving the proposal behind, then create it again
+    // at the same address with a threshold the proposal already meets
+    let ix = fixture.close_ix(&Pubkey::new_unique(), &[], &[]);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let ix = fixture.initialize_ix(vec![creator.pubkey(), member.pubkey()], 1, None);
+    fixture.send(&[ix], &[&creator, &create_key]).await.unwrap();
+    assert_eq!(fixture.multisig_account().await.generation, 2);
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &vault, LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    // The old proposal can neither be approved nor executed
+    let ix = fixture.approve_ix(&member.pubkey(), index);
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::GenerationMismatch);
+    let result = fixture.execute(index, &transfer).await;
+    assert_error(result, MultisigWalletError::GenerationMismatch);
+
+    // Anyone may close it as stale, freeing its index for the new multisig
+    let cranker = Keypair::new();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &cranker.pubkey(), LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let close = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CloseExpiredTransaction {
+            multisig: fixture.multisig,
+            transaction: transaction_pda(&fixture.multisig, index),
+            rent_payer: creator.pubkey(),
+            vault,
+            cranker: cranker.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CloseExpiredTransaction {}.data(),
+    };
+    fixture.send(&[close], &[&cranker]).await.unwrap();
+
+    let index = fixture.propose(&transfer).await;
+    assert_eq!(fixture.transaction_account(index).await.generation, 2);
+    fixture.execute(index, &transfer).await.unwrap();
+}
+
+#[tokio::test]
+async fn registered_wallet_programs_approve_for_their_pdas() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let (authority, _) = streamer_authority();
+    let multisig = fixture.create_multisig(vec![creator.pubkey(), authority], 2).await;
+    fixture.select(multisig);
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    let approve_for = |member: Pubkey| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ApproveFor {
+            multisig,
+            transaction: transaction_pda(&multisig, index),
+            member,
+            program_config: program_config_pda(),
+            instructions: sysvar::instructions::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ApproveFor { expected_hash: None }.data(),
+    };
+
+    // No wallet program is trusted until the config registers one
+    let result = fixture.send(&[via_streamer(approve_for(authority))], &[]).await;
+    assert_error(result, MultisigWalletError::UnregisteredWalletProgram);
+
+    fixture.initialize_program_config(ProgramLimits::DEFAULT).await;
+    let admin = upgrade_authority();
+    let register = |wallet_programs: Vec<Pubkey>| {
+        update_program_config_ix(
+            &admin.pubkey(),
+            ProgramConfigUpdate { wallet_programs: Some(wallet_programs), ..Default::default() },
+        )
+    };
+    let too_many = (0..=MAX_WALLET_PROGRAMS).map(|_| Pubkey::new_unique()).collect();
+    let result = fixture.send(&[register(too_many)], &[&admin]).await;
+    assert_error(result, MultisigWalletError::TooManyWalletPrograms);
+    fixture.send(&[register(vec![streamer_id()])], &[&admin]).await.unwrap();
+
+    // Neither a top-level call nor a CPI from an unregistered program passes
+    let result = fixture.send(&[approve_for(creator.pubkey())], &[&creator]).await;
+    assert_error(result, MultisigWalletError::UnregisteredWalletProgram);
+    let mut via_governance = approve_for(creator.pubkey());
+    via_governance.accounts.insert(0, AccountMeta::new_readonly(multisig_wallet::ID, false));
+    via_governance.program_id = governance_id();
+    let result = fixture.send(&[via_governance], &[&creator]).await;
+    assert_error(result, MultisigWalletError::UnregisteredWalletProgram);
+
+    // The registered wallet approves for its PDA, with the usual checks
+    fixture.send(&[via_streamer(approve_for(authority))], &[]).await.unwrap();
+    assert!(fixture.transaction_account(index).await.signers.contains(&authority));
+    let result = fixture.send(&[via_streamer(approve_for(authority))], &[]).await;
+    assert_error(result, MultisigWalletError::AlreadyApproved);
+    fixture.execute(index, &inner).await.unwrap();
+}
+
+fn reservation_pda(multisig: &Pubkey, index: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"reservation", multisig.as_ref(), &index.to_le_bytes()], &multisig_wallet::ID).0
+}
+
+impl Fixture {
+    fn reserve_nonce_ix(&self, reserver: &Pubkey, index: u64) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ReserveNonce {
+                multisig: self.multisig,
+                reservation: reservation_pda(&self.multisig, index),
+                reserver: *reserver,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ReserveNonce {}.data(),
+        }
+    }
+
+    /// Builds a `propose_transaction_with_reservation` with the defaults of
+    /// `propose_ix`.
+    fn propose_reserved_ix(&self, proposer: &Pubkey, index: u64, inner: &Instruction) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ProposeTransactionWithReservation {
+                multisig: self.multisig,
+                reservation: reservation_pda(&self.multisig, index),
+                transaction: transaction_pda(&self.multisig, index),
+                proposer: *proposer,
+                rent_payer: *proposer,
+                system_program: system_program::ID,
+                program_config: program_config_pda(),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ProposeTransactionWithReservation {
+                program_id: inner.program_id,
+                accounts: serialize_account_metas(&inner.accounts),
+                instruction_data: inner.data.clone(),
+                options: ProposalOptions::default(),
+            }
+            .data(),
+        }
+    }
+
+    fn close_expired_reservation_ix(&self, reserver: &Pubkey, index: u64) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::CloseExpiredReservation {
+                multisig: self.multisig,
+                reservation: reservation_pda(&self.multisig, index),
+                reserver: *reserver,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::CloseExpiredReservation {}.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn reserved_nonces_hold_their_index() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+
+    // Reserving takes the next index at once
+    let ix = fixture.reserve_nonce_ix(&member.pubkey(), 0);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let reservation: NonceReservation = fetch(&mut fixture.context, &reservation_pda(&fixture.multisig, 0)).await;
+    assert_eq!(reservation.tx_index, 0);
+    assert_eq!(reservation.reserver, member.pubkey());
+    assert_eq!(fixture.multisig_account().await.nonce, 1);
+
+    // Unreserved proposals take the live nonce meanwhile
+    assert_eq!(fixture.propose(&inner).await, 1);
+
+    // Only the reserver may use it, and only once
+    let ix = fixture.propose_reserved_ix(&creator.pubkey(), 0, &inner);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::ReservationNotHeld);
+    let ix = fixture.propose_reserved_ix(&member.pubkey(), 0, &inner);
+    fixture.send(&[ix.clone()], &[&member]).await.unwrap();
+    let proposal = fixture.transaction_account(0).await;
+    assert_eq!((proposal.tx_index, proposal.proposer), (0, member.pubkey()));
+    assert_eq!(fixture.multisig_account().await.nonce, 2);
+    let closed = fixture.context.banks_client.get_account(reservation_pda(&fixture.multisig, 0)).await.unwrap();
+    assert!(closed.is_none());
+    let result = fixture.send(&[ix], &[&member]).await;
+    let code = anchor_lang::error::ErrorCode::AccountNotInitialized as u32;
+    assert!(matches!(
+        result,
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(_, InstructionError::Custom(actual))))
+            if actual == code
+    ));
+    fixture.execute(0, &inner).await.unwrap();
+
+    // A lapsed reservation can't be used, and anyone may then close it
+    let ix = fixture.reserve_nonce_ix(&member.pubkey(), 2);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    let ix = fixture.close_expired_reservation_ix(&member.pubkey(), 2);
+    let result = fixture.send(&[ix], &[]).await;
+    assert_error(result, MultisigWalletError::ReservationNotExpired);
+
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += NONCE_RESERVATION_TTL + 1;
+    fixture.context.set_sysvar(&clock);
+    let ix = fixture.propose_reserved_ix(&member.pubkey(), 2, &inner);
+    let result = fixture.send(&[ix], &[&member]).await;
+    assert_error(result, MultisigWalletError::ReservationExpired);
+    let before = fixture.balance(&member.pubkey()).await;
+    let ix = fixture.close_expired_reservation_ix(&member.pubkey(), 2);
+    fixture.send(&[ix], &[]).await.unwrap();
+    assert!(fixture.balance(&member.pubkey()).await > before);
+    assert_eq!(fixture.propose(&inner).await, 3);
+}
+
+impl Fixture {
+    fn confirm_execution_ix(&self, member: &Pubkey, index: u64) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ConfirmExecution {
+                multisig: self.multisig,
+                transaction: transaction_pda(&self.multisig, index),
+                member: *member,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ConfirmExecution {}.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn large_opaque_payloads_need_a_confirmation() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let confirmer = fixture.members[1].insecure_clone();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+
+    // A payload exactly at the threshold executes as before
+    let bytes = inner.data.len() as u16;
+    fixture
+        .configure(ConfigAction::SetConfirmationThreshold { bytes })
+        .await
+        .unwrap();
+    assert_eq!(fixture.multisig_account().await.confirmation_threshold_bytes, bytes);
+    let small = fixture.propose(&inner).await;
+    assert!(!fixture.transaction_account(small).await.confirmation_required);
+    fixture.approve(0, small).await.unwrap();
+    let result = fixture.send(&[fixture.confirm_execution_ix(&confirmer.pubkey(), small)], &[&confirmer]).await;
+    assert_error(result, MultisigWalletError::ConfirmationNotRequired);
+    fixture.execute(small, &inner).await.unwrap();
+
+    // One byte over it waits for a second member
+    fixture
+        .configure(ConfigAction::SetConfirmationThreshold { bytes: bytes - 1 })
+        .await
+        .unwrap();
+    let large = fixture.propose(&inner).await;
+    assert!(fixture.transaction_account(large).await.confirmation_required);
+    let result = fixture.send(&[fixture.confirm_execution_ix(&confirmer.pubkey(), large)], &[&confirmer]).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+    fixture.approve(0, large).await.unwrap();
+    let result = fixture.execute(large, &inner).await;
+    assert_error(result, MultisigWalletError::ConfirmationRequired);
+
+    let result = fixture.send(&[fixture.confirm_execution_ix(&creator.pubkey(), large)], &[&creator]).await;
+    assert_error(result, MultisigWalletError::SelfConfirmation);
+    let outsider = Keypair::new();
+    let result = fixture.send(&[fixture.confirm_execution_ix(&outsider.pubkey(), large)], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+
+    fixture
+        .send(&[fixture.confirm_execution_ix(&confirmer.pubkey(), large)], &[&confirmer])
+        .await
+        .unwrap();
+    assert_eq!(fixture.transaction_account(large).await.confirmed_by(), Some(confirmer.pubkey()));
+    let member = fixture.members[0].insecure_clone();
+    let result = fixture.send(&[fixture.confirm_execution_ix(&member.pubkey(), large)], &[&member]).await;
+    assert_error(result, MultisigWalletError::AlreadyConfirmed);
+
+    fixture.execute(large, &inner).await.unwrap();
+    assert!(fixture.transaction_account(large).await.executed);
+}
+
+impl Fixture {
+    fn close_empty_vault_ata_ix(&self, member: &Pubkey, mint: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::CloseEmptyVaultAta {
+                multisig: self.multisig,
+                registry: registry_pda(&self.multisig),
+                vault: self.vault,
+                mint: *mint,
+                token_account: get_associated_token_address_with_program_id(&self.vault, mint, &spl_token::ID),
+                member: *member,
+                token_program: spl_token::ID,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::CloseEmptyVaultAta {}.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn empty_vault_atas_close_back_into_the_vault() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let payer = fixture.context.payer.insecure_clone();
+    let (vault, multisig) = (fixture.vault, fixture.multisig);
+    let registry = registry_pda(&multisig);
+    let (mint, _) = fixture.create_token_account(&payer.pubkey(), 0).await;
+    let ata = get_associated_token_address_with_program_id(&vault, &mint, &spl_token::ID);
+    let create_ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::CreateVaultAta {
+            multisig,
+            registry,
+            vault,
+            mint,
+            token_account: ata,
+            member: creator.pubkey(),
+            token_program: spl_token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::CreateVaultAta {}.data(),
+    };
+    fixture.send(&[create_ix], &[&creator]).await.unwrap();
+
+    // Only members may close, and only accounts holding nothing
+    let outsider = Keypair::new();
+    let result = fixture.send(&[fixture.close_empty_vault_ata_ix(&outsider.pubkey(), &mint)], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+    let mint_to = spl_token::instruction::mint_to(&spl_token::ID, &mint, &ata, &payer.pubkey(), &[], 5).unwrap();
+    fixture.send(&[mint_to], &[]).await.unwrap();
+    let result = fixture.send(&[fixture.close_empty_vault_ata_ix(&creator.pubkey(), &mint)], &[&creator]).await;
+    assert_error(result, MultisigWalletError::TokenAccountNotEmpty);
+
+    // Nor with a delegate, even an empty one
+    let burn = spl_token::instruction::burn(&spl_token::ID, &ata, &mint, &vault, &[], 5).unwrap();
+    let index = fixture.propose(&burn).await;
+    fixture.execute(index, &burn).await.unwrap();
+    let spender = Pubkey::new_unique();
+    let delegate = spl_token::instruction::approve(&spl_token::ID, &ata, &spender, &vault, &[], 1).unwrap();
+    let index = fixture.propose(&delegate).await;
+    fixture.execute(index, &delegate).await.unwrap();
+    let result = fixture.send(&[fixture.close_empty_vault_ata_ix(&creator.pubkey(), &mint)], &[&creator]).await;
+    assert_error(result, MultisigWalletError::TokenAccountDelegated);
+    let revoke = spl_token::instruction::revoke(&spl_token::ID, &ata, &vault, &[]).unwrap();
+    let index = fixture.propose(&revoke).await;
+    fixture.execute(index, &revoke).await.unwrap();
+
+    // The rent goes back to the vault and the registry forgets the account
+    let rent = fixture.balance(&ata).await;
+    let vault_before = fixture.balance(&vault).await;
+    fixture
+        .send(&[fixture.close_empty_vault_ata_ix(&creator.pubkey(), &mint)], &[&creator])
+        .await
+        .unwrap();
+    assert!(fixture.context.banks_client.get_account(ata).await.unwrap().is_none());
+    assert_eq!(fixture.balance(&vault).await, vault_before + rent);
+    let recorded: TokenRegistry = fetch(&mut fixture.context, &registry).await;
+    assert!(recorded.accounts.is_empty());
+}
+
+fn receipt_pda(multisig: &Pubkey, index: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"receipt", multisig.as_ref(), &index.to_le_bytes()], &multisig_wallet::ID).0
+}
+
+impl Fixture {
+    fn close_executed_ix(&self, index: u64) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::CloseExecutedTransaction {
+                multisig: self.multisig,
+                transaction: transaction_pda(&self.multisig, index),
+                rent_payer: self.creator.pubkey(),
+                vault: self.vault,
+                receipt: receipt_pda(&self.multisig, index),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::CloseExecutedTransaction {}.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn execution_receipts_outlive_closed_transactions() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    fixture.initialize_program_config(ProgramLimits::DEFAULT).await;
+    let creator = fixture.creator.insecure_clone();
+    let (multisig, vault) = (fixture.multisig, fixture.vault);
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+
+    // Proposals made before receipts were asked for execute without one,
+    // and so stay open
+    let before = fixture.propose(&inner).await;
+    fixture.execute(before, &inner).await.unwrap();
+    let result = fixture.send(&[fixture.close_executed_ix(before)], &[]).await;
+    assert_error(result, MultisigWalletError::ReceiptMissing);
+
+    let admin = upgrade_authority();
+    let ix = update_program_config_ix(
+        &admin.pubkey(),
+        ProgramConfigUpdate { execution_receipts: Some(true), ..Default::default() },
+    );
+    fixture.send(&[ix], &[&admin]).await.unwrap();
+    let index = fixture.propose(&inner).await;
+    assert!(fixture.transaction_account(index).await.receipt_required);
+    let result = fixture.send(&[fixture.close_executed_ix(index)], &[]).await;
+    assert_error(result, MultisigWalletError::TransactionNotExecuted);
+
+    // The execution needs the receipt's address, and the vault pays its rent
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientAccounts);
+    let mut ix = fixture.execute_ix(index, &inner);
+    ix.accounts.push(AccountMeta::new(receipt_pda(&multisig, index), false));
+    let vault_before = fixture.balance(&vault).await;
+    fixture.send(&[ix], &[]).await.unwrap();
+    let rent = fixture.balance(&receipt_pda(&multisig, index)).await;
+    assert_eq!(fixture.balance(&vault).await, vault_before - 1_000 - rent);
+    let transaction = fixture.transaction_account(index).await;
+    let receipt: ExecutionReceipt = fetch(&mut fixture.context, &receipt_pda(&multisig, index)).await;
+    assert_eq!((receipt.multisig, receipt.tx_index), (multisig, index));
+    assert_eq!(receipt.transaction_hash, transaction.hash);
+    assert_eq!(receipt.executor, None);
+    assert_eq!((receipt.mint, receipt.amount), (None, 0));
+
+    // With the receipt kept, the transaction can go and its rent returns
+    let creator_before = fixture.balance(&creator.pubkey()).await;
+    let transaction_rent = fixture.balance(&transaction_pda(&multisig, index)).await;
+    fixture.send(&[fixture.close_executed_ix(index)], &[]).await.unwrap();
+    assert!(fixture.context.banks_client.get_account(transaction_pda(&multisig, index)).await.unwrap().is_none());
+    assert_eq!(fixture.balance(&creator.pubkey()).await, creator_before + transaction_rent);
+    let kept: ExecutionReceipt = fetch(&mut fixture.context, &receipt_pda(&multisig, index)).await;
+    assert_eq!(kept.transaction_hash, receipt.transaction_hash);
+
+    // Rent the vault reimbursed at execution goes back to the vault, so
+    // the rent payer isn't paid twice
+    fixture
+        .configure(ConfigAction::SetReimburseProposalRent { enabled: true })
+        .await
+        .unwrap();
+    let index = fixture.propose(&inner).await;
+    let mut ix = fixture.execute_ix(index, &inner);
+    ix.accounts.push(AccountMeta::new(receipt_pda(&multisig, index), false));
+    ix.accounts.push(AccountMeta::new(creator.pubkey(), false));
+    fixture.send(&[ix], &[]).await.unwrap();
+    assert!(fixture.transaction_account(index).await.rent_reimbursed);
+    let creator_before = fixture.balance(&creator.pubkey()).await;
+    let vault_before = fixture.balance(&vault).await;
+    let transaction_rent = fixture.balance(&transaction_pda(&multisig, index)).await;
+    fixture.send(&[fixture.close_executed_ix(index)], &[]).await.unwrap();
+    assert_eq!(fixture.balance(&creator.pubkey()).await, creator_before);
+    assert_eq!(fixture.balance(&vault).await, vault_before + transaction_rent);
+}
+
+#[tokio::test]
+async fn excluded_proposers_need_the_other_members() {
+    // Leaving the proposer out of a 2-of-2 would make it unreachable
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let result = fixture.configure(ConfigAction::SetProposerCounts { enabled: false }).await;
+    assert_error(result, MultisigWalletError::InvalidThreshold);
+
+    // Between two members, the other one's approval becomes a must
+    let mut fixture = Fixture::new(1, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let counted = fixture.propose(&inner).await;
+    fixture
+        .configure(ConfigAction::SetProposerCounts { enabled: false })
+        .await
+        .unwrap();
+    assert!(!fixture.multisig_account().await.proposer_counts());
+    let index = fixture.propose(&inner).await;
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.signers, vec![fixture.creator.pubkey()]);
+    assert_eq!(transaction.approval_count(), 0);
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+    fixture.approve(0, index).await.unwrap();
+    fixture.execute(index, &inner).await.unwrap();
+
+    // Proposals made before keep counting their proposer
+    fixture.execute(counted, &inner).await.unwrap();
+
+    // And the threshold can't be raised past the other members
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey()];
+    let ix = fixture.update_ix(&approvers, None, Some(2), ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::InvalidThreshold);
+}
+
+impl Fixture {
+    fn register_action_ix(&self, action: RegisteredAction, approvers: &[&Keypair]) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::RegisterExternalAction {
+            multisig: self.multisig,
+            registry: action_registry_pda(&self.multisig),
+            payer: self.context.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(approvers.iter().map(|approver| AccountMeta::new_readonly(approver.pubkey(), true)));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::RegisterExternalAction { action }.data(),
+        }
+    }
+
+    fn remove_action_ix(&self, tag: &str, approvers: &[&Keypair]) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::RemoveExternalAction {
+            multisig: self.multisig,
+            registry: action_registry_pda(&self.multisig),
+        }
+        .to_account_metas(None);
+        accounts.extend(approvers.iter().map(|approver| AccountMeta::new_readonly(approver.pubkey(), true)));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::RemoveExternalAction { tag: tag.to_string() }.data(),
+        }
+    }
+
+    fn propose_registered_ix(&self, index: u64, tag: &str, args: Vec<ActionArg>) -> Instruction {
+        let mut accounts = multisig_wallet::accounts::ProposeAction {
+            multisig: self.multisig,
+            transaction: transaction_pda(&self.multisig, index),
+            proposer: self.creator.pubkey(),
+            rent_payer: self.creator.pubkey(),
+            system_program: system_program::ID,
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new_readonly(action_registry_pda(&self.multisig), false));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ProposeRegisteredAction { tag: tag.to_string(), args, category: 0 }
+                .data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn rejections_cancel_without_counting_an_excluded_proposer() {
+    // Three members, with the proposer left out of a threshold of 2
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture
+        .configure(ConfigAction::SetProposerCounts { enabled: false })
+        .await
+        .unwrap();
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    // Once one of the other two rejects, the last can't reach 2 alone
+    let member = fixture.members[0].insecure_clone();
+    fixture.send(&[fixture.reject_ix(&member.pubkey(), index, "")], &[&member]).await.unwrap();
+    assert!(fixture.transaction_account(index).await.cancelled);
+}
+
+#[tokio::test]
+async fn registered_actions_execute_their_current_template() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let registry = action_registry_pda(&fixture.multisig);
+    // The Memo program fails unless every account it's given signed, so a
+    // vault slot only passes if the CPI is signed for the vault
+    let template = RegisteredAction {
+        tag: "memo-lock".to_string(),
+        program_id: multisig_wallet::spl_memo::ID,
+        discriminator: b"lock:".to_vec(),
+        args: vec![ArgKind::U8, ArgKind::U8],
+        accounts: vec![TemplateAccount { source: AccountSource::Vault, is_writable: false }],
+    };
+
+    // Registering takes every signer, and accounts only take pubkey arguments
+    let ix = fixture.register_action_ix(template.clone(), &[&creator]);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::NotAllSignersApproved);
+    let mut invalid = template.clone();
+    invalid.accounts.push(TemplateAccount { source: AccountSource::Arg(0), is_writable: false });
+    let ix = fixture.register_action_ix(invalid, &[&creator, &member]);
+    assert_error(fixture.send(&[ix], &[&creator, &member]).await, MultisigWalletError::InvalidActionTemplate);
+    let ix = fixture.register_action_ix(template.clone(), &[&creator, &member]);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let ix = fixture.register_action_ix(template.clone(), &[&creator, &member]);
+    assert_error(fixture.send(&[ix], &[&creator, &member]).await, MultisigWalletError::ActionAlreadyRegistered);
+
+    // Arguments must match the declared types one for one
+    let index = fixture.multisig_account().await.nonce;
+    let ix = fixture.propose_registered_ix(index, "memo-lock", vec![ActionArg::U8(b'4')]);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InvalidActionArgs);
+    let ix = fixture.propose_registered_ix(index, "memo-lock", vec![ActionArg::U8(b'4'), ActionArg::U64(2)]);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InvalidActionArgs);
+    let ix = fixture.propose_registered_ix(index, "memo-unlock", vec![]);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::ActionNotRegistered);
+
+    let args = vec![ActionArg::U8(b'4'), ActionArg::U8(b'2')];
+    let ix = fixture.propose_registered_ix(index, "memo-lock", args.clone());
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.kind, TransactionKind::TypedAction(ActionKind::Registered));
+    assert_eq!(
+        transaction.action,
+        Some(TypedAction::Registered {
+            tag: "memo-lock".to_string(),
+            program_id: multisig_wallet::spl_memo::ID,
+            args: args.clone(),
+            template_hash: template.hash(),
+        })
+    );
+    fixture.approve(0, index).await.unwrap();
+
+    let accounts = Instruction {
+        program_id: multisig_wallet::spl_memo::ID,
+        accounts: vec![AccountMeta::new_readonly(registry, false), AccountMeta::new_readonly(fixture.vault, false)],
+        data: vec![],
+    };
+    fixture.execute(index, &accounts).await.unwrap();
+    assert!(fixture.transaction_account(index).await.executed);
+
+    // Pending proposals of a removed action can't execute, and registering
+    // a different template under the same tag doesn't revive them
+    let index = fixture.multisig_account().await.nonce;
+    let ix = fixture.propose_registered_ix(index, "memo-lock", args);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    fixture.approve(0, index).await.unwrap();
+    let ix = fixture.remove_action_ix("memo-lock", &[&creator, &member]);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::ActionNotRegistered);
+
+    let mut changed = template;
+    changed.discriminator = b"unlock:".to_vec();
+    let ix = fixture.register_action_ix(changed, &[&creator, &member]);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let result = fixture.execute(index, &accounts).await;
+    assert_error(result, MultisigWalletError::RegisteredActionChanged);
+}
+
+impl Fixture {
+    fn stage_ix(&self, executor: &Pubkey, index: u64) -> Instruction {
+        let transaction = transaction_pda(&self.multisig, index);
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::StageExecution {
+                multisig: self.multisig,
+                transaction,
+                stage: stage_pda(&transaction),
+                executor: *executor,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::StageExecution { expected_hash: None }.data(),
+        }
+    }
+
+    fn complete_ix(&self, executor: &Pubkey, index: u64, inner: &Instruction) -> Instruction {
+        let transaction = transaction_pda(&self.multisig, index);
+        let mut accounts = multisig_wallet::accounts::CompleteExecution {
+            multisig: self.multisig,
+            transaction,
+            stage: stage_pda(&transaction),
+            executor: *executor,
+        }
+        .to_account_metas(None);
+        accounts.extend(inner.accounts.iter().map(|meta| AccountMeta { is_signer: false, ..meta.clone() }));
+        accounts.push(AccountMeta::new_readonly(inner.program_id, false));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::CompleteExecution {}.data(),
+        }
+    }
+
+    fn release_ix(&self, caller: &Pubkey, executor: &Pubkey, index: u64) -> Instruction {
+        let transaction = transaction_pda(&self.multisig, index);
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ReleaseStagedExecution {
+                multisig: self.multisig,
+                transaction,
+                stage: stage_pda(&transaction),
+                executor: *executor,
+                caller: *caller,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ReleaseStagedExecution {}.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn staged_executions_complete_once_or_are_released() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, 10_000_000);
+
+    let index = fixture.propose(&inner).await;
+    let ix = fixture.stage_ix(&creator.pubkey(), index);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::InsufficientApprovals);
+    fixture.approve(0, index).await.unwrap();
+    let ix = fixture.stage_ix(&creator.pubkey(), index);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert!(fixture.transaction_account(index).await.staged);
+
+    // Locked for the stager's completion
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::ExecutionStaged);
+    let ix = fixture.release_ix(&member.pubkey(), &creator.pubkey(), index);
+    assert_error(fixture.send(&[ix], &[&member]).await, MultisigWalletError::StagingNotExpired);
+
+    let ix = fixture.complete_ix(&creator.pubkey(), index, &inner);
+    fixture.send(&[ix.clone()], &[&creator]).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, 10_000_000);
+    let transaction = fixture.transaction_account(index).await;
+    assert!(transaction.executed && !transaction.staged);
+    let stage = stage_pda(&transaction_pda(&fixture.multisig, index));
+    assert!(fixture.context.banks_client.get_account(stage).await.unwrap().is_none());
+
+    // The stage is gone, so the same completion can't run twice
+    assert!(fixture.send(&[ix], &[&creator]).await.is_err());
+    assert_eq!(fixture.balance(&recipient).await, 10_000_000);
+
+    // A stage left uncompleted can be released by anyone after the timeout
+    let index = fixture.propose(&inner).await;
+    fixture.approve(0, index).await.unwrap();
+    let ix = fixture.stage_ix(&creator.pubkey(), index);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += STAGING_TIMEOUT;
+    fixture.context.set_sysvar(&clock);
+    let ix = fixture.release_ix(&member.pubkey(), &creator.pubkey(), index);
+    fixture.send(&[ix], &[&member]).await.unwrap();
+    assert!(!fixture.transaction_account(index).await.staged);
+    fixture.execute(index, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, 20_000_000);
+
+    // Staged accounts lead the completion's, and must be the ones staged
+    let index = fixture.propose(&inner).await;
+    fixture.approve(0, index).await.unwrap();
+    let mut ix = fixture.stage_ix(&creator.pubkey(), index);
+    ix.accounts.push(AccountMeta::new_readonly(fixture.vault, false));
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let mut ix = fixture.complete_ix(&creator.pubkey(), index, &inner);
+    ix.accounts.swap(4, 5);
+    assert_error(fixture.send(&[ix], &[&creator]).await, MultisigWalletError::StagingHashMismatch);
+    let ix = fixture.complete_ix(&creator.pubkey(), index, &inner);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, 30_000_000);
+}
+
+/// A small xorshift generator, so the randomized tests replay the same way.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+#[test]
+fn open_window_tracks_closed_proposals_against_a_model() {
+    let mut furthest = 0;
+    for seed in 1..=20u64 {
+        let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut window = OpenProposalWindow::default();
+        let mut nonce = 0u64;
+        // Which proposals closed, and which of those the window was told about
+        let mut closed: Vec<bool> = vec![];
+        let mut known: Vec<bool> = vec![];
+        let mut first = 0u64;
+
+        for _ in 0..400 {
+            if nonce == 0 || rng.below(3) == 0 {
+                nonce += 1;
+                closed.push(false);
+                known.push(false);
+            } else {
+                // Mostly close proposals near the front of the window, some
+                // of them beyond its reach
+                let index = if rng.below(4) == 0 {
+                    rng.below(nonce as usize) as u64
+                } else {
+                    first + rng.below((nonce - first).clamp(1, 80) as usize) as u64
+                };
+                let index = index.min(nonce - 1);
+                closed[index as usize] = true;
+                if index >= first && index - first < OpenProposalWindow::WIDTH {
+                    known[index as usize] = true;
+                }
+                window.note_closed(index, nonce);
+                while first < nonce && known[first as usize] {
+                    first += 1;
+                }
+            }
+
+            assert_eq!(window.first_open_index, first);
+            for index in 0..nonce + 2 {
+                let expected = index >= first
+                    && index < nonce
+                    && (index - first >= OpenProposalWindow::WIDTH || !known[index as usize]);
+                assert_eq!(window.may_be_open(index, nonce), expected);
+                // The window never hides a proposal that's still open
+                if index < nonce && !closed[index as usize] {
+                    assert!(window.may_be_open(index, nonce));
+                }
+            }
+        }
+        furthest = furthest.max(first);
+    }
+    // Some runs went well past the width of the window
+    assert!(furthest > OpenProposalWindow::WIDTH);
+}
+
+impl Fixture {
+    fn sync_open_window_ix(&self, indices: std::ops::Range<u64>) -> Instruction {
+        let mut accounts =
+            multisig_wallet::accounts::UpdateMultisig { multisig: self.multisig }.to_account_metas(None);
+        for index in indices {
+            accounts.push(AccountMeta::new_readonly(transaction_pda(&self.multisig, index), false));
+            accounts.push(AccountMeta::new_readonly(reservation_pda(&self.multisig, index), false));
+        }
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::SyncOpenWindow {}.data(),
+        }
+    }
+
+    /// Checks the multisig's open-proposal window against which of its
+    /// proposals are still open.
+    async fn assert_open_window(&mut self, open: &[bool]) {
+        let multisig = self.multisig_account().await;
+        let first_open = open.iter().position(|open| *open).unwrap_or(open.len()) as u64;
+        assert_eq!(multisig.open_window.first_open_index, first_open);
+        for (index, open) in open.iter().enumerate() {
+            assert_eq!(multisig.open_window.may_be_open(index as u64, multisig.nonce), *open, "index {index}");
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ProposalState {
+    Pending,
+    Reserved,
+    Cancelled,
+    Closed,
+}
+
+#[tokio::test]
+async fn open_window_follows_random_proposal_lifecycles() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), LAMPORTS_PER_SOL / 100);
+    let members: Vec<Keypair> = fixture.members.iter().map(Keypair::insecure_clone).collect();
+    let payer = fixture.context.payer.pubkey();
+    let mut rng = XorShift(0x5EED_0F_0BE4);
+    let mut states: Vec<ProposalState> = vec![];
+
+    for _ in 0..30 {
+        let pick = |rng: &mut XorShift, states: &[ProposalState], wanted: ProposalState| {
+            let matching: Vec<usize> = (0..states.len()).filter(|index| states[*index] == wanted).collect();
+            (!matching.is_empty()).then(|| matching[rng.below(matching.len())] as u64)
+        };
+        match rng.below(6) {
+            0 if states.len() < 12 => {
+                assert_eq!(fixture.propose(&inner).await, states.len() as u64);
+                states.push(ProposalState::Pending);
+            }
+            1 if states.len() < 12 => {
+                let ix = fixture.reserve_nonce_ix(&members[0].pubkey(), states.len() as u64);
+                fixture.send(&[ix], &[&members[0]]).await.unwrap();
+                states.push(ProposalState::Reserved);
+            }
+            2 => {
+                let Some(index) = pick(&mut rng, &states, ProposalState::Pending) else { continue };
+                fixture.approve(0, index).await.unwrap();
+                fixture.execute(index, &inner).await.unwrap();
+                states[index as usize] = ProposalState::Closed;
+            }
+            3 => {
+                let Some(index) = pick(&mut rng, &states, ProposalState::Pending) else { continue };
+                for member in &members {
+                    let ix = fixture.reject_ix(&member.pubkey(), index, "");
+                    fixture.send(&[ix], &[member]).await.unwrap();
+                }
+                assert!(fixture.transaction_account(index).await.cancelled);
+                states[index as usize] = ProposalState::Cancelled;
+            }
+            4 => {
+                let Some(index) = pick(&mut rng, &states, ProposalState::Cancelled) else { continue };
+                let ix = Instruction {
+                    program_id: multisig_wallet::ID,
+                    accounts: multisig_wallet::accounts::CloseExpiredTransaction {
+                        multisig: fixture.multisig,
+                        transaction: transaction_pda(&fixture.multisig, index),
+                        rent_payer: fixture.creator.pubkey(),
+                        vault: fixture.vault,
+                        cranker: payer,
+                        system_program: system_program::ID,
+                    }
+                    .to_account_metas(None),
+                    data: multisig_wallet::instruction::CloseExpiredTransaction {}.data(),
+                };
+                fixture.send(&[ix], &[]).await.unwrap();
+                states[index as usize] = ProposalState::Closed;
+            }
+            _ => {
+                let Some(index) = pick(&mut rng, &states, ProposalState::Reserved) else { continue };
+                let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+                clock.unix_timestamp += NONCE_RESERVATION_TTL + 1;
+                fixture.context.set_sysvar(&clock);
+                let ix = fixture.close_expired_reservation_ix(&members[0].pubkey(), index);
+                fixture.send(&[ix], &[]).await.unwrap();
+                states[index as usize] = ProposalState::Closed;
+            }
+        }
+
+        let open: Vec<bool> =
+            states.iter().map(|state| matches!(state, ProposalState::Pending | ProposalState::Reserved)).collect();
+        fixture.assert_open_window(&open).await;
+    }
+
+    // A multisig that predates the window reads it as all zeros; syncing
+    // rebuilds it from the proposal and reservation accounts
+    let open: Vec<bool> =
+        states.iter().map(|state| matches!(state, ProposalState::Pending | ProposalState::Reserved)).collect();
+    let mut account = fixture.context.banks_client.get_account(fixture.multisig).await.unwrap().unwrap();
+    let mut multisig = MultisigAccount::try_deserialize(&mut account.data.as_slice()).unwrap();
+    multisig.open_window = OpenProposalWindow::default();
+    multisig.try_serialize(&mut account.data.as_mut_slice()).unwrap();
+    fixture.context.set_account(&fixture.multisig, &account.into());
+    assert_eq!(fixture.multisig_account().await.open_window, OpenProposalWindow::default());
+
+    let nonce = states.len() as u64;
+    let mut ix = fixture.sync_open_window_ix(0..nonce);
+    ix.accounts.swap(1, 2);
+    let result = fixture.send(&[ix], &[]).await;
+    assert_error(result, MultisigWalletError::InvalidTransactionAccount);
+    let mut ix = fixture.sync_open_window_ix(0..nonce);
+    ix.accounts.pop();
+    let result = fixture.send(&[ix], &[]).await;
+    assert_error(result, MultisigWalletError::InsufficientAccounts);
+
+    let ix = fixture.sync_open_window_ix(0..nonce);
+    fixture.send(&[ix], &[]).await.unwrap();
+    fixture.assert_open_window(&open).await;
+}
+
+#[tokio::test]
+async fn expected_hash_must_match_proposal() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    let transaction = fixture.transaction_account(index).await;
+    assert_eq!(transaction.hash, transaction.compute_hash());
+
+    let member = fixture.members[1].insecure_clone();
+    let mut approve = fixture.approve_ix(&member.pubkey(), index);
+    approve.data = multisig_wallet::instruction::ApproveTransaction { expected_hash: Some([7; 32]) }.data();
+    let result = fixture.send(&[approve.clone()], &[&member]).await;
+    assert_error(result, MultisigWalletError::HashMismatch);
+
+    approve.data =
+        multisig_wallet::instruction::ApproveTransaction { expected_hash: Some(transaction.hash) }.data();
+    fixture.send(&[approve], &[&member]).await.unwrap();
+
+    let mut execute = fixture.execute_ix(index, &inner);
+    execute.data = multisig_wallet::instruction::ExecuteTransaction { expected_hash: Some([7; 32]) }.data();
+    let result = fixture.send(&[execute.clone()], &[]).await;
+    assert_error(result, MultisigWalletError::HashMismatch);
+
+    execute.data =
+        multisig_wallet::instruction::ExecuteTransaction { expected_hash: Some(transaction.hash) }.data();
+    fixture.send(&[execute], &[]).await.unwrap();
+}
+
+impl Fixture {
+    /// Creates a multisig of the creator and members that must be accepted by
+    /// `required_acceptances` of them before `deadline`, and selects it.
+    async fn create_pending_multisig(&mut self, required_acceptances: u8, deadline: i64) {
+        let create_key = Keypair::new();
+        let multisig = multisig_pda(&create_key.pubkey());
+        let mut signers = vec![self.creator.pubkey()];
+        signers.extend(self.members.iter().map(|member| member.pubkey()));
+        let mut ix = self.initialize_ix(signers.clone(), 1, None);
+        ix.accounts[0].pubkey = multisig;
+        ix.accounts[1].pubkey = create_key.pubkey();
+        ix.accounts[5].pubkey = generation_pda(&create_key.pubkey());
+        ix.data = multisig_wallet::instruction::InitializeMultisig {
+            initial_signers: signers,
+            threshold: 1,
+            expiration_timestamp: None,
+            activation: Some(Activation { required_acceptances, deadline }),
+        }
+        .data();
+        let creator = self.creator.insecure_clone();
+        self.send(&[ix], &[&creator, &create_key]).await.unwrap();
+        self.select(multisig);
+    }
+
+    fn accept_initial_membership_ix(&self, member: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::AcceptInitialMembership {
+                multisig: self.multisig,
+                member: *member,
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::AcceptInitialMembership {}.data(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn pending_multisig_activates_after_acceptances() {
+    let mut fixture = Fixture::new(2, 1, None).await;
+    let clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    fixture.create_pending_multisig(2, clock.unix_timestamp + 100).await;
+    assert_eq!(fixture.multisig_account().await.status, MultisigStatus::Pending);
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let creator = fixture.creator.insecure_clone();
+    let propose = fixture.propose_ix(&creator.pubkey(), 0, &inner);
+    let result = fixture.send(&[propose.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::MultisigNotActive);
+
+    let outsider = Keypair::new();
+    let accept = fixture.accept_initial_membership_ix(&outsider.pubkey());
+    let result = fixture.send(&[accept], &[&outsider]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+
+    for member in [fixture.members[0].insecure_clone(), fixture.members[1].insecure_clone()] {
+        let accept = fixture.accept_initial_membership_ix(&member.pubkey());
+        fixture.send(&[accept], &[&member]).await.unwrap();
+    }
+    let multisig = fixture.multisig_account().await;
+    assert_eq!(multisig.status, MultisigStatus::Active);
+    assert_eq!(multisig.accepted_members.len(), 2);
+
+    fixture.send(&[propose], &[&creator]).await.unwrap();
+}
+
+#[tokio::test]
+async fn creator_closes_unaccepted_multisig_after_deadline() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    let deadline = clock.unix_timestamp + 100;
+    fixture.create_pending_multisig(2, deadline).await;
+
+    let creator = fixture.creator.insecure_clone();
+    let close = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ClosePendingMultisig {
+            multisig: fixture.multisig,
+            creator: creator.pubkey(),
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ClosePendingMultisig {}.data(),
+    };
+    let result = fixture.send(&[close.clone()], &[&creator]).await;
+    assert_error(result, MultisigWalletError::ActivationDeadlineNotReached);
+
+    clock.unix_timestamp = deadline + 1;
+    fixture.context.set_sysvar(&clock);
+    let member = fixture.members[0].insecure_clone();
+    let accept = fixture.accept_initial_membership_ix(&member.pubkey());
+    let result = fixture.send(&[accept], &[&member]).await;
+    assert_error(result, MultisigWalletError::ActivationDeadlinePassed);
+
+    let before = fixture.balance(&creator.pubkey()).await;
+    fixture.send(&[close], &[&creator]).await.unwrap();
+    assert!(fixture.balance(&creator.pubkey()).await > before);
+    let multisig = fixture.multisig;
+    assert!(fixture.context.banks_client.get_account(multisig).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn explain_transaction_returns_decoded_proposal() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let recipient = Pubkey::new_unique();
+    let typed = fixture.propose_transfer(recipient, 12_500_000_000, false, 0).await;
+    let inner = fixture.vault_transfer(&recipient, 1);
+    let opaque = fixture.propose(&inner).await;
+    let action = ConfigAction::SetProposalCooldown { seconds: 60 };
+    let config = fixture.propose(&config_change(action.clone())).await;
+
+    for (index, expected) in [
+        (typed, Explanation::Action(TypedAction::Transfer { recipient, lamports: 12_500_000_000, escrow: false })),
+        (
+            opaque,
+            Explanation::Opaque {
+                program_id: inner.program_id,
+                data_hash: hash(&inner.data).to_bytes(),
+                compute: None,
+            },
+        ),
+        (config, Explanation::Config(action)),
+    ] {
+        let ix = Instruction {
+            program_id: multisig_wallet::ID,
+            accounts: multisig_wallet::accounts::ExplainTransaction {
+                multisig: fixture.multisig,
+                transaction: transaction_pda(&fixture.multisig, index),
+            }
+            .to_account_metas(None),
+            data: multisig_wallet::instruction::ExplainTransaction {}.data(),
+        };
+        let blockhash = fixture.context.get_new_latest_blockhash().await.unwrap();
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&fixture.context.payer.pubkey()),
+            &[&fixture.context.payer],
+            blockhash,
+        );
+        let result = fixture.context.banks_client.simulate_transaction(tx).await.unwrap();
+        let return_data = result.simulation_details.unwrap().return_data.unwrap();
+        assert_eq!(return_data.program_id, multisig_wallet::ID);
+        assert_eq!(Explanation::try_from_slice(&return_data.data).unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn get_pdas_returns_stored_bumps() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    fixture.propose(&inner).await;
+
+    let ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::GetPdas { multisig: fixture.multisig }.to_account_metas(None),
+        data: multisig_wallet::instruction::GetPdas {}.data(),
+    };
+    let blockhash = fixture.context.get_new_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&fixture.context.payer.pubkey()),
+        &[&fixture.context.payer],
+        blockhash,
+    );
+    let result = fixture.context.banks_client.simulate_transaction(tx).await.unwrap();
+    let return_data = result.simulation_details.unwrap().return_data.unwrap();
+    let pdas = Pdas::try_from_slice(&return_data.data).unwrap();
+
+    let multisig = fixture.multisig_account().await;
+    let (vault, vault_bump) =
+        Pubkey::find_program_address(&[b"vault", fixture.multisig.as_ref()], &multisig_wallet::ID);
+    assert_eq!(multisig.vault_bump, vault_bump);
+    assert_eq!(pdas.multisig, Pda { address: fixture.multisig, bump: multisig.bump });
+    assert_eq!(pdas.vault, Pda { address: vault, bump: vault_bump });
+    assert_eq!(pdas.next_tx_index, 1);
+    assert_eq!(pdas.next_transaction.address, transaction_pda(&fixture.multisig, 1));
+}
+
+#[tokio::test]
+async fn priority_level_sets_threshold_and_timelock() {
+    let mut fixture = Fixture::new(2, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+
+    // A level can't ask for fewer approvals than the base threshold
+    let result = fixture
+        .configure(ConfigAction::SetPriorityLevel { priority: 1, timelock_seconds: 0, required_threshold: 0 })
+        .await;
+    assert_error(result, MultisigWalletError::InvalidPriorityLevel);
+    fixture
+        .configure(ConfigAction::SetPriorityLevel { priority: 1, timelock_seconds: 3600, required_threshold: 2 })
+        .await
+        .unwrap();
+
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.multisig_account().await.nonce;
+    let creator = fixture.creator.insecure_clone();
+    let mut ix = fixture.propose_ix(&creator.pubkey(), index, &inner);
+    ix.data = multisig_wallet::instruction::ProposeTransaction {
+        program_id: inner.program_id,
+        accounts: serialize_account_metas(&inner.accounts),
+        instruction_data: inner.data.clone(),
+        options: ProposalOptions { priority: 1, ..Default::default() },
+    }
+    .data();
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert_eq!(fixture.transaction_account(index).await.priority, 1);
+
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+
+    fixture.approve(0, index).await.unwrap();
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::ExecutionTimelockActive);
+
+    let approved_at = fixture.transaction_account(index).await.approved_at.unwrap();
+    let mut clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = approved_at + 3600;
+    fixture.context.set_sysvar(&clock);
+    fixture.execute(index, &inner).await.unwrap();
+}
+
+fn multisig_v2_pda(create_key: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"multisig_v2", create_key.as_ref()], &multisig_wallet::ID).0
+}
+
+async fn simulate_units(context: &mut ProgramTestContext, ixs: &[Instruction], signers: &[&Keypair]) -> u64 {
+    let blockhash = context.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers: Vec<&Keypair> = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(ixs, Some(&context.payer.pubkey()), &all_signers, blockhash);
+    let result = context.banks_client.simulate_transaction(tx).await.unwrap();
+    result.result.unwrap().unwrap();
+    result.simulation_details.expect("simulation details").units_consumed
+}
+
+#[tokio::test]
+async fn zero_copy_multisig_approves_for_fewer_compute_units() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+    let member = fixture.members[0].insecure_clone();
+    let approve = fixture.approve_ix(&member.pubkey(), index);
+    let v1_units = simulate_units(&mut fixture.context, &[approve], &[&member]).await;
+
+    // The same members in a zero-copy multisig
+    let creator = fixture.creator.insecure_clone();
+    let create_key = Keypair::new();
+    let multisig = multisig_v2_pda(&create_key.pubkey());
+    let vault = vault_pda(&multisig);
+    let mut members = vec![creator.pubkey()];
+    members.extend(fixture.members.iter().map(|member| member.pubkey()));
+    let initialize = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::InitializeMultisigV2 {
+            multisig,
+            create_key: create_key.pubkey(),
+            payer: creator.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::InitializeMultisigV2 { members: members.clone(), threshold: 2 }.data(),
+    };
+    fixture.send(&[initialize], &[&creator, &create_key]).await.unwrap();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &vault, LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let inner = system_instruction::transfer(&vault, &recipient, LAMPORTS_PER_SOL / 2);
+    let transaction = transaction_pda(&multisig, 0);
+    let propose = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ProposeTransactionV2 {
+            multisig,
+            transaction,
+            proposer: creator.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ProposeTransactionV2 {
+            tx_index: 0,
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+        }
+        .data(),
+    };
+    fixture.send(&[propose], &[&creator]).await.unwrap();
+    let proposal: TransactionAccount = fetch(&mut fixture.context, &transaction).await;
+    assert_eq!(proposal.eligible_approvers, members);
+    assert_eq!((proposal.required_approvals, proposal.generation, proposal.expires_at), (2, 0, None));
+    assert_eq!(proposal.approved_at, None);
+
+    let approve = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ApproveTransactionV2 { multisig, transaction, signer: member.pubkey() }
+            .to_account_metas(None),
+        data: multisig_wallet::instruction::ApproveTransactionV2 {}.data(),
+    };
+    let v2_units = simulate_units(&mut fixture.context, &[approve.clone()], &[&member]).await;
+    assert!(v2_units < v1_units, "zero-copy approve used {v2_units} units, Borsh approve {v1_units}");
+
+    // It emits the v1 events: TransactionApproved, then ThresholdReached
+    // on reaching the threshold
+    let events = |logs: Vec<String>| logs.iter().filter(|log| log.starts_with("Program data: ")).count();
+    let logs = simulate_logs(&mut fixture.context, &[approve.clone()], &[&member]).await;
+    assert_eq!(events(logs), 2);
+    fixture.send(&[approve], &[&member]).await.unwrap();
+
+    let mut accounts = multisig_wallet::accounts::ExecuteTransactionV2 { multisig, transaction }.to_account_metas(None);
+    accounts.extend(inner.accounts.iter().map(|meta| AccountMeta {
+        pubkey: meta.pubkey,
+        is_signer: false,
+        is_writable: meta.is_writable,
+    }));
+    accounts.push(AccountMeta::new_readonly(system_program::ID, false));
+    let execute = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts,
+        data: multisig_wallet::instruction::ExecuteTransactionV2 {}.data(),
+    };
+    let logs = simulate_logs(&mut fixture.context, &[execute.clone()], &[]).await;
+    assert_eq!(events(logs), 1);
+    fixture.send(&[execute], &[]).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 2);
+
+    // The v1 status checks apply: an executed proposal takes no more votes
+    let other = fixture.members[1].insecure_clone();
+    let mut approve = approve;
+    approve.accounts[2].pubkey = other.pubkey();
+    let result = fixture.send(&[approve], &[&other]).await;
+    assert_error(result, MultisigWalletError::TransactionAlreadyExecuted);
+
+    // Nor may a proposal hand one of the multisig's proposals to a CPI
+    let propose = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ProposeTransactionV2 {
+            multisig,
+            transaction: transaction_pda(&multisig, 1),
+            proposer: creator.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ProposeTransactionV2 {
+            tx_index: 1,
+            program_id: Pubkey::new_unique(),
+            accounts: serialize_account_metas(&[AccountMeta::new(transaction, false)]),
+            instruction_data: vec![],
+        }
+        .data(),
+    };
+    let result = fixture.send(&[propose], &[&creator]).await;
+    assert_error(result, MultisigWalletError::SelfReferentialProposal);
+}
+
+#[tokio::test]
+async fn zero_copy_executions_keep_the_vault_rent_exempt() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let create_key = Keypair::new();
+    let multisig = multisig_v2_pda(&create_key.pubkey());
+    let vault = vault_pda(&multisig);
+    let initialize = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::InitializeMultisigV2 {
+            multisig,
+            create_key: create_key.pubkey(),
+            payer: creator.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::InitializeMultisigV2 { members: vec![creator.pubkey()], threshold: 1 }
+            .data(),
+    };
+    fixture.send(&[initialize], &[&creator, &create_key]).await.unwrap();
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &vault, LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let propose = |tx_index: u64, inner: &Instruction| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::ProposeTransactionV2 {
+            multisig,
+            transaction: transaction_pda(&multisig, tx_index),
+            proposer: creator.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::ProposeTransactionV2 {
+            tx_index,
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+        }
+        .data(),
+    };
+    let execute = |tx_index: u64, inner: &Instruction| {
+        let transaction = transaction_pda(&multisig, tx_index);
+        let mut accounts =
+            multisig_wallet::accounts::ExecuteTransactionV2 { multisig, transaction }.to_account_metas(None);
+        accounts.extend(inner.accounts.iter().map(|meta| AccountMeta {
+            pubkey: meta.pubkey,
+            is_signer: false,
+            is_writable: meta.is_writable,
+        }));
+        accounts.push(AccountMeta::new_readonly(system_program::ID, false));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ExecuteTransactionV2 {}.data(),
+        }
+    };
+
+    // The vault may be left with exactly the rent-exempt minimum, and not a
+    // lamport less
+    let minimum = fixture.context.banks_client.get_rent().await.unwrap().minimum_balance(0);
+    let spendable = fixture.balance(&vault).await - minimum;
+    let inner = system_instruction::transfer(&vault, &recipient, spendable + 1);
+    fixture.send(&[propose(0, &inner)], &[&creator]).await.unwrap();
+    let result = fixture.send(&[execute(0, &inner)], &[]).await;
+    assert_error(result, MultisigWalletError::WouldBreakRentExemption);
+
+    let inner = system_instruction::transfer(&vault, &recipient, spendable);
+    fixture.send(&[propose(1, &inner)], &[&creator]).await.unwrap();
+    fixture.send(&[execute(1, &inner)], &[]).await.unwrap();
+    assert_eq!(fixture.balance(&vault).await, minimum);
+}
+
+#[tokio::test]
+async fn update_keeps_expiration_and_rejects_no_ops() {
+    let mut fixture = Fixture::new(1, 1, Some(3600)).await;
+    let expiration = fixture.multisig_account().await.expiration_timestamp.unwrap();
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey()];
+
+    // An unrelated threshold change leaves the expiration alone
+    let ix = fixture.update_ix(&approvers, None, Some(2), ExpirationUpdate::Keep);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let multisig = fixture.multisig_account().await;
+    assert_eq!(multisig.threshold, 2);
+    assert_eq!(multisig.expiration_timestamp, Some(expiration));
+
+    // Nothing to change, or only the values already stored
+    let ix = fixture.update_ix(&approvers, None, None, ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::NoOpUpdate);
+    let ix = fixture.update_ix(
+        &approvers,
+        Some(approvers.to_vec()),
+        Some(2),
+        ExpirationUpdate::Set { timestamp: expiration },
+    );
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::NoOpUpdate);
+
+    let ix = fixture.update_ix(&approvers, None, None, ExpirationUpdate::Clear);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    assert_eq!(fixture.multisig_account().await.expiration_timestamp, None);
+}
+
+#[tokio::test]
+async fn update_rechecks_kept_threshold_against_new_signers() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey()];
+
+    // Dropping a signer while keeping a threshold of 2, whether passed again
+    // or left out, would leave it unreachable
+    for threshold in [Some(2), None] {
+        let ix = fixture.update_ix(&approvers, Some(vec![creator.pubkey()]), threshold, ExpirationUpdate::Keep);
+        let result = fixture.send(&[ix], &[&creator, &member]).await;
+        assert_error(result, MultisigWalletError::InvalidThreshold);
+    }
+
+    let ix = fixture.update_ix(&approvers, Some(vec![creator.pubkey()]), Some(1), ExpirationUpdate::Keep);
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+    let multisig = fixture.multisig_account().await;
+    assert_eq!((multisig.signers, multisig.threshold), (vec![creator.pubkey()], 1));
+}
+
+#[tokio::test]
+async fn replacement_signer_sets_are_checked_like_initial_ones() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey()];
+    let duplicated = vec![creator.pubkey(), creator.pubkey(), member.pubkey()];
+
+    // A key listed twice would let a threshold of 3 pass with two members
+    let ix = fixture.update_ix(&approvers, Some(duplicated.clone()), Some(3), ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::AlreadyMember);
+    let ix = fixture.update_ix(&approvers, Some(vec![creator.pubkey(), fixture.vault]), None, ExpirationUpdate::Keep);
+    let result = fixture.send(&[ix], &[&creator, &member]).await;
+    assert_error(result, MultisigWalletError::SelfSignerKey);
+
+    let ix = fixture.propose_member_swap_ix(&creator.pubkey(), duplicated, 3);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::AlreadyMember);
+    let ix = fixture.propose_member_swap_ix(&creator.pubkey(), vec![creator.pubkey(), Pubkey::default()], 1);
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::DefaultSignerKey);
+}
+
+#[tokio::test]
+async fn expiration_must_be_in_the_future_and_within_horizon() {
+    let mut fixture = Fixture::new(1, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let approvers = [creator.pubkey(), member.pubkey()];
+    let now = fixture.context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp;
+
+    for timestamp in [now - 1000, now, now + MAX_EXPIRATION_HORIZON + 1000] {
+        let ix = fixture.update_ix(&approvers, None, None, ExpirationUpdate::Set { timestamp });
+        let result = fixture.send(&[ix], &[&creator, &member]).await;
+        assert_error(result, MultisigWalletError::InvalidExpiration);
+    }
+
+    let timestamp = now + MAX_EXPIRATION_HORIZON - 1000;
+    let ix = fixture.update_ix(&approvers, None, None, ExpirationUpdate::Set { timestamp });
+    fixture.send(&[ix], &[&creator, &member]).await.unwrap();
+
+    // The same bounds apply at creation
+    let create_key = Keypair::new();
+    let mut ix = fixture.initialize_ix(approvers.to_vec(), 1, Some(now - 1));
+    ix.accounts[0].pubkey = multisig_pda(&create_key.pubkey());
+    ix.accounts[1].pubkey = create_key.pubkey();
+    ix.accounts[5].pubkey = generation_pda(&create_key.pubkey());
+    let result = fixture.send(&[ix], &[&creator, &create_key]).await;
+    assert_error(result, MultisigWalletError::InvalidExpiration);
+}
+
+impl Fixture {
+    fn propose_as_ix(&self, index: u64, inner: &Instruction, authority_kind: AuthorityKind) -> Instruction {
+        let mut ix = self.propose_ix(&self.creator.pubkey(), index, inner);
+        ix.data = multisig_wallet::instruction::ProposeTransaction {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            options: ProposalOptions { authority_kind, ..Default::default() },
+        }
+        .data();
+        ix
+    }
+}
+
+#[tokio::test]
+async fn proposal_signs_with_its_authority_kind() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let creator = fixture.creator.insecure_clone();
+
+    // The instruction expects the vault, but the proposal signs as the multisig
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.multisig_account().await.nonce;
+    let ix = fixture.propose_as_ix(index, &inner, AuthorityKind::Multisig);
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InvalidAccountMetas);
+
+    // An indexed vault signs with its own seeds
+    let indexed = Pubkey::find_program_address(
+        &[b"vault", fixture.multisig.as_ref(), &1u16.to_le_bytes()],
+        &multisig_wallet::ID,
+    )
+    .0;
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &indexed, LAMPORTS_PER_SOL);
+    fixture.send(&[fund], &[]).await.unwrap();
+    let recipient = Pubkey::new_unique();
+    let inner = system_instruction::transfer(&indexed, &recipient, LAMPORTS_PER_SOL / 2);
+    let index = fixture.multisig_account().await.nonce;
+    let ix = fixture.propose_as_ix(index, &inner, AuthorityKind::Vault(1));
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    fixture.execute(index, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 2);
+}
+
+#[tokio::test]
+async fn approvers_are_frozen_at_propose_time() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+
+    // Swap the second member for a newcomer after the proposal
+    let creator = fixture.creator.insecure_clone();
+    let member = fixture.members[0].insecure_clone();
+    let removed = fixture.members[1].insecure_clone();
+    let newcomer = Keypair::new();
+    let approvers = [creator.pubkey(), member.pubkey(), removed.pubkey()];
+    let ix = fixture.update_ix(
+        &approvers,
+        Some(vec![creator.pubkey(), member.pubkey(), newcomer.pubkey()]),
+        None,
+        ExpirationUpdate::Keep,
+    );
+    fixture.send(&[ix], &[&creator, &member, &removed]).await.unwrap();
+
+    // The member added after the proposal can't approve it
+    let ix = fixture.approve_ix(&newcomer.pubkey(), index);
+    let result = fixture.send(&[ix], &[&newcomer]).await;
+    assert_error(result, MultisigWalletError::SignerNotFound);
+
+    // The removed member still could, but execution only counts live members
+    let ix = fixture.approve_ix(&removed.pubkey(), index);
+    fixture.send(&[ix], &[&removed]).await.unwrap();
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+
+    fixture.approve(0, index).await.unwrap();
+    fixture.execute(index, &inner).await.unwrap();
+}
+
+fn vote_escrow_pda(transaction: &Pubkey, voter: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vote_escrow", transaction.as_ref(), voter.as_ref()], &multisig_wallet::ID).0
+}
+
+async fn token_balance(context: &mut ProgramTestContext, key: &Pubkey) -> u64 {
+    let account = context.banks_client.get_account(*key).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap().amount
+}
+
+#[tokio::test]
+async fn token_vote_approves_and_unlocks_after_execution() {
+    let mut fixture = Fixture::new(2, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+
+    // A governance mint and a funded holder outside the multisig
+    let mint = Keypair::new();
+    let source = Keypair::new();
+    let voter = Keypair::new();
+    let payer = fixture.context.payer.pubkey();
+    let rent = fixture.context.banks_client.get_rent().await.unwrap();
+    let ixs = [
+        system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_mint2(&spl_token::ID, &mint.pubkey(), &payer, None, 0).unwrap(),
+        system_instruction::create_account(
+            &payer,
+            &source.pubkey(),
+            rent.minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_account3(&spl_token::ID, &source.pubkey(), &mint.pubkey(), &voter.pubkey())
+            .unwrap(),
+        spl_token::instruction::mint_to(&spl_token::ID, &mint.pubkey(), &source.pubkey(), &payer, &[], 100).unwrap(),
+        system_instruction::transfer(&payer, &voter.pubkey(), LAMPORTS_PER_SOL),
+    ];
+    fixture.send(&ixs, &[&mint, &source]).await.unwrap();
+
+    let token_vote = TokenVote { mint: mint.pubkey(), required_amount: 60, vote_window_seconds: 3600 };
+    let invalid = TokenVote { required_amount: 0, ..token_vote.clone() };
+    let result = fixture.configure(ConfigAction::SetTokenVote { token_vote: Some(invalid) }).await;
+    assert_error(result, MultisigWalletError::InvalidTokenVote);
+    fixture.configure(ConfigAction::SetTokenVote { token_vote: Some(token_vote) }).await.unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 2);
+    let index = fixture.propose(&inner).await;
+    assert!(fixture.transaction_account(index).await.vote_ends_at.is_some());
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+
+    let transaction = transaction_pda(&fixture.multisig, index);
+    let escrow = vote_escrow_pda(&transaction, &voter.pubkey());
+    let vote_ix = |amount| Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::VoteWithTokens {
+            multisig: fixture.multisig,
+            transaction,
+            escrow,
+            mint: mint.pubkey(),
+            source: source.pubkey(),
+            voter: voter.pubkey(),
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::VoteWithTokens { amount }.data(),
+    };
+    let unlock_ix = Instruction {
+        program_id: multisig_wallet::ID,
+        accounts: multisig_wallet::accounts::UnlockVoteTokens {
+            transaction,
+            escrow,
+            mint: mint.pubkey(),
+            destination: source.pubkey(),
+            voter: voter.pubkey(),
+            token_program: spl_token::ID,
+        }
+        .to_account_metas(None),
+        data: multisig_wallet::instruction::UnlockVoteTokens {}.data(),
+    };
+
+    let (first, second) = (vote_ix(40), vote_ix(20));
+
+    // Rejections can't cancel the proposal while the token vote is open
+    let members: Vec<Keypair> = fixture.members.iter().map(|member| member.insecure_clone()).collect();
+    for member in &members {
+        fixture.send(&[fixture.reject_ix(&member.pubkey(), index, "")], &[member]).await.unwrap();
+    }
+    assert!(!fixture.transaction_account(index).await.cancelled);
+
+    // Votes add up across calls into the same escrow, and the one reaching
+    // the quorum also emits ThresholdReached
+    let events = |logs: Vec<String>| logs.iter().filter(|log| log.starts_with("Program data: ")).count();
+    let logs = simulate_logs(&mut fixture.context, &[first.clone()], &[&voter]).await;
+    assert_eq!(events(logs), 1);
+    fixture.send(&[first], &[&voter]).await.unwrap();
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+    let logs = simulate_logs(&mut fixture.context, &[second.clone()], &[&voter]).await;
+    assert_eq!(events(logs), 2);
+    fixture.send(&[second], &[&voter]).await.unwrap();
+    assert_eq!(fixture.transaction_account(index).await.token_votes, 60);
+    assert_eq!(token_balance(&mut fixture.context, &escrow).await, 60);
+
+    // The tokens stay locked while the proposal is open
+    let result = fixture.send(&[unlock_ix.clone()], &[&voter]).await;
+    assert_error(result, MultisigWalletError::VoteTokensLocked);
+
+    fixture.execute(index, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 2);
+
+    fixture.send(&[unlock_ix], &[&voter]).await.unwrap();
+    assert_eq!(token_balance(&mut fixture.context, &source.pubkey()).await, 100);
+    assert_eq!(fixture.balance(&escrow).await, 0);
+}
+
+#[tokio::test]
+async fn proposals_at_a_used_index_fail_precisely() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1);
+    let index = fixture.propose(&inner).await;
+
+    // A proposal built against the index that was just consumed
+    let creator = fixture.creator.insecure_clone();
+    let stale = fixture.propose_ix(&creator.pubkey(), index, &inner);
+    let result = fixture.send(&[stale], &[&creator]).await;
+    assert_error(result, MultisigWalletError::NonceAlreadyUsed);
+
+    // An address that isn't a transaction PDA at all
+    let mut bogus = fixture.propose_ix(&creator.pubkey(), index + 1, &inner);
+    bogus.accounts[1].pubkey = Pubkey::new_unique();
+    let result = fixture.send(&[bogus], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InvalidTransactionAccount);
+
+    // Lamports sent to the next address ahead of time don't block it
+    let next = transaction_pda(&fixture.multisig, index + 1);
+    let fund = system_instruction::transfer(&fixture.context.payer.pubkey(), &next, 1_000_000);
+    fixture.send(&[fund], &[]).await.unwrap();
+    assert_eq!(fixture.propose(&inner).await, index + 1);
+    assert_eq!(fixture.transaction_account(index + 1).await.tx_index, index + 1);
+}
+
+#[tokio::test]
+async fn removed_member_approvals_count_only_without_pruning() {
+    for prune in [true, false] {
+        let mut fixture = Fixture::new(2, 2, None).await;
+        fixture.fund_vault(LAMPORTS_PER_SOL).await;
+        if !prune {
+            fixture.configure(ConfigAction::SetPruneRemovedApprovals { enabled: false }).await.unwrap();
+        }
+        assert_eq!(fixture.multisig_account().await.prune_removed_approvals, prune);
+        let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+        let index = fixture.propose(&inner).await;
+
+        // The second member approves, then is removed from the signer set
+        fixture.approve(1, index).await.unwrap();
+        let creator = fixture.creator.insecure_clone();
+        let member = fixture.members[0].insecure_clone();
+        let removed = fixture.members[1].insecure_clone();
+        let ix = fixture.update_ix(
+            &[creator.pubkey(), member.pubkey(), removed.pubkey()],
+            Some(vec![creator.pubkey(), member.pubkey()]),
+            None,
+            ExpirationUpdate::Keep,
+        );
+        fixture.send(&[ix], &[&creator, &member, &removed]).await.unwrap();
+
+        let result = fixture.execute(index, &inner).await;
+        if prune {
+            assert_error(result, MultisigWalletError::InsufficientApprovals);
+        } else {
+            result.unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn program_member_proposes_and_approves_through_cpi() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let (authority, _) = streamer_authority();
+    let multisig = fixture.create_multisig(vec![creator.pubkey(), authority], 2).await;
+    fixture.select(multisig);
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+
+    // The program's PDA proposes, with the creator paying the rent
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, LAMPORTS_PER_SOL / 2);
+    let mut propose = fixture.propose_ix(&authority, 0, &inner);
+    propose.accounts[3] = AccountMeta::new(creator.pubkey(), true);
+    fixture.send(&[via_streamer(propose)], &[&creator]).await.unwrap();
+    let transaction = fixture.transaction_account(0).await;
+    assert_eq!(transaction.proposer, authority);
+    assert_eq!(transaction.rent_payer, creator.pubkey());
+
+    fixture.send(&[fixture.approve_ix(&creator.pubkey(), 0)], &[&creator]).await.unwrap();
+    fixture.execute(0, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL / 2);
+
+    // And approves a member's proposal
+    let index = fixture.propose(&inner).await;
+    let result = fixture.execute(index, &inner).await;
+    assert_error(result, MultisigWalletError::InsufficientApprovals);
+    fixture.send(&[via_streamer(fixture.approve_ix(&authority, index))], &[]).await.unwrap();
+    fixture.execute(index, &inner).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, LAMPORTS_PER_SOL);
+}
+
+#[tokio::test]
+async fn automation_authority_executes_only_automatable_proposals() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let keeper = Keypair::new();
+    fixture
+        .configure(ConfigAction::SetExecutionPolicy { policy: ExecutionPolicy::SignersOnly })
+        .await
+        .unwrap();
+    fixture
+        .configure(ConfigAction::SetAutomationAuthority { authority: Some(keeper.pubkey()) })
+        .await
+        .unwrap();
+
+    let recipient = Pubkey::new_unique();
+    let inner = fixture.vault_transfer(&recipient, 1_000);
+    let creator = fixture.creator.insecure_clone();
+    let propose = |fixture: &Fixture, index: u64, automatable: bool| {
+        let mut ix = fixture.propose_ix(&creator.pubkey(), index, &inner);
+        ix.data = multisig_wallet::instruction::ProposeTransaction {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            options: ProposalOptions { automatable, ..Default::default() },
+        }
+        .data();
+        ix
+    };
+    let executed_by = |fixture: &Fixture, index: u64, executor: &Pubkey| {
+        let mut ix = fixture.execute_ix(index, &inner);
+        ix.accounts[2] = AccountMeta::new_readonly(*executor, true);
+        ix
+    };
+
+    let index = fixture.multisig_account().await.nonce;
+    let ixs = [propose(&fixture, index, false), propose(&fixture, index + 1, true), propose(&fixture, index + 2, true)];
+    fixture.send(&ixs, &[&creator]).await.unwrap();
+    for offset in 0..3 {
+        fixture.approve(0, index + offset).await.unwrap();
+    }
+
+    // Under SignersOnly, anonymous executions are refused
+    let result = fixture.execute(index + 1, &inner).await;
+    assert_error(result, MultisigWalletError::ExecutorNotAllowed);
+
+    // The keeper can only execute proposals flagged automatable
+    let result = fixture.send(&[executed_by(&fixture, index, &keeper.pubkey())], &[&keeper]).await;
+    assert_error(result, MultisigWalletError::NotAutomatable);
+    fixture.send(&[executed_by(&fixture, index + 1, &keeper.pubkey())], &[&keeper]).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, 1_000);
+
+    // Revoking the keeper applies to proposals already pending
+    fixture.configure(ConfigAction::SetAutomationAuthority { authority: None }).await.unwrap();
+    let result = fixture.send(&[executed_by(&fixture, index + 2, &keeper.pubkey())], &[&keeper]).await;
+    assert_error(result, MultisigWalletError::ExecutorNotAllowed);
+
+    // Members still execute anything
+    let ix = executed_by(&fixture, index, &creator.pubkey());
+    fixture.send(&[ix], &[&creator]).await.unwrap();
+    assert_eq!(fixture.balance(&recipient).await, 2_000);
+}
+
+#[tokio::test]
+async fn batch_proposals_take_consecutive_indexes_atomically() {
+    let mut fixture = Fixture::new(1, 2, None).await;
+    fixture.fund_vault(LAMPORTS_PER_SOL).await;
+    let recipients: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let inners: Vec<Instruction> =
+        recipients.iter().map(|recipient| fixture.vault_transfer(recipient, 1_000)).collect();
+    let creator = fixture.creator.insecure_clone();
+    let batch_ix = |fixture: &Fixture, start: u64, proposals: Vec<BatchProposal>| {
+        let mut accounts = multisig_wallet::accounts::ProposeTransactionsBatch {
+            multisig: fixture.multisig,
+            proposer: creator.pubkey(),
+            rent_payer: creator.pubkey(),
+            system_program: system_program::ID,
+            program_config: program_config_pda(),
+        }
+        .to_account_metas(None);
+        let indexes = start..start + proposals.len() as u64;
+        let transactions = indexes.map(|index| transaction_pda(&fixture.multisig, index));
+        accounts.extend(transactions.map(|transaction| AccountMeta::new(transaction, false)));
+        Instruction {
+            program_id: multisig_wallet::ID,
+            accounts,
+            data: multisig_wallet::instruction::ProposeTransactionsBatch { proposals }.data(),
+        }
+    };
+    let proposals: Vec<BatchProposal> = inners
+        .iter()
+        .map(|inner| BatchProposal {
+            program_id: inner.program_id,
+            accounts: serialize_account_metas(&inner.accounts),
+            instruction_data: inner.data.clone(),
+            memo: None,
+            category: 0,
+        })
+        .collect();
+
+    // A single proposal isn't a batch
+    let ix = batch_ix(&fixture, 0, proposals[..1].to_vec());
+    let result = fixture.send(&[ix], &[&creator]).await;
+    assert_error(result, MultisigWalletError::InvalidBatchSize);
+
+    // One invalid proposal fails the whole batch
+    let mut invalid = proposals.clone();
+    invalid[2].memo = Some("x".repeat(200));
+    let result = fixture.send(&[batch_ix(&fixture, 0, invalid)], &[&creator]).await;
+    assert_error(result, MultisigWalletError::MemoTooLong);
+    assert_eq!(fixture.multisig_account().await.nonce, 0);
+    assert_eq!(fixture.balance(&transaction_pda(&fixture.multisig, 0)).await, 0);
+
+    fixture.send(&[batch_ix(&fixture, 0, proposals)], &[&creator]).await.unwrap();
+    assert_eq!(fixture.multisig_account().await.nonce, 3);
+    for (index, inner) in inners.iter().enumerate() {
+        let index = index as u64;
+        assert_eq!(fixture.transaction_account(index).await.tx_index, index);
+        fixture.approve(0, index).await.unwrap();
+        fixture.execute(index, inner).await.unwrap();
+    }
+    for recipient in &recipients {
+        assert_eq!(fixture.balance(recipient).await, 1_000);
+    }
+}
+
+#[tokio::test]
+async fn initialize_rejects_legacy_rent_sysvar_slot() {
+    let mut fixture = Fixture::new(0, 1, None).await;
+    let creator = fixture.creator.insecure_clone();
+    let create_key = Keypair::new();
+    let multisig = multisig_pda(&create_key.pubkey());
+    let mut ix = fixture.initialize_ix(vec![creator.pubkey()], 1, None);
+    ix.accounts[0].pubkey = multisig;
+    ix.accounts[1].pubkey = create_key.pubkey();
+    ix.accounts[5].pubkey = generation_pda(&create_key.pubkey());
+
+    // Older clients pass the rent sysvar where `program_config` now goes
+    let mut legacy = ix.clone();
+    legacy.accounts[4] = AccountMeta::new_readonly(sysvar::rent::ID, false);
+    let result = fixture.send(&[legacy], &[&creator, &create_key]).await;
+    let code = anchor_lang::error::ErrorCode::ConstraintSeeds as u32;
+    assert!(matches!(
+        result,
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(_, InstructionError::Custom(actual))))
+            if actual == code
+    ));
+
+    // Trailing extra accounts are still ignored
+    ix.accounts.push(AccountMeta::new_readonly(sysvar::rent::ID, false));
+    fixture.send(&[ix], &[&creator, &create_key]).await.unwrap();
+
+    fixture.select(multisig);
+    assert_eq!(fixture.multisig_account().await.signers, vec![creator.pubkey()]);
+}
+
+#[tokio::test]
+async fn threshold_reached_fires_once_per_crossing() {
+    let mut fixture = Fixture::new(3, 2, None).await;
+    let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+    let index = fixture.propose(&inner).await;
+    let members: Vec<Keypair> = fixture.members.iter().map(|member| member.insecure_clone()).collect();
+
+    // Approving emits TransactionApproved, plus ThresholdReached on a crossing
+    let approvals = members.iter().map(|member| fixture.approve_ix(&member.pubkey(), index));
+    let [first, second, third] = <[Instruction; 3]>::try_from(approvals.collect::<Vec<_>>()).unwrap();
+    let (first_signer, second_signer, third_signer) = (&members[0], &members[1], &members[2]);
+    let events = |logs: Vec<String>| logs.iter().filter(|log| log.starts_with("Program data: ")).count();
+
+    let logs = simulate_logs(&mut fixture.context, &[first.clone()], &[first_signer]).await;
+    assert_eq!(events(logs), 2);
+    fixture.send(&[first], &[first_signer]).await.unwrap();
+
+    // Removing the approver drops the proposal back below the threshold
+    let creator = fixture.creator.insecure_clone();
+    let mut approvers = vec![creator.pubkey()];
+    approvers.extend(members.iter().map(|member| member.pubkey()));
+    let ix = fixture.update_ix(
+        &approvers,
+        Some(vec![creator.pubkey(), members[1].pubkey(), members[2].pubkey()]),
+        None,
+        ExpirationUpdate::Keep,
+    );
+    fixture.send(&[ix], &[&creator, &members[0], &members[1], &members[2]]).await.unwrap();
+
+    // So the next approval crosses it again, and the one after doesn't
+    let logs = simulate_logs(&mut fixture.context, &[second.clone()], &[second_signer]).await;
+    assert_eq!(events(logs), 2);
+    fixture.send(&[second], &[second_signer]).await.unwrap();
+    let logs = simulate_logs(&mut fixture.context, &[third], &[third_signer]).await;
+    assert_eq!(events(logs), 1);
+}
+
+#[tokio::test]
+async fn threshold_at_propose_time_governs_unless_live() {
+    for live in [false, true] {
+        let mut fixture = Fixture::new(2, 2, None).await;
+        fixture.fund_vault(LAMPORTS_PER_SOL).await;
+        if live {
+            fixture.configure(ConfigAction::SetLiveThreshold { enabled: true }).await.unwrap();
+        }
+        let inner = fixture.vault_transfer(&Pubkey::new_unique(), 1_000);
+        let index = fixture.propose(&inner).await;
+        assert_eq!(fixture.transaction_account(index).await.required_approvals, 2);
+
+        // Raise the threshold while the proposal is pending
+        let creator = fixture.creator.insecure_clone();
+        let members: Vec<Keypair> = fixture.members.iter().map(|member| member.insecure_clone()).collect();
+        let approvers = [creator.pubkey(), members[0].pubkey(), members[1].pubkey()];
+        let ix = fixture.update_ix(&approvers, None, Some(3), ExpirationUpdate::Keep);
+        fixture.send(&[ix], &[&creator, &members[0], &members[1]]).await.unwrap();
+
+        fixture.approve(0, index).await.unwrap();
+        let result = fixture.execute(index, &inner).await;
+        if live {
+            assert_error(result, MultisigWalletError::InsufficientApprovals);
+            fixture.approve(1, index).await.unwrap();
+            fixture.execute(index, &inner).await.unwrap();
+        } else {
+            result.unwrap();
+        }
+    }
+}